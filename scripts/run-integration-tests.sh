@@ -24,4 +24,14 @@ fi
 export POCKET_IC_BIN="$(pwd)/pocket-ic"
 cd ../..
 
+# Mock canisters are not part of dfx.json, build them here
+cargo build --target wasm32-unknown-unknown --release --package mock_swap_pool
+cargo build --target wasm32-unknown-unknown --release --package mock_bitcoin
+cargo build --target wasm32-unknown-unknown --release --package mock_minter
+cargo build --target wasm32-unknown-unknown --release --package mock_nns_governance
+cargo build --target wasm32-unknown-unknown --release --package mock_sns_governance
+cargo build --target wasm32-unknown-unknown --release --package mock_cmc
+cargo build --target wasm32-unknown-unknown --release --package mock_cycles_ledger
+cargo build --target wasm32-unknown-unknown --release --package mock_icrc7
+
 cargo test --package integration $TESTNAME -- --test-threads $TEST_THREADS --nocapture
\ No newline at end of file
@@ -1,29 +1,231 @@
+use std::{cell::RefCell, str::FromStr, time::Duration};
+
 use alloy::{
-    signers::icp::IcpSigner,
-    transports::icp::{EthSepoliaService, RpcApi, RpcService},
+    consensus::SignableTransaction,
+    network::TxSigner,
+    primitives::{Address, ChainId, Signature, B256},
+    signers::{icp::IcpSigner, Error as SignerError, Result as SignerResult, Signer},
+    transports::icp::{EthMainnetService, EthSepoliaService, RpcApi, RpcService},
+};
+use async_trait::async_trait;
+use ic_cdk::api::management_canister::ecdsa::{
+    sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, SignWithEcdsaArgument,
 };
+use ic_stable_structures::StableBTreeMap;
+use keygate_core::types::environment::EvmRpcProvider;
+
+use crate::{evm_types::DerivedEvmKey, get_environment, DERIVED_EVM_KEYS_MEMORY, MEMORY_MANAGER, VM};
+
+thread_local! {
+    /// Public keys derived from the threshold ECDSA key, keyed by key name and
+    /// derivation path, so they are fetched from the management canister only once.
+    static DERIVED_KEYS: RefCell<StableBTreeMap<String, DerivedEvmKey, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(DERIVED_EVM_KEYS_MEMORY)))
+    );
+}
+
+fn derived_key_id(key_name: &str, derivation_path: &[Vec<u8>]) -> String {
+    let path: Vec<String> = derivation_path.iter().map(hex::encode).collect();
+    format!("{}/{}", key_name, path.join("/"))
+}
+
+fn cached_key(key_name: &str, derivation_path: &[Vec<u8>]) -> Option<DerivedEvmKey> {
+    let key_id = derived_key_id(key_name, derivation_path);
+    DERIVED_KEYS.with(|keys| keys.borrow().get(&key_id))
+}
+
+fn cache_key(key: DerivedEvmKey) {
+    let key_id = derived_key_id(&key.key_name, &key.derivation_path);
+    DERIVED_KEYS.with(|keys| keys.borrow_mut().insert(key_id, key));
+}
+
+/// The derived EVM key of a derivation path, if it was fetched already. The empty
+/// path is the vault's main address.
+pub fn cached_evm_key(derivation_path: &[Vec<u8>]) -> Option<DerivedEvmKey> {
+    cached_key(&get_environment().ecdsa_key_name, derivation_path)
+}
+
+/// The derived EVM key of a derivation path, fetched from the management canister
+/// only when it is not cached.
+pub async fn evm_key(derivation_path: Vec<Vec<u8>>) -> DerivedEvmKey {
+    if let Some(key) = cached_evm_key(&derivation_path) {
+        return key;
+    }
+
+    let ecdsa_key_name = get_environment().ecdsa_key_name;
+    let signer = IcpSigner::new(derivation_path.clone(), &ecdsa_key_name, None)
+        .await
+        .unwrap();
+    let key = DerivedEvmKey {
+        key_name: ecdsa_key_name,
+        derivation_path,
+        public_key: signer.public_key().to_vec(),
+        address: signer.address().to_string(),
+    };
+    cache_key(key.clone());
+    key
+}
+
+/// Signs with the threshold ECDSA key like `IcpSigner`, but is built from a cached
+/// key, so only signing calls the management canister.
+#[derive(Clone, Debug)]
+pub struct VaultSigner {
+    key_name: String,
+    derivation_path: Vec<Vec<u8>>,
+    address: Address,
+    chain_id: Option<ChainId>,
+}
+
+impl VaultSigner {
+    pub fn from_key(key: DerivedEvmKey) -> VaultSigner {
+        VaultSigner {
+            address: Address::from_str(&key.address).unwrap(),
+            key_name: key.key_name,
+            derivation_path: key.derivation_path,
+            chain_id: None,
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Signer for VaultSigner {
+    async fn sign_hash(&self, hash: &B256) -> SignerResult<Signature> {
+        let (reply,) = sign_with_ecdsa(SignWithEcdsaArgument {
+            message_hash: hash.to_vec(),
+            derivation_path: self.derivation_path.clone(),
+            key_id: EcdsaKeyId {
+                curve: EcdsaCurve::Secp256k1,
+                name: self.key_name.clone(),
+            },
+        })
+        .await
+        .map_err(|(code, message)| {
+            SignerError::other(format!("Failed to sign: {:?} - {}", code, message))
+        })?;
+
+        // The management canister returns r and s, the parity is the one that recovers
+        // the signer's address
+        for parity in [false, true] {
+            let signature = Signature::from_bytes_and_parity(&reply.signature, parity)?;
+            if signature.recover_address_from_prehash(hash).ok() == Some(self.address) {
+                return Ok(signature);
+            }
+        }
+        Err(SignerError::other(format!(
+            "The signature does not recover to {}",
+            self.address
+        )))
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn chain_id(&self) -> Option<ChainId> {
+        self.chain_id
+    }
 
-use crate::evm::KEY_NAME;
-pub async fn create_icp_sepolia_signer() -> IcpSigner {
-    let ecdsa_key_name = KEY_NAME.with(|key_name| key_name.borrow().clone());
-    IcpSigner::new(vec![], &ecdsa_key_name, None).await.unwrap()
+    fn set_chain_id(&mut self, chain_id: Option<ChainId>) {
+        self.chain_id = chain_id;
+    }
 }
 
-pub fn get_rpc_service_sepolia() -> RpcService {
-    RpcService::EthSepolia(EthSepoliaService::PublicNode)
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl TxSigner<Signature> for VaultSigner {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        tx: &mut dyn SignableTransaction<Signature>,
+    ) -> SignerResult<Signature> {
+        if let Some(chain_id) = self.chain_id {
+            if !tx.set_chain_id_checked(chain_id) {
+                return Err(SignerError::TransactionChainIdMismatch {
+                    signer: chain_id,
+                    tx: tx.chain_id().unwrap(),
+                });
+            }
+        }
+
+        let signature = self.sign_hash(&tx.signature_hash()).await?;
+        match self.chain_id.or_else(|| tx.chain_id()) {
+            Some(chain_id) if tx.use_eip155() => Ok(signature.with_chain_id(chain_id)),
+            _ => Ok(signature),
+        }
+    }
 }
 
-pub fn get_rpc_service_base() -> RpcService {
-    // RpcService::BaseMainnet(L2MainnetService::PublicNode)
-    RpcService::Custom(RpcApi {
-        url: "https://sepolia.base.org/".to_string(),
-        headers: None,
-    })
+pub async fn create_icp_signer(derivation_path: Vec<Vec<u8>>) -> VaultSigner {
+    VaultSigner::from_key(evm_key(derivation_path).await)
 }
 
-pub fn get_rpc_service_polygon() -> RpcService {
-    RpcService::Custom(RpcApi {
-        url: "https://rpc-amoy.polygon.technology".to_string(),
-        headers: None,
-    })
+/// The EVM address of a derivation path, without a management canister call once
+/// it is cached.
+pub async fn evm_address(derivation_path: Vec<Vec<u8>>) -> Address {
+    Address::from_str(&evm_key(derivation_path).await.address).unwrap()
+}
+
+/// Derives the main EVM key in the background after install or upgrade, so queries can
+/// serve it right away.
+pub fn warm_evm_key_cache() {
+    if cached_evm_key(&[]).is_some() {
+        return;
+    }
+
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            evm_key(vec![]).await;
+        })
+    });
+}
+
+pub fn get_rpc_service(provider: &EvmRpcProvider) -> RpcService {
+    match provider {
+        EvmRpcProvider::EthMainnetPublicNode => RpcService::EthMainnet(EthMainnetService::PublicNode),
+        EvmRpcProvider::EthSepoliaPublicNode => RpcService::EthSepolia(EthSepoliaService::PublicNode),
+        EvmRpcProvider::Custom(url) => RpcService::Custom(RpcApi {
+            url: url.clone(),
+            headers: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key_name: &str, derivation_path: Vec<Vec<u8>>) -> DerivedEvmKey {
+        DerivedEvmKey {
+            key_name: key_name.to_string(),
+            derivation_path,
+            public_key: vec![2; 33],
+            address: "0x000000000000000000000000000000000000dEaD".to_string(),
+        }
+    }
+
+    #[test]
+    fn misses_keys_that_were_not_derived() {
+        cache_key(key("key_1", vec![b"treasury".to_vec()]));
+
+        assert_eq!(cached_key("key_1", &[]), None);
+        assert_eq!(cached_key("key_1", &[b"payroll".to_vec()]), None);
+        // Keys of another ECDSA key are not reused
+        assert_eq!(cached_key("test_key_1", &[b"treasury".to_vec()]), None);
+    }
+
+    #[test]
+    fn builds_signers_from_cached_keys() {
+        let cached = key("key_1", vec![]);
+        cache_key(cached.clone());
+        assert_eq!(cached_key("key_1", &[]), Some(cached.clone()));
+
+        let signer = VaultSigner::from_key(cached_key("key_1", &[]).unwrap());
+        assert_eq!(Signer::address(&signer).to_string(), cached.address);
+        assert_eq!(signer.derivation_path, cached.derivation_path);
+        assert_eq!(signer.chain_id(), None);
+    }
 }
@@ -0,0 +1,431 @@
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+use candid::CandidType;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    evm_wallets,
+    intent::{
+        adapter_exists, execute_and_record, validate_network, IntentStatus, ProposedTransaction,
+        SupportedNetwork, TokenPath, TransactionRequest, TransactionType,
+    },
+    push_proposed_transaction, signer_exists, BATCHES_MEMORY, MEMORY_MANAGER,
+    PROPOSED_TRANSACTIONS, THRESHOLD, TRANSACTION_UPDATES, VM,
+};
+
+const MAX_ITEMS: usize = 100;
+
+/// Transfers executed per timer callback, so a chunk stays well within the instruction
+/// limit of a message even when every transfer is an inter-canister call.
+const CHUNK_SIZE: usize = 10;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct BatchTransfer {
+    pub to: String,
+    pub token: TokenPath,
+    pub network: SupportedNetwork,
+    pub amount: f64,
+    /// Named EVM wallet to send from, the vault's main address when empty.
+    pub wallet: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct BatchItem {
+    pub transfer: BatchTransfer,
+    /// Pending until the transfer runs, then the status of its latest attempt.
+    pub status: IntentStatus,
+    pub attempts: u32,
+    /// Index of the latest attempt in `TRANSACTIONS`.
+    pub transaction_index: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Batch {
+    pub items: Vec<BatchItem>,
+    /// Set while timer callbacks are working through the pending items.
+    pub running: bool,
+}
+
+impl Storable for Batch {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    /// Batches keyed by the id of their proposal.
+    static BATCHES: RefCell<StableBTreeMap<u64, Batch, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(BATCHES_MEMORY)))
+    );
+}
+
+fn request(transfer: &BatchTransfer) -> TransactionRequest {
+    TransactionRequest {
+        transaction_type: TransactionType::Transfer,
+        amount: transfer.amount,
+        token: transfer.token.clone(),
+        to: transfer.to.clone(),
+        network: transfer.network.clone(),
+        payload: None,
+        wallet: transfer.wallet.clone(),
+    }
+}
+
+fn is_pending(item: &BatchItem) -> bool {
+    matches!(item.status, IntentStatus::Pending(_))
+}
+
+/// Whether a failed transfer is known not to have moved funds. ICP ledgers either apply a
+/// transfer or reject it, while a failed EVM, Bitcoin or Solana transfer may still have
+/// been broadcast, and be mined after sending it again. Interrupted transfers have no
+/// record and may have gone through anywhere.
+fn is_retryable(item: &BatchItem) -> bool {
+    matches!(item.status, IntentStatus::Failed(_))
+        && item.transaction_index.is_some()
+        && item.transfer.token.starts_with("icp:")
+}
+
+fn update_batch<T>(proposal_id: u64, f: impl FnOnce(&mut Batch) -> T) -> Option<T> {
+    BATCHES.with(|batches| {
+        let mut batches = batches.borrow_mut();
+        let mut batch = batches.get(&proposal_id)?;
+        let result = f(&mut batch);
+        batches.insert(proposal_id, batch);
+        Some(result)
+    })
+}
+
+/// The batch with the statuses its transfers were updated to after their execution,
+/// e.g. once an EVM transfer is confirmed.
+fn resolved(mut batch: Batch) -> Batch {
+    TRANSACTION_UPDATES.with(|updates| {
+        let updates = updates.borrow();
+        for item in batch.items.iter_mut() {
+            if let Some(update) = item.transaction_index.and_then(|index| updates.get(&index)) {
+                item.status = update.status;
+            }
+        }
+    });
+    batch
+}
+
+fn summary(batch: &Batch) -> IntentStatus {
+    let total = batch.items.len();
+    let count = |f: fn(&IntentStatus) -> bool| batch.items.iter().filter(|i| f(&i.status)).count();
+
+    let unsettled = count(|s| matches!(s, IntentStatus::Pending(_) | IntentStatus::InProgress(_)));
+    let failed = count(|s| matches!(s, IntentStatus::Failed(_)));
+
+    if unsettled > 0 {
+        IntentStatus::InProgress(format!(
+            "{} of {} transfers settled",
+            total - unsettled,
+            total
+        ))
+    } else if failed > 0 {
+        IntentStatus::Failed(format!("{} of {} transfers failed", failed, total))
+    } else {
+        IntentStatus::Completed(format!("All {} transfers completed", total))
+    }
+}
+
+/// Stops the batch when a transfer traps after an await, so its remaining transfers can be
+/// resumed with `retry_batch`. Dropped on cleanup, as `evm::NonceGuard` is.
+struct ChunkGuard {
+    proposal_id: u64,
+}
+
+impl Drop for ChunkGuard {
+    fn drop(&mut self) {
+        update_batch(self.proposal_id, |batch| {
+            batch.running = false;
+            for item in batch.items.iter_mut() {
+                if item.transaction_index.is_none()
+                    && matches!(item.status, IntentStatus::InProgress(_))
+                {
+                    item.status = IntentStatus::Failed(
+                        "Interrupted, the transfer may have been sent".to_string(),
+                    );
+                }
+            }
+        });
+    }
+}
+
+fn schedule_chunk(proposal_id: u64) {
+    ic_cdk_timers::set_timer(Duration::ZERO, move || {
+        ic_cdk::spawn(execute_chunk(proposal_id))
+    });
+}
+
+/// Executes the next pending transfers one after the other, and schedules another
+/// chunk while some are left.
+async fn execute_chunk(proposal_id: u64) {
+    let chunk: Vec<(usize, BatchTransfer)> = BATCHES
+        .with(|batches| batches.borrow().get(&proposal_id))
+        .map(|batch| {
+            batch
+                .items
+                .into_iter()
+                .enumerate()
+                .filter(|(_, item)| is_pending(item))
+                .take(CHUNK_SIZE)
+                .map(|(index, item)| (index, item.transfer))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let guard = ChunkGuard { proposal_id };
+    for (index, transfer) in chunk {
+        update_batch(proposal_id, |batch| {
+            let item = &mut batch.items[index];
+            item.status = IntentStatus::InProgress("Executing the transfer".to_string());
+            item.attempts += 1;
+            item.transaction_index = None;
+        });
+
+        let (transaction_index, status) = execute_and_record(proposal_id, request(&transfer)).await;
+
+        update_batch(proposal_id, |batch| {
+            let item = &mut batch.items[index];
+            item.status = status;
+            item.transaction_index = Some(transaction_index);
+        });
+    }
+    drop(guard);
+
+    let more = update_batch(proposal_id, |batch| {
+        batch.running = batch.items.iter().any(is_pending);
+        batch.running
+    });
+    if more == Some(true) {
+        schedule_chunk(proposal_id);
+    }
+}
+
+/// Starts executing the pending transfers of an approved batch, in chunks across timer
+/// callbacks. Transfers that already ran are not executed again.
+pub(crate) fn start_batch(proposal_id: u64) -> IntentStatus {
+    let batch = match BATCHES.with(|batches| batches.borrow().get(&proposal_id)) {
+        Some(batch) => batch,
+        None => return IntentStatus::Failed(format!("Batch not found: {}", proposal_id)),
+    };
+    if batch.running {
+        return IntentStatus::InProgress("The batch is already executing".to_string());
+    }
+
+    let pending = batch.items.iter().filter(|item| is_pending(item)).count();
+    if pending == 0 {
+        return summary(&resolved(batch));
+    }
+
+    update_batch(proposal_id, |batch| batch.running = true);
+    schedule_chunk(proposal_id);
+
+    IntentStatus::InProgress(format!(
+        "Executing {} of {} transfers",
+        pending,
+        batch.items.len()
+    ))
+}
+
+/// Proposes transfers that are approved once and executed together. The proposal only
+/// carries its type, the transfers are returned by `get_batch`.
+#[ic_cdk::update]
+pub fn propose_batch(transfers: Vec<BatchTransfer>) -> ProposedTransaction {
+    let caller = ic_cdk::caller();
+    if !signer_exists(caller) {
+        ic_cdk::trap("Caller is not a signer");
+    }
+    if transfers.is_empty() || transfers.len() > MAX_ITEMS {
+        ic_cdk::trap(&format!("A batch has 1 to {} transfers", MAX_ITEMS));
+    }
+
+    for (index, transfer) in transfers.iter().enumerate() {
+        if let Err(e) = evm_wallets::validate_source(transfer.wallet.as_deref(), &transfer.token) {
+            ic_cdk::trap(&format!("Transfer {}: {}", index, e));
+        }
+        if let Err(e) = validate_network(&transfer.network, &transfer.token) {
+            ic_cdk::trap(&format!("Transfer {}: {}", index, e));
+        }
+        if !adapter_exists(&request(transfer)) {
+            ic_cdk::trap(&format!(
+                "Transfer {}: {} can't be transferred",
+                index, transfer.token
+            ));
+        }
+    }
+
+    let proposal = push_proposed_transaction(ProposedTransaction {
+        id: 0,
+        to: String::new(),
+        token: String::new(),
+        network: SupportedNetwork::ICP,
+        amount: 0.0,
+        transaction_type: TransactionType::Batch,
+        signers: vec![caller],
+        rejections: vec![],
+        payload: None,
+        wallet: None,
+        executed: false,
+    });
+
+    let batch = Batch {
+        items: transfers
+            .into_iter()
+            .map(|transfer| BatchItem {
+                transfer,
+                status: IntentStatus::Pending("Waiting for the batch to execute".to_string()),
+                attempts: 0,
+                transaction_index: None,
+            })
+            .collect(),
+        running: false,
+    };
+    BATCHES.with(|batches| batches.borrow_mut().insert(proposal.id, batch));
+
+    proposal
+}
+
+#[ic_cdk::query]
+pub fn get_batch(proposal_id: u64) -> Option<Batch> {
+    BATCHES
+        .with(|batches| batches.borrow().get(&proposal_id))
+        .map(resolved)
+}
+
+/// Executes the failed transfers of a batch again, or only those at `items`, along with
+/// transfers an interrupted execution left pending. Transfers that succeeded, or that may
+/// have been sent, are never repeated.
+#[ic_cdk::update]
+pub fn retry_batch(proposal_id: u64, items: Option<Vec<u32>>) -> Result<IntentStatus, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+
+    let proposal = PROPOSED_TRANSACTIONS
+        .with(|proposals| proposals.borrow().get(proposal_id))
+        .ok_or(format!("Proposal not found: {}", proposal_id))?;
+    let threshold = THRESHOLD.with(|threshold| *threshold.borrow().get());
+    if (proposal.signers.len() as u64) < threshold {
+        return Err("Threshold not met".to_string());
+    }
+
+    let batch = get_batch(proposal_id).ok_or(format!("Batch not found: {}", proposal_id))?;
+    if batch.running {
+        return Err("The batch is still executing".to_string());
+    }
+
+    let retryable: Vec<usize> = batch
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| is_retryable(item))
+        .map(|(index, _)| index)
+        .collect();
+    let retried = match items {
+        Some(items) => {
+            let items: Vec<usize> = items.into_iter().map(|index| index as usize).collect();
+            if let Some(index) = items.iter().find(|index| !retryable.contains(index)) {
+                return Err(match batch.items.get(*index) {
+                    Some(item) if matches!(item.status, IntentStatus::Failed(_)) => format!(
+                        "Transfer {} may have been sent, check it before proposing it again",
+                        index
+                    ),
+                    _ => format!("Transfer {} did not fail", index),
+                });
+            }
+            items
+        }
+        None => retryable,
+    };
+    if retried.is_empty() && !batch.items.iter().any(is_pending) {
+        return Err("No failed transfers to retry".to_string());
+    }
+
+    update_batch(proposal_id, |batch| {
+        for index in retried {
+            batch.items[index].status = IntentStatus::Pending("Waiting for a retry".to_string());
+        }
+    });
+
+    Ok(start_batch(proposal_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(statuses: Vec<IntentStatus>) -> Batch {
+        Batch {
+            items: statuses
+                .into_iter()
+                .map(|status| BatchItem {
+                    transfer: BatchTransfer {
+                        to: "aaaaa-aa".to_string(),
+                        token: "icp:native".to_string(),
+                        network: SupportedNetwork::ICP,
+                        amount: 1.0,
+                        wallet: None,
+                    },
+                    status,
+                    attempts: 1,
+                    transaction_index: None,
+                })
+                .collect(),
+            running: false,
+        }
+    }
+
+    #[test]
+    fn summarizes_settled_batches() {
+        let completed = IntentStatus::Completed(String::new());
+        let failed = IntentStatus::Failed(String::new());
+
+        assert_eq!(
+            summary(&batch(vec![completed.clone(), completed.clone()])),
+            IntentStatus::Completed("All 2 transfers completed".to_string())
+        );
+        assert_eq!(
+            summary(&batch(vec![completed, failed.clone(), failed])),
+            IntentStatus::Failed("2 of 3 transfers failed".to_string())
+        );
+    }
+
+    #[test]
+    fn only_retries_transfers_known_not_to_be_sent() {
+        let mut batch = batch(vec![IntentStatus::Failed(String::new()); 4]);
+        batch.items[0].transaction_index = Some(0);
+        // Interrupted before its record was appended
+        batch.items[1].transaction_index = None;
+        batch.items[2].transaction_index = Some(2);
+        batch.items[2].transfer.token = "eth:native".to_string();
+        batch.items[3].transaction_index = Some(3);
+        batch.items[3].status = IntentStatus::Completed(String::new());
+
+        let retryable: Vec<bool> = batch.items.iter().map(is_retryable).collect();
+        assert_eq!(retryable, vec![true, false, false, false]);
+    }
+
+    #[test]
+    fn keeps_batches_with_unsettled_transfers_in_progress() {
+        let statuses = vec![
+            IntentStatus::Completed(String::new()),
+            IntentStatus::Failed(String::new()),
+            IntentStatus::InProgress(String::new()),
+            IntentStatus::Pending(String::new()),
+        ];
+
+        assert_eq!(
+            summary(&batch(statuses)),
+            IntentStatus::InProgress("2 of 4 transfers settled".to_string())
+        );
+    }
+}
@@ -0,0 +1,729 @@
+use std::{cell::RefCell, collections::HashSet, future::Future, pin::Pin, str::FromStr};
+
+use bitcoin::{
+    absolute::LockTime,
+    consensus::serialize,
+    hashes::Hash,
+    key::{Secp256k1, XOnlyPublicKey},
+    secp256k1::{ecdsa, schnorr::Signature as SchnorrSignature},
+    sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType},
+    transaction::Version,
+    Address, Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, Transaction,
+    TxIn, TxOut, Txid, Witness,
+};
+use ic_cdk::api::management_canister::{
+    bitcoin::{
+        bitcoin_get_balance, bitcoin_get_current_fee_percentiles, bitcoin_get_utxos,
+        bitcoin_send_transaction, BitcoinNetwork as IcBitcoinNetwork, GetBalanceRequest,
+        GetCurrentFeePercentilesRequest, GetUtxosRequest, Outpoint, SendTransactionRequest, Utxo,
+        UtxoFilter,
+    },
+    ecdsa::{
+        ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
+        SignWithEcdsaArgument,
+    },
+};
+use ic_stable_structures::StableBTreeMap;
+use keygate_core::types::{environment::BitcoinNetwork, vault::BitcoinAddressType};
+
+use crate::{
+    get_environment,
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest},
+    schnorr::{self, SchnorrAlgorithm, SignWithSchnorrAux},
+    signer_exists, BTC_PUBLIC_KEYS_MEMORY, BTC_SPENT_OUTPOINTS_MEMORY, MEMORY_MANAGER, VM,
+};
+
+/// Derivation path of the vault's P2WPKH key, so it differs from its main EVM key.
+const BTC_DERIVATION_PATH: &[u8] = b"btc";
+/// Derivation path of the vault's Taproot key.
+const TAPROOT_DERIVATION_PATH: &[u8] = b"btc_taproot";
+/// Smallest P2WPKH output relayed by default, in satoshis.
+const DUST_LIMIT: u64 = 294;
+/// Fee percentile paid by the vault's transactions, the median.
+const FEE_PERCENTILE: usize = 50;
+/// Fee rate when the network has no recent fees, e.g. on regtest, in millisatoshi per vbyte.
+const DEFAULT_FEE_RATE: u64 = 2_000;
+
+// Virtual sizes of the parts of a transaction, with the witnesses of its inputs
+const TX_OVERHEAD_VBYTES: u64 = 11;
+const P2WPKH_INPUT_VBYTES: u64 = 68;
+const P2TR_KEY_SPEND_INPUT_VBYTES: u64 = 58;
+
+/// Nodes drop unconfirmed transactions after two weeks by default, the outpoints they
+/// spent can be picked again after that.
+const SPENT_OUTPOINT_EXPIRY_NANOS: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+
+thread_local! {
+    /// SEC1 public keys of the vault's BTC addresses, keyed by key name and address
+    /// type, so they are fetched from the management canister only once.
+    static PUBLIC_KEYS: RefCell<StableBTreeMap<String, Vec<u8>, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(BTC_PUBLIC_KEYS_MEMORY)))
+    );
+
+    /// Outpoints spent by sent transactions that the Bitcoin canister still reports
+    /// as unspent, so the next send does not pick them again, with the time they were
+    /// spent at.
+    static SPENT_OUTPOINTS: RefCell<StableBTreeMap<String, u64, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(BTC_SPENT_OUTPOINTS_MEMORY)))
+    );
+
+    static SENDING: RefCell<bool> = const { RefCell::new(false) };
+}
+
+/// Allows a single BTC transaction in flight, as concurrent sends would select the
+/// same UTXOs. Released when dropped.
+struct SendGuard;
+
+impl SendGuard {
+    fn acquire() -> Result<SendGuard, String> {
+        if SENDING.with(|sending| sending.replace(true)) {
+            return Err("Another BTC transaction is being sent, retry once it completes.".to_string());
+        }
+        Ok(SendGuard)
+    }
+}
+
+impl Drop for SendGuard {
+    fn drop(&mut self) {
+        SENDING.with(|sending| sending.replace(false));
+    }
+}
+
+fn bitcoin_network() -> Result<BitcoinNetwork, String> {
+    get_environment()
+        .bitcoin_network
+        .ok_or_else(|| "This vault does not hold BTC.".to_string())
+}
+
+fn ic_network(network: BitcoinNetwork) -> IcBitcoinNetwork {
+    match network {
+        BitcoinNetwork::Mainnet => IcBitcoinNetwork::Mainnet,
+        BitcoinNetwork::Testnet => IcBitcoinNetwork::Testnet,
+        BitcoinNetwork::Regtest => IcBitcoinNetwork::Regtest,
+    }
+}
+
+fn btc_network(network: BitcoinNetwork) -> Network {
+    match network {
+        BitcoinNetwork::Mainnet => Network::Bitcoin,
+        BitcoinNetwork::Testnet => Network::Testnet,
+        BitcoinNetwork::Regtest => Network::Regtest,
+    }
+}
+
+fn key_id() -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: get_environment().ecdsa_key_name,
+    }
+}
+
+fn derivation_path(address_type: BitcoinAddressType) -> Vec<Vec<u8>> {
+    match address_type {
+        BitcoinAddressType::P2wpkh => vec![BTC_DERIVATION_PATH.to_vec()],
+        BitcoinAddressType::P2tr => vec![TAPROOT_DERIVATION_PATH.to_vec()],
+    }
+}
+
+fn input_vbytes(address_type: BitcoinAddressType) -> u64 {
+    match address_type {
+        BitcoinAddressType::P2wpkh => P2WPKH_INPUT_VBYTES,
+        BitcoinAddressType::P2tr => P2TR_KEY_SPEND_INPUT_VBYTES,
+    }
+}
+
+fn public_key_id(address_type: BitcoinAddressType) -> String {
+    format!("{}/{:?}", get_environment().ecdsa_key_name, address_type)
+}
+
+async fn public_key(address_type: BitcoinAddressType) -> Result<Vec<u8>, String> {
+    let key_id = public_key_id(address_type);
+    if let Some(public_key) = PUBLIC_KEYS.with(|keys| keys.borrow().get(&key_id)) {
+        return Ok(public_key);
+    }
+
+    let public_key = match address_type {
+        BitcoinAddressType::P2wpkh => {
+            ecdsa_public_key(EcdsaPublicKeyArgument {
+                canister_id: None,
+                derivation_path: derivation_path(address_type),
+                key_id: key_id(),
+            })
+            .await
+            .map_err(|(code, message)| format!("Could not get the BTC key: {:?} {}", code, message))?
+            .0
+            .public_key
+        }
+        BitcoinAddressType::P2tr => {
+            schnorr::schnorr_public_key(
+                SchnorrAlgorithm::Bip340Secp256k1,
+                derivation_path(address_type),
+            )
+            .await?
+        }
+    };
+    PUBLIC_KEYS.with(|keys| keys.borrow_mut().insert(key_id, public_key.clone()));
+
+    Ok(public_key)
+}
+
+/// The address of a SEC1 public key. Taproot addresses commit to the key alone,
+/// without a script tree, so they are only spent through the key path.
+fn address_from_key(
+    address_type: BitcoinAddressType,
+    public_key: &[u8],
+    network: BitcoinNetwork,
+) -> Result<Address, String> {
+    match address_type {
+        BitcoinAddressType::P2wpkh => {
+            let public_key = CompressedPublicKey::from_slice(public_key)
+                .map_err(|e| format!("Invalid BTC key: {}", e))?;
+            Ok(Address::p2wpkh(&public_key, btc_network(network)))
+        }
+        BitcoinAddressType::P2tr => {
+            let internal_key = public_key
+                .get(1..)
+                .and_then(|key| XOnlyPublicKey::from_slice(key).ok())
+                .ok_or_else(|| "Invalid Taproot key".to_string())?;
+            Ok(Address::p2tr(
+                &Secp256k1::verification_only(),
+                internal_key,
+                None,
+                btc_network(network),
+            ))
+        }
+    }
+}
+
+async fn vault_address(
+    address_type: BitcoinAddressType,
+    network: BitcoinNetwork,
+) -> Result<Address, String> {
+    address_from_key(address_type, &public_key(address_type).await?, network)
+}
+
+/// Every UTXO of an address, following the pages of the Bitcoin canister.
+async fn get_utxos(network: BitcoinNetwork, address: &Address) -> Result<Vec<Utxo>, String> {
+    let mut utxos = vec![];
+    let mut filter = None;
+
+    loop {
+        let (response,) = bitcoin_get_utxos(GetUtxosRequest {
+            address: address.to_string(),
+            network: ic_network(network),
+            filter,
+        })
+        .await
+        .map_err(|(code, message)| format!("Could not get UTXOs: {:?} {}", code, message))?;
+
+        utxos.extend(response.utxos);
+        match response.next_page {
+            Some(page) => filter = Some(UtxoFilter::Page(page)),
+            None => return Ok(utxos),
+        }
+    }
+}
+
+/// Fee rate in millisatoshi per vbyte, the median of the fees paid in recent blocks.
+async fn fee_rate(network: BitcoinNetwork) -> Result<u64, String> {
+    let (percentiles,) = bitcoin_get_current_fee_percentiles(GetCurrentFeePercentilesRequest {
+        network: ic_network(network),
+    })
+    .await
+    .map_err(|(code, message)| format!("Could not get fee percentiles: {:?} {}", code, message))?;
+
+    Ok(percentiles
+        .get(FEE_PERCENTILE)
+        .copied()
+        .unwrap_or(DEFAULT_FEE_RATE))
+}
+
+fn output_vbytes(script_pubkey: &ScriptBuf) -> u64 {
+    // Value, script length and script
+    8 + 1 + script_pubkey.len() as u64
+}
+
+/// Virtual size of a transaction, including the witnesses of its inputs.
+fn estimate_vsize(inputs: usize, input_vbytes: u64, output_sizes: &[u64]) -> u64 {
+    TX_OVERHEAD_VBYTES + input_vbytes * inputs as u64 + output_sizes.iter().sum::<u64>()
+}
+
+/// UTXOs spent by a transaction, with its fee and change in satoshis.
+#[derive(Debug, PartialEq)]
+pub struct CoinSelection {
+    pub utxos: Vec<Utxo>,
+    pub fee: u64,
+    pub change: u64,
+}
+
+/// Selects the largest UTXOs first until they pay the amount and the fee. Change
+/// below the dust limit is left to the miners instead of creating an output.
+pub fn select_utxos(
+    utxos: &[Utxo],
+    amount: u64,
+    fee_rate: u64,
+    input_vbytes: u64,
+    recipient_vbytes: u64,
+    change_vbytes: u64,
+) -> Result<CoinSelection, String> {
+    let mut utxos = utxos.to_vec();
+    utxos.sort_by(|a, b| b.value.cmp(&a.value));
+
+    let mut total = 0u64;
+    for (count, utxo) in utxos.iter().enumerate() {
+        total += utxo.value;
+        let inputs = count + 1;
+
+        let fee = estimate_vsize(inputs, input_vbytes, &[recipient_vbytes, change_vbytes])
+            * fee_rate
+            / 1000;
+        if total < amount + fee {
+            continue;
+        }
+
+        let change = total - amount - fee;
+        let selection = if change >= DUST_LIMIT {
+            CoinSelection {
+                utxos: utxos[..inputs].to_vec(),
+                fee,
+                change,
+            }
+        } else {
+            CoinSelection {
+                utxos: utxos[..inputs].to_vec(),
+                fee: total - amount,
+                change: 0,
+            }
+        };
+        return Ok(selection);
+    }
+
+    Err(format!(
+        "Insufficient BTC: {} satoshis available for {} satoshis and the fee",
+        total, amount
+    ))
+}
+
+fn outpoint_id(outpoint: &Outpoint) -> String {
+    format!("{}:{}", hex::encode(&outpoint.txid), outpoint.vout)
+}
+
+/// The UTXOs that no sent transaction spends. Spent outpoints are forgotten once the
+/// Bitcoin canister no longer reports them, as they are spent on chain, or once they
+/// expire.
+fn unspent_utxos(utxos: &[Utxo], now: u64) -> Vec<Utxo> {
+    let reported: HashSet<String> = utxos
+        .iter()
+        .map(|utxo| outpoint_id(&utxo.outpoint))
+        .collect();
+
+    SPENT_OUTPOINTS.with(|spent| {
+        let mut spent = spent.borrow_mut();
+        let forgotten: Vec<String> = spent
+            .iter()
+            .filter(|(outpoint, spent_at)| {
+                !reported.contains(outpoint)
+                    || now.saturating_sub(*spent_at) >= SPENT_OUTPOINT_EXPIRY_NANOS
+            })
+            .map(|(outpoint, _)| outpoint)
+            .collect();
+        for outpoint in forgotten {
+            spent.remove(&outpoint);
+        }
+
+        utxos
+            .iter()
+            .filter(|utxo| !spent.contains_key(&outpoint_id(&utxo.outpoint)))
+            .cloned()
+            .collect()
+    })
+}
+
+fn mark_spent(utxos: &[Utxo], now: u64) {
+    SPENT_OUTPOINTS.with(|spent| {
+        let mut spent = spent.borrow_mut();
+        for utxo in utxos {
+            spent.insert(outpoint_id(&utxo.outpoint), now);
+        }
+    });
+}
+
+fn outpoint(utxo: &Utxo) -> Result<OutPoint, String> {
+    let txid = Txid::from_slice(&utxo.outpoint.txid)
+        .map_err(|e| format!("Invalid UTXO transaction id: {}", e))?;
+    Ok(OutPoint {
+        txid,
+        vout: utxo.outpoint.vout,
+    })
+}
+
+/// The unsigned transaction paying `amount` to `to` and the change back to `from`.
+pub fn build_transaction(
+    selection: &CoinSelection,
+    from: &Address,
+    to: &Address,
+    amount: u64,
+) -> Result<Transaction, String> {
+    let input = selection
+        .utxos
+        .iter()
+        .map(|utxo| {
+            Ok(TxIn {
+                previous_output: outpoint(utxo)?,
+                script_sig: ScriptBuf::new(),
+                // Opts in to replace-by-fee
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut output = vec![TxOut {
+        value: Amount::from_sat(amount),
+        script_pubkey: to.script_pubkey(),
+    }];
+    if selection.change > 0 {
+        output.push(TxOut {
+            value: Amount::from_sat(selection.change),
+            script_pubkey: from.script_pubkey(),
+        });
+    }
+
+    Ok(Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output,
+    })
+}
+
+/// Signs every P2WPKH input with the vault's threshold ECDSA key.
+async fn sign_p2wpkh_inputs(
+    mut transaction: Transaction,
+    selection: &CoinSelection,
+    public_key: &[u8],
+    from: &Address,
+) -> Result<Transaction, String> {
+    let public_key =
+        CompressedPublicKey::from_slice(public_key).map_err(|e| format!("Invalid BTC key: {}", e))?;
+    let script_pubkey = from.script_pubkey();
+    let mut sighashes = vec![];
+    {
+        let mut cache = SighashCache::new(&transaction);
+        for (index, utxo) in selection.utxos.iter().enumerate() {
+            let sighash = cache
+                .p2wpkh_signature_hash(
+                    index,
+                    &script_pubkey,
+                    Amount::from_sat(utxo.value),
+                    EcdsaSighashType::All,
+                )
+                .map_err(|e| format!("Could not compute the sighash: {}", e))?;
+            sighashes.push(sighash);
+        }
+    }
+
+    for (index, sighash) in sighashes.into_iter().enumerate() {
+        let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+            message_hash: sighash.to_byte_array().to_vec(),
+            derivation_path: derivation_path(BitcoinAddressType::P2wpkh),
+            key_id: key_id(),
+        })
+        .await
+        .map_err(|(code, message)| format!("Could not sign: {:?} {}", code, message))?;
+
+        let mut signature = ecdsa::Signature::from_compact(&response.signature)
+            .map_err(|e| format!("Invalid signature: {}", e))?;
+        // Bitcoin only relays low-S signatures
+        signature.normalize_s();
+
+        let signature = bitcoin::ecdsa::Signature {
+            signature,
+            sighash_type: EcdsaSighashType::All,
+        };
+        transaction.input[index].witness = Witness::p2wpkh(&signature, &public_key.0);
+    }
+
+    Ok(transaction)
+}
+
+/// Signs every Taproot input through the key path with the vault's threshold
+/// Schnorr key, tweaked by the management canister as the address commits to.
+async fn sign_p2tr_inputs(
+    mut transaction: Transaction,
+    selection: &CoinSelection,
+    from: &Address,
+) -> Result<Transaction, String> {
+    let prevouts: Vec<TxOut> = selection
+        .utxos
+        .iter()
+        .map(|utxo| TxOut {
+            value: Amount::from_sat(utxo.value),
+            script_pubkey: from.script_pubkey(),
+        })
+        .collect();
+    let mut sighashes = vec![];
+    {
+        let mut cache = SighashCache::new(&transaction);
+        for index in 0..prevouts.len() {
+            let sighash = cache
+                .taproot_key_spend_signature_hash(
+                    index,
+                    &Prevouts::All(&prevouts),
+                    TapSighashType::Default,
+                )
+                .map_err(|e| format!("Could not compute the sighash: {}", e))?;
+            sighashes.push(sighash);
+        }
+    }
+
+    for (index, sighash) in sighashes.into_iter().enumerate() {
+        let signature = schnorr::sign_with_schnorr(
+            SchnorrAlgorithm::Bip340Secp256k1,
+            derivation_path(BitcoinAddressType::P2tr),
+            sighash.to_byte_array().to_vec(),
+            Some(SignWithSchnorrAux::Bip341 {
+                merkle_root_hash: vec![],
+            }),
+        )
+        .await?;
+
+        let signature = bitcoin::taproot::Signature {
+            signature: SchnorrSignature::from_slice(&signature)
+                .map_err(|e| format!("Invalid signature: {}", e))?,
+            sighash_type: TapSighashType::Default,
+        };
+        transaction.input[index].witness = Witness::p2tr_key_spend(&signature);
+    }
+
+    Ok(transaction)
+}
+
+/// Sends `amount` satoshis from one of the vault's addresses and returns the txid.
+pub async fn send_btc(
+    address_type: BitcoinAddressType,
+    to: &str,
+    amount: u64,
+) -> Result<String, String> {
+    let network = bitcoin_network()?;
+    let to = Address::from_str(to.trim())
+        .map_err(|e| format!("Invalid BTC address {}: {}", to, e))?
+        .require_network(btc_network(network))
+        .map_err(|e| format!("Invalid BTC address {}: {}", to, e))?;
+    if amount < DUST_LIMIT {
+        return Err(format!(
+            "{} satoshis is below the dust limit of {}",
+            amount, DUST_LIMIT
+        ));
+    }
+
+    let _guard = SendGuard::acquire()?;
+    let public_key = public_key(address_type).await?;
+    let from = address_from_key(address_type, &public_key, network)?;
+
+    let utxos = get_utxos(network, &from).await?;
+    let unspent = unspent_utxos(&utxos, ic_cdk::api::time());
+
+    let fee_rate = fee_rate(network).await?;
+    let selection = select_utxos(
+        &unspent,
+        amount,
+        fee_rate,
+        input_vbytes(address_type),
+        output_vbytes(&to.script_pubkey()),
+        output_vbytes(&from.script_pubkey()),
+    )?;
+
+    let transaction = build_transaction(&selection, &from, &to, amount)?;
+    let transaction = match address_type {
+        BitcoinAddressType::P2wpkh => {
+            sign_p2wpkh_inputs(transaction, &selection, &public_key, &from).await?
+        }
+        BitcoinAddressType::P2tr => sign_p2tr_inputs(transaction, &selection, &from).await?,
+    };
+    let txid = transaction.compute_txid().to_string();
+
+    bitcoin_send_transaction(SendTransactionRequest {
+        transaction: serialize(&transaction),
+        network: ic_network(network),
+    })
+    .await
+    .map_err(|(code, message)| format!("Could not send the transaction: {:?} {}", code, message))?;
+
+    mark_spent(&selection.utxos, ic_cdk::api::time());
+
+    Ok(txid)
+}
+
+/// The vault's BTC address on the configured network, P2WPKH unless another type
+/// is given.
+#[ic_cdk::update]
+pub async fn get_btc_address(address_type: Option<BitcoinAddressType>) -> Result<String, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+    let address_type = address_type.unwrap_or(BitcoinAddressType::P2wpkh);
+    Ok(vault_address(address_type, bitcoin_network()?).await?.to_string())
+}
+
+/// Balance of one of the vault's BTC addresses in satoshis, P2WPKH by default.
+#[ic_cdk::update]
+pub async fn get_btc_balance(address_type: Option<BitcoinAddressType>) -> Result<u64, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+    let network = bitcoin_network()?;
+    let address_type = address_type.unwrap_or(BitcoinAddressType::P2wpkh);
+    let address = vault_address(address_type, network).await?;
+    let (balance,) = bitcoin_get_balance(GetBalanceRequest {
+        address: address.to_string(),
+        network: ic_network(network),
+        min_confirmations: None,
+    })
+    .await
+    .map_err(|(code, message)| format!("Could not get the balance: {:?} {}", code, message))?;
+
+    Ok(balance)
+}
+
+/// Transfers BTC from one of the vault's addresses: "btc:native" spends from the
+/// P2WPKH address and "btc:taproot" from the Taproot address. Amounts are in satoshis.
+#[derive(Clone)]
+pub struct BTCNativeTransferAdapter {
+    address_type: BitcoinAddressType,
+}
+
+impl BTCNativeTransferAdapter {
+    pub fn new(address_type: BitcoinAddressType) -> BTCNativeTransferAdapter {
+        BTCNativeTransferAdapter { address_type }
+    }
+}
+
+impl BlockchainAdapter for BTCNativeTransferAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing BTCNativeTransferAdapter for {:?}", self.address_type);
+            let txid =
+                send_btc(self.address_type, &transaction.to, transaction.amount as u64).await?;
+            Ok(IntentStatus::Completed(format!(
+                "Sent {} satoshis in transaction {}",
+                transaction.amount as u64, txid
+            )))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxo(value: u64, vout: u32) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![vout as u8; 32],
+                vout,
+            },
+            value,
+            height: 1,
+        }
+    }
+
+    #[test]
+    fn selects_largest_utxos_first() {
+        let utxos = vec![utxo(10_000, 0), utxo(50_000, 1), utxo(30_000, 2)];
+        let selection = select_utxos(&utxos, 60_000, 1_000, P2WPKH_INPUT_VBYTES, 31, 31).unwrap();
+
+        // 2 inputs and 2 outputs at 1 sat/vbyte
+        let fee = TX_OVERHEAD_VBYTES + 2 * P2WPKH_INPUT_VBYTES + 62;
+        assert_eq!(selection.utxos, vec![utxo(50_000, 1), utxo(30_000, 2)]);
+        assert_eq!(selection.fee, fee);
+        assert_eq!(selection.change, 80_000 - 60_000 - fee);
+    }
+
+    #[test]
+    fn leaves_dust_change_to_the_fee() {
+        let utxos = vec![utxo(10_200, 0)];
+        let selection = select_utxos(&utxos, 10_000, 1_000, P2WPKH_INPUT_VBYTES, 31, 31).unwrap();
+
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.fee, 200);
+    }
+
+    #[test]
+    fn fails_without_enough_funds() {
+        let utxos = vec![utxo(10_000, 0)];
+        assert!(select_utxos(&utxos, 10_000, 1_000, P2WPKH_INPUT_VBYTES, 31, 31).is_err());
+        assert!(select_utxos(&[], 1_000, 1_000, P2WPKH_INPUT_VBYTES, 31, 31).is_err());
+    }
+
+    #[test]
+    fn builds_transaction_with_change() {
+        let key = CompressedPublicKey::from_slice(
+            &hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap(),
+        )
+        .unwrap();
+        let from = Address::p2wpkh(&key, Network::Regtest);
+        let to = Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080")
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap();
+
+        let selection = CoinSelection {
+            utxos: vec![utxo(50_000, 1)],
+            fee: 141,
+            change: 29_859,
+        };
+        let transaction = build_transaction(&selection, &from, &to, 20_000).unwrap();
+
+        assert_eq!(transaction.input.len(), 1);
+        assert_eq!(transaction.output[0].value, Amount::from_sat(20_000));
+        assert_eq!(transaction.output[1].script_pubkey, from.script_pubkey());
+        assert_eq!(
+            estimate_vsize(
+                1,
+                P2WPKH_INPUT_VBYTES,
+                &[output_vbytes(&to.script_pubkey()), output_vbytes(&from.script_pubkey())]
+            ),
+            TX_OVERHEAD_VBYTES + P2WPKH_INPUT_VBYTES + 62
+        );
+    }
+
+    #[test]
+    fn derives_bip86_taproot_addresses() {
+        // First receiving address of the BIP86 test vectors
+        let internal_key =
+            hex::decode("02cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")
+                .unwrap();
+        let address =
+            address_from_key(BitcoinAddressType::P2tr, &internal_key, BitcoinNetwork::Mainnet)
+                .unwrap();
+
+        assert_eq!(
+            address.to_string(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+    }
+
+    #[test]
+    fn skips_spent_outpoints_until_they_are_confirmed_or_expire() {
+        let utxos = vec![utxo(10_000, 0), utxo(20_000, 1), utxo(30_000, 2)];
+        mark_spent(&utxos[..2], 0);
+        assert_eq!(unspent_utxos(&utxos, 1), vec![utxo(30_000, 2)]);
+
+        // The first outpoint is no longer reported, its transaction is confirmed
+        assert_eq!(unspent_utxos(&utxos[1..], 2), vec![utxo(30_000, 2)]);
+        let first = outpoint_id(&utxos[0].outpoint);
+        assert!(SPENT_OUTPOINTS.with(|spent| !spent.borrow().contains_key(&first)));
+
+        // The transaction spending the second one was dropped
+        assert_eq!(
+            unspent_utxos(&utxos[1..], SPENT_OUTPOINT_EXPIRY_NANOS),
+            vec![utxo(20_000, 1), utxo(30_000, 2)]
+        );
+        assert!(SPENT_OUTPOINTS.with(|spent| spent.borrow().is_empty()));
+    }
+}
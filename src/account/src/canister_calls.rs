@@ -0,0 +1,252 @@
+use std::{cell::RefCell, future::Future, pin::Pin};
+
+use candid::{IDLArgs, Principal};
+use candid_parser::utils::CandidSource;
+use ic_stable_structures::StableBTreeMap;
+use keygate_core::types::vault::{
+    CanisterCallArgs, CanisterCallDetails, CanisterCallResult, TransactionPayload,
+};
+
+use crate::{
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest, MAX_PAYLOAD_BYTES},
+    CANISTER_CALL_RESULTS_MEMORY, MEMORY_MANAGER, VM,
+};
+
+const MAX_METHOD_BYTES: usize = 96;
+
+/// The arguments share the payload with the method name, and with the cycles and the
+/// field names, which take at most 32 bytes.
+const MAX_ARGS_BYTES: usize = MAX_PAYLOAD_BYTES - MAX_METHOD_BYTES - 32;
+
+thread_local! {
+    /// Replies and rejects of executed canister calls, keyed by proposal id.
+    static CALL_RESULTS: RefCell<StableBTreeMap<u64, CanisterCallResult, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CANISTER_CALL_RESULTS_MEMORY)))
+    );
+
+    /// Outcome of the last call, picked up by `execute_transaction`, which knows the
+    /// proposal it belongs to.
+    static LAST_RESULT: RefCell<Option<CanisterCallResult>> = const { RefCell::new(None) };
+}
+
+pub fn take_call_result() -> Option<CanisterCallResult> {
+    LAST_RESULT.with(|result| result.borrow_mut().take())
+}
+
+pub fn record_call_result(proposal_id: u64, result: CanisterCallResult) {
+    CALL_RESULTS.with(|results| results.borrow_mut().insert(proposal_id, result));
+}
+
+/// Rejects arguments that are not Candid or do not fit in a proposal.
+pub fn validate_args(args: &[u8]) -> Result<(), String> {
+    if args.len() > MAX_ARGS_BYTES {
+        return Err(format!(
+            "Arguments take {} bytes, the limit is {}",
+            args.len(),
+            MAX_ARGS_BYTES
+        ));
+    }
+    IDLArgs::from_bytes(args)
+        .map(|_| ())
+        .map_err(|e| format!("Arguments are not valid Candid: {}", e))
+}
+
+pub fn validate_call(call: &CanisterCallArgs) -> Result<(), String> {
+    if call.method.is_empty() {
+        return Err("The method of a canister call is empty".to_string());
+    }
+    if call.method.len() > MAX_METHOD_BYTES {
+        return Err(format!(
+            "The method name takes {} bytes, the limit is {}",
+            call.method.len(),
+            MAX_METHOD_BYTES
+        ));
+    }
+    validate_args(&call.args)
+}
+
+/// Candid text of `args`, typed by the arguments of `method` in `did` when it is given.
+pub fn decode_args(args: &[u8], method: &str, did: Option<&str>) -> Result<String, String> {
+    let args = match did {
+        Some(did) => {
+            let (env, actor) = CandidSource::Text(did)
+                .load()
+                .map_err(|e| format!("Invalid Candid interface: {}", e))?;
+            let actor = actor.ok_or_else(|| "The Candid interface has no service".to_string())?;
+            let function = env
+                .get_method(&actor, method)
+                .map_err(|e| format!("Method {} not found in the interface: {}", method, e))?;
+
+            IDLArgs::from_bytes_with_types(args, &env, &function.args)
+        }
+        None => IDLArgs::from_bytes(args),
+    };
+
+    args.map(|args| args.to_string())
+        .map_err(|e| format!("Failed to decode the arguments: {}", e))
+}
+
+fn call_args(transaction: &TransactionRequest) -> Result<&CanisterCallArgs, String> {
+    match &transaction.payload {
+        Some(TransactionPayload::CanisterCall(call)) => Ok(call),
+        _ => Err("Canister calls require a CanisterCall payload".to_string()),
+    }
+}
+
+/// Calls a method of any canister with the vault as the caller, and keeps the raw
+/// reply or reject for `get_canister_call_result`.
+#[derive(Clone)]
+pub struct CanisterCallAdapter {}
+
+impl CanisterCallAdapter {
+    pub fn new() -> CanisterCallAdapter {
+        CanisterCallAdapter {}
+    }
+}
+
+impl BlockchainAdapter for CanisterCallAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing CanisterCallAdapter");
+
+            let call = call_args(transaction)?;
+            let canister_id = Principal::from_text(&transaction.to)
+                .map_err(|e| format!("Invalid canister id {}: {}", transaction.to, e))?;
+
+            let result = ic_cdk::api::call::call_raw(
+                canister_id,
+                &call.method,
+                &call.args,
+                call.cycles.unwrap_or_default(),
+            )
+            .await;
+
+            match result {
+                Ok(reply) => {
+                    let message = format!(
+                        "Canister {} replied to {} with {} bytes.",
+                        canister_id,
+                        call.method,
+                        reply.len()
+                    );
+                    LAST_RESULT.with(|last| {
+                        *last.borrow_mut() = Some(CanisterCallResult::Reply(reply))
+                    });
+                    Ok(IntentStatus::Completed(message))
+                }
+                Err((rejection_code, message)) => {
+                    let error = format!(
+                        "Canister {} rejected {}: {:?} - {}",
+                        canister_id, call.method, rejection_code, message
+                    );
+                    LAST_RESULT.with(|last| {
+                        *last.borrow_mut() = Some(CanisterCallResult::Reject {
+                            code: rejection_code as i32,
+                            message,
+                        })
+                    });
+                    Err(error)
+                }
+            }
+        })
+    }
+}
+
+/// Decodes a canister call proposal for review before approving it. Passing the Candid
+/// interface of the called canister names the fields of the arguments.
+#[ic_cdk::query]
+pub fn get_canister_call_details(
+    proposal_id: u64,
+    did: Option<String>,
+) -> Result<CanisterCallDetails, String> {
+    let proposal = crate::get_proposed_transaction(proposal_id)
+        .ok_or_else(|| format!("Proposal not found: {}", proposal_id))?;
+    let call = match proposal.payload {
+        Some(TransactionPayload::CanisterCall(call)) => call,
+        _ => return Err(format!("Proposal {} is not a canister call", proposal_id)),
+    };
+    let canister_id = Principal::from_text(&proposal.to)
+        .map_err(|e| format!("Invalid canister id {}: {}", proposal.to, e))?;
+
+    Ok(CanisterCallDetails {
+        canister_id,
+        args: decode_args(&call.args, &call.method, did.as_deref())?,
+        method: call.method,
+        cycles: call.cycles,
+    })
+}
+
+/// Raw reply or reject of an executed canister call proposal.
+#[ic_cdk::query]
+pub fn get_canister_call_result(proposal_id: u64) -> Option<CanisterCallResult> {
+    CALL_RESULTS.with(|results| results.borrow().get(&proposal_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::{encode_args, encode_one, CandidType};
+
+    use super::*;
+
+    const LEDGER_DID: &str = r#"
+        type Account = record { owner : principal; subaccount : opt blob };
+        service : {
+            icrc1_balance_of : (Account) -> (nat) query;
+        }
+    "#;
+
+    #[derive(CandidType)]
+    struct Account {
+        owner: Principal,
+        subaccount: Option<Vec<u8>>,
+    }
+
+    fn account_args() -> Vec<u8> {
+        encode_one(Account {
+            owner: Principal::anonymous(),
+            subaccount: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn decodes_args_with_the_interface() {
+        let decoded = decode_args(&account_args(), "icrc1_balance_of", Some(LEDGER_DID)).unwrap();
+        assert!(decoded.contains("owner = principal \"2vxsx-fae\""), "{}", decoded);
+        assert!(decoded.contains("subaccount = null"), "{}", decoded);
+    }
+
+    #[test]
+    fn decodes_args_without_the_interface() {
+        let decoded = decode_args(&account_args(), "icrc1_balance_of", None).unwrap();
+        assert!(decoded.contains("principal \"2vxsx-fae\""), "{}", decoded);
+        assert!(!decoded.contains("owner"), "{}", decoded);
+    }
+
+    #[test]
+    fn rejects_unknown_methods() {
+        assert!(decode_args(&account_args(), "icrc1_transfer", Some(LEDGER_DID)).is_err());
+    }
+
+    #[test]
+    fn validates_call_args() {
+        let call = |args: Vec<u8>| CanisterCallArgs {
+            method: "claim".to_string(),
+            args,
+            cycles: None,
+        };
+
+        assert!(validate_call(&call(encode_args(()).unwrap())).is_ok());
+        assert!(validate_call(&call(vec![1, 2, 3])).is_err());
+        assert!(validate_call(&call(encode_one(vec![0u8; 600]).unwrap())).is_err());
+
+        let long_method = CanisterCallArgs {
+            method: "a".repeat(MAX_METHOD_BYTES + 1),
+            ..call(encode_args(()).unwrap())
+        };
+        assert!(validate_call(&long_method).is_err());
+    }
+}
@@ -0,0 +1,586 @@
+use std::{borrow::Cow, cell::RefCell, future::Future, pin::Pin, time::Duration};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use icrc_ledger_types::icrc1::account::Account;
+use keygate_core::types::vault::{ApprovalArgs, ChainKeyMinter, TransactionPayload};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    get_default_icrc_subaccount,
+    icrc2::{approve, resolve_ledger},
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest, TransactionUpdate},
+    CK_WITHDRAWALS_MEMORY, MEMORY_MANAGER, TRANSACTION_UPDATES, VM,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A withdrawal the vault requested from a minter that did not settle yet.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PendingWithdrawal {
+    pub minter: ChainKeyMinter,
+    /// Index of the burn on the ck token ledger, which identifies the withdrawal.
+    pub block_index: u64,
+    pub polls: u32,
+}
+
+impl Storable for PendingWithdrawal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 128,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    /// Pending withdrawals, keyed by their index in `TRANSACTIONS`.
+    static PENDING: RefCell<StableBTreeMap<u64, PendingWithdrawal, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CK_WITHDRAWALS_MEMORY)))
+    );
+
+    /// Withdrawal requested by the running execution, handed to `execute_transaction`
+    /// the same way as `evm_confirmations::LAST_SENT`.
+    static LAST_REQUESTED: RefCell<Option<PendingWithdrawal>> = const { RefCell::new(None) };
+
+    static POLL_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static POLLING: RefCell<bool> = const { RefCell::new(false) };
+}
+
+// ckBTC minter interface, see https://github.com/dfinity/ic/tree/master/rs/bitcoin/ckbtc/minter
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct MinterAccountArgs {
+    owner: Option<Principal>,
+    subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct Utxo {
+    value: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum UtxoStatus {
+    ValueTooSmall(Utxo),
+    Tainted(Utxo),
+    Checked(Utxo),
+    Minted { block_index: u64, minted_amount: u64 },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum UpdateBalanceError {
+    GenericError { error_code: u64, error_message: String },
+    TemporarilyUnavailable(String),
+    AlreadyProcessing,
+    NoNewUtxos {
+        current_confirmations: Option<u32>,
+        required_confirmations: u32,
+    },
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct RetrieveBtcWithApprovalArgs {
+    address: String,
+    amount: u64,
+    from_subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct RetrieveBtcOk {
+    block_index: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum RetrieveBtcWithApprovalError {
+    MalformedAddress(String),
+    GenericError { error_code: u64, error_message: String },
+    TemporarilyUnavailable(String),
+    InsufficientAllowance { allowance: u64 },
+    AlreadyProcessing,
+    AmountTooLow(u64),
+    InsufficientFunds { balance: u64 },
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct RetrieveBtcStatusRequest {
+    block_index: u64,
+}
+
+/// Deposit the minter returns for a failed withdrawal.
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct Reimbursement {
+    amount: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum RetrieveBtcStatusV2 {
+    Unknown,
+    Pending,
+    Signing,
+    Sending { txid: Vec<u8> },
+    Submitted { txid: Vec<u8> },
+    AmountTooLow,
+    Confirmed { txid: Vec<u8> },
+    Reimbursed(Reimbursement),
+    WillReimburse(Reimbursement),
+}
+
+// ckETH minter interface, see https://github.com/dfinity/ic/tree/master/rs/ethereum/cketh/minter
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct WithdrawalArg {
+    amount: Nat,
+    recipient: String,
+    from_subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct RetrieveEthRequest {
+    block_index: Nat,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum WithdrawalError {
+    AmountTooLow { min_withdrawal_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    RecipientAddressBlocked { address: String },
+    TemporarilyUnavailable(String),
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct EthTransaction {
+    transaction_hash: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum TxFinalizedStatus {
+    Success { transaction_hash: String },
+    PendingReimbursement(EthTransaction),
+    Reimbursed { transaction_hash: String },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum RetrieveEthStatus {
+    NotFound,
+    Pending,
+    TxCreated,
+    TxSigned(EthTransaction),
+    TxSent(EthTransaction),
+    TxFinalized(TxFinalizedStatus),
+}
+
+fn rejected(rejection_code: impl std::fmt::Debug, message: String) -> String {
+    format!("Canister call rejected: {:?} - {}", rejection_code, message)
+}
+
+fn minter_of(transaction: &TransactionRequest) -> Result<ChainKeyMinter, String> {
+    match &transaction.payload {
+        Some(TransactionPayload::Minter(minter)) => Ok(*minter),
+        _ => Err("Deposits and withdrawals require a Minter payload".to_string()),
+    }
+}
+
+fn vault_subaccount() -> Option<Vec<u8>> {
+    Some(get_default_icrc_subaccount().0.to_vec())
+}
+
+/// Bitcoin shows transaction ids in reverse byte order.
+fn txid_to_string(txid: &[u8]) -> String {
+    hex::encode(txid.iter().rev().copied().collect::<Vec<u8>>())
+}
+
+fn btc_withdrawal_status(block_index: u64, status: &RetrieveBtcStatusV2) -> IntentStatus {
+    match status {
+        RetrieveBtcStatusV2::Unknown => {
+            IntentStatus::Failed(format!("The minter has no withdrawal {}", block_index))
+        }
+        RetrieveBtcStatusV2::Pending | RetrieveBtcStatusV2::Signing => {
+            IntentStatus::InProgress(format!("Withdrawal {} is queued at the minter", block_index))
+        }
+        RetrieveBtcStatusV2::Sending { txid } | RetrieveBtcStatusV2::Submitted { txid } => {
+            IntentStatus::InProgress(format!(
+                "Withdrawal {} sent in Bitcoin transaction {}",
+                block_index,
+                txid_to_string(txid)
+            ))
+        }
+        RetrieveBtcStatusV2::Confirmed { txid } => IntentStatus::Completed(format!(
+            "Withdrawal {} confirmed in Bitcoin transaction {}",
+            block_index,
+            txid_to_string(txid)
+        )),
+        RetrieveBtcStatusV2::AmountTooLow => IntentStatus::Failed(format!(
+            "Withdrawal {} does not cover the Bitcoin fees",
+            block_index
+        )),
+        RetrieveBtcStatusV2::WillReimburse(reimbursement) => IntentStatus::InProgress(format!(
+            "Withdrawal {} failed, {} ckBTC satoshis will be reimbursed",
+            block_index, reimbursement.amount
+        )),
+        RetrieveBtcStatusV2::Reimbursed(reimbursement) => IntentStatus::Failed(format!(
+            "Withdrawal {} failed, {} ckBTC satoshis were reimbursed",
+            block_index, reimbursement.amount
+        )),
+    }
+}
+
+fn eth_withdrawal_status(block_index: u64, status: &RetrieveEthStatus) -> IntentStatus {
+    match status {
+        RetrieveEthStatus::NotFound => {
+            IntentStatus::Failed(format!("The minter has no withdrawal {}", block_index))
+        }
+        RetrieveEthStatus::Pending | RetrieveEthStatus::TxCreated => {
+            IntentStatus::InProgress(format!("Withdrawal {} is queued at the minter", block_index))
+        }
+        RetrieveEthStatus::TxSigned(tx) | RetrieveEthStatus::TxSent(tx) => {
+            IntentStatus::InProgress(format!(
+                "Withdrawal {} sent in Ethereum transaction {}",
+                block_index, tx.transaction_hash
+            ))
+        }
+        RetrieveEthStatus::TxFinalized(TxFinalizedStatus::Success { transaction_hash }) => {
+            IntentStatus::Completed(format!(
+                "Withdrawal {} finalized in Ethereum transaction {}",
+                block_index, transaction_hash
+            ))
+        }
+        RetrieveEthStatus::TxFinalized(TxFinalizedStatus::PendingReimbursement(tx)) => {
+            IntentStatus::InProgress(format!(
+                "Ethereum transaction {} of withdrawal {} reverted and will be reimbursed",
+                tx.transaction_hash, block_index
+            ))
+        }
+        RetrieveEthStatus::TxFinalized(TxFinalizedStatus::Reimbursed { transaction_hash }) => {
+            IntentStatus::Failed(format!(
+                "Ethereum transaction {} of withdrawal {} reverted and was reimbursed",
+                transaction_hash, block_index
+            ))
+        }
+    }
+}
+
+async fn retrieve_btc(minter: Principal, address: String, amount: u64) -> Result<u64, String> {
+    let args = RetrieveBtcWithApprovalArgs {
+        address,
+        amount,
+        from_subaccount: vault_subaccount(),
+    };
+    let result: CallResult<(Result<RetrieveBtcOk, RetrieveBtcWithApprovalError>,)> =
+        ic_cdk::call(minter, "retrieve_btc_with_approval", (args,)).await;
+
+    match result {
+        Ok((Ok(ok),)) => Ok(ok.block_index),
+        Ok((Err(e),)) => Err(format!("ckBTC withdrawal error: {:?}", e)),
+        Err((rejection_code, message)) => Err(rejected(rejection_code, message)),
+    }
+}
+
+async fn withdraw_eth(minter: Principal, recipient: String, amount: u128) -> Result<u64, String> {
+    let args = WithdrawalArg {
+        amount: Nat::from(amount),
+        recipient,
+        from_subaccount: vault_subaccount(),
+    };
+    let result: CallResult<(Result<RetrieveEthRequest, WithdrawalError>,)> =
+        ic_cdk::call(minter, "withdraw_eth", (args,)).await;
+
+    match result {
+        Ok((Ok(request),)) => u64::try_from(&request.block_index.0)
+            .map_err(|e| format!("Invalid block index {}: {}", request.block_index, e)),
+        Ok((Err(e),)) => Err(format!("ckETH withdrawal error: {:?}", e)),
+        Err((rejection_code, message)) => Err(rejected(rejection_code, message)),
+    }
+}
+
+async fn withdrawal_status(withdrawal: &PendingWithdrawal) -> Result<IntentStatus, String> {
+    match withdrawal.minter {
+        ChainKeyMinter::CkBtc(minter) => {
+            let request = RetrieveBtcStatusRequest {
+                block_index: withdrawal.block_index,
+            };
+            let result: CallResult<(RetrieveBtcStatusV2,)> =
+                ic_cdk::call(minter, "retrieve_btc_status_v2", (request,)).await;
+            let (status,) = result.map_err(|(code, message)| rejected(code, message))?;
+            Ok(btc_withdrawal_status(withdrawal.block_index, &status))
+        }
+        ChainKeyMinter::CkEth(minter) => {
+            let result: CallResult<(RetrieveEthStatus,)> =
+                ic_cdk::call(minter, "retrieve_eth_status", (withdrawal.block_index,)).await;
+            let (status,) = result.map_err(|(code, message)| rejected(code, message))?;
+            Ok(eth_withdrawal_status(withdrawal.block_index, &status))
+        }
+    }
+}
+
+/// Burns ck tokens of the vault for BTC or ETH sent to `to`. The ledger allowance the
+/// minter needs is granted right before the request.
+#[derive(Clone)]
+pub struct ChainKeyWithdrawAdapter {}
+
+impl ChainKeyWithdrawAdapter {
+    pub fn new() -> ChainKeyWithdrawAdapter {
+        ChainKeyWithdrawAdapter {}
+    }
+}
+
+impl BlockchainAdapter for ChainKeyWithdrawAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ChainKeyWithdrawAdapter");
+
+            let minter = minter_of(transaction)?;
+            let ledger = resolve_ledger(&transaction.token)?;
+            let amount = transaction.amount as u128;
+
+            let spender = Account {
+                owner: minter.canister_id(),
+                subaccount: None,
+            };
+            let approval = ApprovalArgs {
+                expires_at: None,
+                expected_allowance: None,
+            };
+            approve(ledger, spender, Nat::from(amount), approval).await?;
+
+            let block_index = match minter {
+                ChainKeyMinter::CkBtc(minter) => {
+                    let amount = u64::try_from(amount)
+                        .map_err(|_| format!("Amount {} out of range", amount))?;
+                    retrieve_btc(minter, transaction.to.clone(), amount).await?
+                }
+                ChainKeyMinter::CkEth(minter) => {
+                    withdraw_eth(minter, transaction.to.clone(), amount).await?
+                }
+            };
+
+            LAST_REQUESTED.with(|requested| {
+                *requested.borrow_mut() = Some(PendingWithdrawal {
+                    minter,
+                    block_index,
+                    polls: 0,
+                })
+            });
+
+            Ok(IntentStatus::InProgress(format!(
+                "Withdrawal {} requested from minter {}",
+                block_index,
+                minter.canister_id()
+            )))
+        })
+    }
+}
+
+/// Mints ckBTC for the confirmed Bitcoin sent to the vault's deposit address, see
+/// `get_ckbtc_deposit_address`. ckETH deposits go through the minter's helper contract
+/// and are proposed as EVM contract calls instead.
+#[derive(Clone)]
+pub struct ChainKeyDepositAdapter {}
+
+impl ChainKeyDepositAdapter {
+    pub fn new() -> ChainKeyDepositAdapter {
+        ChainKeyDepositAdapter {}
+    }
+}
+
+impl BlockchainAdapter for ChainKeyDepositAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ChainKeyDepositAdapter");
+
+            let minter = match minter_of(transaction)? {
+                ChainKeyMinter::CkBtc(minter) => minter,
+                ChainKeyMinter::CkEth(_) => {
+                    return Err(
+                        "ckETH deposits go through the minter's helper contract".to_string()
+                    )
+                }
+            };
+
+            let args = MinterAccountArgs {
+                owner: Some(ic_cdk::id()),
+                subaccount: vault_subaccount(),
+            };
+            let result: CallResult<(Result<Vec<UtxoStatus>, UpdateBalanceError>,)> =
+                ic_cdk::call(minter, "update_balance", (args,)).await;
+            let utxos = match result {
+                Ok((Ok(utxos),)) => utxos,
+                Ok((Err(e),)) => return Err(format!("ckBTC update_balance error: {:?}", e)),
+                Err((rejection_code, message)) => return Err(rejected(rejection_code, message)),
+            };
+
+            let minted: u64 = utxos
+                .iter()
+                .map(|utxo| match utxo {
+                    UtxoStatus::Minted { minted_amount, .. } => *minted_amount,
+                    _ => 0,
+                })
+                .sum();
+
+            Ok(IntentStatus::Completed(format!(
+                "Minted {} ckBTC satoshis from {} UTXOs",
+                minted,
+                utxos.len()
+            )))
+        })
+    }
+}
+
+/// The Bitcoin address to send BTC to for the vault to receive ckBTC.
+#[ic_cdk::update]
+pub async fn get_ckbtc_deposit_address(minter: Principal) -> Result<String, String> {
+    let args = MinterAccountArgs {
+        owner: Some(ic_cdk::id()),
+        subaccount: vault_subaccount(),
+    };
+    let result: CallResult<(String,)> = ic_cdk::call(minter, "get_btc_address", (args,)).await;
+
+    result
+        .map(|(address,)| address)
+        .map_err(|(rejection_code, message)| rejected(rejection_code, message))
+}
+
+/// Withdrawals that did not settle yet, with the index of their transaction.
+#[ic_cdk::query]
+pub fn get_pending_withdrawals() -> Vec<(u64, PendingWithdrawal)> {
+    PENDING.with(|pending| pending.borrow().iter().collect())
+}
+
+pub fn take_requested_withdrawal() -> Option<PendingWithdrawal> {
+    LAST_REQUESTED.with(|requested| requested.borrow_mut().take())
+}
+
+/// Follows a withdrawal until the minter settles it.
+pub fn track_withdrawal(transaction_index: u64, withdrawal: PendingWithdrawal) {
+    PENDING.with(|pending| pending.borrow_mut().insert(transaction_index, withdrawal));
+    start_polling();
+}
+
+/// Starts the poll timer when withdrawals are pending. Timers do not survive upgrades,
+/// so this also runs in `post_upgrade`.
+pub fn start_polling() {
+    let idle = PENDING.with(|pending| pending.borrow().is_empty());
+    if idle || POLL_TIMER.with(|timer| timer.borrow().is_some()) {
+        return;
+    }
+
+    let timer =
+        ic_cdk_timers::set_timer_interval(POLL_INTERVAL, || ic_cdk::spawn(poll_withdrawals()));
+    POLL_TIMER.with(|t| *t.borrow_mut() = Some(timer));
+}
+
+fn stop_polling() {
+    if let Some(timer) = POLL_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer);
+    }
+}
+
+async fn poll_withdrawals() {
+    if POLLING.with(|polling| polling.replace(true)) {
+        return;
+    }
+
+    let pending: Vec<(u64, PendingWithdrawal)> =
+        PENDING.with(|pending| pending.borrow().iter().collect());
+
+    for (index, mut withdrawal) in pending {
+        withdrawal.polls += 1;
+
+        let finished = match withdrawal_status(&withdrawal).await {
+            Ok(status) => {
+                let finished = !matches!(status, IntentStatus::InProgress(_));
+                let update = TransactionUpdate {
+                    status,
+                    receipt: None,
+                };
+                TRANSACTION_UPDATES.with(|updates| updates.borrow_mut().insert(index, update));
+                finished
+            }
+            Err(e) => {
+                ic_cdk::println!("Failed to check withdrawal {}: {}", withdrawal.block_index, e);
+                false
+            }
+        };
+
+        PENDING.with(|pending| {
+            let mut pending = pending.borrow_mut();
+            if finished {
+                pending.remove(&index);
+            } else {
+                pending.insert(index, withdrawal);
+            }
+        });
+    }
+
+    POLLING.with(|polling| polling.replace(false));
+
+    if PENDING.with(|pending| pending.borrow().is_empty()) {
+        stop_polling();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_btc_withdrawal_statuses() {
+        let txid: Vec<u8> = (0..32).collect();
+        assert!(matches!(
+            btc_withdrawal_status(1, &RetrieveBtcStatusV2::Submitted { txid: txid.clone() }),
+            IntentStatus::InProgress(_)
+        ));
+        assert!(matches!(
+            btc_withdrawal_status(1, &RetrieveBtcStatusV2::AmountTooLow),
+            IntentStatus::Failed(_)
+        ));
+
+        match btc_withdrawal_status(1, &RetrieveBtcStatusV2::Confirmed { txid }) {
+            IntentStatus::Completed(message) => assert!(message
+                .ends_with("1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100")),
+            status => panic!("Unexpected status: {:?}", status),
+        }
+    }
+
+    #[test]
+    fn maps_eth_withdrawal_statuses() {
+        let hash = || "0x01".to_string();
+        assert!(matches!(
+            eth_withdrawal_status(1, &RetrieveEthStatus::TxSent(EthTransaction { transaction_hash: hash() })),
+            IntentStatus::InProgress(_)
+        ));
+        assert!(matches!(
+            eth_withdrawal_status(
+                1,
+                &RetrieveEthStatus::TxFinalized(TxFinalizedStatus::Success { transaction_hash: hash() })
+            ),
+            IntentStatus::Completed(_)
+        ));
+        assert!(matches!(
+            eth_withdrawal_status(
+                1,
+                &RetrieveEthStatus::TxFinalized(TxFinalizedStatus::Reimbursed { transaction_hash: hash() })
+            ),
+            IntentStatus::Failed(_)
+        ));
+        assert!(matches!(
+            eth_withdrawal_status(1, &RetrieveEthStatus::NotFound),
+            IntentStatus::Failed(_)
+        ));
+    }
+}
@@ -0,0 +1,564 @@
+use std::{cell::RefCell, future::Future, pin::Pin};
+
+use candid::{
+    utils::{ArgumentDecoder, ArgumentEncoder},
+    CandidType, Nat, Principal,
+};
+use ic_cdk::api::{
+    call::RejectionCode,
+    management_canister::main::{
+        canister_info, canister_status, delete_canister, deposit_cycles, start_canister,
+        stop_canister, CanisterIdRecord, CanisterInfoRequest, CanisterStatusType,
+    },
+};
+use ic_stable_structures::StableBTreeMap;
+use keygate_core::types::vault::{
+    CanisterCommand, CanisterInstallMode, CanisterRunStatus, CanisterSettingsArgs,
+    CanisterSnapshot, ControlledCanister, TransactionPayload, WasmModule,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    canister_calls::validate_args,
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest},
+    CONTROLLED_CANISTERS_MEMORY, MEMORY_MANAGER, VM, WASM_CHUNKS_MEMORY, WASM_MODULES_MEMORY,
+};
+
+/// Largest chunk `upload_chunk` of the management canister accepts.
+const MAX_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// Chunks kept at once, so uploads can't take more than 100 MiB of stable memory.
+const MAX_STORED_CHUNKS: u64 = 100;
+
+thread_local! {
+    /// Last known status of the canisters the vault controls.
+    static CONTROLLED_CANISTERS: RefCell<StableBTreeMap<Principal, ControlledCanister, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CONTROLLED_CANISTERS_MEMORY)))
+    );
+
+    /// Uploaded Wasm chunks by their SHA-256.
+    static WASM_CHUNKS: RefCell<StableBTreeMap<[u8; 32], Vec<u8>, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(WASM_CHUNKS_MEMORY)))
+    );
+
+    /// Registered Wasm modules by the SHA-256 of the whole module.
+    static WASM_MODULES: RefCell<StableBTreeMap<[u8; 32], WasmModule, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(WASM_MODULES_MEMORY)))
+    );
+}
+
+// Management canister methods ic-cdk 0.15 does not wrap, or wraps with settings this
+// vault does not use, see https://internetcomputer.org/docs/current/references/ic-interface-spec#ic-management-canister
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct UpgradeOptions {
+    skip_pre_upgrade: Option<bool>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum InstallMode {
+    #[serde(rename = "install")]
+    Install,
+    #[serde(rename = "reinstall")]
+    Reinstall,
+    #[serde(rename = "upgrade")]
+    Upgrade(Option<UpgradeOptions>),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ChunkHash {
+    hash: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct UploadChunkArgs {
+    canister_id: Principal,
+    chunk: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct InstallChunkedCodeArgs {
+    mode: InstallMode,
+    target_canister: Principal,
+    store_canister: Option<Principal>,
+    chunk_hashes_list: Vec<ChunkHash>,
+    wasm_module_hash: Vec<u8>,
+    arg: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Settings {
+    controllers: Option<Vec<Principal>>,
+    compute_allocation: Option<Nat>,
+    memory_allocation: Option<Nat>,
+    freezing_threshold: Option<Nat>,
+    reserved_cycles_limit: Option<Nat>,
+    wasm_memory_limit: Option<Nat>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct UpdateSettingsArgs {
+    canister_id: Principal,
+    settings: Settings,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct TakeSnapshotArgs {
+    canister_id: Principal,
+    replace_snapshot: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct SnapshotArgs {
+    canister_id: Principal,
+    snapshot_id: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Snapshot {
+    id: Vec<u8>,
+    taken_at_timestamp: u64,
+    total_size: u64,
+}
+
+async fn call_management<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+    method: &str,
+    args: T,
+) -> Result<R, String> {
+    ic_cdk::call(Principal::management_canister(), method, args)
+        .await
+        .map_err(|(rejection_code, message)| {
+            format!("Management canister rejected {}: {:?} - {}", method, rejection_code, message)
+        })
+}
+
+fn to_hash(bytes: &[u8]) -> Result<[u8; 32], String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("Expected a 32 bytes SHA-256 hash, got {} bytes", bytes.len()))
+}
+
+fn only_signers() -> Result<(), String> {
+    if crate::signer_exists(ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err("Caller is not a signer".to_string())
+    }
+}
+
+/// Rejects install proposals of modules that were not uploaded, or whose arguments
+/// do not fit in a proposal.
+pub fn validate_command(command: &CanisterCommand) -> Result<(), String> {
+    if let CanisterCommand::InstallCode {
+        wasm_module_hash,
+        arg,
+        ..
+    } = command
+    {
+        let hash = to_hash(wasm_module_hash)?;
+        if !WASM_MODULES.with(|modules| modules.borrow().contains_key(&hash)) {
+            return Err(format!("Wasm module {} was not uploaded", hex::encode(hash)));
+        }
+        validate_args(arg)?;
+    }
+    Ok(())
+}
+
+/// Reads back the chunks of a registered module, checking they still hash to it.
+fn wasm_chunks(hash: [u8; 32]) -> Result<Vec<Vec<u8>>, String> {
+    let module = WASM_MODULES
+        .with(|modules| modules.borrow().get(&hash))
+        .ok_or_else(|| format!("Wasm module {} was not uploaded", hex::encode(hash)))?;
+
+    let mut hasher = Sha256::new();
+    let mut chunks = vec![];
+    for chunk_hash in &module.chunk_hashes {
+        let key = to_hash(chunk_hash)?;
+        let chunk = WASM_CHUNKS
+            .with(|stored| stored.borrow().get(&key))
+            .ok_or_else(|| format!("Wasm chunk {} is missing", hex::encode(chunk_hash)))?;
+        hasher.update(&chunk);
+        chunks.push(chunk);
+    }
+
+    if <[u8; 32]>::from(hasher.finalize()) != hash {
+        return Err(format!("Wasm module does not match its hash {}", hex::encode(hash)));
+    }
+    Ok(chunks)
+}
+
+/// Installs a registered module through the chunk store of the target, so modules
+/// larger than a message can be installed.
+async fn install_code(
+    canister_id: Principal,
+    mode: CanisterInstallMode,
+    wasm_module_hash: &[u8],
+    arg: &[u8],
+) -> Result<(), String> {
+    let hash = to_hash(wasm_module_hash)?;
+    let chunks = wasm_chunks(hash)?;
+
+    let () = call_management("clear_chunk_store", (CanisterIdRecord { canister_id },)).await?;
+    let mut chunk_hashes_list = vec![];
+    for chunk in chunks {
+        let (chunk_hash,): (ChunkHash,) =
+            call_management("upload_chunk", (UploadChunkArgs { canister_id, chunk },)).await?;
+        chunk_hashes_list.push(chunk_hash);
+    }
+
+    let args = InstallChunkedCodeArgs {
+        mode: match mode {
+            CanisterInstallMode::Install => InstallMode::Install,
+            CanisterInstallMode::Reinstall => InstallMode::Reinstall,
+            CanisterInstallMode::Upgrade => InstallMode::Upgrade(None),
+        },
+        target_canister: canister_id,
+        store_canister: None,
+        chunk_hashes_list,
+        wasm_module_hash: hash.to_vec(),
+        arg: arg.to_vec(),
+    };
+    let () = call_management("install_chunked_code", (args,)).await?;
+
+    call_management("clear_chunk_store", (CanisterIdRecord { canister_id },)).await
+}
+
+async fn update_settings(canister_id: Principal, settings: &CanisterSettingsArgs) -> Result<(), String> {
+    let args = UpdateSettingsArgs {
+        canister_id,
+        settings: Settings {
+            controllers: settings.controllers.clone(),
+            compute_allocation: settings.compute_allocation.map(Nat::from),
+            memory_allocation: settings.memory_allocation.map(Nat::from),
+            freezing_threshold: settings.freezing_threshold.map(Nat::from),
+            reserved_cycles_limit: settings.reserved_cycles_limit.map(Nat::from),
+            wasm_memory_limit: settings.wasm_memory_limit.map(Nat::from),
+        },
+    };
+    call_management("update_settings", (args,)).await
+}
+
+fn to_u64(value: &Nat) -> u64 {
+    value.0.clone().try_into().unwrap_or(u64::MAX)
+}
+
+/// Whether a canister whose status could not be read is gone or no longer controlled by
+/// the vault, rather than failing for a transient reason. The controllers of a canister
+/// are public, so they are read with `canister_info` instead of being guessed from the
+/// rejection. A canister is kept when they can't be read either.
+async fn lost_control(canister_id: Principal, rejection_code: RejectionCode) -> bool {
+    if rejection_code == RejectionCode::DestinationInvalid {
+        return true;
+    }
+
+    let request = CanisterInfoRequest {
+        canister_id,
+        num_requested_changes: None,
+    };
+    match canister_info(request).await {
+        Ok((info,)) => !info.controllers.contains(&ic_cdk::id()),
+        Err((rejection_code, _)) => rejection_code == RejectionCode::DestinationInvalid,
+    }
+}
+
+/// Checks the status of a canister and records it. A canister the vault lost control of,
+/// or that was deleted, is forgotten. Other failures keep its last known status.
+pub(crate) async fn refresh_status(canister_id: Principal) -> Result<ControlledCanister, String> {
+    let status = match canister_status(CanisterIdRecord { canister_id }).await {
+        Ok((status,)) => status,
+        Err((rejection_code, message)) => {
+            if lost_control(canister_id, rejection_code).await {
+                CONTROLLED_CANISTERS.with(|canisters| canisters.borrow_mut().remove(&canister_id));
+            }
+            return Err(format!(
+                "Failed to get the status of canister {}: {:?} - {}",
+                canister_id, rejection_code, message
+            ));
+        }
+    };
+
+    // Snapshots only add to the status, it is recorded without them when they can't be listed
+    let snapshots: Vec<Snapshot> =
+        call_management("list_canister_snapshots", (CanisterIdRecord { canister_id },))
+            .await
+            .map(|(snapshots,)| snapshots)
+            .unwrap_or_default();
+
+    let canister = ControlledCanister {
+        canister_id,
+        status: match status.status {
+            CanisterStatusType::Running => CanisterRunStatus::Running,
+            CanisterStatusType::Stopping => CanisterRunStatus::Stopping,
+            CanisterStatusType::Stopped => CanisterRunStatus::Stopped,
+        },
+        module_hash: status.module_hash,
+        cycles: status.cycles.0.try_into().unwrap_or(u128::MAX),
+        memory_size: to_u64(&status.memory_size),
+        controllers: status.settings.controllers,
+        snapshots: snapshots
+            .into_iter()
+            .map(|snapshot| CanisterSnapshot {
+                id: snapshot.id,
+                taken_at_timestamp: snapshot.taken_at_timestamp,
+                total_size: snapshot.total_size,
+            })
+            .collect(),
+        updated_at: ic_cdk::api::time(),
+    };
+
+    CONTROLLED_CANISTERS.with(|canisters| {
+        canisters
+            .borrow_mut()
+            .insert(canister_id, canister.clone())
+    });
+    Ok(canister)
+}
+
+async fn execute_command(canister_id: Principal, command: &CanisterCommand) -> Result<String, String> {
+    let record = CanisterIdRecord { canister_id };
+    let rejected = |(rejection_code, message): (RejectionCode, String)| {
+        format!("Management canister rejected the call: {:?} - {}", rejection_code, message)
+    };
+
+    let message = match command {
+        CanisterCommand::InstallCode {
+            mode,
+            wasm_module_hash,
+            arg,
+        } => {
+            install_code(canister_id, *mode, wasm_module_hash, arg).await?;
+            let verb = match mode {
+                CanisterInstallMode::Install => "installed",
+                CanisterInstallMode::Reinstall => "reinstalled",
+                CanisterInstallMode::Upgrade => "upgraded",
+            };
+            format!(
+                "Successfully {} canister {} with module {}.",
+                verb,
+                canister_id,
+                hex::encode(wasm_module_hash)
+            )
+        }
+        CanisterCommand::UpdateSettings(settings) => {
+            update_settings(canister_id, settings).await?;
+            format!("Successfully updated the settings of canister {}.", canister_id)
+        }
+        CanisterCommand::Start => {
+            start_canister(record).await.map_err(rejected)?;
+            format!("Successfully started canister {}.", canister_id)
+        }
+        CanisterCommand::Stop => {
+            stop_canister(record).await.map_err(rejected)?;
+            format!("Successfully stopped canister {}.", canister_id)
+        }
+        CanisterCommand::Delete => {
+            delete_canister(record).await.map_err(rejected)?;
+            CONTROLLED_CANISTERS.with(|canisters| canisters.borrow_mut().remove(&canister_id));
+            return Ok(format!("Successfully deleted canister {}.", canister_id));
+        }
+        CanisterCommand::TakeSnapshot { replace_snapshot } => {
+            let args = TakeSnapshotArgs {
+                canister_id,
+                replace_snapshot: replace_snapshot.clone(),
+            };
+            let (snapshot,): (Snapshot,) = call_management("take_canister_snapshot", (args,)).await?;
+            format!(
+                "Successfully took snapshot {} of canister {}.",
+                hex::encode(snapshot.id),
+                canister_id
+            )
+        }
+        CanisterCommand::LoadSnapshot { snapshot_id } => {
+            let args = SnapshotArgs {
+                canister_id,
+                snapshot_id: snapshot_id.clone(),
+            };
+            let () = call_management("load_canister_snapshot", (args,)).await?;
+            format!(
+                "Successfully loaded snapshot {} into canister {}.",
+                hex::encode(snapshot_id),
+                canister_id
+            )
+        }
+        CanisterCommand::DeleteSnapshot { snapshot_id } => {
+            let args = SnapshotArgs {
+                canister_id,
+                snapshot_id: snapshot_id.clone(),
+            };
+            let () = call_management("delete_canister_snapshot", (args,)).await?;
+            format!(
+                "Successfully deleted snapshot {} of canister {}.",
+                hex::encode(snapshot_id),
+                canister_id
+            )
+        }
+        CanisterCommand::TopUp { cycles } => {
+            deposit_cycles(record, *cycles).await.map_err(rejected)?;
+            format!("Successfully topped up canister {} with {} cycles.", canister_id, cycles)
+        }
+    };
+
+    // The operation went through even if the new status can't be read
+    if let Err(e) = refresh_status(canister_id).await {
+        ic_cdk::println!("{}", e);
+    }
+    Ok(message)
+}
+
+/// Runs a management canister operation on a canister the vault controls.
+#[derive(Clone)]
+pub struct ManageCanisterAdapter {}
+
+impl ManageCanisterAdapter {
+    pub fn new() -> ManageCanisterAdapter {
+        ManageCanisterAdapter {}
+    }
+}
+
+impl BlockchainAdapter for ManageCanisterAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ManageCanisterAdapter");
+
+            let command = match &transaction.payload {
+                Some(TransactionPayload::Canister(command)) => command,
+                _ => return Err("Managing a canister requires a Canister payload".to_string()),
+            };
+            let canister_id = Principal::from_text(&transaction.to)
+                .map_err(|e| format!("Invalid canister id {}: {}", transaction.to, e))?;
+
+            execute_command(canister_id, command)
+                .await
+                .map(IntentStatus::Completed)
+        })
+    }
+}
+
+/// Stores a chunk of a Wasm module to install with a proposal, and returns its SHA-256.
+#[ic_cdk::update]
+pub fn upload_wasm_chunk(chunk: Vec<u8>) -> Result<Vec<u8>, String> {
+    only_signers()?;
+    if chunk.is_empty() || chunk.len() > MAX_CHUNK_BYTES {
+        return Err(format!(
+            "Wasm chunks must hold between 1 and {} bytes, got {}",
+            MAX_CHUNK_BYTES,
+            chunk.len()
+        ));
+    }
+
+    let hash: [u8; 32] = Sha256::digest(&chunk).into();
+    WASM_CHUNKS.with(|chunks| {
+        let mut chunks = chunks.borrow_mut();
+        if !chunks.contains_key(&hash) && chunks.len() >= MAX_STORED_CHUNKS {
+            return Err(format!(
+                "The vault already stores {} Wasm chunks, delete unused modules first",
+                MAX_STORED_CHUNKS
+            ));
+        }
+        chunks.insert(hash, chunk);
+        Ok(())
+    })?;
+    Ok(hash.to_vec())
+}
+
+/// Assembles uploaded chunks into a module that install proposals refer to by hash.
+#[ic_cdk::update]
+pub fn register_wasm_module(chunk_hashes: Vec<Vec<u8>>) -> Result<WasmModule, String> {
+    only_signers()?;
+    if chunk_hashes.is_empty() {
+        return Err("A Wasm module needs at least one chunk".to_string());
+    }
+
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    for chunk_hash in &chunk_hashes {
+        let key = to_hash(chunk_hash)?;
+        let chunk = WASM_CHUNKS
+            .with(|chunks| chunks.borrow().get(&key))
+            .ok_or_else(|| format!("Wasm chunk {} was not uploaded", hex::encode(chunk_hash)))?;
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+    }
+
+    let hash: [u8; 32] = hasher.finalize().into();
+    let module = WasmModule {
+        hash: hash.to_vec(),
+        size,
+        chunk_hashes,
+    };
+    WASM_MODULES.with(|modules| modules.borrow_mut().insert(hash, module.clone()));
+    Ok(module)
+}
+
+/// Deletes a registered module, along with its chunks no other module uses.
+#[ic_cdk::update]
+pub fn delete_wasm_module(hash: Vec<u8>) -> Result<(), String> {
+    only_signers()?;
+    let hash = to_hash(&hash)?;
+    let module = WASM_MODULES
+        .with(|modules| modules.borrow_mut().remove(&hash))
+        .ok_or_else(|| format!("Wasm module {} was not uploaded", hex::encode(hash)))?;
+
+    for chunk_hash in module.chunk_hashes {
+        if !chunk_in_use(&chunk_hash) {
+            let key = to_hash(&chunk_hash)?;
+            WASM_CHUNKS.with(|chunks| chunks.borrow_mut().remove(&key));
+        }
+    }
+    Ok(())
+}
+
+/// Deletes an uploaded chunk that no registered module uses, e.g. after a failed upload.
+#[ic_cdk::update]
+pub fn delete_wasm_chunk(hash: Vec<u8>) -> Result<(), String> {
+    only_signers()?;
+    if chunk_in_use(&hash) {
+        return Err(format!("Wasm chunk {} is part of a registered module", hex::encode(&hash)));
+    }
+
+    let key = to_hash(&hash)?;
+    WASM_CHUNKS
+        .with(|chunks| chunks.borrow_mut().remove(&key))
+        .map(|_| ())
+        .ok_or_else(|| format!("Wasm chunk {} was not uploaded", hex::encode(&hash)))
+}
+
+fn chunk_in_use(chunk_hash: &[u8]) -> bool {
+    WASM_MODULES.with(|modules| {
+        modules.borrow().iter().any(|(_, module)| {
+            module
+                .chunk_hashes
+                .iter()
+                .any(|hash| hash.as_slice() == chunk_hash)
+        })
+    })
+}
+
+#[ic_cdk::query]
+pub fn get_wasm_modules() -> Vec<WasmModule> {
+    WASM_MODULES.with(|modules| modules.borrow().iter().map(|(_, module)| module).collect())
+}
+
+/// Checks the status of a canister, which adds it to the controlled canisters when the
+/// vault is one of its controllers.
+#[ic_cdk::update]
+pub async fn refresh_controlled_canister(canister_id: Principal) -> Result<ControlledCanister, String> {
+    only_signers()?;
+    refresh_status(canister_id).await
+}
+
+/// The canisters the vault controls, with their status as of their last check.
+#[ic_cdk::query]
+pub fn get_controlled_canisters() -> Vec<ControlledCanister> {
+    CONTROLLED_CANISTERS.with(|canisters| {
+        canisters
+            .borrow()
+            .iter()
+            .map(|(_, canister)| canister)
+            .collect()
+    })
+}
@@ -0,0 +1,333 @@
+use std::{future::Future, pin::Pin, str::FromStr};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::{CallResult, RejectionCode};
+use ic_ledger_types::{AccountIdentifier, Memo, Subaccount, Tokens, TransferArgs};
+use icrc_ledger_types::icrc1::{
+    account::Account,
+    transfer::{TransferArg, TransferError},
+};
+use keygate_core::utils::to_subaccount;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controlled_canisters, get_default_icrc_subaccount, get_environment,
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest, RECOMMENDED_ICP_TRANSACTION_FEE},
+};
+
+/// Cycles ledger, um5iw-rqaaa-aaaaq-qaaba-cai.
+pub(crate) const CYCLES_LEDGER_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 2, 16, 0, 2, 1, 1]);
+
+/// Cycles minting canister, rkp4c-7iaaa-aaaaa-aaaca-cai.
+pub(crate) const CYCLES_MINTING_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 4, 1, 1]);
+
+// Memos the CMC expects on the ICP transfer it is notified about
+const MEMO_TOP_UP_CANISTER: u64 = 0x50555054; // TPUP
+const MEMO_CREATE_CANISTER: u64 = 0x41455243; // CREA
+
+// Cycles ledger interface, see https://github.com/dfinity/cycles-ledger/blob/main/cycles-ledger/cycles-ledger.did
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct WithdrawArgs {
+    amount: Nat,
+    from_subaccount: Option<Vec<u8>>,
+    to: Principal,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum WithdrawError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    FailedToWithdraw {
+        fee_block: Option<Nat>,
+        rejection_code: RejectionCode,
+        rejection_reason: String,
+    },
+    GenericError { error_code: Nat, message: String },
+    InvalidReceiver { receiver: Principal },
+}
+
+// CMC interface, see https://github.com/dfinity/ic/blob/master/rs/nns/cmc/cmc.did
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct NotifyTopUpArg {
+    block_index: u64,
+    canister_id: Principal,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct NotifyCreateCanisterArg {
+    block_index: u64,
+    controller: Principal,
+    subnet_type: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum NotifyError {
+    Refunded {
+        reason: String,
+        block_index: Option<u64>,
+    },
+    Processing,
+    TransactionTooOld(u64),
+    InvalidTransaction(String),
+    Other { error_code: u64, error_message: String },
+}
+
+fn rejected(rejection_code: impl std::fmt::Debug, message: String) -> String {
+    format!("Canister call rejected: {:?} - {}", rejection_code, message)
+}
+
+fn parse_canister_id(to: &str) -> Result<Principal, String> {
+    Principal::from_text(to).map_err(|e| format!("Invalid canister id {}: {}", to, e))
+}
+
+/// Subaccount of the CMC that ICP for `principal` is sent to, see `Subaccount::from(&PrincipalId)`
+/// in the IC repository.
+fn principal_subaccount(principal: Principal) -> Subaccount {
+    let bytes = principal.as_slice();
+    let mut subaccount = [0; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    Subaccount(subaccount)
+}
+
+/// Sends ICP of the vault to the CMC account of `beneficiary`, returning the block index
+/// to notify the CMC about.
+async fn send_to_cmc(e8s: u64, beneficiary: Principal, memo: u64) -> Result<u64, String> {
+    let args = TransferArgs {
+        memo: Memo(memo),
+        amount: Tokens::from_e8s(e8s),
+        fee: Tokens::from_e8s(RECOMMENDED_ICP_TRANSACTION_FEE),
+        from_subaccount: Some(to_subaccount(0)),
+        to: AccountIdentifier::new(
+            &CYCLES_MINTING_CANISTER_ID,
+            &principal_subaccount(beneficiary),
+        ),
+        created_at_time: None,
+    };
+    match ic_ledger_types::transfer(get_environment().icp_ledger, args).await {
+        Ok(Ok(block_index)) => Ok(block_index),
+        Ok(Err(e)) => Err(format!("transfer error: {:?}", e)),
+        Err((rejection_code, message)) => Err(rejected(rejection_code, message)),
+    }
+}
+
+/// The ICP stays on the CMC account when notifying fails, notifying again with the same
+/// block index recovers it.
+fn notify_error(method: &str, block_index: u64, error: String) -> String {
+    format!("Could not {} for ICP transfer {}: {}", method, block_index, error)
+}
+
+/// Sends cycles the vault holds on the cycles ledger to another ICRC-1 account.
+#[derive(Clone)]
+pub struct CyclesTransferAdapter {}
+
+impl CyclesTransferAdapter {
+    pub fn new() -> CyclesTransferAdapter {
+        CyclesTransferAdapter {}
+    }
+}
+
+impl BlockchainAdapter for CyclesTransferAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing CyclesTransferAdapter");
+
+            let to = Account::from_str(&transaction.to)
+                .map_err(|e| format!("Invalid ICRC-1 account {}: {}", transaction.to, e))?;
+            let args = TransferArg {
+                from_subaccount: Some(get_default_icrc_subaccount().0),
+                to,
+                // The cycles ledger fee depends on the deployment, let it apply its own
+                fee: None,
+                created_at_time: None,
+                memo: None,
+                amount: Nat::from(transaction.amount as u128),
+            };
+
+            let result: CallResult<(Result<Nat, TransferError>,)> =
+                ic_cdk::call(CYCLES_LEDGER_CANISTER_ID, "icrc1_transfer", (args,)).await;
+            match result {
+                Ok((Ok(block_index),)) => Ok(IntentStatus::Completed(format!(
+                    "Successfully transferred {} cycles in block {}.",
+                    transaction.amount as u128, block_index
+                ))),
+                Ok((Err(e),)) => Err(format!("Cycles transfer error: {:?}", e)),
+                Err((rejection_code, message)) => Err(rejected(rejection_code, message)),
+            }
+        })
+    }
+}
+
+/// Withdraws cycles the vault holds on the cycles ledger into a canister.
+#[derive(Clone)]
+pub struct CyclesWithdrawAdapter {}
+
+impl CyclesWithdrawAdapter {
+    pub fn new() -> CyclesWithdrawAdapter {
+        CyclesWithdrawAdapter {}
+    }
+}
+
+impl BlockchainAdapter for CyclesWithdrawAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing CyclesWithdrawAdapter");
+
+            let canister_id = parse_canister_id(&transaction.to)?;
+            let args = WithdrawArgs {
+                amount: Nat::from(transaction.amount as u128),
+                from_subaccount: Some(get_default_icrc_subaccount().0.to_vec()),
+                to: canister_id,
+                created_at_time: None,
+            };
+
+            let result: CallResult<(Result<Nat, WithdrawError>,)> =
+                ic_cdk::call(CYCLES_LEDGER_CANISTER_ID, "withdraw", (args,)).await;
+            match result {
+                Ok((Ok(block_index),)) => Ok(IntentStatus::Completed(format!(
+                    "Successfully withdrew {} cycles to canister {} in block {}.",
+                    transaction.amount as u128, canister_id, block_index
+                ))),
+                Ok((Err(e),)) => Err(format!("Cycles withdrawal error: {:?}", e)),
+                Err((rejection_code, message)) => Err(rejected(rejection_code, message)),
+            }
+        })
+    }
+}
+
+/// Sends `e8s` of the vault's ICP to the CMC and has it converted into cycles for
+/// `canister_id`, returning the cycles it received.
+pub(crate) async fn top_up_with_icp(canister_id: Principal, e8s: u64) -> Result<Nat, String> {
+    let block_index = send_to_cmc(e8s, canister_id, MEMO_TOP_UP_CANISTER).await?;
+
+    let args = NotifyTopUpArg {
+        block_index,
+        canister_id,
+    };
+    let result: CallResult<(Result<Nat, NotifyError>,)> =
+        ic_cdk::call(CYCLES_MINTING_CANISTER_ID, "notify_top_up", (args,)).await;
+    match result {
+        Ok((Ok(cycles),)) => Ok(cycles),
+        Ok((Err(e),)) => Err(notify_error("top up", block_index, format!("{:?}", e))),
+        Err((rejection_code, message)) => Err(notify_error(
+            "top up",
+            block_index,
+            rejected(rejection_code, message),
+        )),
+    }
+}
+
+/// Converts ICP of the vault into cycles for a canister through the CMC. The amount
+/// is in e8s.
+#[derive(Clone)]
+pub struct CMCTopUpAdapter {}
+
+impl CMCTopUpAdapter {
+    pub fn new() -> CMCTopUpAdapter {
+        CMCTopUpAdapter {}
+    }
+}
+
+impl BlockchainAdapter for CMCTopUpAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing CMCTopUpAdapter");
+
+            let canister_id = parse_canister_id(&transaction.to)?;
+            let cycles = top_up_with_icp(canister_id, transaction.amount as u64).await?;
+
+            Ok(IntentStatus::Completed(format!(
+                "Successfully topped up canister {} with {} cycles.",
+                canister_id, cycles
+            )))
+        })
+    }
+}
+
+/// Creates a canister controlled by the vault, paid with ICP of the vault through the
+/// CMC. The amount is in e8s and the new canister joins the controlled canisters.
+#[derive(Clone)]
+pub struct CMCCreateCanisterAdapter {}
+
+impl CMCCreateCanisterAdapter {
+    pub fn new() -> CMCCreateCanisterAdapter {
+        CMCCreateCanisterAdapter {}
+    }
+}
+
+impl BlockchainAdapter for CMCCreateCanisterAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing CMCCreateCanisterAdapter");
+
+            let controller = ic_cdk::id();
+            let block_index =
+                send_to_cmc(transaction.amount as u64, controller, MEMO_CREATE_CANISTER).await?;
+
+            let args = NotifyCreateCanisterArg {
+                block_index,
+                controller,
+                subnet_type: None,
+            };
+            let result: CallResult<(Result<Principal, NotifyError>,)> =
+                ic_cdk::call(CYCLES_MINTING_CANISTER_ID, "notify_create_canister", (args,)).await;
+            let canister_id = match result {
+                Ok((Ok(canister_id),)) => canister_id,
+                Ok((Err(e),)) => {
+                    return Err(notify_error("create a canister", block_index, format!("{:?}", e)))
+                }
+                Err((rejection_code, message)) => {
+                    return Err(notify_error(
+                        "create a canister",
+                        block_index,
+                        rejected(rejection_code, message),
+                    ))
+                }
+            };
+
+            if let Err(e) = controlled_canisters::refresh_status(canister_id).await {
+                ic_cdk::println!("{}", e);
+            }
+            Ok(IntentStatus::Completed(format!(
+                "Successfully created canister {}.",
+                canister_id
+            )))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_cmc_subaccounts() {
+        let canister_id = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        let subaccount = principal_subaccount(canister_id);
+
+        assert_eq!(subaccount.0[0], 10);
+        assert_eq!(&subaccount.0[1..11], canister_id.as_slice());
+        assert!(subaccount.0[11..].iter().all(|byte| *byte == 0));
+    }
+}
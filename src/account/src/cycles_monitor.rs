@@ -0,0 +1,352 @@
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::{storable::Bound, StableCell, StableLog, Storable};
+use keygate_core::types::vault::{
+    AuditEvent, AuditEventKind, CyclesMonitorConfig, CyclesRefuelSource, CyclesStatus,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cycles, intent::RECOMMENDED_ICP_TRANSACTION_FEE, signer_exists, AUDIT_LOG_DATA_MEMORY,
+    AUDIT_LOG_INDEX_MEMORY, CYCLES_MONITOR_MEMORY, MEMORY_MANAGER, VM,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A day of hourly checks.
+const MAX_SAMPLES: usize = 24;
+
+/// Vaults are deployed with 240B cycles, refuel well before threshold ECDSA calls fail.
+const DEFAULT_THRESHOLD: u128 = 100_000_000_000;
+
+const NANOS_PER_DAY: u128 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Refuels stop once the balance is above the threshold, so capping it keeps a signer
+/// from making the vault convert ICP at every check.
+const MAX_THRESHOLD: u128 = 10_000_000_000_000;
+
+/// 1 ICP, a refuel a single signer can configure without a proposal.
+const MAX_REFUEL_E8S: u64 = 100_000_000;
+
+#[derive(CandidType, Deserialize, Serialize, Clone)]
+struct MonitorState {
+    config: CyclesMonitorConfig,
+    /// Balance at each check, oldest first, as (timestamp, cycles).
+    samples: Vec<(u64, u128)>,
+    /// Central canister that deployed the vault, the only one it requests cycles from.
+    central: Option<Principal>,
+}
+
+impl Default for MonitorState {
+    fn default() -> Self {
+        MonitorState {
+            config: CyclesMonitorConfig {
+                threshold: DEFAULT_THRESHOLD,
+                refuel: None,
+            },
+            samples: vec![],
+            central: None,
+        }
+    }
+}
+
+impl Storable for MonitorState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static STATE: RefCell<StableCell<MonitorState, VM>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(CYCLES_MONITOR_MEMORY)),
+            MonitorState::default(),
+        ).expect("Failed to initialize the cycles monitor StableCell")
+    );
+
+    /// Events the vault logs on its own, see `AuditEvent`.
+    static AUDIT_LOG: RefCell<StableLog<AuditEvent, VM, VM>> = RefCell::new(
+        StableLog::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_LOG_INDEX_MEMORY)),
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_LOG_DATA_MEMORY)),
+        ).expect("Failed to initialize the audit log")
+    );
+
+    static CHECK_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static CHECKING: RefCell<bool> = const { RefCell::new(false) };
+}
+
+fn state() -> MonitorState {
+    STATE.with(|state| state.borrow().get().clone())
+}
+
+fn update_state(f: impl FnOnce(&mut MonitorState)) {
+    STATE.with(|cell| {
+        let mut state = cell.borrow().get().clone();
+        f(&mut state);
+        cell.borrow_mut()
+            .set(state)
+            .expect("Failed to save the cycles monitor state");
+    });
+}
+
+fn log_event(kind: AuditEventKind) {
+    let event = AuditEvent {
+        timestamp: ic_cdk::api::time(),
+        kind,
+    };
+    AUDIT_LOG.with(|log| {
+        log.borrow_mut()
+            .append(&event)
+            .expect("Failed to append to the audit log")
+    });
+}
+
+/// Cycles burnt per day between the first and the last sample. Increases are top-ups
+/// and don't count, so cycles burnt in an interval with a top-up are missed.
+fn burn_rate_per_day(samples: &[(u64, u128)]) -> Option<u128> {
+    let (first, last) = (samples.first()?, samples.last()?);
+    let elapsed = last.0.checked_sub(first.0).filter(|elapsed| *elapsed > 0)?;
+    let burnt: u128 = samples
+        .windows(2)
+        .map(|pair| pair[0].1.saturating_sub(pair[1].1))
+        .sum();
+
+    Some(burnt.saturating_mul(NANOS_PER_DAY) / elapsed as u128)
+}
+
+fn status(state: MonitorState) -> CyclesStatus {
+    CyclesStatus {
+        balance: ic_cdk::api::canister_balance128(),
+        burn_rate_per_day: burn_rate_per_day(&state.samples),
+        checked_at: state.samples.last().map(|(timestamp, _)| *timestamp),
+        config: state.config,
+    }
+}
+
+fn to_u128(cycles: Nat) -> u128 {
+    cycles.0.try_into().unwrap_or(u128::MAX)
+}
+
+async fn refuel(source: &CyclesRefuelSource) -> Result<u128, String> {
+    match source {
+        CyclesRefuelSource::Icp { e8s } => cycles::top_up_with_icp(ic_cdk::id(), *e8s)
+            .await
+            .map(to_u128),
+        CyclesRefuelSource::Central(central) => {
+            let result: CallResult<(Result<Nat, String>,)> =
+                ic_cdk::call(*central, "request_cycles", ()).await;
+            match result {
+                Ok((result,)) => result.map(to_u128),
+                Err((rejection_code, message)) => Err(format!(
+                    "Canister call rejected: {:?} - {}",
+                    rejection_code, message
+                )),
+            }
+        }
+    }
+}
+
+/// Marks a check as running until dropped, including when the check traps after
+/// the refuel call.
+struct CheckGuard;
+
+impl CheckGuard {
+    fn try_acquire() -> Option<CheckGuard> {
+        (!CHECKING.with(|checking| checking.replace(true))).then_some(CheckGuard)
+    }
+}
+
+impl Drop for CheckGuard {
+    fn drop(&mut self) {
+        CHECKING.with(|checking| *checking.borrow_mut() = false);
+    }
+}
+
+/// Records the balance and refuels when it is under the threshold. Low balances are
+/// logged to the audit trail whether the refuel works or not.
+async fn check_cycles() {
+    let Some(_guard) = CheckGuard::try_acquire() else {
+        return;
+    };
+
+    let balance = ic_cdk::api::canister_balance128();
+    update_state(|state| {
+        state.samples.push((ic_cdk::api::time(), balance));
+        let excess = state.samples.len().saturating_sub(MAX_SAMPLES);
+        state.samples.drain(..excess);
+    });
+
+    let config = state().config;
+    if balance < config.threshold {
+        log_event(AuditEventKind::CyclesLow {
+            balance,
+            threshold: config.threshold,
+        });
+
+        if let Some(source) = config.refuel {
+            let kind = match refuel(&source).await {
+                Ok(cycles) => AuditEventKind::CyclesRefueled { source, cycles },
+                Err(error) => AuditEventKind::CyclesRefuelFailed { source, error },
+            };
+            log_event(kind);
+        }
+    }
+}
+
+/// Refuels from the central canister that deployed the vault, unless configured otherwise.
+pub fn init(central: Option<Principal>) {
+    update_state(|state| {
+        state.config.refuel = central.map(CyclesRefuelSource::Central);
+        state.central = central;
+    });
+}
+
+pub fn start_monitoring() {
+    if CHECK_TIMER.with(|timer| timer.borrow().is_some()) {
+        return;
+    }
+
+    let timer = ic_cdk_timers::set_timer_interval(CHECK_INTERVAL, || ic_cdk::spawn(check_cycles()));
+    CHECK_TIMER.with(|t| *t.borrow_mut() = Some(timer));
+}
+
+#[ic_cdk::query]
+pub fn get_cycles_status() -> CyclesStatus {
+    status(state())
+}
+
+#[ic_cdk::update]
+pub fn set_cycles_monitor_config(config: CyclesMonitorConfig) -> Result<(), String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+    validate_config(&config, state().central)?;
+
+    update_state(|state| state.config = config);
+    Ok(())
+}
+
+/// Any signer can change the config, so it is bounded: cycles only come from the
+/// vault's own ICP or the central canister that deployed it.
+fn validate_config(config: &CyclesMonitorConfig, central: Option<Principal>) -> Result<(), String> {
+    if config.threshold > MAX_THRESHOLD {
+        return Err(format!(
+            "The threshold can be at most {} cycles",
+            MAX_THRESHOLD
+        ));
+    }
+
+    match config.refuel {
+        Some(CyclesRefuelSource::Icp { e8s }) if e8s <= RECOMMENDED_ICP_TRANSACTION_FEE => {
+            Err(format!(
+                "Refuels must convert more than the {} e8s transfer fee",
+                RECOMMENDED_ICP_TRANSACTION_FEE
+            ))
+        }
+        Some(CyclesRefuelSource::Icp { e8s }) if e8s > MAX_REFUEL_E8S => Err(format!(
+            "Refuels can convert at most {} e8s",
+            MAX_REFUEL_E8S
+        )),
+        Some(CyclesRefuelSource::Central(principal)) if Some(principal) != central => Err(format!(
+            "{} is not the central canister that deployed the vault",
+            principal
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the balance right away instead of waiting for the hourly check.
+#[ic_cdk::update]
+pub async fn check_cycles_now() -> Result<CyclesStatus, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+    if CHECKING.with(|checking| *checking.borrow()) {
+        return Err("A check is already running".to_string());
+    }
+
+    check_cycles().await;
+    Ok(status(state()))
+}
+
+#[ic_cdk::query]
+pub fn get_audit_trail() -> Vec<AuditEvent> {
+    AUDIT_LOG.with(|log| log.borrow().iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60 * 1_000_000_000;
+
+    #[test]
+    fn needs_two_samples_for_a_burn_rate() {
+        assert_eq!(burn_rate_per_day(&[]), None);
+        assert_eq!(burn_rate_per_day(&[(HOUR, 1_000)]), None);
+    }
+
+    #[test]
+    fn computes_the_daily_burn_rate() {
+        let samples = [(0, 10_000), (HOUR, 9_000), (2 * HOUR, 8_000)];
+        assert_eq!(burn_rate_per_day(&samples), Some(24_000));
+    }
+
+    #[test]
+    fn bounds_the_monitor_config() {
+        let central = Principal::from_slice(&[1]);
+        let config = |threshold: u128, refuel: Option<CyclesRefuelSource>| CyclesMonitorConfig {
+            threshold,
+            refuel,
+        };
+
+        assert!(validate_config(&config(DEFAULT_THRESHOLD, None), None).is_ok());
+        assert!(validate_config(&config(MAX_THRESHOLD + 1, None), None).is_err());
+
+        let icp = |e8s: u64| Some(CyclesRefuelSource::Icp { e8s });
+        assert!(validate_config(&config(DEFAULT_THRESHOLD, icp(MAX_REFUEL_E8S)), None).is_ok());
+        assert!(
+            validate_config(&config(DEFAULT_THRESHOLD, icp(MAX_REFUEL_E8S + 1)), None).is_err()
+        );
+        assert!(validate_config(&config(DEFAULT_THRESHOLD, icp(10_000)), None).is_err());
+
+        let from = |principal: Principal| Some(CyclesRefuelSource::Central(principal));
+        assert!(validate_config(&config(DEFAULT_THRESHOLD, from(central)), Some(central)).is_ok());
+        assert!(validate_config(
+            &config(DEFAULT_THRESHOLD, from(Principal::from_slice(&[2]))),
+            Some(central)
+        )
+        .is_err());
+        assert!(validate_config(&config(DEFAULT_THRESHOLD, from(central)), None).is_err());
+    }
+
+    #[test]
+    fn ignores_top_ups_in_the_burn_rate() {
+        let samples = [
+            (0, 10_000),
+            (HOUR, 9_000),
+            (2 * HOUR, 50_000),
+            (3 * HOUR, 49_000),
+        ];
+        assert_eq!(burn_rate_per_day(&samples), Some(16_000));
+    }
+
+    #[test]
+    fn runs_one_check_at_a_time() {
+        let guard = CheckGuard::try_acquire().unwrap();
+        assert!(CheckGuard::try_acquire().is_none());
+
+        drop(guard);
+        assert!(!CHECKING.with(|checking| *checking.borrow()));
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use alloy::primitives::{keccak256, B256};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::evm_abi::{encode_word, parse_hex};
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+#[derive(Deserialize, Debug, Clone)]
+struct Field {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// EIP-712 typed data in the JSON form of `eth_signTypedData_v4`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    types: BTreeMap<String, Vec<Field>>,
+    primary_type: String,
+    domain: Value,
+    message: Value,
+}
+
+impl TypedData {
+    pub fn parse(json: &str) -> Result<TypedData, String> {
+        let typed_data: TypedData =
+            serde_json::from_str(json).map_err(|e| format!("Invalid EIP-712 typed data: {}", e))?;
+
+        if !typed_data.types.contains_key(DOMAIN_TYPE) {
+            return Err(format!("Typed data does not define {}", DOMAIN_TYPE));
+        }
+        if !typed_data.types.contains_key(&typed_data.primary_type) {
+            return Err(format!(
+                "Typed data does not define its primary type {}",
+                typed_data.primary_type
+            ));
+        }
+        Ok(typed_data)
+    }
+
+    /// `keccak256(0x1901 ‖ domainSeparator ‖ hashStruct(message))`, the digest wallets sign.
+    pub fn signing_hash(&self) -> Result<B256, String> {
+        let mut encoded = vec![0x19, 0x01];
+        encoded.extend_from_slice(self.hash_struct(DOMAIN_TYPE, &self.domain)?.as_slice());
+        // Signing the domain alone leaves out the message hash
+        if self.primary_type != DOMAIN_TYPE {
+            encoded.extend_from_slice(
+                self.hash_struct(&self.primary_type, &self.message)?
+                    .as_slice(),
+            );
+        }
+        Ok(keccak256(encoded))
+    }
+
+    fn fields(&self, name: &str) -> Result<&[Field], String> {
+        self.types
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| format!("Unknown type {}", name))
+    }
+
+    /// The type followed by the types it references, sorted by name, e.g.
+    /// `Mail(Person from,Person to,string contents)Person(string name,address wallet)`.
+    fn encode_type(&self, name: &str) -> Result<String, String> {
+        let mut dependencies = BTreeSet::new();
+        self.collect_dependencies(name, &mut dependencies)?;
+        dependencies.remove(name);
+
+        let mut encoded = String::new();
+        for name in std::iter::once(name).chain(dependencies.iter().map(String::as_str)) {
+            let fields: Vec<String> = self
+                .fields(name)?
+                .iter()
+                .map(|field| format!("{} {}", field.kind, field.name))
+                .collect();
+            encoded.push_str(&format!("{}({})", name, fields.join(",")));
+        }
+        Ok(encoded)
+    }
+
+    fn collect_dependencies(
+        &self,
+        name: &str,
+        dependencies: &mut BTreeSet<String>,
+    ) -> Result<(), String> {
+        if !dependencies.insert(name.to_string()) {
+            return Ok(());
+        }
+        for field in self.fields(name)? {
+            let base = base_type(&field.kind);
+            if self.types.contains_key(base) {
+                self.collect_dependencies(base, dependencies)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn hash_struct(&self, name: &str, value: &Value) -> Result<B256, String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("{} is not an object: {}", name, value))?;
+
+        let mut encoded = keccak256(self.encode_type(name)?.as_bytes()).to_vec();
+        for field in self.fields(name)? {
+            let value = object
+                .get(&field.name)
+                .ok_or_else(|| format!("{} is missing {}", name, field.name))?;
+            encoded.extend_from_slice(&self.encode_field(&field.kind, value)?);
+        }
+        Ok(keccak256(encoded))
+    }
+
+    fn encode_field(&self, kind: &str, value: &Value) -> Result<[u8; 32], String> {
+        if let Some((item_kind, length)) = array_type(kind) {
+            let items = value
+                .as_array()
+                .ok_or_else(|| format!("{} is not an array: {}", kind, value))?;
+            if length.is_some_and(|length| length != items.len()) {
+                return Err(format!("{} has {} items", kind, items.len()));
+            }
+
+            let mut encoded = vec![];
+            for item in items {
+                encoded.extend_from_slice(&self.encode_field(item_kind, item)?);
+            }
+            return Ok(keccak256(encoded).0);
+        }
+
+        if self.types.contains_key(kind) {
+            return Ok(self.hash_struct(kind, value)?.0);
+        }
+
+        let text = atomic_value(value)?;
+        match kind {
+            "string" => Ok(keccak256(text.as_bytes()).0),
+            "bytes" => Ok(keccak256(parse_hex(&text)?).0),
+            _ => encode_word(kind, &text),
+        }
+    }
+}
+
+fn base_type(kind: &str) -> &str {
+    kind.split('[').next().unwrap_or(kind)
+}
+
+/// The item type and length of `T[]` and `T[n]`.
+fn array_type(kind: &str) -> Option<(&str, Option<usize>)> {
+    let open = kind.strip_suffix(']')?.rfind('[')?;
+    let length = kind[open + 1..kind.len() - 1].parse::<usize>().ok();
+    Some((&kind[..open], length))
+}
+
+fn atomic_value(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Bool(value) => Ok(value.to_string()),
+        Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+            (Some(value), _) => Ok(value.to_string()),
+            (None, Some(value)) => Ok(value.to_string()),
+            // Larger integers lose precision as JSON numbers
+            _ => Err(format!("Pass {} as a string", number)),
+        },
+        _ => Err(format!("Expected a string, number or bool, got {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of the EIP-712 specification
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallet", "type": "address"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person"},
+                {"name": "contents", "type": "string"}
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    #[test]
+    fn hashes_the_specification_example() {
+        let typed_data = TypedData::parse(MAIL).unwrap();
+
+        assert_eq!(
+            typed_data.encode_type("Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            typed_data
+                .hash_struct(DOMAIN_TYPE, &typed_data.domain)
+                .unwrap()
+                .to_string(),
+            "0xf2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            typed_data.signing_hash().unwrap().to_string(),
+            "0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_typed_data() {
+        let missing_field = MAIL.replace(r#""contents": "Hello, Bob!""#, r#""body": "Hello""#);
+        assert!(TypedData::parse(&missing_field)
+            .unwrap()
+            .signing_hash()
+            .is_err());
+
+        let unknown_primary_type =
+            MAIL.replace(r#""primaryType": "Mail""#, r#""primaryType": "Letter""#);
+        assert!(TypedData::parse(&unknown_primary_type).is_err());
+
+        assert!(TypedData::parse("{}").is_err());
+    }
+}
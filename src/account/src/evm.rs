@@ -1,136 +1,629 @@
 use crate::alloy_services;
+use crate::evm_abi::{self, FunctionSignature};
+use crate::evm_confirmations;
 use crate::evm_types;
+use crate::evm_wallets;
+use crate::{signer_exists, EVM_FEE_CAPS_MEMORY, EVM_NONCES_MEMORY, MEMORY_MANAGER, VM};
+use crate::intent::{BlockchainAdapter, IntentStatus, TransactionRequest as IntentRequest};
 use alloy::{
     network::{EthereumWallet, TransactionBuilder},
-    primitives::{Address, U256},
+    primitives::{Address, Bytes, U256},
     providers::{Provider, ProviderBuilder},
-    rpc::types::TransactionRequest,
+    rpc::types::{BlockNumberOrTag, TransactionRequest},
     signers::Signer,
-    transports::icp::IcpConfig,
+    transports::{icp::IcpConfig, Transport},
 };
+use ic_stable_structures::StableBTreeMap;
+use keygate_core::types::environment::EvmChainConfig;
+use keygate_core::types::vault::{ContractCallArgs, ContractCallDetails, TransactionPayload};
 use std::cell::RefCell;
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
 use std::str::FromStr;
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+// ERC-20 function selectors, the first 4 bytes of keccak256 of the signature
+const ERC20_TRANSFER_SELECTOR: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb]; // transfer(address,uint256)
+const ERC20_BALANCE_OF_SELECTOR: [u8; 4] = [0x70, 0xa0, 0x82, 0x31]; // balanceOf(address)
+const ERC20_DECIMALS_SELECTOR: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67]; // decimals()
+
+/// Number of recent blocks whose priority fees are sampled.
+const FEE_HISTORY_BLOCKS: u64 = 10;
+/// Percentile of the priority fees paid in each block that is sampled.
+const PRIORITY_FEE_PERCENTILE: f64 = 50.0;
+
+/// A send takes a few HTTPS outcalls, waiting 3 minutes covers a handful of them.
+const NONCE_LOCK_ATTEMPTS: u32 = 90;
+const NONCE_LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(2);
 
 thread_local! {
-    pub static KEY_NAME : std::cell::RefCell<String> = std::cell::RefCell::new("dfx_test_key".to_string());
-    static NONCE: RefCell<Option<u64>> = const { RefCell::new(None) };
+    /// Next nonce to use, keyed by `<chain>:<address>`.
+    static NONCES: RefCell<StableBTreeMap<String, u64, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(EVM_NONCES_MEMORY)))
+    );
+
+    /// Maximum fee in wei the vault accepts to pay for a transaction, per chain.
+    static FEE_CAPS: RefCell<StableBTreeMap<String, u128, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(EVM_FEE_CAPS_MEMORY)))
+    );
+
+    /// Chain and address pairs with a transaction being sent.
+    static NONCE_LOCKS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
 }
 
-#[ic_cdk::update]
-pub async fn pubkey_bytes_to_address() -> String {
-    let signer = alloy_services::create_icp_sepolia_signer().await;
-    let address = signer.address();
-    address.to_string()
+fn nonce_key(chain: &str, address: &Address) -> String {
+    format!("{}:{}", chain, address)
+}
+
+/// Allows a single transaction in flight per chain and address, so concurrent
+/// executions never pick the same nonce. Released when dropped, including when
+/// the execution traps after an await.
+struct NonceGuard {
+    key: String,
+}
+
+impl NonceGuard {
+    fn try_acquire(key: &str) -> Option<NonceGuard> {
+        NONCE_LOCKS
+            .with(|locks| locks.borrow_mut().insert(key.to_string()))
+            .then(|| NonceGuard {
+                key: key.to_string(),
+            })
+    }
+
+    /// Waits for the transaction in flight from the same address, e.g. the previous
+    /// transfer of a batch, instead of failing.
+    async fn acquire(key: String) -> Result<NonceGuard, String> {
+        for _ in 0..NONCE_LOCK_ATTEMPTS {
+            if let Some(guard) = NonceGuard::try_acquire(&key) {
+                return Ok(guard);
+            }
+            sleep(NONCE_LOCK_RETRY_INTERVAL).await;
+        }
+        Err(format!(
+            "Another transaction is still being sent from {}, retry once it completes.",
+            key
+        ))
+    }
+}
+
+/// Resolves after `delay`, from a timer, so other messages run in the meantime.
+fn sleep(delay: Duration) -> impl Future<Output = ()> {
+    let state: Rc<RefCell<(bool, Option<Waker>)>> = Rc::default();
+    let timer_state = state.clone();
+    ic_cdk_timers::set_timer(delay, move || {
+        let waker = {
+            let mut state = timer_state.borrow_mut();
+            state.0 = true;
+            state.1.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    });
+
+    std::future::poll_fn(move |cx| {
+        let mut state = state.borrow_mut();
+        if state.0 {
+            return Poll::Ready(());
+        }
+        state.1 = Some(cx.waker().clone());
+        Poll::Pending
+    })
+}
+
+impl Drop for NonceGuard {
+    fn drop(&mut self) {
+        NONCE_LOCKS.with(|locks| locks.borrow_mut().remove(&self.key));
+    }
+}
+
+fn failed(status: String) -> evm_types::TransactionResult {
+    evm_types::TransactionResult {
+        hash: String::new(),
+        status: format!("Failed: {}", status),
+    }
+}
+
+/// The vault's main EVM address, from the key derived after install or upgrade.
+#[ic_cdk::query]
+pub fn pubkey_bytes_to_address() -> String {
+    match alloy_services::cached_evm_key(&[]) {
+        Some(key) => key.address,
+        None => ic_cdk::trap("The EVM key is not derived yet, retry shortly"),
+    }
 }
 
 #[ic_cdk::update]
 pub async fn get_public_key() -> Result<evm_types::PublicKeyReply, String> {
-    let signer = alloy_services::create_icp_sepolia_signer().await;
-    let public_key = signer.public_key().to_vec();
+    let public_key = alloy_services::evm_key(vec![]).await.public_key;
     Ok(evm_types::PublicKeyReply { public_key })
 }
 
-fn eth_to_wei(eth: f64) -> U256 {
-    let eth_scaled = (eth * 1_000_000.0) as u64;
-    let eth_u256 = U256::from(eth_scaled);
+/// The vault's main EVM address and public key, once derived.
+#[ic_cdk::query]
+pub fn get_evm_key() -> Option<evm_types::DerivedEvmKey> {
+    alloy_services::cached_evm_key(&[])
+}
+
+/// Converts a token amount to its base units. The amount is read as the shortest
+/// decimal that round-trips, e.g. 0.1 and not 0.1000000000000000055, and refused when
+/// the token has fewer decimals than it needs.
+fn to_base_units(amount: f64, decimals: u8) -> Result<U256, String> {
+    if !amount.is_finite() || amount < 0.0 {
+        return Err(format!("Invalid amount {}", amount));
+    }
 
-    // Multiplica en `U256` para evitar overflow en f64
-    let wei_per_eth = U256::from(1e18); // 10^18 en wei
-    (eth_u256 * wei_per_eth) / U256::from(1_000_000)
+    // Display of f64 never uses an exponent
+    let text = amount.to_string();
+    let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > decimals as usize {
+        return Err(format!(
+            "{} has more than the {} decimals of the token",
+            text, decimals
+        ));
+    }
+
+    // Read the digits as base units, which can overflow f64 and u128
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals as usize);
+    U256::from_str_radix(&digits, 10).map_err(|e| format!("Invalid amount {}: {}", text, e))
 }
 
-#[ic_cdk::update]
-pub async fn execute_transaction_evm(
-    request: evm_types::TransactionRequestBasic,
+fn eth_to_wei(eth: f64) -> Result<U256, String> {
+    to_base_units(eth, 18)
+}
+
+/// Left-pads an address to a 32 bytes ABI word.
+fn address_word(address: Address) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(address.as_slice());
+    word
+}
+
+fn encode_call(selector: [u8; 4], words: &[[u8; 32]]) -> Bytes {
+    let mut calldata = selector.to_vec();
+    for word in words {
+        calldata.extend_from_slice(word);
+    }
+    Bytes::from(calldata)
+}
+
+pub fn encode_erc20_transfer(to: Address, amount: U256) -> Bytes {
+    encode_call(
+        ERC20_TRANSFER_SELECTOR,
+        &[address_word(to), amount.to_be_bytes::<32>()],
+    )
+}
+
+pub(crate) fn chain_config(chain: &str) -> Result<EvmChainConfig, String> {
+    crate::get_environment()
+        .evm_chain(chain)
+        .cloned()
+        .ok_or_else(|| "Unsupported chain.".to_string())
+}
+
+/// Explorer link for a transaction when the chain has an explorer, the hash otherwise.
+pub fn transaction_reference(chain: &str, hash: &str) -> String {
+    chain_config(chain)
+        .ok()
+        .and_then(|config| config.explorer_tx_url(hash))
+        .unwrap_or_else(|| hash.to_string())
+}
+
+pub(crate) fn parse_address(address: &str) -> Result<Address, String> {
+    Address::from_str(address.trim()).map_err(|e| format!("Invalid address {}: {}", address, e))
+}
+
+/// Runs a read-only call against a contract and returns the raw result.
+async fn call_contract(chain: &str, contract: Address, calldata: Bytes) -> Result<Bytes, String> {
+    let config = IcpConfig::new(alloy_services::get_rpc_service(&chain_config(chain)?.rpc));
+    let provider = ProviderBuilder::new().on_icp(config);
+    let tx = TransactionRequest::default()
+        .with_to(contract)
+        .with_input(calldata);
+
+    provider.call(&tx).await.map_err(|e| e.to_string())
+}
+
+async fn call_uint(chain: &str, contract: Address, calldata: Bytes) -> Result<U256, String> {
+    let result = call_contract(chain, contract, calldata).await?;
+    if result.len() < 32 {
+        return Err(format!("Unexpected return data: {}", result));
+    }
+
+    Ok(U256::from_be_slice(&result[..32]))
+}
+
+pub async fn erc20_decimals(chain: &str, contract: Address) -> Result<u8, String> {
+    let decimals =
+        call_uint(chain, contract, encode_call(ERC20_DECIMALS_SELECTOR, &[])).await?;
+    u8::try_from(decimals).map_err(|_| format!("Invalid decimals: {}", decimals))
+}
+
+pub async fn native_balance(chain: &str, owner: Address) -> Result<U256, String> {
+    let config = IcpConfig::new(alloy_services::get_rpc_service(&chain_config(chain)?.rpc));
+    let provider = ProviderBuilder::new().on_icp(config);
+    provider.get_balance(owner).await.map_err(|e| e.to_string())
+}
+
+pub async fn erc20_balance_of(chain: &str, contract: Address, owner: Address) -> Result<U256, String> {
+    let calldata = encode_call(ERC20_BALANCE_OF_SELECTOR, &[address_word(owner)]);
+    call_uint(chain, contract, calldata).await
+}
+
+/// Builds the EVM transaction of an intent, returning the chain it goes to.
+pub(crate) async fn prepare_transaction(
+    transaction: &IntentRequest,
+) -> Result<(String, TransactionRequest), String> {
+    let parts: Vec<&str> = transaction.token.split(':').collect();
+    let to = parse_address(&transaction.to)?;
+
+    if let Some(TransactionPayload::ContractCall(call)) = &transaction.payload {
+        return match parts.as_slice() {
+            [chain, "native"] => Ok((
+                chain.to_string(),
+                TransactionRequest::default()
+                    .with_to(to)
+                    .with_value(eth_to_wei(transaction.amount)?)
+                    .with_input(Bytes::from(evm_abi::parse_hex(&call.calldata)?)),
+            )),
+            _ => Err(format!(
+                "Contract calls use the chain's native token, got {}",
+                transaction.token
+            )),
+        };
+    }
+
+    match parts.as_slice() {
+        [chain, "native"] => Ok((
+            chain.to_string(),
+            TransactionRequest::default()
+                .with_to(to)
+                .with_value(eth_to_wei(transaction.amount)?),
+        )),
+        [chain, "erc20", contract] => {
+            let contract = parse_address(contract)?;
+            let decimals = erc20_decimals(chain, contract).await?;
+            let calldata = encode_erc20_transfer(to, to_base_units(transaction.amount, decimals)?);
+            Ok((
+                chain.to_string(),
+                TransactionRequest::default()
+                    .with_to(contract)
+                    .with_input(calldata),
+            ))
+        }
+        _ => Err(format!("Unsupported EVM token: {}", transaction.token)),
+    }
+}
+
+/// Validates a contract call and fills in its calldata, encoded from the signature and
+/// arguments when it is not given. Given calldata must match the signature.
+pub fn complete_contract_call(call: ContractCallArgs) -> Result<ContractCallArgs, String> {
+    let signature = match &call.signature {
+        Some(signature) => FunctionSignature::parse(signature)?,
+        None => {
+            if evm_abi::parse_hex(&call.calldata)?.len() < 4 {
+                return Err("Calldata must start with a function selector".to_string());
+            }
+            if !call.args.is_empty() {
+                return Err("Arguments need a function signature".to_string());
+            }
+            return Ok(call);
+        }
+    };
+
+    let calldata = match call.calldata.trim() {
+        "" => signature.encode(&call.args)?,
+        calldata => evm_abi::parse_hex(calldata)?,
+    };
+    let args = signature.decode(&calldata)?;
+
+    Ok(ContractCallArgs {
+        calldata: format!("0x{}", hex::encode(calldata)),
+        signature: Some(signature.canonical()),
+        args,
+    })
+}
+
+/// Decodes a contract call proposal for review before approving it.
+#[ic_cdk::query]
+pub fn get_contract_call_details(proposal_id: u64) -> Result<ContractCallDetails, String> {
+    let proposal = crate::get_proposed_transaction(proposal_id)
+        .ok_or_else(|| format!("Proposal not found: {}", proposal_id))?;
+    let call = match proposal.payload {
+        Some(TransactionPayload::ContractCall(call)) => call,
+        _ => return Err(format!("Proposal {} is not a contract call", proposal_id)),
+    };
+
+    let calldata = evm_abi::parse_hex(&call.calldata)?;
+    if calldata.len() < 4 {
+        return Err("Calldata must start with a function selector".to_string());
+    }
+
+    let (function, args) = match &call.signature {
+        Some(signature) => {
+            let signature = FunctionSignature::parse(signature)?;
+            (Some(signature.canonical()), signature.decode(&calldata)?)
+        }
+        None => (
+            None,
+            calldata[4..]
+                .chunks(32)
+                .map(|word| format!("0x{}", hex::encode(word)))
+                .collect(),
+        ),
+    };
+
+    Ok(ContractCallDetails {
+        chain: proposal.token.split(':').next().unwrap_or_default().to_string(),
+        contract: proposal.to,
+        value: proposal.amount,
+        selector: format!("0x{}", hex::encode(&calldata[..4])),
+        function,
+        args,
+    })
+}
+
+/// EIP-1559 fees from the next block's base fee and the priority fees sampled from
+/// the fee history. The max fee leaves room for the base fee to double.
+pub fn eip1559_fees(next_base_fee: u128, priority_fees: &[u128]) -> (u128, u128) {
+    let mut priority_fees = priority_fees.to_vec();
+    priority_fees.sort_unstable();
+    let max_priority_fee_per_gas = priority_fees
+        .get(priority_fees.len() / 2)
+        .copied()
+        .unwrap_or(0);
+
+    (
+        next_base_fee * 2 + max_priority_fee_per_gas,
+        max_priority_fee_per_gas,
+    )
+}
+
+pub fn fee_cap(chain: &str) -> Option<u128> {
+    FEE_CAPS.with(|caps| caps.borrow().get(&chain.to_string()))
+}
+
+/// Estimates the gas limit, unless the transaction sets one, and the EIP-1559 fees.
+async fn quote_fees<T: Transport + Clone, P: Provider<T>>(
+    provider: &P,
+    chain: &str,
+    tx: &TransactionRequest,
+) -> Result<evm_types::EvmFeeQuote, String> {
+    let gas_limit = match tx.gas_limit() {
+        Some(gas_limit) => gas_limit,
+        None => provider
+            .estimate_gas(tx)
+            .await
+            .map_err(|e| format!("Could not estimate gas: {}", e))?,
+    };
+
+    let history = provider
+        .get_fee_history(
+            FEE_HISTORY_BLOCKS,
+            BlockNumberOrTag::Latest,
+            &[PRIORITY_FEE_PERCENTILE],
+        )
+        .await
+        .map_err(|e| format!("Could not get the fee history: {}", e))?;
+    let next_base_fee = history
+        .next_block_base_fee()
+        .ok_or_else(|| "The fee history has no base fee".to_string())?;
+    let priority_fees: Vec<u128> = history
+        .reward
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|rewards| rewards.first().copied())
+        .collect();
+
+    let (max_fee_per_gas, max_priority_fee_per_gas) = eip1559_fees(next_base_fee, &priority_fees);
+
+    Ok(evm_types::EvmFeeQuote {
+        chain: chain.to_string(),
+        gas_limit,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        max_fee: gas_limit.saturating_mul(max_fee_per_gas),
+        fee_cap: fee_cap(chain),
+    })
+}
+
+/// Signs and sends a transaction on the given chain from one of the vault's
+/// wallets, the main address when `wallet` is `None`.
+///
+/// The gas limit is estimated when the transaction does not set one, and the
+/// transaction is refused when its maximum fee is above the vault's cap.
+pub async fn send_transaction(
+    chain: &str,
+    wallet: Option<&str>,
+    tx: TransactionRequest,
 ) -> evm_types::TransactionResult {
+    let derivation_path = match evm_wallets::derivation_path(wallet) {
+        Ok(derivation_path) => derivation_path,
+        Err(e) => return failed(e),
+    };
     // Setup signer
-    let signer = alloy_services::create_icp_sepolia_signer().await;
+    let signer = alloy_services::create_icp_signer(derivation_path).await;
     let address = signer.address();
     // Setup provider
     let wallet = EthereumWallet::from(signer);
-    let config = match request.chain.as_str() {
-        "eth" => IcpConfig::new(alloy_services::get_rpc_service_sepolia()),
-        "base" => IcpConfig::new(alloy_services::get_rpc_service_base()),
-        "polygon" => IcpConfig::new(alloy_services::get_rpc_service_polygon()),
-        _ => {
-            return evm_types::TransactionResult {
-                hash: String::new(),
-                status: "Failed: Unsupported chain.".to_string(),
-            }
-        }
+    let chain = match chain_config(chain) {
+        Ok(chain) => chain,
+        Err(e) => return failed(e),
     };
-    let provider = ProviderBuilder::new()
-        .with_gas_estimation()
-        .wallet(wallet)
-        .on_icp(config);
-
-    // Attempt to get nonce from thread-local storage
-    let maybe_nonce = NONCE.with_borrow(|maybe_nonce| {
-        // If a nonce exists, the next nonce to use is latest nonce + 1
-        maybe_nonce.map(|nonce| nonce + 1)
-    });
+    let config = IcpConfig::new(alloy_services::get_rpc_service(&chain.rpc));
+    let provider = ProviderBuilder::new().wallet(wallet).on_icp(config);
 
-    // If no nonce exists, get it from the provider
-    let nonce = if let Some(nonce) = maybe_nonce {
-        nonce
-    } else {
-        provider.get_transaction_count(address).await.unwrap_or(0)
+    let key = nonce_key(&chain.chain, &address);
+    let _guard = match NonceGuard::acquire(key.clone()).await {
+        Ok(guard) => guard,
+        Err(e) => return failed(e),
     };
 
-    let chain_id = match request.chain.as_str() {
-        "eth" => 11155111,
-        "base" => 84532,
-        "polygon" => 80002,
-        _ => 0,
+    // Without a stored nonce, e.g. after a failed send, start from the chain's count
+    let nonce = match NONCES.with(|nonces| nonces.borrow().get(&key)) {
+        Some(nonce) => nonce,
+        None => match provider.get_transaction_count(address).await {
+            Ok(count) => count,
+            Err(e) => return failed(format!("Could not get the nonce: {}", e)),
+        },
     };
 
-    let tx = TransactionRequest::default()
-        .with_to(Address::from_str(&request.to).unwrap())
-        .with_value(eth_to_wei(request.value.parse::<f64>().unwrap()))
+    let tx = tx
+        .with_from(address)
         .with_nonce(nonce)
-        .with_gas_limit(21_000)
-        .with_chain_id(chain_id);
+        .with_chain_id(chain.chain_id);
 
-    let transport_result = provider.send_transaction(tx.clone()).await;
+    let quote = match quote_fees(&provider, &chain.chain, &tx).await {
+        Ok(quote) => quote,
+        Err(e) => return failed(e),
+    };
+    if let Some(cap) = quote.fee_cap.filter(|cap| quote.max_fee > *cap) {
+        return failed(format!(
+            "The maximum fee of {} wei is above the vault's cap of {} wei on {}.",
+            quote.max_fee, cap, chain.chain
+        ));
+    }
+
+    let tx = tx
+        .with_gas_limit(quote.gas_limit)
+        .with_max_fee_per_gas(quote.max_fee_per_gas)
+        .with_max_priority_fee_per_gas(quote.max_priority_fee_per_gas);
+
+    let transport_result = provider.send_transaction(tx).await;
     match transport_result {
         Ok(builder) => {
-            let node_hash = *builder.tx_hash();
-            let tx_response = provider.get_transaction_by_hash(node_hash).await.unwrap();
-
-            match tx_response {
-                Some(tx) => {
-                    // The transaction has been mined and included in a block, the nonce
-                    // has been consumed. Save it to thread-local storage. Next transaction
-                    // for this address will use a nonce that is = this nonce + 1
-                    NONCE.with_borrow_mut(|nonce| {
-                        *nonce = Some(tx.nonce);
-                    });
-                    evm_types::TransactionResult {
-                        hash: format!("{:?}", tx.hash),
-                        status: "Success".to_string(),
-                    }
-                }
-                None => evm_types::TransactionResult {
-                    hash: String::new(),
-                    status: "Failed: Could not get transaction.".to_string(),
-                },
+            // The node accepted the transaction, so its nonce is taken even if it is
+            // still pending.
+            NONCES.with(|nonces| nonces.borrow_mut().insert(key, nonce + 1));
+            let hash = format!("{:?}", builder.tx_hash());
+            evm_confirmations::record_sent_transaction(chain.chain.clone(), hash.clone());
+            evm_types::TransactionResult {
+                hash,
+                status: "Success".to_string(),
             }
         }
-        Err(_e) => evm_types::TransactionResult {
-            hash: String::new(),
-            status: "Failed: Could not get transaction.".to_string(),
-        },
+        Err(e) => {
+            // The stored nonce may be out of sync with the chain, reconcile it
+            // on the next send.
+            NONCES.with(|nonces| nonces.borrow_mut().remove(&key));
+            failed(format!("Could not send transaction: {}", e))
+        }
+    }
+}
+
+/// Quotes the gas and fees of an EVM proposal before it is executed.
+#[ic_cdk::update]
+pub async fn quote_evm_transaction(proposal_id: u64) -> Result<evm_types::EvmFeeQuote, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+    let proposal = crate::get_proposed_transaction(proposal_id)
+        .ok_or_else(|| format!("Proposal not found: {}", proposal_id))?;
+    let transaction = IntentRequest {
+        transaction_type: proposal.transaction_type,
+        amount: proposal.amount,
+        token: proposal.token,
+        to: proposal.to,
+        network: proposal.network,
+        payload: proposal.payload,
+        wallet: proposal.wallet,
+    };
+
+    let (chain, tx) = prepare_transaction(&transaction).await?;
+    let config = chain_config(&chain)?;
+    let derivation_path = evm_wallets::derivation_path(transaction.wallet.as_deref())?;
+    let address = alloy_services::evm_address(derivation_path).await;
+    let provider =
+        ProviderBuilder::new().on_icp(IcpConfig::new(alloy_services::get_rpc_service(&config.rpc)));
+
+    quote_fees(&provider, &config.chain, &tx.with_from(address)).await
+}
+
+fn store_fee_cap(chain: String, cap: Option<u128>) {
+    FEE_CAPS.with(|caps| match cap {
+        Some(cap) => caps.borrow_mut().insert(chain, cap),
+        None => caps.borrow_mut().remove(&chain),
+    });
+}
+
+/// Whether `cap` only tightens `current`, which no cap at all is the loosest of.
+fn lowers_fee_cap(current: Option<u128>, cap: Option<u128>) -> bool {
+    match (current, cap) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(current), Some(cap)) => cap <= current,
+    }
+}
+
+/// Sets the maximum fee in wei the vault pays for a transaction on a chain. Any signer
+/// can lower it, raising or removing it takes a `SetFeeCap` proposal.
+#[ic_cdk::update]
+pub fn set_evm_fee_cap(chain: String, cap: Option<u128>) -> Result<(), String> {
+    if !signer_exists(ic_cdk::caller()) {
+        ic_cdk::trap("Caller is not a signer");
+    }
+
+    let chain = chain_config(&chain)?.chain;
+    if !lowers_fee_cap(fee_cap(&chain), cap) {
+        return Err(format!(
+            "Raising or removing the fee cap of {} takes a SetFeeCap proposal",
+            chain
+        ));
     }
+    store_fee_cap(chain, cap);
+    Ok(())
+}
+
+#[ic_cdk::query]
+pub fn get_evm_fee_caps() -> Vec<(String, u128)> {
+    FEE_CAPS.with(|caps| caps.borrow().iter().collect())
+}
+
+/// Resynchronizes the nonce of a wallet on a chain with the chain's transaction
+/// count, e.g. to replace a transaction that is stuck in the mempool.
+#[ic_cdk::update]
+pub async fn reset_evm_nonce(chain: String, wallet: Option<String>) -> Result<u64, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        ic_cdk::trap("Caller is not a signer");
+    }
+
+    let config = chain_config(&chain)?;
+    let derivation_path = evm_wallets::derivation_path(wallet.as_deref())?;
+    let address = alloy_services::evm_address(derivation_path).await;
+    let key = nonce_key(&config.chain, &address);
+    let _guard = NonceGuard::acquire(key.clone()).await?;
+
+    let provider =
+        ProviderBuilder::new().on_icp(IcpConfig::new(alloy_services::get_rpc_service(&config.rpc)));
+    let count = provider
+        .get_transaction_count(address)
+        .await
+        .map_err(|e| format!("Could not get the nonce: {}", e))?;
+
+    NONCES.with(|nonces| nonces.borrow_mut().insert(key, count));
+    Ok(count)
+}
+
+/// Next nonce of each chain and address the vault has sent from.
+#[ic_cdk::query]
+pub fn get_evm_nonces() -> Vec<(String, u64)> {
+    NONCES.with(|nonces| nonces.borrow().iter().collect())
 }
 
 #[ic_cdk::update]
 pub async fn get_balance(chain: String) -> String {
-    let address = alloy_services::create_icp_sepolia_signer().await.address();
-    let config = match chain.as_str() {
-        "eth" => IcpConfig::new(alloy_services::get_rpc_service_sepolia()),
-        "base" => IcpConfig::new(alloy_services::get_rpc_service_base()),
-        "polygon" => IcpConfig::new(alloy_services::get_rpc_service_polygon()),
-        _ => {
+    if !signer_exists(ic_cdk::caller()) {
+        ic_cdk::trap("Caller is not a signer");
+    }
+    let address = alloy_services::evm_address(vec![]).await;
+    let config = match crate::get_environment().evm_chain(&chain) {
+        Some(chain) => IcpConfig::new(alloy_services::get_rpc_service(&chain.rpc)),
+        None => {
             return "Unsupported chain.".to_string();
         }
     };
@@ -142,3 +635,239 @@ pub async fn get_balance(chain: String) -> String {
         Err(e) => e.to_string(),
     }
 }
+
+#[ic_cdk::update]
+pub async fn get_erc20_balance(chain: String, contract: String) -> Result<String, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+    let contract = parse_address(&contract)?;
+    let address = alloy_services::evm_address(vec![]).await;
+    let balance = erc20_balance_of(&chain, contract, address).await?;
+    Ok(balance.to_string())
+}
+
+#[ic_cdk::update]
+pub async fn get_erc20_decimals(chain: String, contract: String) -> Result<u8, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+    erc20_decimals(&chain, parse_address(&contract)?).await
+}
+
+/// ERC-20 transfers for token paths like `eth:erc20:<contract address>`, on any
+/// configured EVM chain.
+#[derive(Clone)]
+pub struct ERC20TransferAdapter {}
+
+impl ERC20TransferAdapter {
+    pub fn new() -> ERC20TransferAdapter {
+        ERC20TransferAdapter {}
+    }
+
+    async fn transfer(&self, transaction: &IntentRequest) -> Result<String, String> {
+        let (chain, tx) = prepare_transaction(transaction).await?;
+
+        let result = send_transaction(&chain, transaction.wallet.as_deref(), tx).await;
+        match result.status.as_str() {
+            "Success" => Ok(transaction_reference(&chain, &result.hash)),
+            _ => Err(result.status),
+        }
+    }
+}
+
+impl BlockchainAdapter for ERC20TransferAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a IntentRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ERC20TransferAdapter");
+            match self.transfer(transaction).await {
+                Ok(hash) => Ok(IntentStatus::InProgress(
+                    "Sent an ERC-20 transfer, waiting for confirmations: ".to_string() + &hash,
+                )),
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+/// Calls a contract with the calldata of a `ContractCall` payload, on any configured
+/// EVM chain.
+#[derive(Clone)]
+pub struct EVMContractCallAdapter {}
+
+impl EVMContractCallAdapter {
+    pub fn new() -> EVMContractCallAdapter {
+        EVMContractCallAdapter {}
+    }
+}
+
+impl BlockchainAdapter for EVMContractCallAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a IntentRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing EVMContractCallAdapter");
+            if !matches!(transaction.payload, Some(TransactionPayload::ContractCall(_))) {
+                return Err("Contract calls require a ContractCall payload".to_string());
+            }
+
+            let (chain, tx) = prepare_transaction(transaction).await?;
+            let result = send_transaction(&chain, transaction.wallet.as_deref(), tx).await;
+            match result.status.as_str() {
+                "Success" => Ok(IntentStatus::InProgress(
+                    "Sent a contract call, waiting for confirmations: ".to_string()
+                        + &transaction_reference(&chain, &result.hash),
+                )),
+                _ => Err(result.status),
+            }
+        })
+    }
+}
+
+/// Sets or removes the fee cap of its chain once a `SetFeeCap` proposal is approved.
+#[derive(Clone)]
+pub struct EVMSetFeeCapAdapter {
+    chain: String,
+}
+
+impl EVMSetFeeCapAdapter {
+    pub fn new(chain: String) -> EVMSetFeeCapAdapter {
+        EVMSetFeeCapAdapter { chain }
+    }
+}
+
+impl BlockchainAdapter for EVMSetFeeCapAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a IntentRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing EVMSetFeeCapAdapter on {}", self.chain);
+            let cap = match transaction.payload {
+                Some(TransactionPayload::FeeCap(cap)) => cap,
+                _ => return Err("Setting a fee cap requires a FeeCap payload".to_string()),
+            };
+
+            store_fee_cap(self.chain.clone(), cap);
+            Ok(IntentStatus::Completed(match cap {
+                Some(cap) => format!("Capped fees on {} at {} wei", self.chain, cap),
+                None => format!("Removed the fee cap of {}", self.chain),
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserves_nonces_until_the_guard_is_dropped() {
+        let guard = NonceGuard::try_acquire("eth:0xdead").unwrap();
+        assert!(NonceGuard::try_acquire("eth:0xdead").is_none());
+        // Other chains and addresses are independent
+        let other = NonceGuard::try_acquire("base:0xdead").unwrap();
+
+        drop(guard);
+        assert!(NonceGuard::try_acquire("eth:0xdead").is_some());
+        drop(other);
+        assert!(NONCE_LOCKS.with(|locks| locks.borrow().is_empty()));
+    }
+
+    #[test]
+    fn links_transactions_to_the_explorer_of_their_chain() {
+        let mut environment = keygate_core::types::environment::VaultEnvironment::local();
+        environment.evm_chains[2].explorer_url = None;
+        crate::ENVIRONMENT.with(|e| e.borrow_mut().set(environment).unwrap());
+
+        assert_eq!(
+            transaction_reference("base", "0xabc"),
+            "https://sepolia.basescan.org/tx/0xabc"
+        );
+        // Chains without an explorer, or unknown ones, keep the bare hash
+        assert_eq!(transaction_reference("polygon", "0xabc"), "0xabc");
+        assert_eq!(transaction_reference("avalanche", "0xabc"), "0xabc");
+    }
+
+    #[test]
+    fn lets_signers_only_lower_fee_caps() {
+        assert!(lowers_fee_cap(None, Some(1_000)));
+        assert!(lowers_fee_cap(Some(1_000), Some(1_000)));
+        assert!(lowers_fee_cap(Some(1_000), Some(500)));
+        assert!(!lowers_fee_cap(Some(1_000), Some(1_001)));
+        assert!(!lowers_fee_cap(Some(1_000), None));
+        assert!(!lowers_fee_cap(None, None));
+    }
+
+    #[test]
+    fn encodes_erc20_transfer_calldata() {
+        let to = Address::from_str("0x000000000000000000000000000000000000dEaD").unwrap();
+        let calldata = encode_erc20_transfer(to, U256::from(1_000_000u64));
+
+        assert_eq!(
+            calldata.to_string(),
+            "0xa9059cbb\
+             000000000000000000000000000000000000000000000000000000000000dead\
+             00000000000000000000000000000000000000000000000000000000000f4240"
+        );
+    }
+
+    #[test]
+    fn completes_contract_call_calldata() {
+        let call = complete_contract_call(ContractCallArgs {
+            calldata: String::new(),
+            signature: Some("transfer(address, uint)".to_string()),
+            args: vec![
+                "0x000000000000000000000000000000000000dEaD".to_string(),
+                "1000000".to_string(),
+            ],
+        })
+        .unwrap();
+
+        assert_eq!(call.signature, Some("transfer(address,uint256)".to_string()));
+        assert_eq!(
+            call.calldata,
+            encode_erc20_transfer(
+                Address::from_str("0x000000000000000000000000000000000000dEaD").unwrap(),
+                U256::from(1_000_000u64)
+            )
+            .to_string()
+        );
+
+        assert!(complete_contract_call(ContractCallArgs {
+            calldata: "0x1234".to_string(),
+            signature: None,
+            args: vec![],
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn estimates_eip1559_fees_from_history() {
+        assert_eq!(eip1559_fees(100, &[3, 1, 2]), (202, 2));
+        assert_eq!(eip1559_fees(100, &[]), (200, 0));
+    }
+
+    #[test]
+    fn converts_amounts_to_base_units() {
+        assert_eq!(to_base_units(1.5, 6), Ok(U256::from(1_500_000u64)));
+        assert_eq!(to_base_units(2.0, 18), Ok(U256::from(2_000_000_000_000_000_000u128)));
+        assert_eq!(to_base_units(0.0, 6), Ok(U256::ZERO));
+        // Digits past 6 decimals used to be dropped
+        assert_eq!(to_base_units(0.123456789, 18), Ok(U256::from(123_456_789_000_000_000u64)));
+        assert_eq!(to_base_units(1e21, 0), Ok(U256::from(10u64).pow(U256::from(21))));
+    }
+
+    #[test]
+    fn rejects_amounts_the_token_cannot_represent() {
+        // 0.25 of a token without decimals used to round to zero
+        assert!(to_base_units(0.25, 0).is_err());
+        assert!(to_base_units(0.1234567, 6).is_err());
+        assert!(to_base_units(-1.0, 18).is_err());
+        assert!(to_base_units(f64::NAN, 18).is_err());
+    }
+}
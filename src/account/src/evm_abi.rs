@@ -0,0 +1,323 @@
+use std::str::FromStr;
+
+use alloy::primitives::{keccak256, Address, U256};
+
+/// Solidity types supported in contract call proposals. Tuples and arrays are not.
+#[derive(Debug, Clone, PartialEq)]
+enum AbiType {
+    Address,
+    Bool,
+    Uint(usize),
+    Int(usize),
+    FixedBytes(usize),
+    Bytes,
+    String,
+}
+
+impl AbiType {
+    fn parse(name: &str) -> Result<AbiType, String> {
+        let bits = |digits: &str| -> Result<usize, String> {
+            match digits {
+                "" => Ok(256),
+                digits => match digits.parse::<usize>() {
+                    Ok(bits) if bits > 0 && bits <= 256 && bits % 8 == 0 => Ok(bits),
+                    _ => Err(format!("Invalid ABI type: {}", name)),
+                },
+            }
+        };
+
+        match name {
+            "address" => Ok(AbiType::Address),
+            "bool" => Ok(AbiType::Bool),
+            "bytes" => Ok(AbiType::Bytes),
+            "string" => Ok(AbiType::String),
+            _ if name.starts_with("uint") => Ok(AbiType::Uint(bits(&name[4..])?)),
+            _ if name.starts_with("int") => Ok(AbiType::Int(bits(&name[3..])?)),
+            _ if name.starts_with("bytes") => match name[5..].parse::<usize>() {
+                Ok(size) if size > 0 && size <= 32 => Ok(AbiType::FixedBytes(size)),
+                _ => Err(format!("Invalid ABI type: {}", name)),
+            },
+            _ => Err(format!("Unsupported ABI type: {}", name)),
+        }
+    }
+
+    fn canonical(&self) -> String {
+        match self {
+            AbiType::Address => "address".to_string(),
+            AbiType::Bool => "bool".to_string(),
+            AbiType::Uint(bits) => format!("uint{}", bits),
+            AbiType::Int(bits) => format!("int{}", bits),
+            AbiType::FixedBytes(size) => format!("bytes{}", size),
+            AbiType::Bytes => "bytes".to_string(),
+            AbiType::String => "string".to_string(),
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        matches!(self, AbiType::Bytes | AbiType::String)
+    }
+}
+
+/// A function signature such as `approve(address,uint256)`.
+pub struct FunctionSignature {
+    name: String,
+    inputs: Vec<AbiType>,
+}
+
+impl FunctionSignature {
+    pub fn parse(signature: &str) -> Result<FunctionSignature, String> {
+        let signature: String = signature.chars().filter(|c| !c.is_whitespace()).collect();
+        let (name, inputs) = signature
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| format!("Invalid function signature: {}", signature))?;
+
+        if name.is_empty() || inputs.contains('(') {
+            return Err(format!("Invalid function signature: {}", signature));
+        }
+
+        let inputs = match inputs {
+            "" => vec![],
+            inputs => inputs
+                .split(',')
+                .map(AbiType::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        Ok(FunctionSignature {
+            name: name.to_string(),
+            inputs,
+        })
+    }
+
+    /// The signature with canonical type names, e.g. `uint` becomes `uint256`.
+    pub fn canonical(&self) -> String {
+        let inputs: Vec<String> = self.inputs.iter().map(AbiType::canonical).collect();
+        format!("{}({})", self.name, inputs.join(","))
+    }
+
+    pub fn selector(&self) -> [u8; 4] {
+        let hash = keccak256(self.canonical().as_bytes());
+        [hash[0], hash[1], hash[2], hash[3]]
+    }
+
+    /// ABI-encodes a call from its arguments in text form.
+    pub fn encode(&self, args: &[String]) -> Result<Vec<u8>, String> {
+        if args.len() != self.inputs.len() {
+            return Err(format!(
+                "{} expects {} arguments, got {}",
+                self.canonical(),
+                self.inputs.len(),
+                args.len()
+            ));
+        }
+
+        let mut head: Vec<u8> = vec![];
+        let mut tail: Vec<u8> = vec![];
+        let head_size = 32 * self.inputs.len();
+
+        for (kind, arg) in self.inputs.iter().zip(args) {
+            if kind.is_dynamic() {
+                let data = match kind {
+                    AbiType::Bytes => parse_hex(arg)?,
+                    _ => arg.as_bytes().to_vec(),
+                };
+                head.extend_from_slice(&U256::from(head_size + tail.len()).to_be_bytes::<32>());
+                tail.extend_from_slice(&U256::from(data.len()).to_be_bytes::<32>());
+                tail.extend_from_slice(&pad_right(&data));
+            } else {
+                head.extend_from_slice(&encode_static(kind, arg)?);
+            }
+        }
+
+        let mut calldata = self.selector().to_vec();
+        calldata.extend(head);
+        calldata.extend(tail);
+        Ok(calldata)
+    }
+
+    /// Decodes the arguments of a call to this function back to text.
+    pub fn decode(&self, calldata: &[u8]) -> Result<Vec<String>, String> {
+        if calldata.len() < 4 || calldata[..4] != self.selector() {
+            return Err(format!(
+                "Calldata does not call {}",
+                self.canonical()
+            ));
+        }
+
+        let data = &calldata[4..];
+        self.inputs
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| {
+                let word = read_word(data, 32 * i)?;
+                if kind.is_dynamic() {
+                    let offset = word_to_usize(&word)?;
+                    let length = word_to_usize(&read_word(data, offset)?)?;
+                    let bytes = data
+                        .get(offset + 32..offset + 32 + length)
+                        .ok_or_else(|| "Calldata is too short".to_string())?;
+                    Ok(match kind {
+                        AbiType::Bytes => format!("0x{}", hex::encode(bytes)),
+                        _ => String::from_utf8_lossy(bytes).to_string(),
+                    })
+                } else {
+                    Ok(decode_static(kind, &word))
+                }
+            })
+            .collect()
+    }
+}
+
+/// Encodes a value of a static type as its 32 bytes word, as EIP-712 hashing does.
+pub fn encode_word(type_name: &str, value: &str) -> Result<[u8; 32], String> {
+    let kind = AbiType::parse(type_name)?;
+    if kind.is_dynamic() {
+        return Err(format!("{} is not a static type", type_name));
+    }
+    encode_static(&kind, value)
+}
+
+pub fn parse_hex(value: &str) -> Result<Vec<u8>, String> {
+    let value = value.trim();
+    hex::decode(value.strip_prefix("0x").unwrap_or(value))
+        .map_err(|e| format!("Invalid hex {}: {}", value, e))
+}
+
+fn pad_right(data: &[u8]) -> Vec<u8> {
+    let mut padded = data.to_vec();
+    padded.resize(data.len().div_ceil(32) * 32, 0);
+    padded
+}
+
+fn parse_uint(value: &str) -> Result<U256, String> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16),
+        None => U256::from_str_radix(value, 10),
+    }
+    .map_err(|e| format!("Invalid integer {}: {}", value, e))
+}
+
+fn encode_static(kind: &AbiType, arg: &str) -> Result<[u8; 32], String> {
+    let arg = arg.trim();
+    let mut word = [0u8; 32];
+
+    match kind {
+        AbiType::Address => {
+            let address =
+                Address::from_str(arg).map_err(|e| format!("Invalid address {}: {}", arg, e))?;
+            word[12..].copy_from_slice(address.as_slice());
+        }
+        AbiType::Bool => match arg {
+            "true" => word[31] = 1,
+            "false" => (),
+            _ => return Err(format!("Invalid bool: {}", arg)),
+        },
+        AbiType::Uint(bits) => {
+            let value = parse_uint(arg)?;
+            if *bits < 256 && value >= U256::from(1) << *bits {
+                return Err(format!("{} does not fit in uint{}", arg, bits));
+            }
+            word = value.to_be_bytes::<32>();
+        }
+        AbiType::Int(bits) => {
+            let (negative, magnitude) = match arg.strip_prefix('-') {
+                Some(magnitude) => (true, parse_uint(magnitude)?),
+                None => (false, parse_uint(arg)?),
+            };
+            let limit = U256::from(1) << (*bits - 1);
+            if (!negative && magnitude >= limit) || (negative && magnitude > limit) {
+                return Err(format!("{} does not fit in int{}", arg, bits));
+            }
+            // Two's complement
+            let value = if negative {
+                U256::ZERO.wrapping_sub(magnitude)
+            } else {
+                magnitude
+            };
+            word = value.to_be_bytes::<32>();
+        }
+        AbiType::FixedBytes(size) => {
+            let bytes = parse_hex(arg)?;
+            if bytes.len() > *size {
+                return Err(format!("{} does not fit in bytes{}", arg, size));
+            }
+            word[..bytes.len()].copy_from_slice(&bytes);
+        }
+        AbiType::Bytes | AbiType::String => unreachable!("dynamic types are encoded in the tail"),
+    }
+
+    Ok(word)
+}
+
+fn decode_static(kind: &AbiType, word: &[u8; 32]) -> String {
+    let value = U256::from_be_bytes(*word);
+    match kind {
+        AbiType::Address => Address::from_slice(&word[12..]).to_string(),
+        AbiType::Bool => (value != U256::ZERO).to_string(),
+        AbiType::Uint(_) => value.to_string(),
+        AbiType::Int(_) if value.bit(255) => format!("-{}", U256::ZERO.wrapping_sub(value)),
+        AbiType::Int(_) => value.to_string(),
+        AbiType::FixedBytes(size) => format!("0x{}", hex::encode(&word[..*size])),
+        AbiType::Bytes | AbiType::String => unreachable!("dynamic types are decoded from the tail"),
+    }
+}
+
+fn read_word(data: &[u8], offset: usize) -> Result<[u8; 32], String> {
+    data.get(offset..offset + 32)
+        .and_then(|word| word.try_into().ok())
+        .ok_or_else(|| "Calldata is too short".to_string())
+}
+
+fn word_to_usize(word: &[u8; 32]) -> Result<usize, String> {
+    usize::try_from(U256::from_be_bytes(*word)).map_err(|_| "Invalid offset in calldata".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_decodes_static_arguments() {
+        let signature = FunctionSignature::parse("approve(address, uint)").unwrap();
+        assert_eq!(signature.canonical(), "approve(address,uint256)");
+        assert_eq!(signature.selector(), [0x09, 0x5e, 0xa7, 0xb3]);
+
+        let args = vec![
+            "0x000000000000000000000000000000000000dEaD".to_string(),
+            "1000".to_string(),
+        ];
+        let calldata = signature.encode(&args).unwrap();
+        assert_eq!(calldata.len(), 4 + 64);
+        assert_eq!(signature.decode(&calldata).unwrap(), args);
+    }
+
+    #[test]
+    fn encodes_and_decodes_dynamic_arguments() {
+        let signature = FunctionSignature::parse("vote(uint256,int8,string,bytes)").unwrap();
+        let args = vec![
+            "7".to_string(),
+            "-2".to_string(),
+            "For".to_string(),
+            "0x0102".to_string(),
+        ];
+
+        let calldata = signature.encode(&args).unwrap();
+        // 4 head words, then length and data words for each dynamic argument
+        assert_eq!(calldata.len(), 4 + 32 * 8);
+        assert_eq!(signature.decode(&calldata).unwrap(), args);
+    }
+
+    #[test]
+    fn rejects_out_of_range_arguments() {
+        let signature = FunctionSignature::parse("set(uint8,int8)").unwrap();
+        assert!(signature
+            .encode(&["256".to_string(), "0".to_string()])
+            .is_err());
+        assert!(signature
+            .encode(&["0".to_string(), "-129".to_string()])
+            .is_err());
+        assert!(signature.encode(&["0".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::{borrow::Cow, cell::RefCell, str::FromStr, time::Duration};
+
+use alloy::{
+    primitives::TxHash,
+    providers::{Provider, ProviderBuilder},
+    transports::icp::IcpConfig,
+};
+use candid::CandidType;
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use keygate_core::types::vault::EvmReceipt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alloy_services, evm,
+    intent::{IntentStatus, TransactionUpdate},
+    EVM_PENDING_CONFIRMATIONS_MEMORY, MEMORY_MANAGER, TRANSACTION_UPDATES, VM,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Used for chains that do not configure their confirmations.
+const DEFAULT_CONFIRMATIONS: u64 = 12;
+/// Polls without a receipt before a transaction is considered stuck, about 2 hours.
+const MAX_POLLS: u32 = 240;
+
+/// An EVM transaction sent by the vault that is not confirmed yet.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct PendingConfirmation {
+    chain: String,
+    hash: String,
+    polls: u32,
+}
+
+impl Storable for PendingConfirmation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    /// Pending EVM transactions, keyed by their index in `TRANSACTIONS`.
+    static PENDING: RefCell<StableBTreeMap<u64, PendingConfirmation, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(EVM_PENDING_CONFIRMATIONS_MEMORY)))
+    );
+
+    /// Chain and hash of the transaction sent by the running execution.
+    ///
+    /// Set right after the send, which is the adapter's last await, and taken by
+    /// `execute_transaction` once the record is appended. No other message can run
+    /// in between.
+    static LAST_SENT: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+
+    static POLL_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static POLLING: RefCell<bool> = const { RefCell::new(false) };
+}
+
+pub fn record_sent_transaction(chain: String, hash: String) {
+    LAST_SENT.with(|sent| *sent.borrow_mut() = Some((chain, hash)));
+}
+
+pub fn take_sent_transaction() -> Option<(String, String)> {
+    LAST_SENT.with(|sent| sent.borrow_mut().take())
+}
+
+/// Follows a sent transaction until it is confirmed or reverts.
+pub fn track_confirmations(transaction_index: u64, chain: String, hash: String) {
+    PENDING.with(|pending| {
+        pending.borrow_mut().insert(
+            transaction_index,
+            PendingConfirmation {
+                chain,
+                hash,
+                polls: 0,
+            },
+        )
+    });
+    start_polling();
+}
+
+/// Starts the poll timer when transactions are pending. Timers do not survive
+/// upgrades, so this also runs in `post_upgrade`.
+pub fn start_polling() {
+    let idle = PENDING.with(|pending| pending.borrow().is_empty());
+    if idle || POLL_TIMER.with(|timer| timer.borrow().is_some()) {
+        return;
+    }
+
+    let timer = ic_cdk_timers::set_timer_interval(POLL_INTERVAL, || {
+        ic_cdk::spawn(poll_confirmations())
+    });
+    POLL_TIMER.with(|t| *t.borrow_mut() = Some(timer));
+}
+
+fn stop_polling() {
+    if let Some(timer) = POLL_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer);
+    }
+}
+
+/// Status of a transaction given its receipt and the confirmations it needs.
+pub fn receipt_status(receipt: &EvmReceipt, required_confirmations: u64) -> IntentStatus {
+    let reference = evm::transaction_reference(&receipt.chain, &receipt.hash);
+
+    if !receipt.success {
+        IntentStatus::Failed(format!(
+            "Transaction reverted in block {}: {}",
+            receipt.block_number, reference
+        ))
+    } else if receipt.confirmations >= required_confirmations {
+        IntentStatus::Completed(format!(
+            "Confirmed in block {} with {} confirmations: {}",
+            receipt.block_number, receipt.confirmations, reference
+        ))
+    } else {
+        IntentStatus::InProgress(format!(
+            "Included in block {}, {} of {} confirmations: {}",
+            receipt.block_number, receipt.confirmations, required_confirmations, reference
+        ))
+    }
+}
+
+/// Fetches the receipt of a transaction, `None` while it is not in a block.
+async fn fetch_receipt(pending: &PendingConfirmation) -> Result<Option<(EvmReceipt, u64)>, String> {
+    let config = evm::chain_config(&pending.chain)?;
+    let provider =
+        ProviderBuilder::new().on_icp(IcpConfig::new(alloy_services::get_rpc_service(&config.rpc)));
+    let hash = TxHash::from_str(&pending.hash)
+        .map_err(|e| format!("Invalid transaction hash {}: {}", pending.hash, e))?;
+
+    let receipt = match provider
+        .get_transaction_receipt(hash)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        Some(receipt) => receipt,
+        None => return Ok(None),
+    };
+    let block_number = match receipt.block_number {
+        Some(block_number) => block_number,
+        None => return Ok(None),
+    };
+    let latest_block = provider.get_block_number().await.map_err(|e| e.to_string())?;
+
+    let receipt = EvmReceipt {
+        chain: pending.chain.clone(),
+        hash: pending.hash.clone(),
+        success: receipt.status(),
+        gas_used: receipt.gas_used,
+        effective_gas_price: receipt.effective_gas_price,
+        block_number,
+        confirmations: latest_block.saturating_sub(block_number) + 1,
+    };
+
+    Ok(Some((
+        receipt,
+        config.confirmations.unwrap_or(DEFAULT_CONFIRMATIONS),
+    )))
+}
+
+/// Marks a polling round as running, so a slow round doesn't overlap with the next
+/// tick. Released when dropped, including when the round traps after an await.
+struct PollGuard;
+
+impl PollGuard {
+    fn try_acquire() -> Option<PollGuard> {
+        (!POLLING.with(|polling| polling.replace(true))).then_some(PollGuard)
+    }
+}
+
+impl Drop for PollGuard {
+    fn drop(&mut self) {
+        POLLING.with(|polling| polling.replace(false));
+    }
+}
+
+async fn poll_confirmations() {
+    let Some(guard) = PollGuard::try_acquire() else {
+        return;
+    };
+
+    let pending: Vec<(u64, PendingConfirmation)> =
+        PENDING.with(|pending| pending.borrow().iter().collect());
+
+    for (index, mut confirmation) in pending {
+        confirmation.polls += 1;
+
+        let (update, finished) = match fetch_receipt(&confirmation).await {
+            Ok(Some((receipt, required_confirmations))) => {
+                let status = receipt_status(&receipt, required_confirmations);
+                let finished = !matches!(status, IntentStatus::InProgress(_));
+                let update = TransactionUpdate {
+                    status,
+                    receipt: Some(receipt),
+                };
+                (Some(update), finished)
+            }
+            Ok(None) if confirmation.polls >= MAX_POLLS => {
+                let update = TransactionUpdate {
+                    status: IntentStatus::Failed(format!(
+                        "Not included in a block after {} checks, reset the nonce to replace it: {}",
+                        confirmation.polls,
+                        evm::transaction_reference(&confirmation.chain, &confirmation.hash)
+                    )),
+                    receipt: None,
+                };
+                (Some(update), true)
+            }
+            Ok(None) => (None, false),
+            Err(e) => {
+                ic_cdk::println!("Failed to check transaction {}: {}", confirmation.hash, e);
+                (None, false)
+            }
+        };
+
+        if let Some(update) = update {
+            TRANSACTION_UPDATES.with(|updates| updates.borrow_mut().insert(index, update));
+        }
+
+        PENDING.with(|pending| {
+            let mut pending = pending.borrow_mut();
+            if finished {
+                pending.remove(&index);
+            } else {
+                pending.insert(index, confirmation);
+            }
+        });
+    }
+
+    drop(guard);
+
+    if PENDING.with(|pending| pending.borrow().is_empty()) {
+        stop_polling();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(success: bool, confirmations: u64) -> EvmReceipt {
+        EvmReceipt {
+            chain: "unknown".to_string(),
+            hash: "0x01".to_string(),
+            success,
+            gas_used: 21_000,
+            effective_gas_price: 1,
+            block_number: 100,
+            confirmations,
+        }
+    }
+
+    #[test]
+    fn receipt_status_follows_confirmations() {
+        assert!(matches!(
+            receipt_status(&receipt(true, 2), 3),
+            IntentStatus::InProgress(_)
+        ));
+        assert!(matches!(
+            receipt_status(&receipt(true, 3), 3),
+            IntentStatus::Completed(_)
+        ));
+        assert!(matches!(
+            receipt_status(&receipt(false, 1), 3),
+            IntentStatus::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn runs_one_polling_round_at_a_time() {
+        let guard = PollGuard::try_acquire().unwrap();
+        assert!(PollGuard::try_acquire().is_none());
+
+        drop(guard);
+        assert!(PollGuard::try_acquire().is_some());
+        assert!(!POLLING.with(|polling| *polling.borrow()));
+    }
+}
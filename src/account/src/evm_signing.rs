@@ -0,0 +1,175 @@
+use std::{cell::RefCell, future::Future, pin::Pin};
+
+use alloy::{
+    primitives::{eip191_hash_message, B256},
+    signers::Signer,
+};
+use ic_stable_structures::StableBTreeMap;
+use keygate_core::types::vault::{MessageSignature, MessageToSign, TransactionPayload};
+
+use crate::{
+    alloy_services,
+    eip712::TypedData,
+    evm_abi::parse_hex,
+    evm_wallets,
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest, MAX_PAYLOAD_BYTES},
+    MEMORY_MANAGER, MESSAGE_SIGNATURES_MEMORY, VM,
+};
+
+/// The message is the whole payload of its proposal.
+const MAX_MESSAGE_BYTES: usize = MAX_PAYLOAD_BYTES;
+
+thread_local! {
+    /// Signatures produced by approved proposals, keyed by proposal id.
+    static SIGNATURES: RefCell<StableBTreeMap<u64, MessageSignature, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MESSAGE_SIGNATURES_MEMORY)))
+    );
+
+    /// Signature of the last signing, picked up by `execute_transaction`, which knows the
+    /// proposal it belongs to.
+    static LAST_SIGNATURE: RefCell<Option<MessageSignature>> = const { RefCell::new(None) };
+}
+
+pub fn take_signature() -> Option<MessageSignature> {
+    LAST_SIGNATURE.with(|signature| signature.borrow_mut().take())
+}
+
+pub fn record_signature(proposal_id: u64, signature: MessageSignature) {
+    SIGNATURES.with(|signatures| signatures.borrow_mut().insert(proposal_id, signature));
+}
+
+/// The digest the vault signs for a message.
+pub fn signing_hash(message: &MessageToSign) -> Result<B256, String> {
+    match message {
+        MessageToSign::PersonalSign(message) => match message.strip_prefix("0x") {
+            Some(_) => Ok(eip191_hash_message(parse_hex(message)?)),
+            None => Ok(eip191_hash_message(message.as_bytes())),
+        },
+        MessageToSign::TypedData(json) => TypedData::parse(json)?.signing_hash(),
+    }
+}
+
+/// Checks a message when it is proposed and stores typed data without whitespace, so it
+/// takes less of the proposal.
+pub fn complete_message(message: MessageToSign) -> Result<MessageToSign, String> {
+    let message = match message {
+        MessageToSign::TypedData(json) => {
+            let value: serde_json::Value = serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid EIP-712 typed data: {}", e))?;
+            MessageToSign::TypedData(value.to_string())
+        }
+        message => message,
+    };
+
+    let size = match &message {
+        MessageToSign::PersonalSign(text) | MessageToSign::TypedData(text) => text.len(),
+    };
+    if size > MAX_MESSAGE_BYTES {
+        return Err(format!(
+            "The message takes {} bytes, the limit is {}",
+            size, MAX_MESSAGE_BYTES
+        ));
+    }
+
+    signing_hash(&message)?;
+    Ok(message)
+}
+
+fn message_to_sign(transaction: &TransactionRequest) -> Result<&MessageToSign, String> {
+    match &transaction.payload {
+        Some(TransactionPayload::SignMessage(message)) => Ok(message),
+        _ => Err("Signing requires a SignMessage payload".to_string()),
+    }
+}
+
+/// Signs an EIP-191 or EIP-712 message with the key of the proposal's wallet. Like every
+/// adapter, it only runs once the proposal reached the threshold.
+#[derive(Clone)]
+pub struct EVMSignMessageAdapter {}
+
+impl EVMSignMessageAdapter {
+    pub fn new() -> EVMSignMessageAdapter {
+        EVMSignMessageAdapter {}
+    }
+}
+
+impl BlockchainAdapter for EVMSignMessageAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing EVMSignMessageAdapter");
+
+            let hash = signing_hash(message_to_sign(transaction)?)?;
+            let derivation_path = evm_wallets::derivation_path(transaction.wallet.as_deref())?;
+            let signer = alloy_services::create_icp_signer(derivation_path).await;
+            let signature = signer
+                .sign_hash(&hash)
+                .await
+                .map_err(|e| format!("Failed to sign message: {}", e))?;
+
+            let signature = MessageSignature {
+                hash: hash.to_string(),
+                signature: format!("0x{}", hex::encode(signature.as_bytes())),
+                address: signer.address().to_string(),
+            };
+            LAST_SIGNATURE.with(|last| *last.borrow_mut() = Some(signature.clone()));
+
+            Ok(IntentStatus::Completed(format!(
+                "Successfully signed message {}: {}",
+                signature.hash, signature.signature
+            )))
+        })
+    }
+}
+
+/// Signature of an executed message signing proposal.
+#[ic_cdk::query]
+pub fn get_message_signature(proposal_id: u64) -> Option<MessageSignature> {
+    SIGNATURES.with(|signatures| signatures.borrow().get(&proposal_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_personal_sign_messages() {
+        // Text and its hex encoding sign the same bytes
+        let text = signing_hash(&MessageToSign::PersonalSign("hello".to_string())).unwrap();
+        let bytes = signing_hash(&MessageToSign::PersonalSign("0x68656c6c6f".to_string())).unwrap();
+
+        assert_eq!(text, bytes);
+        assert_eq!(text, eip191_hash_message(b"hello"));
+    }
+
+    #[test]
+    fn hashes_typed_data_from_json() {
+        let typed_data = r#"{
+            "types": {
+                "EIP712Domain": [{"name": "name", "type": "string"}],
+                "Vote": [{"name": "proposal", "type": "uint256"}]
+            },
+            "primaryType": "Vote",
+            "domain": {"name": "DAO"},
+            "message": {"proposal": 7}
+        }"#;
+
+        let message = complete_message(MessageToSign::TypedData(typed_data.to_string())).unwrap();
+        // Whitespace is dropped, the digest stays the same
+        assert!(matches!(&message, MessageToSign::TypedData(json) if !json.contains(' ')));
+        assert_eq!(
+            signing_hash(&message).unwrap(),
+            signing_hash(&MessageToSign::TypedData(typed_data.to_string())).unwrap()
+        );
+
+        assert!(complete_message(MessageToSign::TypedData("{}".to_string())).is_err());
+    }
+
+    #[test]
+    fn rejects_messages_that_do_not_fit_in_a_proposal() {
+        assert!(complete_message(MessageToSign::PersonalSign("a".repeat(640))).is_ok());
+        assert!(complete_message(MessageToSign::PersonalSign("a".repeat(641))).is_err());
+    }
+}
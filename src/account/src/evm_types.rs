@@ -1,4 +1,7 @@
+use std::borrow::Cow;
+
 use candid::{CandidType, Principal};
+use ic_stable_structures::{storable::Bound, Storable};
 use serde::{Deserialize, Serialize};
 
 #[derive(CandidType, Serialize, Deserialize, Clone)]
@@ -7,11 +10,17 @@ pub struct TransactionResult {
     pub status: String,
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct TransactionRequestBasic {
-    pub to: String,
-    pub value: String,
-    pub chain: String, //Values can be "eth", "base", "polygon"
+/// Gas and EIP-1559 fees a transaction is expected to use, all amounts in wei.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EvmFeeQuote {
+    pub chain: String,
+    pub gas_limit: u128,
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
+    /// Upper bound of the fee, `gas_limit * max_fee_per_gas`.
+    pub max_fee: u128,
+    /// The vault's cap on `max_fee` for the chain, if any.
+    pub fee_cap: Option<u128>,
 }
 
 #[derive(CandidType, Serialize, Debug, Clone)]
@@ -39,6 +48,55 @@ pub struct ECDSAPublicKeyReply {
     pub chain_code: Vec<u8>,
 }
 
+/// Public key and address derived from the threshold ECDSA key.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DerivedEvmKey {
+    pub key_name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub public_key: Vec<u8>,
+    pub address: String,
+}
+
+impl Storable for DerivedEvmKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// A named EVM address of the vault, derived with its own derivation path.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EvmWallet {
+    pub name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub address: String,
+}
+
+impl Storable for EvmWallet {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvmWalletBalance {
+    pub wallet: String,
+    pub address: String,
+    /// In the token's base units, e.g. wei.
+    pub balance: String,
+}
+
 #[derive(CandidType, Serialize, Debug)]
 pub struct PublicKeyReply {
     pub public_key: Vec<u8>,
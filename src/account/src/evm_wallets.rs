@@ -0,0 +1,172 @@
+use std::cell::RefCell;
+
+use ic_stable_structures::StableBTreeMap;
+
+use crate::{
+    alloy_services, evm,
+    evm_types::{EvmWallet, EvmWalletBalance},
+    signer_exists, EVM_WALLETS_MEMORY, MEMORY_MANAGER, VM,
+};
+
+/// Name of the vault's original address, derived with the empty path.
+pub const MAIN_WALLET: &str = "main";
+const MAX_WALLET_NAME_LENGTH: usize = 32;
+
+thread_local! {
+    /// Named EVM wallets, keyed by name. The main wallet is not stored.
+    static WALLETS: RefCell<StableBTreeMap<String, EvmWallet, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(EVM_WALLETS_MEMORY)))
+    );
+}
+
+fn wallet_derivation_path(name: &str) -> Vec<Vec<u8>> {
+    vec![b"evm_wallet".to_vec(), name.as_bytes().to_vec()]
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_WALLET_NAME_LENGTH {
+        return Err(format!(
+            "Wallet names have 1 to {} characters",
+            MAX_WALLET_NAME_LENGTH
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    {
+        return Err(format!(
+            "Invalid wallet name {}, use lowercase letters, digits, '_' and '-'",
+            name
+        ));
+    }
+    if name == MAIN_WALLET {
+        return Err(format!("{} is the vault's main wallet", MAIN_WALLET));
+    }
+    Ok(())
+}
+
+fn named_wallets() -> Vec<EvmWallet> {
+    WALLETS.with(|wallets| wallets.borrow().iter().map(|(_, wallet)| wallet).collect())
+}
+
+/// Derivation path of the wallet a transaction is sent from, the empty path for
+/// the main wallet.
+pub fn derivation_path(wallet: Option<&str>) -> Result<Vec<Vec<u8>>, String> {
+    match wallet {
+        None | Some(MAIN_WALLET) => Ok(vec![]),
+        Some(name) => WALLETS
+            .with(|wallets| wallets.borrow().get(&name.to_string()))
+            .map(|wallet| wallet.derivation_path)
+            .ok_or_else(|| format!("Unknown EVM wallet: {}", name)),
+    }
+}
+
+/// Checks that the wallet a proposal spends from exists and holds the proposal's
+/// token, which must be on an EVM chain.
+pub fn validate_source(wallet: Option<&str>, token: &str) -> Result<(), String> {
+    let wallet = match wallet {
+        Some(wallet) => wallet,
+        None => return Ok(()),
+    };
+
+    derivation_path(Some(wallet))?;
+    let chain = token.split(':').next().unwrap_or_default();
+    evm::chain_config(chain)
+        .map(|_| ())
+        .map_err(|_| format!("Wallets only hold tokens of EVM chains, got {}", token))
+}
+
+/// Derives a new named EVM address, e.g. for operations, payroll or reserves.
+/// Its funds are spent through proposals that name it as their wallet.
+#[ic_cdk::update]
+pub async fn add_evm_wallet(name: String) -> Result<EvmWallet, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        ic_cdk::trap("Caller is not a signer");
+    }
+
+    let name = name.trim().to_string();
+    validate_name(&name)?;
+    if WALLETS.with(|wallets| wallets.borrow().contains_key(&name)) {
+        return Err(format!("Wallet {} already exists", name));
+    }
+
+    let derivation_path = wallet_derivation_path(&name);
+    let address = alloy_services::evm_address(derivation_path.clone()).await;
+    let wallet = EvmWallet {
+        name: name.clone(),
+        derivation_path,
+        address: address.to_string(),
+    };
+    WALLETS.with(|wallets| wallets.borrow_mut().insert(name, wallet.clone()));
+
+    Ok(wallet)
+}
+
+/// The main wallet, once its key is derived, followed by the named wallets.
+#[ic_cdk::query]
+pub fn get_evm_wallets() -> Vec<EvmWallet> {
+    let main = alloy_services::cached_evm_key(&[]).map(|key| EvmWallet {
+        name: MAIN_WALLET.to_string(),
+        derivation_path: vec![],
+        address: key.address,
+    });
+
+    main.into_iter().chain(named_wallets()).collect()
+}
+
+/// Balance of every wallet on a chain, in the native token or in the ERC-20 token
+/// of `contract`.
+#[ic_cdk::update]
+pub async fn get_evm_wallet_balances(
+    chain: String,
+    contract: Option<String>,
+) -> Result<Vec<EvmWalletBalance>, String> {
+    if !signer_exists(ic_cdk::caller()) {
+        return Err("Caller is not a signer".to_string());
+    }
+    let contract = contract.as_deref().map(evm::parse_address).transpose()?;
+    let main = alloy_services::evm_address(vec![]).await;
+    let wallets = std::iter::once((MAIN_WALLET.to_string(), main)).chain(
+        named_wallets()
+            .into_iter()
+            .map(|wallet| (wallet.name, evm::parse_address(&wallet.address).unwrap())),
+    );
+
+    let mut balances = vec![];
+    for (wallet, address) in wallets {
+        let balance = match contract {
+            Some(contract) => evm::erc20_balance_of(&chain, contract, address).await?,
+            None => evm::native_balance(&chain, address).await?,
+        };
+        balances.push(EvmWalletBalance {
+            wallet,
+            address: address.to_string(),
+            balance: balance.to_string(),
+        });
+    }
+
+    Ok(balances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_wallet_names() {
+        assert!(validate_name("payroll").is_ok());
+        assert!(validate_name("ops_2025-q1").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("Payroll").is_err());
+        assert!(validate_name("pay roll").is_err());
+        assert!(validate_name(MAIN_WALLET).is_err());
+        assert!(validate_name(&"a".repeat(MAX_WALLET_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn main_wallet_uses_the_empty_path() {
+        assert_eq!(derivation_path(None).unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(derivation_path(Some(MAIN_WALLET)).unwrap(), Vec::<Vec<u8>>::new());
+        assert_ne!(wallet_derivation_path("payroll"), wallet_derivation_path("reserves"));
+    }
+}
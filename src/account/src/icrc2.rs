@@ -0,0 +1,301 @@
+use std::{borrow::Cow, cell::RefCell, future::Future, pin::Pin, str::FromStr};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use icrc_ledger_types::{
+    icrc1::account::Account,
+    icrc2::{
+        allowance::{Allowance, AllowanceArgs},
+        approve::{ApproveArgs, ApproveError},
+        transfer_from::{TransferFromArgs as ICRC2TransferFromArgs, TransferFromError},
+    },
+};
+use keygate_core::types::vault::{ApprovalArgs, GrantedAllowance, TransactionPayload};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    get_default_icrc_subaccount, get_environment,
+    intent::{BlockchainAdapter, IntentStatus, TokenPath, TransactionRequest},
+    APPROVALS_MEMORY, MEMORY_MANAGER, VM,
+};
+
+thread_local! {
+    static APPROVALS: RefCell<StableBTreeMap<String, GrantedApproval, VM>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(APPROVALS_MEMORY)))
+    );
+}
+
+/// A spender the vault has approved on a ledger.
+///
+/// ICRC-2 ledgers cannot enumerate the approvals of an account, so the vault keeps
+/// track of them itself and asks the ledger for the current allowance when listing.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+struct GrantedApproval {
+    ledger: Principal,
+    spender: Account,
+}
+
+impl Storable for GrantedApproval {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+fn approval_key(ledger: &Principal, spender: &Account) -> String {
+    format!("{}:{}", ledger, spender)
+}
+
+fn vault_account() -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(get_default_icrc_subaccount().0),
+    }
+}
+
+/// Resolves the ledger canister behind an ICP token path.
+pub fn resolve_ledger(token: &TokenPath) -> Result<Principal, String> {
+    let parts: Vec<&str> = token.split(':').collect();
+    match parts.as_slice() {
+        ["icp", "native"] => Ok(get_environment().icp_ledger),
+        ["icp", "icrc1", ledger] => Principal::from_text(ledger)
+            .map_err(|e| format!("Invalid ledger principal {}: {}", ledger, e)),
+        _ => Err(format!("Unsupported token for ICRC-2: {}", token)),
+    }
+}
+
+pub fn parse_account(account: &str) -> Result<Account, String> {
+    Account::from_str(account.trim()).map_err(|e| format!("Invalid account {}: {}", account, e))
+}
+
+pub async fn approve(
+    ledger: Principal,
+    spender: Account,
+    amount: Nat,
+    approval: ApprovalArgs,
+) -> Result<Nat, String> {
+    let args = ApproveArgs {
+        from_subaccount: Some(get_default_icrc_subaccount().0),
+        spender,
+        amount,
+        expected_allowance: approval.expected_allowance.map(Nat::from),
+        expires_at: approval.expires_at,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+
+    ic_cdk::println!("Args: {:?}", args);
+
+    let approve_result: CallResult<(Result<Nat, ApproveError>,)> =
+        ic_cdk::call(ledger, "icrc2_approve", (args,)).await;
+    match approve_result {
+        Ok((Ok(block_index),)) => Ok(block_index),
+        Ok((Err(approve_error),)) => Err(format!("ICRC-2 approve error: {:?}", approve_error)),
+        Err((rejection_code, message)) => Err(format!(
+            "Canister call rejected: {:?} - {}",
+            rejection_code, message
+        )),
+    }
+}
+
+fn approval_args(transaction: &TransactionRequest) -> Result<ApprovalArgs, String> {
+    match &transaction.payload {
+        None => Ok(ApprovalArgs {
+            expires_at: None,
+            expected_allowance: None,
+        }),
+        Some(TransactionPayload::Approval(args)) => Ok(args.clone()),
+        Some(other) => Err(format!("Unexpected payload for an approval: {:?}", other)),
+    }
+}
+
+#[derive(Clone)]
+pub struct ICRC2ApproveAdapter {}
+
+impl ICRC2ApproveAdapter {
+    pub fn new() -> ICRC2ApproveAdapter {
+        ICRC2ApproveAdapter {}
+    }
+}
+
+impl BlockchainAdapter for ICRC2ApproveAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ICRC2ApproveAdapter");
+
+            let ledger = resolve_ledger(&transaction.token)?;
+            let spender = parse_account(&transaction.to)?;
+            let approval = approval_args(transaction)?;
+
+            approve(
+                ledger,
+                spender,
+                Nat::from(transaction.amount as u128),
+                approval,
+            )
+            .await?;
+
+            APPROVALS.with(|approvals| {
+                approvals.borrow_mut().insert(
+                    approval_key(&ledger, &spender),
+                    GrantedApproval { ledger, spender },
+                )
+            });
+
+            Ok(IntentStatus::Completed(
+                "Successfully approved an ICRC-2 allowance.".to_string(),
+            ))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ICRC2RevokeApprovalAdapter {}
+
+impl ICRC2RevokeApprovalAdapter {
+    pub fn new() -> ICRC2RevokeApprovalAdapter {
+        ICRC2RevokeApprovalAdapter {}
+    }
+}
+
+impl BlockchainAdapter for ICRC2RevokeApprovalAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ICRC2RevokeApprovalAdapter");
+
+            let ledger = resolve_ledger(&transaction.token)?;
+            let spender = parse_account(&transaction.to)?;
+            let approval = approval_args(transaction)?;
+
+            // ICRC-2 has no dedicated revocation, an allowance of zero replaces the old one.
+            approve(ledger, spender, Nat::from(0u64), approval).await?;
+
+            APPROVALS.with(|approvals| {
+                approvals
+                    .borrow_mut()
+                    .remove(&approval_key(&ledger, &spender))
+            });
+
+            Ok(IntentStatus::Completed(
+                "Successfully revoked an ICRC-2 allowance.".to_string(),
+            ))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ICRC2TransferFromAdapter {}
+
+impl ICRC2TransferFromAdapter {
+    pub fn new() -> ICRC2TransferFromAdapter {
+        ICRC2TransferFromAdapter {}
+    }
+
+    async fn transfer_from(&self, transaction: &TransactionRequest) -> Result<Nat, String> {
+        let from = match &transaction.payload {
+            Some(TransactionPayload::TransferFrom(args)) => parse_account(&args.from)?,
+            _ => return Err("transfer_from requires a TransferFrom payload".to_string()),
+        };
+
+        let args = ICRC2TransferFromArgs {
+            spender_subaccount: Some(get_default_icrc_subaccount().0),
+            from,
+            to: parse_account(&transaction.to)?,
+            amount: Nat::from(transaction.amount as u128),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+
+        ic_cdk::println!("Args: {:?}", args);
+
+        let ledger = resolve_ledger(&transaction.token)?;
+        let transfer_result: CallResult<(Result<Nat, TransferFromError>,)> =
+            ic_cdk::call(ledger, "icrc2_transfer_from", (args,)).await;
+        match transfer_result {
+            Ok((Ok(block_index),)) => Ok(block_index),
+            Ok((Err(transfer_error),)) => {
+                Err(format!("ICRC-2 transfer_from error: {:?}", transfer_error))
+            }
+            Err((rejection_code, message)) => Err(format!(
+                "Canister call rejected: {:?} - {}",
+                rejection_code, message
+            )),
+        }
+    }
+}
+
+impl BlockchainAdapter for ICRC2TransferFromAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ICRC2TransferFromAdapter");
+            match self.transfer_from(transaction).await {
+                Ok(_) => Ok(IntentStatus::Completed(
+                    "Successfully transferred an ICRC-2 allowance.".to_string(),
+                )),
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+/// Lists the allowances granted by the vault that are still active.
+#[ic_cdk::query(composite = true)]
+pub async fn get_allowances() -> Vec<GrantedAllowance> {
+    let approvals: Vec<GrantedApproval> =
+        APPROVALS.with(|approvals| approvals.borrow().iter().map(|(_, a)| a).collect());
+    let now = ic_cdk::api::time();
+
+    let mut allowances = vec![];
+    for approval in approvals {
+        let args = AllowanceArgs {
+            account: vault_account(),
+            spender: approval.spender,
+        };
+        let allowance_result: CallResult<(Allowance,)> =
+            ic_cdk::call(approval.ledger, "icrc2_allowance", (args,)).await;
+
+        match allowance_result {
+            Ok((allowance,)) => {
+                let expired = allowance.expires_at.map_or(false, |expires_at| expires_at <= now);
+                if allowance.allowance == Nat::from(0u64) || expired {
+                    continue;
+                }
+
+                allowances.push(GrantedAllowance {
+                    ledger: approval.ledger,
+                    spender: approval.spender,
+                    allowance: allowance.allowance,
+                    expires_at: allowance.expires_at,
+                });
+            }
+            Err((rejection_code, message)) => ic_cdk::println!(
+                "Failed to fetch allowance from {}: {:?} - {}",
+                approval.ledger,
+                rejection_code,
+                message
+            ),
+        }
+    }
+
+    allowances
+}
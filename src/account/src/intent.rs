@@ -1,20 +1,19 @@
-use std::{collections::HashMap, future::Future, pin::Pin};
+use std::{collections::HashMap, future::Future, pin::Pin, str::FromStr};
 
-use crate::evm;
+use crate::{batches, canister_calls, ck_minters, evm, evm_confirmations, evm_signing};
 use candid::{CandidType, Nat, Principal};
 use dyn_clone::DynClone;
 use ic_cdk::api::call::CallResult;
-use ic_ledger_types::{
-    AccountIdentifier, BlockIndex, Memo, Tokens, TransferArgs, MAINNET_LEDGER_CANISTER_ID,
-};
+use ic_ledger_types::{AccountIdentifier, BlockIndex, Memo, Tokens, TransferArgs};
 use icrc_ledger_types::icrc1::{
     account::Account,
     transfer::{TransferArg as ICRC1TransferArgs, TransferError},
 };
+use keygate_core::types::vault::{EvmReceipt, TransactionPayload};
 use serde_bytes::ByteBuf;
 
 use crate::{
-    evm_types::TransactionRequestBasic, get_default_icrc_subaccount, to_subaccount, ADAPTERS,
+    get_default_icrc_subaccount, get_environment, to_subaccount, ADAPTERS,
     PROPOSED_TRANSACTIONS, THRESHOLD,
 };
 
@@ -27,7 +26,7 @@ use ic_cdk::update;
 use ic_stable_structures::{storable::Bound, Storable};
 use serde::{Deserialize, Serialize};
 
-use crate::TRANSACTIONS;
+use crate::{TRANSACTIONS, TRANSACTION_UPDATES};
 
 pub(crate) trait BlockchainAdapter: DynClone {
     fn execute<'a>(
@@ -40,21 +39,31 @@ dyn_clone::clone_trait_object!(BlockchainAdapter);
 
 pub type TokenPath = String;
 
-pub async fn execute(transaction: &TransactionRequest) -> IntentStatus {
+/// Key of the adapter that executes `transaction`, see `register_adapters`.
+fn adapter_key(transaction: &TransactionRequest) -> String {
     let it: &'static str = transaction.transaction_type.clone().into();
 
     let token = transaction.token.clone();
 
-    // if icrc, ignore last token : split of str
+    // network:standard:<ledger, collection or contract>[:<token id>] share the adapter of
+    // their standard, so only the first two parts are part of the key
     let token_parts: Vec<&str> = token.split(':').collect();
     ic_cdk::println!("Token parts: {:?}", token_parts);
 
-    let token_key = if token_parts.len() > 2 && token_parts[1] == "icrc1" {
-        // For ICRC, ignore the last part
-        token_parts[..token_parts.len() - 1].join(":") + ":" + it.to_ascii_lowercase().as_str()
+    if token_parts.len() > 2 {
+        token_parts[..2].join(":") + ":" + it.to_ascii_lowercase().as_str()
     } else {
         token.to_string() + ":" + it.to_ascii_lowercase().as_str()
-    };
+    }
+}
+
+pub(crate) fn adapter_exists(transaction: &TransactionRequest) -> bool {
+    let token_key = adapter_key(transaction);
+    ADAPTERS.with(|adapters| adapters.borrow().contains_key(&token_key))
+}
+
+pub async fn execute(transaction: &TransactionRequest) -> IntentStatus {
+    let token_key = adapter_key(transaction);
 
     ic_cdk::println!("Token key: {:?}", token_key);
 
@@ -110,18 +119,38 @@ impl BlockchainAdapter for ICPNativeTransferAdapter {
 
             ic_cdk::println!("Fee: {:?}", RECOMMENDED_ICP_TRANSACTION_FEE);
 
-            let args = ICPNativeTransferArgs {
-                to: AccountIdentifier::from_hex(&transaction.to).unwrap(),
-                amount: Tokens::from_e8s(transaction.amount as u64),
-                fee: Tokens::from_e8s(RECOMMENDED_ICP_TRANSACTION_FEE),
-                memo: Memo(0),
-                from_subaccount: Some(to_subaccount(0)),
-                created_at_time: None,
+            let result = match ICPDestination::parse(&transaction.to)? {
+                ICPDestination::AccountIdentifier(to) => {
+                    let args = ICPNativeTransferArgs {
+                        to,
+                        amount: Tokens::from_e8s(transaction.amount as u64),
+                        fee: Tokens::from_e8s(RECOMMENDED_ICP_TRANSACTION_FEE),
+                        memo: Memo(0),
+                        from_subaccount: Some(to_subaccount(0)),
+                        created_at_time: None,
+                    };
+
+                    ic_cdk::println!("Args: {:?}", args);
+
+                    ICPNativeTransferAdapter::transfer(args).await.map(Nat::from)
+                }
+                ICPDestination::Account(to) => {
+                    let args = ICRC1TransferArgs {
+                        to,
+                        amount: Nat::from(transaction.amount as u64),
+                        fee: Some(Nat::from(RECOMMENDED_ICP_TRANSACTION_FEE)),
+                        memo: None,
+                        from_subaccount: Some(to_subaccount(0).0),
+                        created_at_time: None,
+                    };
+
+                    ic_cdk::println!("Args: {:?}", args);
+
+                    ICPNativeTransferAdapter::icrc1_transfer(args).await
+                }
             };
 
-            ic_cdk::println!("Args: {:?}", args);
-
-            match ICPNativeTransferAdapter::transfer(args).await {
+            match result {
                 Ok(_) => Ok(IntentStatus::Completed(
                     "Successfully transferred native ICP.".to_string(),
                 )),
@@ -137,7 +166,7 @@ impl ICPNativeTransferAdapter {
     }
 
     async fn transfer(args: ICPNativeTransferArgs) -> Result<BlockIndex, String> {
-        match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, args).await {
+        match ic_ledger_types::transfer(get_environment().icp_ledger, args).await {
             Ok(Ok(block_index)) => return Ok(block_index),
             Ok(Err(transfer_error)) => {
                 let error_message = format!("transfer error: {:?}", transfer_error);
@@ -149,42 +178,83 @@ impl ICPNativeTransferAdapter {
             }
         }
     }
+
+    async fn icrc1_transfer(args: ICRC1TransferArgs) -> Result<Nat, String> {
+        let transfer_result: CallResult<(Result<Nat, TransferError>,)> =
+            ic_cdk::call(get_environment().icp_ledger, "icrc1_transfer", (args,)).await;
+        match transfer_result {
+            Ok((Ok(block_index),)) => Ok(block_index),
+            Ok((Err(transfer_error),)) => Err(format!("ICRC-1 transfer error: {:?}", transfer_error)),
+            Err((rejection_code, message)) => Err(format!(
+                "Canister call rejected: {:?} - {}",
+                rejection_code, message
+            )),
+        }
+    }
 }
 
+/// Destination formats accepted by the ICP ledger.
+///
+/// A 64 character hex string is a legacy `AccountIdentifier` and goes through the
+/// `transfer` endpoint. Anything else is parsed as an ICRC-1 account, either a plain
+/// principal or the textual `<principal>-<checksum>.<subaccount>` encoding, and goes
+/// through `icrc1_transfer`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ICPDestination {
+    AccountIdentifier(AccountIdentifier),
+    Account(Account),
+}
+
+impl ICPDestination {
+    pub fn parse(destination: &str) -> Result<ICPDestination, String> {
+        let destination = destination.trim();
+
+        if destination.len() == 64 && destination.chars().all(|c| c.is_ascii_hexdigit()) {
+            return AccountIdentifier::from_hex(destination)
+                .map(ICPDestination::AccountIdentifier)
+                .map_err(|e| format!("Invalid account identifier {}: {}", destination, e));
+        }
+
+        Account::from_str(destination)
+            .map(ICPDestination::Account)
+            .map_err(|e| format!("Invalid ICP destination {}: {}", destination, e))
+    }
+}
+
+/// Transfers the native token of an EVM chain, e.g. ETH on "eth" and "base" or POL on "polygon".
 #[derive(Clone)]
-pub struct ETHNativeTransferAdapter { }
+pub struct EVMNativeTransferAdapter {
+    chain: String,
+}
 
-impl ETHNativeTransferAdapter {
-    pub fn new() -> ETHNativeTransferAdapter {
-        ETHNativeTransferAdapter { }
+impl EVMNativeTransferAdapter {
+    pub fn new(chain: String) -> EVMNativeTransferAdapter {
+        EVMNativeTransferAdapter { chain }
     }
 
     async fn transfer(&self, transaction: &TransactionRequest) -> Result<String, String> {
-        ic_cdk::println!("Executing ETHAdapter");
-        let request = TransactionRequestBasic {
-            to: transaction.to.clone(),
-            value: transaction.amount.to_string(),
-            chain: "eth".to_string(),
-        };
-        let result = evm::execute_transaction_evm(request).await;
+        ic_cdk::println!("Executing EVMNativeTransferAdapter on {}", self.chain);
+        let (chain, tx) = evm::prepare_transaction(transaction).await?;
+        let result = evm::send_transaction(&chain, transaction.wallet.as_deref(), tx).await;
         match result.status.as_str() {
-            "Success" => Ok(result.hash),
+            "Success" => Ok(evm::transaction_reference(&self.chain, &result.hash)),
             _ => Err(result.status),
         }
     }
 }
 
-impl BlockchainAdapter for ETHNativeTransferAdapter {
+impl BlockchainAdapter for EVMNativeTransferAdapter {
     fn execute<'a>(
         &'a self,
         transaction: &'a TransactionRequest,
     ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
         Box::pin(async move {
-            ic_cdk::println!("Executing ETHAdapter");
             match self.transfer(transaction).await {
-                Ok(result) => Ok(IntentStatus::Completed(
-                    "Successfully transferred native ETH: ".to_string() + &result,
-                )),
+                Ok(result) => Ok(IntentStatus::InProgress(format!(
+                    "Sent native {}, waiting for confirmations: {}",
+                    self.chain.to_ascii_uppercase(),
+                    result
+                ))),
                 Err(e) => Err(e.to_string()),
             }
         })
@@ -269,6 +339,31 @@ impl ICRC1TransferAdapter {
 pub enum TransactionType {
     Swap,
     Transfer,
+    Approve,
+    #[strum(serialize = "revoke_approval")]
+    RevokeApproval,
+    #[strum(serialize = "transfer_from")]
+    TransferFrom,
+    #[strum(serialize = "contract_call")]
+    ContractCall,
+    #[strum(serialize = "sign_message")]
+    SignMessage,
+    Deposit,
+    Withdraw,
+    Stake,
+    #[strum(serialize = "manage_neuron")]
+    ManageNeuron,
+    #[strum(serialize = "canister_call")]
+    CanisterCall,
+    #[strum(serialize = "manage_canister")]
+    ManageCanister,
+    #[strum(serialize = "top_up")]
+    TopUp,
+    #[strum(serialize = "create_canister")]
+    CreateCanister,
+    Batch,
+    #[strum(serialize = "set_fee_cap")]
+    SetFeeCap,
 }
 
 #[derive(
@@ -288,12 +383,46 @@ pub enum IntentStatus {
 pub enum SupportedNetwork {
     ICP,
     ETH,
+    BTC,
+    SOL,
+    BASE,
+    POLYGON,
+}
+
+/// Rejects a `network` other than the one of the token's chain. Proposals execute on the
+/// chain of their token, so approvers would otherwise be shown a network the funds never
+/// move on. Chains without a network of their own, e.g. other configured EVM chains, are
+/// not checked.
+pub fn validate_network(network: &SupportedNetwork, token: &str) -> Result<(), String> {
+    let chain = token.split(':').next().unwrap_or_default();
+    let expected = match chain.to_ascii_lowercase().as_str() {
+        "icp" => SupportedNetwork::ICP,
+        "eth" => SupportedNetwork::ETH,
+        "btc" => SupportedNetwork::BTC,
+        "sol" => SupportedNetwork::SOL,
+        "base" => SupportedNetwork::BASE,
+        "polygon" => SupportedNetwork::POLYGON,
+        _ => return Ok(()),
+    };
+
+    if *network != expected {
+        return Err(format!(
+            "{} is on {:?}, not on {:?}",
+            token, expected, network
+        ));
+    }
+    Ok(())
 }
 
 // Formats for tokens:
 // icp:native
 // icp:icrc1:<principal_id>
-// eth:{erc20}:{0x0000000000000000000000000000000000000000}
+// icp:icrc7:<collection_principal_id>:<token_id>
+// icp:cycles, cycles held on the cycles ledger
+// <evm chain>:native, e.g. eth:native or base:native
+// <evm chain>:{erc20}:{0x0000000000000000000000000000000000000000}
+// btc:native (P2WPKH) or btc:taproot
+// sol:native or sol:spl:<mint address>
 #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Token(pub String);
 
@@ -313,8 +442,8 @@ a transaction on a supported blockchain network.
 
 * `intent_type` - The type of the intent (e.g., transfer, swap).
 * `amount` - The amount of tokens involved in the transaction.
-* `token` - The token identifier for the transaction. For native ICP, it's "ICP:native". For ICRC-1 tokens, it's "ICP:<icrc_standard>:<principal_id>". For ETH, it's "eth:<token_standard>:<token_address>".
-* `to` - The recipient's address or identifier. For ICP and ICRC-1 tokens, it's a Principal ID. For ETH, it's the address of the recipient.
+* `token` - The token identifier for the transaction. For native ICP, it's "ICP:native". For ICRC-1 tokens, it's "ICP:<icrc_standard>:<principal_id>". For ICRC-7 NFTs, it's "icp:icrc7:<collection_principal_id>:<token_id>". For EVM chains, it's "<chain>:native" or "<chain>:erc20:<token_address>", where chain is one of the configured chains such as "eth", "base" or "polygon". For BTC, it's "btc:native" for the P2WPKH address or "btc:taproot" for the Taproot address, and the amount is in satoshis. For Solana, it's "sol:native" or "sol:spl:<mint_address>", and the amount is in lamports or the token's base unit. For cycles on the cycles ledger, it's "icp:cycles" and the amount is in cycles.
+* `to` - The recipient's address or identifier. For native ICP, it's a hex account identifier, a Principal ID or an ICRC-1 textual account. For ICRC-1 tokens, it's a Principal ID. For EVM chains, it's the address of the recipient, or of the contract for contract calls. For BTC, it's a Bitcoin address on the vault's network. For Solana, it's the base58 address of the recipient wallet. For ckBTC and ckETH withdrawals, it's the Bitcoin or Ethereum address that receives the withdrawal. For SNS neurons, it's the SNS governance canister. For canister calls, canister management, cycles withdrawals and top-ups, it's the target canister. For cycles transfers, it's an ICRC-1 textual account.
 * `network` - The blockchain network on which the transaction should occur.
 * `status` - The current status of the intent."#]
 pub struct Intent {
@@ -334,6 +463,9 @@ pub struct TransactionRequest {
     pub token: TokenPath,
     pub to: String,
     pub network: SupportedNetwork,
+    pub payload: Option<TransactionPayload>,
+    /// Named EVM wallet the transaction is sent from, the main address when empty.
+    pub wallet: Option<String>,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
@@ -344,6 +476,32 @@ pub struct Transaction {
     pub network: SupportedNetwork,
     pub amount: f64,
     pub transaction_type: TransactionType,
+    #[serde(default)]
+    pub payload: Option<TransactionPayload>,
+    /// Receipt of an EVM transaction, once it is in a block.
+    #[serde(default)]
+    pub receipt: Option<EvmReceipt>,
+    #[serde(default)]
+    pub wallet: Option<String>,
+}
+
+/// Later changes to a record of `TRANSACTIONS`, which is append-only.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TransactionUpdate {
+    pub status: IntentStatus,
+    pub receipt: Option<EvmReceipt>,
+}
+
+impl Storable for TransactionUpdate {
+    const BOUND: Bound = Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).unwrap()
+    }
 }
 
 impl Storable for Transaction {
@@ -373,11 +531,47 @@ pub struct ProposedTransaction {
     pub transaction_type: TransactionType,
     pub signers: Vec<Principal>,
     pub rejections: Vec<Principal>,
+    #[serde(default)]
+    pub payload: Option<TransactionPayload>,
+    #[serde(default)]
+    pub wallet: Option<String>,
+    /// Set when execution starts, a proposal is never executed twice.
+    #[serde(default)]
+    pub executed: bool,
+}
+
+/// Size of the stable memory slot of a proposal.
+const MAX_PROPOSAL_BYTES: usize = 1024;
+
+/// Part of a proposal's slot its payload may take. The other fields and the approvals
+/// share the remaining 384 bytes, `check_size` rejects proposals that would still
+/// overflow once every signer approved.
+pub(crate) const MAX_PAYLOAD_BYTES: usize = MAX_PROPOSAL_BYTES * 5 / 8;
+
+impl ProposedTransaction {
+    /// Rejects proposals that would not fit in their slot once every signer approved
+    /// them, which happens with large contract calls or messages to sign.
+    pub fn check_size(&self, signers: Vec<Principal>) -> Result<(), String> {
+        let mut approved = self.clone();
+        approved.signers = signers;
+        approved.executed = true;
+
+        let size = serde_cbor::to_vec(&approved)
+            .map_err(|e| format!("Failed to encode the proposal: {}", e))?
+            .len();
+        if size > MAX_PROPOSAL_BYTES {
+            return Err(format!(
+                "The proposal takes {} bytes once approved by every signer, the limit is {}. Shorten its payload.",
+                size, MAX_PROPOSAL_BYTES
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Storable for ProposedTransaction {
     const BOUND: Bound = Bound::Bounded {
-        max_size: 1024,
+        max_size: MAX_PROPOSAL_BYTES as u32,
         is_fixed_size: false,
     };
 
@@ -409,7 +603,20 @@ pub fn get_adapters() -> Vec<String> {
 pub fn get_transactions() -> Vec<Transaction> {
     TRANSACTIONS.with(|transactions| {
         let transactions = transactions.borrow();
-        transactions.iter().collect()
+        TRANSACTION_UPDATES.with(|updates| {
+            let updates = updates.borrow();
+            transactions
+                .iter()
+                .enumerate()
+                .map(|(index, mut transaction)| {
+                    if let Some(update) = updates.get(&(index as u64)) {
+                        transaction.status = update.status;
+                        transaction.receipt = update.receipt;
+                    }
+                    transaction
+                })
+                .collect()
+        })
     })
 }
 
@@ -433,21 +640,53 @@ pub async fn execute_transaction(proposal_id: u64) -> IntentStatus {
         return IntentStatus::Failed("Threshold not met".to_string());
     }
 
+    if proposal.executed {
+        return IntentStatus::Failed(format!("Proposal {} was already executed", proposal_id));
+    }
+    // Marked before the first await, so a concurrent call can't execute it too
+    PROPOSED_TRANSACTIONS.with(|proposed_transactions| {
+        let proposed_transactions = proposed_transactions.borrow_mut();
+        let mut executed = proposal.clone();
+        executed.executed = true;
+        proposed_transactions.set(proposal_id, &executed);
+    });
+
+    if proposal.transaction_type == TransactionType::Batch {
+        return batches::start_batch(proposal_id);
+    }
+
     let transaction = TransactionRequest {
         transaction_type: proposal.transaction_type,
         amount: proposal.amount,
         token: proposal.token,
         to: proposal.to,
         network: proposal.network,
+        payload: proposal.payload,
+        wallet: proposal.wallet,
     };
 
+    execute_and_record(proposal_id, transaction).await.1
+}
+
+/// Executes the transaction of a proposal and appends it to `TRANSACTIONS`, returning
+/// its index there and its status.
+pub(crate) async fn execute_and_record(
+    proposal_id: u64,
+    transaction: TransactionRequest,
+) -> (u64, IntentStatus) {
     ic_cdk::println!("Executing transaction: {:?}", transaction);
 
+    // Drop handoffs left over by an execution that trapped before taking them
+    evm_confirmations::take_sent_transaction();
+    ck_minters::take_requested_withdrawal();
+    canister_calls::take_call_result();
+    evm_signing::take_signature();
+
     let execution_result = super::execute(&transaction).await;
 
     ic_cdk::println!("Execution result: {:?}", execution_result);
 
-    TRANSACTIONS.with(|transactions| {
+    let index = TRANSACTIONS.with(|transactions| {
         let transactions = transactions.borrow_mut();
         let transaction = Transaction {
             status: execution_result.clone(),
@@ -456,14 +695,84 @@ pub async fn execute_transaction(proposal_id: u64) -> IntentStatus {
             network: transaction.network,
             amount: transaction.amount,
             transaction_type: transaction.transaction_type,
+            payload: transaction.payload,
+            receipt: None,
+            wallet: transaction.wallet,
         };
 
         println!("Appending transaction: {:?}", transaction);
         match transactions.append(&transaction) {
-            Ok(_) => (),
+            Ok(index) => index,
             Err(e) => panic!("Failed to append transaction: {:?}", e),
         }
     });
 
-    execution_result
+    if let Some(result) = canister_calls::take_call_result() {
+        canister_calls::record_call_result(proposal_id, result);
+    }
+    if let Some(signature) = evm_signing::take_signature() {
+        evm_signing::record_signature(proposal_id, signature);
+    }
+
+    // EVM transactions stay in progress until they are confirmed, and chain-key
+    // withdrawals until the minter settles them
+    if let IntentStatus::InProgress(_) = execution_result {
+        if let Some((chain, hash)) = evm_confirmations::take_sent_transaction() {
+            evm_confirmations::track_confirmations(index, chain, hash);
+        }
+        if let Some(withdrawal) = ck_minters::take_requested_withdrawal() {
+            ck_minters::track_withdrawal(index, withdrawal);
+        }
+    }
+
+    (index, execution_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use keygate_core::types::vault::ContractCallArgs;
+
+    use super::*;
+
+    fn contract_call(calldata_bytes: usize) -> ProposedTransaction {
+        ProposedTransaction {
+            id: 0,
+            to: "0x000000000000000000000000000000000000dEaD".to_string(),
+            token: "eth:native".to_string(),
+            network: SupportedNetwork::ETH,
+            amount: 0.0,
+            transaction_type: TransactionType::ContractCall,
+            signers: vec![],
+            rejections: vec![],
+            payload: Some(TransactionPayload::ContractCall(ContractCallArgs {
+                calldata: format!("0x{}", "ab".repeat(calldata_bytes)),
+                signature: None,
+                args: vec![],
+            })),
+            wallet: None,
+            executed: false,
+        }
+    }
+
+    #[test]
+    fn leaves_room_in_proposals_for_every_approval() {
+        let signers: Vec<Principal> = (0..5u8)
+            .map(|i| Principal::self_authenticating([i; 32]))
+            .collect();
+
+        assert!(contract_call(100).check_size(signers.clone()).is_ok());
+        assert!(contract_call(400).check_size(vec![]).is_ok());
+        assert!(contract_call(400).check_size(signers).is_err());
+        assert!(contract_call(600).check_size(vec![]).is_err());
+    }
+
+    #[test]
+    fn matches_the_network_to_the_token_chain() {
+        assert!(validate_network(&SupportedNetwork::ICP, "icp:native").is_ok());
+        assert!(validate_network(&SupportedNetwork::ICP, "ICP:native").is_ok());
+        assert!(validate_network(&SupportedNetwork::BASE, "base:erc20:0xdead").is_ok());
+        assert!(validate_network(&SupportedNetwork::POLYGON, "eth:native").is_err());
+        assert!(validate_network(&SupportedNetwork::ETH, "btc:native").is_err());
+        assert!(validate_network(&SupportedNetwork::ETH, "arbitrum:native").is_ok());
+    }
 }
@@ -1,8 +1,28 @@
 mod alloy_services;
+mod batches;
+mod btc;
+mod canister_calls;
+mod ck_minters;
+mod controlled_canisters;
+mod cycles;
+mod cycles_monitor;
+mod eip712;
 mod evm;
+mod evm_abi;
+mod evm_confirmations;
+mod evm_signing;
 mod evm_types;
+mod evm_wallets;
+mod icrc2;
 mod intent;
 mod ledger;
+mod nft;
+mod nns;
+mod schnorr;
+mod sns;
+mod solana;
+mod solana_rpc;
+mod swap;
 pub mod types;
 
 use b3_utils::{
@@ -15,10 +35,17 @@ use ic_cdk::{query, update};
 use ic_ledger_types::AccountIdentifier;
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager, VirtualMemory},
-    DefaultMemoryImpl, StableCell, StableLog, StableVec,
+    DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog, StableVec,
 };
+use icrc2::*;
 use intent::*;
+use keygate_core::types::{
+    environment::VaultEnvironment,
+    vault::{BitcoinAddressType, TransactionPayload},
+};
 use ledger::*;
+use nft::*;
+use swap::*;
 use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
@@ -32,6 +59,27 @@ const PROPOSED_TRANSACTIONS_MEMORY: MemoryId = MemoryId::new(5);
 const PROPOSED_TRANSACTIONS_LAST_ID_MEMORY: MemoryId = MemoryId::new(6);
 const THRESHOLD_MEMORY: MemoryId = MemoryId::new(7);
 const NAME_MEMORY: MemoryId = MemoryId::new(8);
+const ENVIRONMENT_MEMORY: MemoryId = MemoryId::new(9);
+const APPROVALS_MEMORY: MemoryId = MemoryId::new(10);
+const NFT_COLLECTIONS_MEMORY: MemoryId = MemoryId::new(11);
+const EVM_NONCES_MEMORY: MemoryId = MemoryId::new(12);
+const EVM_FEE_CAPS_MEMORY: MemoryId = MemoryId::new(13);
+const EVM_PENDING_CONFIRMATIONS_MEMORY: MemoryId = MemoryId::new(14);
+const TRANSACTION_UPDATES_MEMORY: MemoryId = MemoryId::new(15);
+const MESSAGE_SIGNATURES_MEMORY: MemoryId = MemoryId::new(16);
+const DERIVED_EVM_KEYS_MEMORY: MemoryId = MemoryId::new(17);
+const EVM_WALLETS_MEMORY: MemoryId = MemoryId::new(18);
+const CK_WITHDRAWALS_MEMORY: MemoryId = MemoryId::new(19);
+const CANISTER_CALL_RESULTS_MEMORY: MemoryId = MemoryId::new(20);
+const CONTROLLED_CANISTERS_MEMORY: MemoryId = MemoryId::new(21);
+const WASM_CHUNKS_MEMORY: MemoryId = MemoryId::new(22);
+const WASM_MODULES_MEMORY: MemoryId = MemoryId::new(23);
+const CYCLES_MONITOR_MEMORY: MemoryId = MemoryId::new(24);
+const AUDIT_LOG_INDEX_MEMORY: MemoryId = MemoryId::new(25);
+const AUDIT_LOG_DATA_MEMORY: MemoryId = MemoryId::new(26);
+const BATCHES_MEMORY: MemoryId = MemoryId::new(27);
+const BTC_PUBLIC_KEYS_MEMORY: MemoryId = MemoryId::new(28);
+const BTC_SPENT_OUTPOINTS_MEMORY: MemoryId = MemoryId::new(29);
 pub type VM = VirtualMemory<DefaultMemoryImpl>;
 
 // Thread-local storage
@@ -52,9 +100,12 @@ thread_local! {
         ).expect("Failed to initialize INTENTS StableLog")
     );
 
+    pub static TRANSACTION_UPDATES: RefCell<StableBTreeMap<u64, TransactionUpdate, VM>> = RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TRANSACTION_UPDATES_MEMORY))));
+
     pub static ADAPTERS: RefCell<HashMap<String, Box<dyn BlockchainAdapter>>> = RefCell::default();
     pub static THRESHOLD: RefCell<StableCell<u64, VM>> = RefCell::new(DefaultStableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(THRESHOLD_MEMORY)), 1).expect("Failed to initialize THRESHOLD StableCell"));
     pub static NAME: RefCell<StableCell<String, VM>> = RefCell::new(DefaultStableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(NAME_MEMORY)), "".to_string()).expect("Failed to initialize NAME StableCell"));
+    pub static ENVIRONMENT: RefCell<StableCell<VaultEnvironment, VM>> = RefCell::new(DefaultStableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(ENVIRONMENT_MEMORY)), VaultEnvironment::default()).expect("Failed to initialize ENVIRONMENT StableCell"));
 }
 
 // Structs and Traits
@@ -99,6 +150,15 @@ fn get_name() -> String {
     NAME.with(|name| name.borrow().get().clone())
 }
 
+pub fn get_environment() -> VaultEnvironment {
+    ENVIRONMENT.with(|environment| environment.borrow().get().clone())
+}
+
+#[query(name = "get_environment")]
+fn get_environment_query() -> VaultEnvironment {
+    get_environment()
+}
+
 #[ic_cdk::query]
 fn get_supported_blockchain_adapters() -> Vec<String> {
     ADAPTERS.with(|adapters| adapters.borrow().keys().cloned().collect())
@@ -137,15 +197,33 @@ struct ProposeTransactionArgs {
     pub network: SupportedNetwork,
     pub amount: f64,
     pub transaction_type: TransactionType,
+    pub payload: Option<TransactionPayload>,
+    /// Named EVM wallet to send from, the vault's main address when empty.
+    #[serde(default)]
+    pub wallet: Option<String>,
 }
 
 #[update]
 fn propose_transaction(proposed_transaction: ProposeTransactionArgs) -> ProposedTransaction {
     let caller = ic_cdk::caller();
-    let last_id = PROPOSED_TRANSACTIONS_LAST_ID.with(|last_id| last_id.borrow().get().clone());
+    if !signer_exists(caller) {
+        ic_cdk::trap("Caller is not a signer");
+    }
+    if let Err(e) = evm_wallets::validate_source(
+        proposed_transaction.wallet.as_deref(),
+        &proposed_transaction.token,
+    ) {
+        ic_cdk::trap(&e);
+    }
+    if let Err(e) = validate_network(&proposed_transaction.network, &proposed_transaction.token) {
+        ic_cdk::trap(&e);
+    }
+    if proposed_transaction.transaction_type == TransactionType::Batch {
+        ic_cdk::trap("Batches are proposed with propose_batch");
+    }
 
-    let proposed_transaction = ProposedTransaction {
-        id: last_id,
+    push_proposed_transaction(ProposedTransaction {
+        id: 0,
         to: proposed_transaction.to,
         token: proposed_transaction.token,
         network: proposed_transaction.network,
@@ -153,7 +231,37 @@ fn propose_transaction(proposed_transaction: ProposeTransactionArgs) -> Proposed
         transaction_type: proposed_transaction.transaction_type,
         signers: vec![caller],
         rejections: vec![],
-    };
+        payload: match proposed_transaction.payload {
+            Some(TransactionPayload::ContractCall(call)) => Some(TransactionPayload::ContractCall(
+                evm::complete_contract_call(call).unwrap_or_else(|e| ic_cdk::trap(&e)),
+            )),
+            Some(TransactionPayload::SignMessage(message)) => Some(TransactionPayload::SignMessage(
+                evm_signing::complete_message(message).unwrap_or_else(|e| ic_cdk::trap(&e)),
+            )),
+            Some(TransactionPayload::CanisterCall(call)) => {
+                canister_calls::validate_call(&call).unwrap_or_else(|e| ic_cdk::trap(&e));
+                Some(TransactionPayload::CanisterCall(call))
+            }
+            Some(TransactionPayload::Canister(command)) => {
+                controlled_canisters::validate_command(&command)
+                    .unwrap_or_else(|e| ic_cdk::trap(&e));
+                Some(TransactionPayload::Canister(command))
+            }
+            payload => payload,
+        },
+        wallet: proposed_transaction.wallet,
+        executed: false,
+    })
+}
+
+/// Stores a new proposal under the next id, which replaces the one it has.
+fn push_proposed_transaction(mut proposed_transaction: ProposedTransaction) -> ProposedTransaction {
+    proposed_transaction.id =
+        PROPOSED_TRANSACTIONS_LAST_ID.with(|last_id| last_id.borrow().get().clone());
+    let signers = SIGNERS.with(|signers| signers.borrow().iter().collect());
+    if let Err(e) = proposed_transaction.check_size(signers) {
+        ic_cdk::trap(&e);
+    }
 
     PROPOSED_TRANSACTIONS.with(|proposed_transactions| {
         proposed_transactions
@@ -236,6 +344,9 @@ fn approve_transaction(id: u64) -> ProposedTransaction {
                 ic_cdk::trap(&format!("Proposed transaction with id {} not found", id));
             });
 
+        if dxdy.signers.contains(&caller) {
+            ic_cdk::trap(&format!("Proposed transaction {} is already approved by the caller", id));
+        }
         dxdy.signers.push(caller);
 
         proposed_transactions.set(index_of as u64, &dxdy);
@@ -263,6 +374,9 @@ fn reject_transaction(id: u64) -> ProposedTransaction {
                 ic_cdk::trap(&format!("Proposed transaction with id {} not found", id));
             });
 
+        if dxdy.rejections.contains(&caller) {
+            ic_cdk::trap(&format!("Proposed transaction {} is already rejected by the caller", id));
+        }
         dxdy.rejections.push(caller);
 
         proposed_transactions.set(index_of as u64, &dxdy);
@@ -271,43 +385,173 @@ fn reject_transaction(id: u64) -> ProposedTransaction {
     })
 }
 
-#[ic_cdk::post_upgrade]
-fn post_upgrade() {
+fn register_adapters() {
     ADAPTERS.with(|adapters| {
-        adapters.borrow_mut().insert(
+        let mut adapters = adapters.borrow_mut();
+        adapters.insert(
             "icp:native:transfer".to_string(),
             Box::new(ICPNativeTransferAdapter::new()),
         );
-        adapters.borrow_mut().insert(
+        adapters.insert(
             "icp:icrc1:transfer".to_string(),
             Box::new(ICRC1TransferAdapter::new()),
-        )
+        );
+        adapters.insert(
+            "btc:native:transfer".to_string(),
+            Box::new(btc::BTCNativeTransferAdapter::new(BitcoinAddressType::P2wpkh)),
+        );
+        adapters.insert(
+            "btc:taproot:transfer".to_string(),
+            Box::new(btc::BTCNativeTransferAdapter::new(BitcoinAddressType::P2tr)),
+        );
+        adapters.insert(
+            "sol:native:transfer".to_string(),
+            Box::new(solana::SolanaTransferAdapter::new()),
+        );
+        adapters.insert(
+            "sol:spl:transfer".to_string(),
+            Box::new(solana::SolanaTransferAdapter::new()),
+        );
+
+        // Every configured EVM chain is a network of its own, e.g. base:native
+        for chain in get_environment().evm_chains {
+            adapters.insert(
+                format!("{}:native:transfer", chain.chain),
+                Box::new(EVMNativeTransferAdapter::new(chain.chain.clone())),
+            );
+            adapters.insert(
+                format!("{}:erc20:transfer", chain.chain),
+                Box::new(evm::ERC20TransferAdapter::new()),
+            );
+            adapters.insert(
+                format!("{}:native:contract_call", chain.chain),
+                Box::new(evm::EVMContractCallAdapter::new()),
+            );
+            adapters.insert(
+                format!("{}:native:sign_message", chain.chain),
+                Box::new(evm_signing::EVMSignMessageAdapter::new()),
+            );
+            adapters.insert(
+                format!("{}:native:set_fee_cap", chain.chain),
+                Box::new(evm::EVMSetFeeCapAdapter::new(chain.chain.clone())),
+            );
+        }
+
+        // Both the ICP ledger and ICRC-1 ledgers implement ICRC-2.
+        for token in ["icp:native", "icp:icrc1"] {
+            adapters.insert(
+                format!("{}:approve", token),
+                Box::new(ICRC2ApproveAdapter::new()),
+            );
+            adapters.insert(
+                format!("{}:revoke_approval", token),
+                Box::new(ICRC2RevokeApprovalAdapter::new()),
+            );
+            adapters.insert(
+                format!("{}:transfer_from", token),
+                Box::new(ICRC2TransferFromAdapter::new()),
+            );
+        }
+
+        adapters.insert(
+            "icp:native:swap".to_string(),
+            Box::new(SwapAdapter::new()),
+        );
+        adapters.insert(
+            "icp:icrc1:swap".to_string(),
+            Box::new(SwapAdapter::new()),
+        );
+        adapters.insert(
+            "icp:native:stake".to_string(),
+            Box::new(nns::NNSStakeAdapter::new()),
+        );
+        adapters.insert(
+            "icp:native:manage_neuron".to_string(),
+            Box::new(nns::NNSManageNeuronAdapter::new()),
+        );
+        adapters.insert(
+            "icp:native:canister_call".to_string(),
+            Box::new(canister_calls::CanisterCallAdapter::new()),
+        );
+        adapters.insert(
+            "icp:native:manage_canister".to_string(),
+            Box::new(controlled_canisters::ManageCanisterAdapter::new()),
+        );
+        adapters.insert(
+            "icp:native:top_up".to_string(),
+            Box::new(cycles::CMCTopUpAdapter::new()),
+        );
+        adapters.insert(
+            "icp:native:create_canister".to_string(),
+            Box::new(cycles::CMCCreateCanisterAdapter::new()),
+        );
+        adapters.insert(
+            "icp:cycles:transfer".to_string(),
+            Box::new(cycles::CyclesTransferAdapter::new()),
+        );
+        adapters.insert(
+            "icp:cycles:withdraw".to_string(),
+            Box::new(cycles::CyclesWithdrawAdapter::new()),
+        );
+        adapters.insert(
+            "icp:icrc1:stake".to_string(),
+            Box::new(sns::SNSStakeAdapter::new()),
+        );
+        adapters.insert(
+            "icp:icrc1:manage_neuron".to_string(),
+            Box::new(sns::SNSManageNeuronAdapter::new()),
+        );
+        adapters.insert(
+            "icp:icrc1:deposit".to_string(),
+            Box::new(ck_minters::ChainKeyDepositAdapter::new()),
+        );
+        adapters.insert(
+            "icp:icrc1:withdraw".to_string(),
+            Box::new(ck_minters::ChainKeyWithdrawAdapter::new()),
+        );
+        adapters.insert(
+            "icp:icrc7:transfer".to_string(),
+            Box::new(ICRC7TransferAdapter::new()),
+        );
+        adapters.insert(
+            "icp:icrc7:approve".to_string(),
+            Box::new(ICRC37ApproveAdapter::new()),
+        );
+        adapters.insert(
+            "icp:icrc7:revoke_approval".to_string(),
+            Box::new(ICRC37RevokeApprovalAdapter::new()),
+        );
     });
 }
 
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    register_adapters();
+    evm_confirmations::start_polling();
+    ck_minters::start_polling();
+    cycles_monitor::start_monitoring();
+    alloy_services::warm_evm_key_cache();
+}
+
 #[ic_cdk::init]
-async fn init(keygate_core::types::canister_init::VaultInitArgs { name, signers }: keygate_core::types::canister_init::VaultInitArgs) {
+async fn init(keygate_core::types::canister_init::VaultInitArgs { name, signers, environment, central }: keygate_core::types::canister_init::VaultInitArgs) {
     NAME.with(|n| {
         n.borrow_mut().set(name)
             .map_err(|e| ic_cdk::trap(&format!("Failed to set name: {:?}", e)))
             .unwrap();
     });
 
-    ADAPTERS.with(|adapters| {
-        adapters.borrow_mut().insert(
-            "icp:native:transfer".to_string(),
-            Box::new(ICPNativeTransferAdapter::new()),
-        );
-        adapters.borrow_mut().insert(
-            "icp:icrc1:transfer".to_string(),
-            Box::new(ICRC1TransferAdapter::new()),
-        );
-        adapters.borrow_mut().insert(
-            "eth:native:transfer".to_string(),
-            Box::new(ETHNativeTransferAdapter::new()),
-        );
+    ENVIRONMENT.with(|e| {
+        e.borrow_mut().set(environment.unwrap_or_default())
+            .map_err(|e| ic_cdk::trap(&format!("Failed to set environment: {:?}", e)))
+            .unwrap();
     });
 
+    register_adapters();
+    cycles_monitor::init(central);
+    cycles_monitor::start_monitoring();
+    alloy_services::warm_evm_key_cache();
+
     SIGNERS.with(|s| {
         for signer in signers {
             s.borrow_mut().push(&signer).expect("Failed to add signer");
@@ -0,0 +1,374 @@
+use std::{cell::RefCell, future::Future, pin::Pin};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_stable_structures::StableVec;
+use icrc_ledger_types::icrc1::account::Account;
+use keygate_core::types::vault::{ApprovalArgs, NftHoldings, TransactionPayload};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::{
+    get_default_icrc_subaccount,
+    icrc2::parse_account,
+    intent::{BlockchainAdapter, IntentStatus, TokenPath, TransactionRequest},
+    signer_exists, Error, MEMORY_MANAGER, NFT_COLLECTIONS_MEMORY, VM,
+};
+
+thread_local! {
+    static NFT_COLLECTIONS: RefCell<StableVec<Principal, VM>> = RefCell::new(
+        StableVec::init(MEMORY_MANAGER.with(|m| m.borrow().get(NFT_COLLECTIONS_MEMORY)))
+            .expect("Failed to initialize NFT_COLLECTIONS StableVec")
+    );
+}
+
+/// Page size used when listing the tokens of a collection.
+const TOKENS_PAGE_SIZE: u64 = 100;
+
+// ICRC-7 and ICRC-37 types, see https://github.com/dfinity/ICRC/tree/main/ICRCs
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct ICRC7TransferArg {
+    from_subaccount: Option<ByteBuf>,
+    to: Account,
+    token_id: Nat,
+    memo: Option<ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct ApprovalInfo {
+    spender: Account,
+    from_subaccount: Option<ByteBuf>,
+    expires_at: Option<u64>,
+    memo: Option<ByteBuf>,
+    created_at_time: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct ApproveTokenArg {
+    token_id: Nat,
+    approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct ApproveCollectionArg {
+    approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct RevokeTokenApprovalArg {
+    spender: Option<Account>,
+    from_subaccount: Option<ByteBuf>,
+    token_id: Nat,
+    memo: Option<ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct RevokeCollectionApprovalArg {
+    spender: Option<Account>,
+    from_subaccount: Option<ByteBuf>,
+    memo: Option<ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+/// Union of the errors returned by ICRC-7 transfers and ICRC-37 approvals.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+enum NftError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    InvalidSpender,
+    Unauthorized,
+    ApprovalDoesNotExist,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+type NftBatchResult = Vec<Option<Result<Nat, NftError>>>;
+
+/// An ICRC-7 token path: `icp:icrc7:<collection>` or `icp:icrc7:<collection>:<token_id>`.
+struct NftPath {
+    collection: Principal,
+    token_id: Option<Nat>,
+}
+
+impl NftPath {
+    fn parse(token: &TokenPath) -> Result<NftPath, String> {
+        let parts: Vec<&str> = token.split(':').collect();
+        let (collection, token_id) = match parts.as_slice() {
+            ["icp", "icrc7", collection] => (collection, None),
+            ["icp", "icrc7", collection, token_id] => (collection, Some(token_id)),
+            _ => return Err(format!("Invalid ICRC-7 token path: {}", token)),
+        };
+
+        let collection = Principal::from_text(collection)
+            .map_err(|e| format!("Invalid collection principal {}: {}", collection, e))?;
+        let token_id = token_id
+            .map(|id| {
+                id.parse::<u128>()
+                    .map(Nat::from)
+                    .map_err(|e| format!("Invalid token id {}: {}", id, e))
+            })
+            .transpose()?;
+
+        Ok(NftPath {
+            collection,
+            token_id,
+        })
+    }
+
+    fn require_token_id(&self) -> Result<Nat, String> {
+        self.token_id
+            .clone()
+            .ok_or_else(|| "A token id is required for this operation".to_string())
+    }
+}
+
+fn vault_subaccount() -> Option<ByteBuf> {
+    Some(ByteBuf::from(get_default_icrc_subaccount().0.to_vec()))
+}
+
+/// Calls a batch endpoint with a single argument and unwraps its single result.
+async fn call_single<A: CandidType>(
+    collection: Principal,
+    method: &str,
+    arg: A,
+) -> Result<Nat, String> {
+    let call_result: CallResult<(NftBatchResult,)> =
+        ic_cdk::call(collection, method, (vec![arg],)).await;
+    match call_result {
+        Ok((results,)) => match results.into_iter().next() {
+            Some(Some(Ok(index))) => Ok(index),
+            Some(Some(Err(e))) => Err(format!("{} error: {:?}", method, e)),
+            _ => Err(format!("{} returned no result", method)),
+        },
+        Err((rejection_code, message)) => Err(format!(
+            "Canister call rejected: {:?} - {}",
+            rejection_code, message
+        )),
+    }
+}
+
+#[derive(Clone)]
+pub struct ICRC7TransferAdapter {}
+
+impl ICRC7TransferAdapter {
+    pub fn new() -> ICRC7TransferAdapter {
+        ICRC7TransferAdapter {}
+    }
+}
+
+impl BlockchainAdapter for ICRC7TransferAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ICRC7TransferAdapter");
+
+            let path = NftPath::parse(&transaction.token)?;
+            let arg = ICRC7TransferArg {
+                from_subaccount: vault_subaccount(),
+                to: parse_account(&transaction.to)?,
+                token_id: path.require_token_id()?,
+                memo: None,
+                created_at_time: None,
+            };
+
+            ic_cdk::println!("Args: {:?}", arg);
+
+            call_single(path.collection, "icrc7_transfer", arg).await?;
+
+            Ok(IntentStatus::Completed(
+                "Successfully transferred an ICRC-7 token.".to_string(),
+            ))
+        })
+    }
+}
+
+/// ICRC-37 approval of a single token, or of the whole collection when the token
+/// path has no token id.
+#[derive(Clone)]
+pub struct ICRC37ApproveAdapter {}
+
+impl ICRC37ApproveAdapter {
+    pub fn new() -> ICRC37ApproveAdapter {
+        ICRC37ApproveAdapter {}
+    }
+}
+
+impl BlockchainAdapter for ICRC37ApproveAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ICRC37ApproveAdapter");
+
+            let path = NftPath::parse(&transaction.token)?;
+            let expires_at = match &transaction.payload {
+                None => None,
+                Some(TransactionPayload::Approval(ApprovalArgs { expires_at, .. })) => *expires_at,
+                Some(other) => {
+                    return Err(format!("Unexpected payload for an approval: {:?}", other))
+                }
+            };
+            let approval_info = ApprovalInfo {
+                spender: parse_account(&transaction.to)?,
+                from_subaccount: vault_subaccount(),
+                expires_at,
+                memo: None,
+                created_at_time: ic_cdk::api::time(),
+            };
+
+            match path.token_id {
+                Some(token_id) => {
+                    let arg = ApproveTokenArg {
+                        token_id,
+                        approval_info,
+                    };
+                    call_single(path.collection, "icrc37_approve_tokens", arg).await?;
+                }
+                None => {
+                    let arg = ApproveCollectionArg { approval_info };
+                    call_single(path.collection, "icrc37_approve_collection", arg).await?;
+                }
+            }
+
+            Ok(IntentStatus::Completed(
+                "Successfully approved an ICRC-37 spender.".to_string(),
+            ))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ICRC37RevokeApprovalAdapter {}
+
+impl ICRC37RevokeApprovalAdapter {
+    pub fn new() -> ICRC37RevokeApprovalAdapter {
+        ICRC37RevokeApprovalAdapter {}
+    }
+}
+
+impl BlockchainAdapter for ICRC37RevokeApprovalAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing ICRC37RevokeApprovalAdapter");
+
+            let path = NftPath::parse(&transaction.token)?;
+            // An empty recipient revokes the approvals of every spender
+            let spender = match transaction.to.trim() {
+                "" => None,
+                to => Some(parse_account(to)?),
+            };
+
+            match path.token_id {
+                Some(token_id) => {
+                    let arg = RevokeTokenApprovalArg {
+                        spender,
+                        from_subaccount: vault_subaccount(),
+                        token_id,
+                        memo: None,
+                        created_at_time: None,
+                    };
+                    call_single(path.collection, "icrc37_revoke_token_approvals", arg).await?;
+                }
+                None => {
+                    let arg = RevokeCollectionApprovalArg {
+                        spender,
+                        from_subaccount: vault_subaccount(),
+                        memo: None,
+                        created_at_time: None,
+                    };
+                    call_single(path.collection, "icrc37_revoke_collection_approvals", arg)
+                        .await?;
+                }
+            }
+
+            Ok(IntentStatus::Completed(
+                "Successfully revoked an ICRC-37 approval.".to_string(),
+            ))
+        })
+    }
+}
+
+#[ic_cdk::update]
+pub fn register_nft_collection(collection: Principal) -> Result<(), Error> {
+    if !signer_exists(ic_cdk::caller()) {
+        ic_cdk::trap("Caller is not a signer");
+    }
+
+    NFT_COLLECTIONS.with(|collections| {
+        let collections = collections.borrow_mut();
+        if collections.iter().any(|c| c == collection) {
+            return Err(Error {
+                message: "Collection already registered".to_string(),
+            });
+        }
+
+        collections.push(&collection).map_err(|e| Error {
+            message: format!("Failed to register collection: {:?}", e),
+        })
+    })
+}
+
+#[ic_cdk::query]
+pub fn get_nft_collections() -> Vec<Principal> {
+    NFT_COLLECTIONS.with(|collections| collections.borrow().iter().collect())
+}
+
+/// Lists the tokens held by the vault in every registered collection.
+#[ic_cdk::query(composite = true)]
+pub async fn get_nfts() -> Vec<NftHoldings> {
+    let account = Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(get_default_icrc_subaccount().0),
+    };
+
+    let mut holdings = vec![];
+    for collection in get_nft_collections() {
+        let mut token_ids: Vec<Nat> = vec![];
+        loop {
+            let prev = token_ids.last().cloned();
+            let page_result: CallResult<(Vec<Nat>,)> = ic_cdk::call(
+                collection,
+                "icrc7_tokens_of",
+                (account, prev, Some(Nat::from(TOKENS_PAGE_SIZE))),
+            )
+            .await;
+
+            match page_result {
+                Ok((page,)) => {
+                    let last_page = (page.len() as u64) < TOKENS_PAGE_SIZE;
+                    token_ids.extend(page);
+                    if last_page {
+                        break;
+                    }
+                }
+                Err((rejection_code, message)) => {
+                    ic_cdk::println!(
+                        "Failed to list tokens of {}: {:?} - {}",
+                        collection,
+                        rejection_code,
+                        message
+                    );
+                    break;
+                }
+            }
+        }
+
+        holdings.push(NftHoldings {
+            collection,
+            token_ids,
+        });
+    }
+
+    holdings
+}
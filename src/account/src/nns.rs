@@ -0,0 +1,494 @@
+use std::{future::Future, pin::Pin};
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_ledger_types::{AccountIdentifier, Memo, Subaccount, Tokens, TransferArgs};
+use keygate_core::{
+    types::vault::{NeuronCommand, NeuronState, NnsNeuron, TransactionPayload},
+    utils::to_subaccount,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    get_environment,
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest, RECOMMENDED_ICP_TRANSACTION_FEE},
+};
+
+/// NNS governance, rrkah-fqaaa-aaaaa-aaaaq-cai.
+const NNS_GOVERNANCE_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+
+// NNS governance interface, see https://github.com/dfinity/ic/blob/master/rs/nns/governance/canister/governance.did
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct NeuronId {
+    id: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct ProposalId {
+    id: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct GovernanceError {
+    error_type: i32,
+    error_message: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ClaimOrRefreshNeuronFromAccount {
+    controller: Option<Principal>,
+    memo: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum ClaimOrRefreshResult {
+    Error(GovernanceError),
+    NeuronId(NeuronId),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ClaimOrRefreshNeuronFromAccountResponse {
+    result: Option<ClaimOrRefreshResult>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct Empty {}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct IncreaseDissolveDelay {
+    additional_dissolve_delay_seconds: u32,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum Operation {
+    IncreaseDissolveDelay(IncreaseDissolveDelay),
+    StartDissolving(Empty),
+    StopDissolving(Empty),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Configure {
+    operation: Option<Operation>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Follow {
+    topic: i32,
+    followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct RegisterVote {
+    vote: i32,
+    proposal: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct LedgerAccount {
+    hash: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Amount {
+    e8s: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Disburse {
+    to_account: Option<LedgerAccount>,
+    amount: Option<Amount>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum Command {
+    Configure(Configure),
+    Follow(Follow),
+    RegisterVote(RegisterVote),
+    Disburse(Disburse),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ManageNeuron {
+    id: Option<NeuronId>,
+    command: Option<Command>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct DisburseResponse {
+    transfer_block_height: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum CommandResponse {
+    Error(GovernanceError),
+    Configure(Empty),
+    Follow(Empty),
+    RegisterVote(Empty),
+    Disburse(DisburseResponse),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ManageNeuronResponse {
+    command: Option<CommandResponse>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ListNeurons {
+    neuron_ids: Vec<u64>,
+    include_neurons_readable_by_caller: bool,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct BallotInfo {
+    vote: i32,
+    proposal_id: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct NeuronInfo {
+    dissolve_delay_seconds: u64,
+    state: i32,
+    stake_e8s: u64,
+    recent_ballots: Vec<BallotInfo>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Followees {
+    followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Neuron {
+    id: Option<NeuronId>,
+    followees: Vec<(i32, Followees)>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ListNeuronsResponse {
+    neuron_infos: Vec<(u64, NeuronInfo)>,
+    full_neurons: Vec<Neuron>,
+}
+
+// Ballot values of `RegisterVote`
+const VOTE_YES: i32 = 1;
+const VOTE_NO: i32 = 2;
+
+fn governance_error(error: GovernanceError) -> String {
+    format!("NNS governance error {}: {}", error.error_type, error.error_message)
+}
+
+fn rejected(rejection_code: impl std::fmt::Debug, message: String) -> String {
+    format!("Canister call rejected: {:?} - {}", rejection_code, message)
+}
+
+/// Subaccount of the governance canister a neuron of `controller` is staked from. SNS
+/// governance canisters derive it the same way.
+pub(crate) fn neuron_subaccount(controller: Principal, memo: u64) -> Subaccount {
+    let mut hasher = Sha256::new();
+    hasher.update([0x0c]);
+    hasher.update(b"neuron-stake");
+    hasher.update(controller.as_slice());
+    hasher.update(memo.to_be_bytes());
+    Subaccount(hasher.finalize().into())
+}
+
+fn neuron_state(state: i32) -> NeuronState {
+    match state {
+        1 => NeuronState::Locked,
+        2 => NeuronState::Dissolving,
+        3 => NeuronState::Dissolved,
+        4 => NeuronState::Spawning,
+        _ => NeuronState::Unspecified,
+    }
+}
+
+fn neuron_command(command: &NeuronCommand) -> Result<(u64, Command), String> {
+    let configure = |operation| {
+        Command::Configure(Configure {
+            operation: Some(operation),
+        })
+    };
+
+    Ok(match command {
+        NeuronCommand::IncreaseDissolveDelay {
+            neuron_id,
+            additional_dissolve_delay_seconds,
+        } => (
+            *neuron_id,
+            configure(Operation::IncreaseDissolveDelay(IncreaseDissolveDelay {
+                additional_dissolve_delay_seconds: *additional_dissolve_delay_seconds,
+            })),
+        ),
+        NeuronCommand::StartDissolving { neuron_id } => {
+            (*neuron_id, configure(Operation::StartDissolving(Empty {})))
+        }
+        NeuronCommand::StopDissolving { neuron_id } => {
+            (*neuron_id, configure(Operation::StopDissolving(Empty {})))
+        }
+        NeuronCommand::SetFollowing {
+            neuron_id,
+            topic,
+            followees,
+        } => (
+            *neuron_id,
+            Command::Follow(Follow {
+                topic: *topic,
+                followees: followees.iter().map(|id| NeuronId { id: *id }).collect(),
+            }),
+        ),
+        NeuronCommand::Vote {
+            neuron_id,
+            proposal_id,
+            approve,
+        } => (
+            *neuron_id,
+            Command::RegisterVote(RegisterVote {
+                vote: if *approve { VOTE_YES } else { VOTE_NO },
+                proposal: Some(ProposalId { id: *proposal_id }),
+            }),
+        ),
+        NeuronCommand::Disburse {
+            neuron_id,
+            to_account,
+            amount_e8s,
+        } => {
+            let to_account = to_account
+                .as_ref()
+                .map(|account| {
+                    AccountIdentifier::from_hex(account)
+                        .map_err(|e| format!("Invalid account identifier {}: {}", account, e))
+                })
+                .transpose()?;
+
+            (
+                *neuron_id,
+                Command::Disburse(Disburse {
+                    to_account: to_account.map(|account| LedgerAccount {
+                        hash: account.as_ref().to_vec(),
+                    }),
+                    amount: amount_e8s.map(|e8s| Amount { e8s }),
+                }),
+            )
+        }
+    })
+}
+
+async fn manage_neuron(neuron_id: u64, command: Command) -> Result<CommandResponse, String> {
+    let request = ManageNeuron {
+        id: Some(NeuronId { id: neuron_id }),
+        command: Some(command),
+    };
+    let result: CallResult<(ManageNeuronResponse,)> =
+        ic_cdk::call(NNS_GOVERNANCE_CANISTER_ID, "manage_neuron", (request,)).await;
+
+    match result {
+        Ok((ManageNeuronResponse {
+            command: Some(CommandResponse::Error(error)),
+        },)) => Err(governance_error(error)),
+        Ok((ManageNeuronResponse {
+            command: Some(response),
+        },)) => Ok(response),
+        Ok((ManageNeuronResponse { command: None },)) => {
+            Err("NNS governance returned no response".to_string())
+        }
+        Err((rejection_code, message)) => Err(rejected(rejection_code, message)),
+    }
+}
+
+/// Stakes ICP of the vault in a new neuron it controls: the stake is sent to a
+/// subaccount of the governance canister, which then claims it as a neuron.
+#[derive(Clone)]
+pub struct NNSStakeAdapter {}
+
+impl NNSStakeAdapter {
+    pub fn new() -> NNSStakeAdapter {
+        NNSStakeAdapter {}
+    }
+}
+
+impl BlockchainAdapter for NNSStakeAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing NNSStakeAdapter");
+
+            // Every neuron of a controller needs its own memo
+            let memo = ic_cdk::api::time();
+            let args = TransferArgs {
+                memo: Memo(memo),
+                amount: Tokens::from_e8s(transaction.amount as u64),
+                fee: Tokens::from_e8s(RECOMMENDED_ICP_TRANSACTION_FEE),
+                from_subaccount: Some(to_subaccount(0)),
+                to: AccountIdentifier::new(
+                    &NNS_GOVERNANCE_CANISTER_ID,
+                    &neuron_subaccount(ic_cdk::id(), memo),
+                ),
+                created_at_time: None,
+            };
+            match ic_ledger_types::transfer(get_environment().icp_ledger, args).await {
+                Ok(Ok(_)) => (),
+                Ok(Err(e)) => return Err(format!("transfer error: {:?}", e)),
+                Err((rejection_code, message)) => return Err(rejected(rejection_code, message)),
+            }
+
+            let request = ClaimOrRefreshNeuronFromAccount {
+                controller: Some(ic_cdk::id()),
+                memo,
+            };
+            let result: CallResult<(ClaimOrRefreshNeuronFromAccountResponse,)> = ic_cdk::call(
+                NNS_GOVERNANCE_CANISTER_ID,
+                "claim_or_refresh_neuron_from_account",
+                (request,),
+            )
+            .await;
+
+            // The stake stays on the governance account when claiming fails, a later
+            // claim with the same memo recovers it
+            match result {
+                Ok((ClaimOrRefreshNeuronFromAccountResponse {
+                    result: Some(ClaimOrRefreshResult::NeuronId(neuron_id)),
+                },)) => Ok(IntentStatus::Completed(format!(
+                    "Successfully staked neuron {}.",
+                    neuron_id.id
+                ))),
+                Ok((ClaimOrRefreshNeuronFromAccountResponse {
+                    result: Some(ClaimOrRefreshResult::Error(error)),
+                },)) => Err(format!(
+                    "Could not claim the neuron of memo {}: {}",
+                    memo,
+                    governance_error(error)
+                )),
+                Ok((ClaimOrRefreshNeuronFromAccountResponse { result: None },)) => Err(format!(
+                    "Could not claim the neuron of memo {}: no result",
+                    memo
+                )),
+                Err((rejection_code, message)) => Err(format!(
+                    "Could not claim the neuron of memo {}: {}",
+                    memo,
+                    rejected(rejection_code, message)
+                )),
+            }
+        })
+    }
+}
+
+/// Configures, votes with or disburses one of the vault's NNS neurons.
+#[derive(Clone)]
+pub struct NNSManageNeuronAdapter {}
+
+impl NNSManageNeuronAdapter {
+    pub fn new() -> NNSManageNeuronAdapter {
+        NNSManageNeuronAdapter {}
+    }
+}
+
+impl BlockchainAdapter for NNSManageNeuronAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing NNSManageNeuronAdapter");
+
+            let command = match &transaction.payload {
+                Some(TransactionPayload::Neuron(command)) => command,
+                _ => return Err("Managing a neuron requires a Neuron payload".to_string()),
+            };
+            let (neuron_id, request) = neuron_command(command)?;
+
+            let message = match manage_neuron(neuron_id, request).await? {
+                CommandResponse::Disburse(response) => format!(
+                    "Successfully disbursed neuron {} in block {}.",
+                    neuron_id, response.transfer_block_height
+                ),
+                _ => format!("Successfully managed neuron {}.", neuron_id),
+            };
+
+            Ok(IntentStatus::Completed(message))
+        })
+    }
+}
+
+/// The NNS neurons controlled by the vault.
+#[ic_cdk::update]
+pub async fn get_nns_neurons() -> Result<Vec<NnsNeuron>, String> {
+    let request = ListNeurons {
+        neuron_ids: vec![],
+        include_neurons_readable_by_caller: true,
+    };
+    let result: CallResult<(ListNeuronsResponse,)> =
+        ic_cdk::call(NNS_GOVERNANCE_CANISTER_ID, "list_neurons", (request,)).await;
+    let (response,) = result.map_err(|(code, message)| rejected(code, message))?;
+
+    let neurons = response
+        .neuron_infos
+        .into_iter()
+        .map(|(id, info)| {
+            let followees = response
+                .full_neurons
+                .iter()
+                .find(|neuron| neuron.id.as_ref().map(|n| n.id) == Some(id))
+                .map(|neuron| {
+                    neuron
+                        .followees
+                        .iter()
+                        .map(|(topic, followees)| {
+                            (*topic, followees.followees.iter().map(|f| f.id).collect())
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            NnsNeuron {
+                id,
+                stake_e8s: info.stake_e8s,
+                dissolve_delay_seconds: info.dissolve_delay_seconds,
+                state: neuron_state(info.state),
+                followees,
+                recent_votes: info
+                    .recent_ballots
+                    .iter()
+                    .filter_map(|ballot| {
+                        let proposal = ballot.proposal_id.as_ref()?;
+                        Some((proposal.id, ballot.vote == VOTE_YES))
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Ok(neurons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_neuron_subaccounts() {
+        let controller = Principal::from_slice(&[1, 2, 3]);
+        let subaccount = neuron_subaccount(controller, 7);
+
+        let mut preimage = vec![0x0c];
+        preimage.extend_from_slice(b"neuron-stake");
+        preimage.extend_from_slice(&[1, 2, 3]);
+        preimage.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 7]);
+        assert_eq!(subaccount.0, <[u8; 32]>::from(Sha256::digest(&preimage)));
+        assert_ne!(subaccount, neuron_subaccount(controller, 8));
+    }
+
+    #[test]
+    fn rejects_invalid_disburse_accounts() {
+        let command = NeuronCommand::Disburse {
+            neuron_id: 1,
+            to_account: Some("not hex".to_string()),
+            amount_e8s: None,
+        };
+        assert!(neuron_command(&command).is_err());
+    }
+}
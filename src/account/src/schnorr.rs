@@ -0,0 +1,109 @@
+//! Threshold Schnorr API of the management canister, which ic-cdk 0.15 does not wrap.
+
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+
+use crate::get_environment;
+
+/// Cycles attached to `sign_with_schnorr`, the fee of the production key. The
+/// unused part is refunded.
+const SIGN_WITH_SCHNORR_FEE: u128 = 26_153_846_153;
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SchnorrAlgorithm {
+    #[serde(rename = "bip340secp256k1")]
+    Bip340Secp256k1,
+    #[serde(rename = "ed25519")]
+    Ed25519,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrKeyId {
+    algorithm: SchnorrAlgorithm,
+    name: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrPublicKeyArgument {
+    canister_id: Option<Principal>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrPublicKeyResponse {
+    public_key: Vec<u8>,
+}
+
+/// Tweak applied to a BIP340 key before signing. An empty merkle root tweaks the
+/// key for a Taproot output without a script tree, as in BIP86.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum SignWithSchnorrAux {
+    #[serde(rename = "bip341")]
+    Bip341 { merkle_root_hash: Vec<u8> },
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SignWithSchnorrArgument {
+    message: Vec<u8>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: SchnorrKeyId,
+    aux: Option<SignWithSchnorrAux>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SignWithSchnorrResponse {
+    signature: Vec<u8>,
+}
+
+fn key_id(algorithm: SchnorrAlgorithm) -> SchnorrKeyId {
+    // Threshold keys share their names between ECDSA and Schnorr
+    SchnorrKeyId {
+        algorithm,
+        name: get_environment().ecdsa_key_name,
+    }
+}
+
+/// Public key of the vault for a derivation path, 33 bytes SEC1 for BIP340 and 32
+/// bytes for Ed25519.
+pub async fn schnorr_public_key(
+    algorithm: SchnorrAlgorithm,
+    derivation_path: Vec<Vec<u8>>,
+) -> Result<Vec<u8>, String> {
+    let (response,): (SchnorrPublicKeyResponse,) = ic_cdk::call(
+        Principal::management_canister(),
+        "schnorr_public_key",
+        (SchnorrPublicKeyArgument {
+            canister_id: None,
+            derivation_path,
+            key_id: key_id(algorithm),
+        },),
+    )
+    .await
+    .map_err(|(code, message)| format!("Could not get the Schnorr key: {:?} {}", code, message))?;
+
+    Ok(response.public_key)
+}
+
+pub async fn sign_with_schnorr(
+    algorithm: SchnorrAlgorithm,
+    derivation_path: Vec<Vec<u8>>,
+    message: Vec<u8>,
+    aux: Option<SignWithSchnorrAux>,
+) -> Result<Vec<u8>, String> {
+    let (response,): (SignWithSchnorrResponse,) = ic_cdk::api::call::call_with_payment128(
+        Principal::management_canister(),
+        "sign_with_schnorr",
+        (SignWithSchnorrArgument {
+            message,
+            derivation_path,
+            key_id: key_id(algorithm),
+            aux,
+        },),
+        SIGN_WITH_SCHNORR_FEE,
+    )
+    .await
+    .map_err(|(code, message)| format!("Could not sign: {:?} {}", code, message))?;
+
+    Ok(response.signature)
+}
@@ -0,0 +1,571 @@
+use std::{future::Future, pin::Pin};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use icrc_ledger_types::icrc1::{
+    account::Account as IcrcAccount,
+    transfer::{TransferArg, TransferError},
+};
+use keygate_core::types::vault::{
+    NeuronState, SnsNeuron, SnsNeuronCommand, SnsProposalAction, TransactionPayload,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    get_default_icrc_subaccount,
+    icrc2::{parse_account, resolve_ledger},
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest},
+    nns::neuron_subaccount,
+};
+
+/// Neurons fetched per `list_neurons` call.
+const LIST_NEURONS_PAGE: u32 = 100;
+
+// SNS governance interface, see https://github.com/dfinity/ic/blob/master/rs/sns/governance/canister/governance.did
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct NeuronId {
+    id: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct ProposalId {
+    id: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct GovernanceError {
+    error_type: i32,
+    error_message: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct MemoAndController {
+    controller: Option<Principal>,
+    memo: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum By {
+    MemoAndController(MemoAndController),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ClaimOrRefresh {
+    by: Option<By>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct Empty {}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct IncreaseDissolveDelay {
+    additional_dissolve_delay_seconds: u32,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum Operation {
+    IncreaseDissolveDelay(IncreaseDissolveDelay),
+    StartDissolving(Empty),
+    StopDissolving(Empty),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Configure {
+    operation: Option<Operation>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Follow {
+    function_id: u64,
+    followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct RegisterVote {
+    vote: i32,
+    proposal: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Motion {
+    motion_text: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ExecuteGenericNervousSystemFunction {
+    function_id: u64,
+    payload: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum Action {
+    Motion(Motion),
+    ExecuteGenericNervousSystemFunction(ExecuteGenericNervousSystemFunction),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Proposal {
+    title: String,
+    url: String,
+    summary: String,
+    action: Option<Action>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Subaccount {
+    subaccount: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Account {
+    owner: Option<Principal>,
+    subaccount: Option<Subaccount>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Amount {
+    e8s: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Disburse {
+    to_account: Option<Account>,
+    amount: Option<Amount>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum Command {
+    ClaimOrRefresh(ClaimOrRefresh),
+    Configure(Configure),
+    Follow(Follow),
+    RegisterVote(RegisterVote),
+    MakeProposal(Proposal),
+    Disburse(Disburse),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ManageNeuron {
+    subaccount: Vec<u8>,
+    command: Option<Command>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct ClaimOrRefreshResponse {
+    refreshed_neuron_id: Option<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct GetProposal {
+    proposal_id: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+struct DisburseResponse {
+    transfer_block_height: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+enum CommandResponse {
+    Error(GovernanceError),
+    ClaimOrRefresh(ClaimOrRefreshResponse),
+    Configure(Empty),
+    Follow(Empty),
+    RegisterVote(Empty),
+    MakeProposal(GetProposal),
+    Disburse(DisburseResponse),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ManageNeuronResponse {
+    command: Option<CommandResponse>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ListNeurons {
+    of_principal: Option<Principal>,
+    limit: u32,
+    start_page_at: Option<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+enum DissolveState {
+    DissolveDelaySeconds(u64),
+    WhenDissolvedTimestampSeconds(u64),
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Followees {
+    followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct Neuron {
+    id: Option<NeuronId>,
+    cached_neuron_stake_e8s: u64,
+    neuron_fees_e8s: u64,
+    dissolve_state: Option<DissolveState>,
+    followees: Vec<(u64, Followees)>,
+}
+
+#[derive(CandidType, Deserialize, Serialize)]
+struct ListNeuronsResponse {
+    neurons: Vec<Neuron>,
+}
+
+// Ballot values of `RegisterVote`
+const VOTE_YES: i32 = 1;
+const VOTE_NO: i32 = 2;
+
+fn governance_error(error: GovernanceError) -> String {
+    format!("SNS governance error {}: {}", error.error_type, error.error_message)
+}
+
+fn rejected(rejection_code: impl std::fmt::Debug, message: String) -> String {
+    format!("Canister call rejected: {:?} - {}", rejection_code, message)
+}
+
+fn governance_of(transaction: &TransactionRequest) -> Result<Principal, String> {
+    Principal::from_text(&transaction.to)
+        .map_err(|e| format!("Invalid SNS governance principal {}: {}", transaction.to, e))
+}
+
+/// Remaining dissolve delay and state of a neuron at `now`, in seconds since the epoch.
+fn dissolve_state(state: &Option<DissolveState>, now: u64) -> (u64, NeuronState) {
+    match state {
+        Some(DissolveState::DissolveDelaySeconds(delay)) if *delay > 0 => {
+            (*delay, NeuronState::Locked)
+        }
+        Some(DissolveState::WhenDissolvedTimestampSeconds(when)) if *when > now => {
+            (when - now, NeuronState::Dissolving)
+        }
+        _ => (0, NeuronState::Dissolved),
+    }
+}
+
+fn neuron_command(command: &SnsNeuronCommand) -> Result<(Vec<u8>, Command), String> {
+    let configure = |operation| {
+        Command::Configure(Configure {
+            operation: Some(operation),
+        })
+    };
+
+    Ok(match command {
+        SnsNeuronCommand::IncreaseDissolveDelay {
+            neuron_id,
+            additional_dissolve_delay_seconds,
+        } => (
+            neuron_id.clone(),
+            configure(Operation::IncreaseDissolveDelay(IncreaseDissolveDelay {
+                additional_dissolve_delay_seconds: *additional_dissolve_delay_seconds,
+            })),
+        ),
+        SnsNeuronCommand::StartDissolving { neuron_id } => {
+            (neuron_id.clone(), configure(Operation::StartDissolving(Empty {})))
+        }
+        SnsNeuronCommand::StopDissolving { neuron_id } => {
+            (neuron_id.clone(), configure(Operation::StopDissolving(Empty {})))
+        }
+        SnsNeuronCommand::SetFollowing {
+            neuron_id,
+            function_id,
+            followees,
+        } => (
+            neuron_id.clone(),
+            Command::Follow(Follow {
+                function_id: *function_id,
+                followees: followees
+                    .iter()
+                    .map(|id| NeuronId { id: id.clone() })
+                    .collect(),
+            }),
+        ),
+        SnsNeuronCommand::Vote {
+            neuron_id,
+            proposal_id,
+            approve,
+        } => (
+            neuron_id.clone(),
+            Command::RegisterVote(RegisterVote {
+                vote: if *approve { VOTE_YES } else { VOTE_NO },
+                proposal: Some(ProposalId { id: *proposal_id }),
+            }),
+        ),
+        SnsNeuronCommand::MakeProposal {
+            neuron_id,
+            title,
+            url,
+            summary,
+            action,
+        } => {
+            let action = match action {
+                SnsProposalAction::Motion { motion_text } => Action::Motion(Motion {
+                    motion_text: motion_text.clone(),
+                }),
+                SnsProposalAction::ExecuteGenericNervousSystemFunction {
+                    function_id,
+                    payload,
+                } => Action::ExecuteGenericNervousSystemFunction(
+                    ExecuteGenericNervousSystemFunction {
+                        function_id: *function_id,
+                        payload: payload.clone(),
+                    },
+                ),
+            };
+
+            (
+                neuron_id.clone(),
+                Command::MakeProposal(Proposal {
+                    title: title.clone(),
+                    url: url.clone(),
+                    summary: summary.clone(),
+                    action: Some(action),
+                }),
+            )
+        }
+        SnsNeuronCommand::Disburse {
+            neuron_id,
+            to_account,
+            amount_e8s,
+        } => {
+            let to_account = to_account.as_deref().map(parse_account).transpose()?;
+
+            (
+                neuron_id.clone(),
+                Command::Disburse(Disburse {
+                    to_account: to_account.map(|account| Account {
+                        owner: Some(account.owner),
+                        subaccount: account.subaccount.map(|subaccount| Subaccount {
+                            subaccount: subaccount.to_vec(),
+                        }),
+                    }),
+                    amount: amount_e8s.map(|e8s| Amount { e8s }),
+                }),
+            )
+        }
+    })
+}
+
+async fn manage_neuron(
+    governance: Principal,
+    neuron_id: Vec<u8>,
+    command: Command,
+) -> Result<CommandResponse, String> {
+    let request = ManageNeuron {
+        subaccount: neuron_id,
+        command: Some(command),
+    };
+    let result: CallResult<(ManageNeuronResponse,)> =
+        ic_cdk::call(governance, "manage_neuron", (request,)).await;
+
+    match result {
+        Ok((ManageNeuronResponse {
+            command: Some(CommandResponse::Error(error)),
+        },)) => Err(governance_error(error)),
+        Ok((ManageNeuronResponse {
+            command: Some(response),
+        },)) => Ok(response),
+        Ok((ManageNeuronResponse { command: None },)) => {
+            Err("SNS governance returned no response".to_string())
+        }
+        Err((rejection_code, message)) => Err(rejected(rejection_code, message)),
+    }
+}
+
+/// Stakes SNS tokens of the vault in a new neuron it controls. The token is the SNS
+/// ledger and `to` its governance canister, which claims the stake sent to one of its
+/// subaccounts.
+#[derive(Clone)]
+pub struct SNSStakeAdapter {}
+
+impl SNSStakeAdapter {
+    pub fn new() -> SNSStakeAdapter {
+        SNSStakeAdapter {}
+    }
+}
+
+impl BlockchainAdapter for SNSStakeAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing SNSStakeAdapter");
+
+            let governance = governance_of(transaction)?;
+            let ledger = resolve_ledger(&transaction.token)?;
+
+            // Every neuron of a controller needs its own memo
+            let memo = ic_cdk::api::time();
+            let subaccount = neuron_subaccount(ic_cdk::id(), memo).0;
+            let args = TransferArg {
+                from_subaccount: Some(get_default_icrc_subaccount().0),
+                to: IcrcAccount {
+                    owner: governance,
+                    subaccount: Some(subaccount),
+                },
+                // SNS ledgers have their own fees
+                fee: None,
+                created_at_time: None,
+                memo: None,
+                amount: Nat::from(transaction.amount as u64),
+            };
+            let result: CallResult<(Result<Nat, TransferError>,)> =
+                ic_cdk::call(ledger, "icrc1_transfer", (args,)).await;
+            match result {
+                Ok((Ok(_),)) => (),
+                Ok((Err(e),)) => return Err(format!("ICRC-1 transfer error: {:?}", e)),
+                Err((rejection_code, message)) => return Err(rejected(rejection_code, message)),
+            }
+
+            let claim = Command::ClaimOrRefresh(ClaimOrRefresh {
+                by: Some(By::MemoAndController(MemoAndController {
+                    controller: Some(ic_cdk::id()),
+                    memo,
+                })),
+            });
+
+            // The stake stays on the governance account when claiming fails, a later
+            // claim with the same memo recovers it
+            match manage_neuron(governance, subaccount.to_vec(), claim).await {
+                Ok(CommandResponse::ClaimOrRefresh(ClaimOrRefreshResponse {
+                    refreshed_neuron_id: Some(neuron_id),
+                })) => Ok(IntentStatus::Completed(format!(
+                    "Successfully staked SNS neuron {}.",
+                    hex::encode(neuron_id.id)
+                ))),
+                Ok(response) => Err(format!(
+                    "Could not claim the neuron of memo {}: unexpected response {:?}",
+                    memo, response
+                )),
+                Err(e) => Err(format!("Could not claim the neuron of memo {}: {}", memo, e)),
+            }
+        })
+    }
+}
+
+/// Configures, votes with, proposes with or disburses one of the vault's SNS neurons.
+#[derive(Clone)]
+pub struct SNSManageNeuronAdapter {}
+
+impl SNSManageNeuronAdapter {
+    pub fn new() -> SNSManageNeuronAdapter {
+        SNSManageNeuronAdapter {}
+    }
+}
+
+impl BlockchainAdapter for SNSManageNeuronAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing SNSManageNeuronAdapter");
+
+            let command = match &transaction.payload {
+                Some(TransactionPayload::SnsNeuron(command)) => command,
+                _ => return Err("Managing an SNS neuron requires an SnsNeuron payload".to_string()),
+            };
+            let governance = governance_of(transaction)?;
+            let (neuron_id, request) = neuron_command(command)?;
+            let neuron = hex::encode(&neuron_id);
+
+            let message = match manage_neuron(governance, neuron_id, request).await? {
+                CommandResponse::MakeProposal(GetProposal {
+                    proposal_id: Some(proposal_id),
+                }) => format!(
+                    "Successfully submitted SNS proposal {} with neuron {}.",
+                    proposal_id.id, neuron
+                ),
+                CommandResponse::Disburse(response) => format!(
+                    "Successfully disbursed SNS neuron {} in block {}.",
+                    neuron, response.transfer_block_height
+                ),
+                _ => format!("Successfully managed SNS neuron {}.", neuron),
+            };
+
+            Ok(IntentStatus::Completed(message))
+        })
+    }
+}
+
+/// The neurons the vault controls in the SNS of `governance`.
+#[ic_cdk::update]
+pub async fn get_sns_neurons(governance: Principal) -> Result<Vec<SnsNeuron>, String> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let mut neurons = vec![];
+    let mut start_page_at = None;
+
+    loop {
+        let request = ListNeurons {
+            of_principal: Some(ic_cdk::id()),
+            limit: LIST_NEURONS_PAGE,
+            start_page_at,
+        };
+        let result: CallResult<(ListNeuronsResponse,)> =
+            ic_cdk::call(governance, "list_neurons", (request,)).await;
+        let (response,) = result.map_err(|(code, message)| rejected(code, message))?;
+
+        let page_size = response.neurons.len();
+        start_page_at = response.neurons.last().and_then(|neuron| neuron.id.clone());
+
+        neurons.extend(response.neurons.into_iter().filter_map(|neuron| {
+            let (dissolve_delay_seconds, state) = dissolve_state(&neuron.dissolve_state, now);
+            Some(SnsNeuron {
+                id: neuron.id?.id,
+                stake_e8s: neuron
+                    .cached_neuron_stake_e8s
+                    .saturating_sub(neuron.neuron_fees_e8s),
+                dissolve_delay_seconds,
+                state,
+                followees: neuron
+                    .followees
+                    .into_iter()
+                    .map(|(function_id, followees)| {
+                        (function_id, followees.followees.into_iter().map(|f| f.id).collect())
+                    })
+                    .collect(),
+            })
+        }));
+
+        if page_size < LIST_NEURONS_PAGE as usize || start_page_at.is_none() {
+            return Ok(neurons);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_neuron_states() {
+        assert_eq!(dissolve_state(&None, 100), (0, NeuronState::Dissolved));
+        assert_eq!(
+            dissolve_state(&Some(DissolveState::DissolveDelaySeconds(30)), 100),
+            (30, NeuronState::Locked)
+        );
+        assert_eq!(
+            dissolve_state(&Some(DissolveState::DissolveDelaySeconds(0)), 100),
+            (0, NeuronState::Dissolved)
+        );
+        assert_eq!(
+            dissolve_state(&Some(DissolveState::WhenDissolvedTimestampSeconds(130)), 100),
+            (30, NeuronState::Dissolving)
+        );
+        assert_eq!(
+            dissolve_state(&Some(DissolveState::WhenDissolvedTimestampSeconds(90)), 100),
+            (0, NeuronState::Dissolved)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_disburse_accounts() {
+        let command = SnsNeuronCommand::Disburse {
+            neuron_id: vec![1; 32],
+            to_account: Some("not an account".to_string()),
+            amount_e8s: None,
+        };
+        assert!(neuron_command(&command).is_err());
+    }
+}
@@ -0,0 +1,577 @@
+use std::{cell::RefCell, fmt, future::Future, pin::Pin};
+
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    get_environment,
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest},
+    schnorr::{self, SchnorrAlgorithm},
+    solana_rpc::{HttpSolanaRpc, SolanaRpc},
+};
+
+/// Derivation path of the vault's Ed25519 key.
+const SOLANA_DERIVATION_PATH: &[u8] = b"solana";
+
+const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+
+// Instruction discriminators
+const SYSTEM_TRANSFER: u32 = 2;
+const TOKEN_TRANSFER: u8 = 3;
+const CREATE_ASSOCIATED_ACCOUNT_IDEMPOTENT: u8 = 1;
+
+thread_local! {
+    /// Ed25519 public key of the vault, fetched once per upgrade.
+    static PUBLIC_KEY: RefCell<Option<Pubkey>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pubkey(pub [u8; 32]);
+
+impl Pubkey {
+    pub fn from_base58(address: &str) -> Result<Pubkey, String> {
+        bs58::decode(address.trim())
+            .into_vec()
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Pubkey)
+            .ok_or_else(|| format!("Invalid Solana address: {}", address))
+    }
+
+    fn is_on_curve(&self) -> bool {
+        CompressedEdwardsY(self.0).decompress().is_some()
+    }
+}
+
+impl fmt::Display for Pubkey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", bs58::encode(self.0).into_string())
+    }
+}
+
+fn program_id(address: &str) -> Pubkey {
+    Pubkey::from_base58(address).expect("program ids are valid")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl AccountMeta {
+    fn new(pubkey: Pubkey, is_signer: bool, is_writable: bool) -> AccountMeta {
+        AccountMeta {
+            pubkey,
+            is_signer,
+            is_writable,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub program_id: Pubkey,
+    pub accounts: Vec<AccountMeta>,
+    pub data: Vec<u8>,
+}
+
+/// Program derived address, the first bump from 255 down whose hash is off the curve.
+fn find_program_address(seeds: &[&[u8]], program: &Pubkey) -> Result<Pubkey, String> {
+    for bump in (0..=u8::MAX).rev() {
+        let mut hasher = Sha256::new();
+        for seed in seeds {
+            hasher.update(seed);
+        }
+        hasher.update([bump]);
+        hasher.update(program.0);
+        hasher.update(b"ProgramDerivedAddress");
+
+        let address = Pubkey(hasher.finalize().into());
+        if !address.is_on_curve() {
+            return Ok(address);
+        }
+    }
+
+    Err(format!("No program address found for program {}", program))
+}
+
+/// The token account holding `mint` for `owner`.
+pub fn associated_token_address(owner: &Pubkey, mint: &Pubkey) -> Result<Pubkey, String> {
+    let token_program = program_id(TOKEN_PROGRAM_ID);
+    find_program_address(
+        &[&owner.0, &token_program.0, &mint.0],
+        &program_id(ASSOCIATED_TOKEN_PROGRAM_ID),
+    )
+}
+
+pub fn system_transfer(from: &Pubkey, to: &Pubkey, lamports: u64) -> Instruction {
+    let mut data = SYSTEM_TRANSFER.to_le_bytes().to_vec();
+    data.extend_from_slice(&lamports.to_le_bytes());
+
+    Instruction {
+        program_id: program_id(SYSTEM_PROGRAM_ID),
+        accounts: vec![
+            AccountMeta::new(*from, true, true),
+            AccountMeta::new(*to, false, true),
+        ],
+        data,
+    }
+}
+
+/// Moves SPL tokens between the associated token accounts of `owner` and `recipient`,
+/// creating the recipient's account first when it does not exist. Token-2022 mints are
+/// not supported.
+pub fn spl_transfer(
+    owner: &Pubkey,
+    recipient: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Result<Vec<Instruction>, String> {
+    let source = associated_token_address(owner, mint)?;
+    let destination = associated_token_address(recipient, mint)?;
+
+    let create_destination = Instruction {
+        program_id: program_id(ASSOCIATED_TOKEN_PROGRAM_ID),
+        accounts: vec![
+            AccountMeta::new(*owner, true, true),
+            AccountMeta::new(destination, false, true),
+            AccountMeta::new(*recipient, false, false),
+            AccountMeta::new(*mint, false, false),
+            AccountMeta::new(program_id(SYSTEM_PROGRAM_ID), false, false),
+            AccountMeta::new(program_id(TOKEN_PROGRAM_ID), false, false),
+        ],
+        data: vec![CREATE_ASSOCIATED_ACCOUNT_IDEMPOTENT],
+    };
+
+    let mut data = vec![TOKEN_TRANSFER];
+    data.extend_from_slice(&amount.to_le_bytes());
+    let transfer = Instruction {
+        program_id: program_id(TOKEN_PROGRAM_ID),
+        accounts: vec![
+            AccountMeta::new(source, false, true),
+            AccountMeta::new(destination, false, true),
+            AccountMeta::new(*owner, true, false),
+        ],
+        data,
+    };
+
+    Ok(vec![create_destination, transfer])
+}
+
+/// Instructions of a transfer of a "sol:native" or "sol:spl:<mint>" token.
+pub fn transfer_instructions(
+    token: &str,
+    owner: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+) -> Result<Vec<Instruction>, String> {
+    let parts: Vec<&str> = token.split(':').collect();
+    match parts.as_slice() {
+        ["sol", "native"] => Ok(vec![system_transfer(owner, recipient, amount)]),
+        ["sol", "spl", mint] => spl_transfer(owner, recipient, &Pubkey::from_base58(mint)?, amount),
+        _ => Err(format!("Unsupported Solana token: {}", token)),
+    }
+}
+
+/// Solana's compact-u16 length prefix.
+fn encode_length(out: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let byte = (length & 0x7f) as u8;
+        length >>= 7;
+        if length == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Serializes a legacy message paid by `payer`. Accounts are deduplicated and ordered
+/// writable signers first, then read-only signers, writable and read-only accounts.
+pub fn compile_message(payer: &Pubkey, instructions: &[Instruction], recent_blockhash: [u8; 32]) -> Vec<u8> {
+    let mut accounts: Vec<AccountMeta> = vec![AccountMeta::new(*payer, true, true)];
+    let metas = instructions.iter().flat_map(|instruction| {
+        instruction
+            .accounts
+            .iter()
+            .cloned()
+            .chain([AccountMeta::new(instruction.program_id, false, false)])
+    });
+    for meta in metas {
+        match accounts.iter_mut().find(|account| account.pubkey == meta.pubkey) {
+            Some(account) => {
+                account.is_signer |= meta.is_signer;
+                account.is_writable |= meta.is_writable;
+            }
+            None => accounts.push(meta),
+        }
+    }
+    // Stable, so the payer stays first
+    accounts.sort_by_key(|account| (!account.is_signer, !account.is_writable));
+
+    let index_of = |pubkey: &Pubkey| {
+        accounts
+            .iter()
+            .position(|account| &account.pubkey == pubkey)
+            .expect("every account is collected") as u8
+    };
+
+    let signers = accounts.iter().filter(|a| a.is_signer).count();
+    let readonly_signers = accounts.iter().filter(|a| a.is_signer && !a.is_writable).count();
+    let readonly = accounts.iter().filter(|a| !a.is_signer && !a.is_writable).count();
+
+    let mut message = vec![signers as u8, readonly_signers as u8, readonly as u8];
+    encode_length(&mut message, accounts.len());
+    for account in &accounts {
+        message.extend_from_slice(&account.pubkey.0);
+    }
+    message.extend_from_slice(&recent_blockhash);
+
+    encode_length(&mut message, instructions.len());
+    for instruction in instructions {
+        message.push(index_of(&instruction.program_id));
+        encode_length(&mut message, instruction.accounts.len());
+        for meta in &instruction.accounts {
+            message.push(index_of(&meta.pubkey));
+        }
+        encode_length(&mut message, instruction.data.len());
+        message.extend_from_slice(&instruction.data);
+    }
+
+    message
+}
+
+/// Signs Solana messages with an Ed25519 key.
+pub trait SolanaSigner {
+    fn pubkey(&self) -> Pubkey;
+
+    fn sign<'a>(
+        &'a self,
+        message: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, String>> + 'a>>;
+}
+
+/// The vault's threshold Ed25519 key.
+pub struct ThresholdSigner {
+    pubkey: Pubkey,
+}
+
+impl ThresholdSigner {
+    pub async fn new() -> Result<ThresholdSigner, String> {
+        Ok(ThresholdSigner {
+            pubkey: vault_pubkey().await?,
+        })
+    }
+}
+
+impl SolanaSigner for ThresholdSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    fn sign<'a>(
+        &'a self,
+        message: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, String>> + 'a>> {
+        Box::pin(schnorr::sign_with_schnorr(
+            SchnorrAlgorithm::Ed25519,
+            vec![SOLANA_DERIVATION_PATH.to_vec()],
+            message.to_vec(),
+            None,
+        ))
+    }
+}
+
+/// A transaction with the single signature of its payer.
+pub async fn sign_transaction(signer: &dyn SolanaSigner, message: Vec<u8>) -> Result<Vec<u8>, String> {
+    let signature = signer.sign(&message).await?;
+    if signature.len() != 64 {
+        return Err(format!("Expected a 64 bytes signature, got {}", signature.len()));
+    }
+
+    let mut transaction = vec![];
+    encode_length(&mut transaction, 1);
+    transaction.extend(signature);
+    transaction.extend(message);
+    Ok(transaction)
+}
+
+/// Builds, signs and submits a transfer, returning the transaction signature.
+pub async fn send_transfer(
+    rpc: &dyn SolanaRpc,
+    signer: &dyn SolanaSigner,
+    token: &str,
+    to: &str,
+    amount: u64,
+) -> Result<String, String> {
+    let owner = signer.pubkey();
+    let instructions = transfer_instructions(token, &owner, &Pubkey::from_base58(to)?, amount)?;
+    let blockhash = rpc.latest_blockhash().await?;
+
+    let transaction = sign_transaction(signer, compile_message(&owner, &instructions, blockhash)).await?;
+    rpc.send_transaction(&transaction).await
+}
+
+async fn vault_pubkey() -> Result<Pubkey, String> {
+    if let Some(pubkey) = PUBLIC_KEY.with(|key| *key.borrow()) {
+        return Ok(pubkey);
+    }
+
+    let public_key = schnorr::schnorr_public_key(
+        SchnorrAlgorithm::Ed25519,
+        vec![SOLANA_DERIVATION_PATH.to_vec()],
+    )
+    .await?;
+    let pubkey = Pubkey(
+        public_key
+            .try_into()
+            .map_err(|key: Vec<u8>| format!("Expected a 32 bytes Ed25519 key, got {}", key.len()))?,
+    );
+    PUBLIC_KEY.with(|key| *key.borrow_mut() = Some(pubkey));
+
+    Ok(pubkey)
+}
+
+fn rpc() -> Result<HttpSolanaRpc, String> {
+    get_environment()
+        .solana
+        .map(|config| HttpSolanaRpc::new(config.rpc_url))
+        .ok_or_else(|| "Solana is not configured for this vault".to_string())
+}
+
+fn transaction_reference(signature: &str) -> String {
+    let explorer_url = get_environment().solana.and_then(|config| config.explorer_url);
+    match explorer_url {
+        // Cluster selection is a query parameter, e.g. "?cluster=devnet"
+        Some(url) => match url.split_once('?') {
+            Some((base, query)) => {
+                format!("{}/tx/{}?{}", base.trim_end_matches('/'), signature, query)
+            }
+            None => format!("{}/tx/{}", url.trim_end_matches('/'), signature),
+        },
+        None => signature.to_string(),
+    }
+}
+
+/// The vault's Solana address, the base58 encoding of its Ed25519 key.
+#[ic_cdk::update]
+pub async fn get_solana_address() -> Result<String, String> {
+    Ok(vault_pubkey().await?.to_string())
+}
+
+/// Balance of the vault's Solana address in lamports.
+#[ic_cdk::update]
+pub async fn get_sol_balance() -> Result<u64, String> {
+    let rpc = rpc()?;
+    let address = vault_pubkey().await?.to_string();
+    rpc.balance(&address).await
+}
+
+/// Transfers SOL or SPL tokens from the vault's Solana address. Amounts are in lamports
+/// or the token's base unit.
+#[derive(Clone)]
+pub struct SolanaTransferAdapter {}
+
+impl SolanaTransferAdapter {
+    pub fn new() -> SolanaTransferAdapter {
+        SolanaTransferAdapter {}
+    }
+}
+
+impl BlockchainAdapter for SolanaTransferAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing SolanaTransferAdapter");
+
+            let rpc = rpc()?;
+            let signer = ThresholdSigner::new().await?;
+            let signature = send_transfer(
+                &rpc,
+                &signer,
+                &transaction.token,
+                &transaction.to,
+                transaction.amount as u64,
+            )
+            .await?;
+
+            Ok(IntentStatus::Completed(format!(
+                "Successfully sent Solana transaction: {}",
+                transaction_reference(&signature)
+            )))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    };
+
+    use ed25519_dalek::{Signature, Signer, SigningKey, Verifier};
+
+    use super::*;
+
+    /// Polls a future that never waits, which holds for the offline signer and RPC.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        fn raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                raw_waker()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+
+        let waker = unsafe { Waker::from_raw(raw_waker()) };
+        let mut future = Box::pin(future);
+        match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("The future is waiting"),
+        }
+    }
+
+    struct LocalSigner(SigningKey);
+
+    impl SolanaSigner for LocalSigner {
+        fn pubkey(&self) -> Pubkey {
+            Pubkey(self.0.verifying_key().to_bytes())
+        }
+
+        fn sign<'a>(
+            &'a self,
+            message: &'a [u8],
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, String>> + 'a>> {
+            Box::pin(async move { Ok(self.0.sign(message).to_bytes().to_vec()) })
+        }
+    }
+
+    #[derive(Default)]
+    struct MockRpc {
+        sent: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl SolanaRpc for MockRpc {
+        fn latest_blockhash(&self) -> Pin<Box<dyn Future<Output = Result<[u8; 32], String>> + '_>> {
+            Box::pin(async { Ok([7; 32]) })
+        }
+
+        fn send_transaction<'a>(
+            &'a self,
+            transaction: &'a [u8],
+        ) -> Pin<Box<dyn Future<Output = Result<String, String>> + 'a>> {
+            Box::pin(async move {
+                self.sent.borrow_mut().push(transaction.to_vec());
+                Ok(bs58::encode(&transaction[1..65]).into_string())
+            })
+        }
+
+        fn balance<'a>(
+            &'a self,
+            _address: &'a str,
+        ) -> Pin<Box<dyn Future<Output = Result<u64, String>> + 'a>> {
+            Box::pin(async { Ok(0) })
+        }
+    }
+
+    fn signer() -> LocalSigner {
+        LocalSigner(SigningKey::from_bytes(&[1; 32]))
+    }
+
+    #[test]
+    fn encodes_compact_lengths() {
+        let encode = |length| {
+            let mut out = vec![];
+            encode_length(&mut out, length);
+            out
+        };
+        assert_eq!(encode(0), vec![0x00]);
+        assert_eq!(encode(0x7f), vec![0x7f]);
+        assert_eq!(encode(0x80), vec![0x80, 0x01]);
+        assert_eq!(encode(0x3fff), vec![0xff, 0x7f]);
+        assert_eq!(encode(0x4000), vec![0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn parses_program_ids() {
+        assert_eq!(program_id(SYSTEM_PROGRAM_ID), Pubkey([0; 32]));
+        assert_eq!(program_id(TOKEN_PROGRAM_ID).to_string(), TOKEN_PROGRAM_ID);
+        assert!(Pubkey::from_base58("not base58 0OIl").is_err());
+    }
+
+    #[test]
+    fn compiles_sol_transfers() {
+        let from = signer().pubkey();
+        let to = Pubkey([2; 32]);
+        let message = compile_message(&from, &[system_transfer(&from, &to, 1_000)], [7; 32]);
+
+        // One writable signer, no read-only signer and the read-only system program
+        assert_eq!(message[..4], [1, 0, 1, 3]);
+        assert_eq!(message[4..36], from.0);
+        assert_eq!(message[36..68], to.0);
+        assert_eq!(message[68..100], [0; 32]);
+        assert_eq!(message[100..132], [7; 32]);
+        // A single instruction of the system program moving from account 0 to 1
+        assert_eq!(message[132..137], [1, 2, 2, 0, 1]);
+        assert_eq!(message[137], 12);
+        assert_eq!(message[138..142], 2u32.to_le_bytes());
+        assert_eq!(message[142..], 1_000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn derives_off_curve_token_accounts() {
+        let owner = signer().pubkey();
+        let mint = Pubkey::from_base58("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v").unwrap();
+
+        let account = associated_token_address(&owner, &mint).unwrap();
+        assert!(!account.is_on_curve());
+        assert_eq!(account, associated_token_address(&owner, &mint).unwrap());
+        assert_ne!(account, associated_token_address(&Pubkey([2; 32]), &mint).unwrap());
+    }
+
+    #[test]
+    fn signs_and_sends_spl_transfers() {
+        let signer = signer();
+        let rpc = MockRpc::default();
+        let mint = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+        let recipient = Pubkey([2; 32]).to_string();
+
+        let signature = block_on(send_transfer(
+            &rpc,
+            &signer,
+            &format!("sol:spl:{}", mint),
+            &recipient,
+            5_000,
+        ))
+        .unwrap();
+
+        let sent = rpc.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        let transaction = &sent[0];
+        assert_eq!(transaction[0], 1);
+        assert_eq!(bs58::encode(&transaction[1..65]).into_string(), signature);
+
+        let message = &transaction[65..];
+        let signature = Signature::from_slice(&transaction[1..65]).unwrap();
+        signer.0.verifying_key().verify(message, &signature).unwrap();
+
+        // The vault signs alone, the recipient's wallet, mint and programs are read-only
+        assert_eq!(message[..3], [1, 0, 5]);
+        assert_eq!(message[4..36], signer.pubkey().0);
+    }
+
+    #[test]
+    fn rejects_unknown_tokens() {
+        let owner = signer().pubkey();
+        assert!(transfer_instructions("sol:token2022:x", &owner, &owner, 1).is_err());
+        assert!(transfer_instructions("sol:spl:invalid0", &owner, &owner, 1).is_err());
+    }
+}
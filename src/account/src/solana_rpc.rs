@@ -0,0 +1,153 @@
+use std::{future::Future, pin::Pin};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse,
+    TransformArgs, TransformContext,
+};
+use serde_json::{json, Value};
+
+/// Cycles attached to each outcall. The unused part is refunded.
+const HTTP_OUTCALL_CYCLES: u128 = 2_000_000_000;
+const MAX_RESPONSE_BYTES: u64 = 4_096;
+
+/// The Solana JSON-RPC methods the vault needs, so transfers can run against a mock.
+pub trait SolanaRpc {
+    fn latest_blockhash(&self) -> Pin<Box<dyn Future<Output = Result<[u8; 32], String>> + '_>>;
+
+    /// Submits a signed transaction and returns its base58 signature.
+    fn send_transaction<'a>(
+        &'a self,
+        transaction: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + 'a>>;
+
+    /// Balance of an address in lamports.
+    fn balance<'a>(
+        &'a self,
+        address: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<u64, String>> + 'a>>;
+}
+
+/// Calls a JSON-RPC endpoint through HTTPS outcalls. Every replica sends the request, so
+/// reads use the finalized commitment for the replicas to agree on the response.
+pub struct HttpSolanaRpc {
+    url: String,
+}
+
+impl HttpSolanaRpc {
+    pub fn new(url: String) -> HttpSolanaRpc {
+        HttpSolanaRpc { url }
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+
+        let request = CanisterHttpRequestArgument {
+            url: self.url.clone(),
+            max_response_bytes: Some(MAX_RESPONSE_BYTES),
+            method: HttpMethod::POST,
+            headers: vec![HttpHeader {
+                name: "Content-Type".to_string(),
+                value: "application/json".to_string(),
+            }],
+            body: Some(body.to_string().into_bytes()),
+            transform: Some(TransformContext::from_name(
+                "solana_transform".to_string(),
+                vec![],
+            )),
+        };
+
+        let (response,) = http_request(request, HTTP_OUTCALL_CYCLES)
+            .await
+            .map_err(|(code, message)| format!("Solana RPC call failed: {:?} {}", code, message))?;
+        let response: Value = serde_json::from_slice(&response.body)
+            .map_err(|e| format!("Invalid Solana RPC response: {}", e))?;
+
+        if let Some(error) = response.get("error") {
+            return Err(format!("Solana RPC error: {}", error));
+        }
+        response
+            .get("result")
+            .cloned()
+            .ok_or_else(|| format!("Solana RPC response without a result: {}", response))
+    }
+}
+
+impl SolanaRpc for HttpSolanaRpc {
+    fn latest_blockhash(&self) -> Pin<Box<dyn Future<Output = Result<[u8; 32], String>> + '_>> {
+        Box::pin(async move {
+            let result = self
+                .call("getLatestBlockhash", json!([{ "commitment": "finalized" }]))
+                .await?;
+            let blockhash = result["value"]["blockhash"]
+                .as_str()
+                .ok_or_else(|| format!("Unexpected getLatestBlockhash result: {}", result))?;
+
+            bs58::decode(blockhash)
+                .into_vec()
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("Invalid blockhash: {}", blockhash))
+        })
+    }
+
+    fn send_transaction<'a>(
+        &'a self,
+        transaction: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + 'a>> {
+        Box::pin(async move {
+            let result = self
+                .call(
+                    "sendTransaction",
+                    json!([BASE64.encode(transaction), { "encoding": "base64" }]),
+                )
+                .await?;
+
+            result
+                .as_str()
+                .map(|signature| signature.to_string())
+                .ok_or_else(|| format!("Unexpected sendTransaction result: {}", result))
+        })
+    }
+
+    fn balance<'a>(
+        &'a self,
+        address: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<u64, String>> + 'a>> {
+        Box::pin(async move {
+            let result = self
+                .call("getBalance", json!([address, { "commitment": "finalized" }]))
+                .await?;
+
+            result["value"]
+                .as_u64()
+                .ok_or_else(|| format!("Unexpected getBalance result: {}", result))
+        })
+    }
+}
+
+/// Drops the headers and the response context, which holds the slot the node answered
+/// at, so the responses of all replicas match.
+#[ic_cdk::query]
+fn solana_transform(args: TransformArgs) -> HttpResponse {
+    let body = match serde_json::from_slice::<Value>(&args.response.body) {
+        Ok(mut body) => {
+            if let Some(result) = body.get_mut("result").and_then(Value::as_object_mut) {
+                result.remove("context");
+            }
+            body.to_string().into_bytes()
+        }
+        Err(_) => args.response.body,
+    };
+
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body,
+    }
+}
@@ -0,0 +1,361 @@
+use std::{future::Future, pin::Pin};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use icrc_ledger_types::icrc1::account::Account;
+use icrc_ledger_types::icrc2::approve::{ApproveArgs, ApproveError};
+use keygate_core::types::vault::TransactionPayload;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    get_default_icrc_subaccount,
+    icrc2::resolve_ledger,
+    intent::{BlockchainAdapter, IntentStatus, TransactionRequest},
+    signer_exists, Error,
+};
+
+/// Slippage is expressed in basis points of the quoted amount.
+const BPS_DENOMINATOR: u128 = 10_000;
+
+// ICPSwap pool interface, see https://github.com/ICPSwap-Labs/docs
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+enum PoolError {
+    CommonError,
+    InternalError(String),
+    UnsupportedToken(String),
+    InsufficientFunds,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+enum PoolResult<T> {
+    #[serde(rename = "ok")]
+    Ok(T),
+    #[serde(rename = "err")]
+    Err(PoolError),
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct PoolToken {
+    address: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct PoolMetadata {
+    token0: PoolToken,
+    token1: PoolToken,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct PoolSwapArgs {
+    #[serde(rename = "amountIn")]
+    amount_in: String,
+    #[serde(rename = "zeroForOne")]
+    zero_for_one: bool,
+    #[serde(rename = "amountOutMinimum")]
+    amount_out_minimum: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct PoolDepositArgs {
+    token: String,
+    amount: Nat,
+    fee: Nat,
+}
+
+type PoolWithdrawArgs = PoolDepositArgs;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+struct UnusedBalance {
+    balance0: Nat,
+    balance1: Nat,
+}
+
+async fn call_pool<A: CandidType, T: CandidType + for<'de> Deserialize<'de>>(
+    pool: Principal,
+    method: &str,
+    arg: A,
+) -> Result<T, String> {
+    let call_result: CallResult<(PoolResult<T>,)> = ic_cdk::call(pool, method, (arg,)).await;
+    match call_result {
+        Ok((PoolResult::Ok(value),)) => Ok(value),
+        Ok((PoolResult::Err(e),)) => Err(format!("Pool {} error: {:?}", method, e)),
+        Err((rejection_code, message)) => Err(format!(
+            "Canister call rejected: {:?} - {}",
+            rejection_code, message
+        )),
+    }
+}
+
+async fn ledger_fee(ledger: Principal) -> Result<Nat, String> {
+    let fee_result: CallResult<(Nat,)> = ic_cdk::call(ledger, "icrc1_fee", ()).await;
+    fee_result
+        .map(|(fee,)| fee)
+        .map_err(|(rejection_code, message)| {
+            format!("Canister call rejected: {:?} - {}", rejection_code, message)
+        })
+}
+
+/// Direction of the swap in the pool, `true` when the input token is token0.
+async fn swap_direction(pool: Principal, ledger_in: Principal, ledger_out: Principal) -> Result<bool, String> {
+    let metadata: PoolMetadata = call_pool(pool, "metadata", ()).await?;
+    let (token0, token1) = (metadata.token0.address, metadata.token1.address);
+
+    if token0 == ledger_in.to_text() && token1 == ledger_out.to_text() {
+        Ok(true)
+    } else if token1 == ledger_in.to_text() && token0 == ledger_out.to_text() {
+        Ok(false)
+    } else {
+        Err(format!(
+            "Pool {} does not trade {} for {}",
+            pool, ledger_in, ledger_out
+        ))
+    }
+}
+
+/// Smallest output accepted for a swap: the explicit minimum, tightened by the
+/// slippage bound applied to the current quote.
+pub fn minimum_amount_out(quote: u128, min_amount_out: u128, max_slippage_bps: u16) -> u128 {
+    let slippage_floor =
+        quote * (BPS_DENOMINATOR - (max_slippage_bps as u128).min(BPS_DENOMINATOR)) / BPS_DENOMINATOR;
+    slippage_floor.max(min_amount_out)
+}
+
+fn nat_to_u128(value: &Nat) -> Result<u128, String> {
+    u128::try_from(&value.0).map_err(|e| format!("Amount {} out of range: {}", value, e))
+}
+
+#[derive(Clone)]
+pub struct SwapAdapter {}
+
+impl SwapAdapter {
+    pub fn new() -> SwapAdapter {
+        SwapAdapter {}
+    }
+
+    async fn swap(&self, transaction: &TransactionRequest) -> Result<Nat, String> {
+        let args = match &transaction.payload {
+            Some(TransactionPayload::Swap(args)) => args.clone(),
+            _ => return Err("Swap requires a Swap payload".to_string()),
+        };
+
+        let pool = Principal::from_text(&transaction.to)
+            .map_err(|e| format!("Invalid pool principal {}: {}", transaction.to, e))?;
+        let ledger_in = resolve_ledger(&transaction.token)?;
+        let ledger_out = resolve_ledger(&args.token_out)?;
+        let amount_in = transaction.amount as u128;
+        let zero_for_one = swap_direction(pool, ledger_in, ledger_out).await?;
+
+        let quote = quote(pool, zero_for_one, amount_in).await?;
+        let amount_out_minimum =
+            minimum_amount_out(quote, args.min_amount_out, args.max_slippage_bps);
+        if quote < amount_out_minimum {
+            return Err(format!(
+                "Quoted amount {} is below the minimum of {}",
+                quote, amount_out_minimum
+            ));
+        }
+
+        // Nothing has left the vault before this point.
+        let fee_in = ledger_fee(ledger_in).await?;
+        approve_pool(ledger_in, pool, Nat::from(amount_in) + fee_in.clone()).await?;
+        call_pool::<_, Nat>(
+            pool,
+            "depositFrom",
+            PoolDepositArgs {
+                token: ledger_in.to_text(),
+                amount: Nat::from(amount_in),
+                fee: fee_in.clone(),
+            },
+        )
+        .await?;
+
+        let swap_result = call_pool::<_, Nat>(
+            pool,
+            "swap",
+            PoolSwapArgs {
+                amount_in: amount_in.to_string(),
+                zero_for_one,
+                amount_out_minimum: amount_out_minimum.to_string(),
+            },
+        )
+        .await;
+
+        let amount_out = match swap_result {
+            Ok(amount_out) => amount_out,
+            Err(swap_error) => {
+                // Return the deposit so the input does not stay locked in the pool.
+                let refund = call_pool::<_, Nat>(
+                    pool,
+                    "withdraw",
+                    PoolWithdrawArgs {
+                        token: ledger_in.to_text(),
+                        amount: Nat::from(amount_in),
+                        fee: fee_in,
+                    },
+                )
+                .await;
+
+                return Err(match refund {
+                    Ok(_) => format!("{}. The deposit was returned to the vault.", swap_error),
+                    Err(refund_error) => format!(
+                        "{}. Refund failed ({}), call recover_swap_funds for pool {}.",
+                        swap_error, refund_error, pool
+                    ),
+                });
+            }
+        };
+
+        let fee_out = ledger_fee(ledger_out).await?;
+        call_pool::<_, Nat>(
+            pool,
+            "withdraw",
+            PoolWithdrawArgs {
+                token: ledger_out.to_text(),
+                amount: amount_out.clone(),
+                fee: fee_out,
+            },
+        )
+        .await
+        .map_err(|e| {
+            format!(
+                "Swapped {} but withdrawal failed ({}), call recover_swap_funds for pool {}.",
+                amount_out, e, pool
+            )
+        })
+    }
+}
+
+impl BlockchainAdapter for SwapAdapter {
+    fn execute<'a>(
+        &'a self,
+        transaction: &'a TransactionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<IntentStatus, String>> + 'a>> {
+        Box::pin(async move {
+            ic_cdk::println!("Executing SwapAdapter");
+            match self.swap(transaction).await {
+                Ok(amount_out) => Ok(IntentStatus::Completed(format!(
+                    "Successfully swapped tokens, received {}.",
+                    amount_out
+                ))),
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+async fn approve_pool(ledger: Principal, pool: Principal, amount: Nat) -> Result<Nat, String> {
+    let args = ApproveArgs {
+        from_subaccount: Some(get_default_icrc_subaccount().0),
+        spender: Account {
+            owner: pool,
+            subaccount: None,
+        },
+        amount,
+        expected_allowance: None,
+        expires_at: None,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+
+    let approve_result: CallResult<(Result<Nat, ApproveError>,)> =
+        ic_cdk::call(ledger, "icrc2_approve", (args,)).await;
+    match approve_result {
+        Ok((Ok(block_index),)) => Ok(block_index),
+        Ok((Err(approve_error),)) => Err(format!("ICRC-2 approve error: {:?}", approve_error)),
+        Err((rejection_code, message)) => Err(format!(
+            "Canister call rejected: {:?} - {}",
+            rejection_code, message
+        )),
+    }
+}
+
+async fn quote(pool: Principal, zero_for_one: bool, amount_in: u128) -> Result<u128, String> {
+    let quote: Nat = call_pool(
+        pool,
+        "quote",
+        PoolSwapArgs {
+            amount_in: amount_in.to_string(),
+            zero_for_one,
+            amount_out_minimum: "0".to_string(),
+        },
+    )
+    .await?;
+
+    nat_to_u128(&quote)
+}
+
+/// Quotes how much of `token_out` the pool returns for `amount_in` of `token_in`.
+#[ic_cdk::query(composite = true)]
+pub async fn quote_swap(
+    pool: Principal,
+    token_in: String,
+    token_out: String,
+    amount_in: u128,
+) -> Result<u128, Error> {
+    let result = async {
+        let ledger_in = resolve_ledger(&token_in)?;
+        let ledger_out = resolve_ledger(&token_out)?;
+        let zero_for_one = swap_direction(pool, ledger_in, ledger_out).await?;
+        quote(pool, zero_for_one, amount_in).await
+    };
+
+    result.await.map_err(|message| Error { message })
+}
+
+/// Withdraws whatever the vault still holds in a pool after a partially failed swap.
+#[ic_cdk::update]
+pub async fn recover_swap_funds(pool: Principal) -> Result<Vec<Nat>, Error> {
+    if !signer_exists(ic_cdk::caller()) {
+        ic_cdk::trap("Caller is not a signer");
+    }
+
+    let recover = async {
+        let metadata: PoolMetadata = call_pool(pool, "metadata", ()).await?;
+        let unused: UnusedBalance =
+            call_pool(pool, "getUserUnusedBalance", ic_cdk::id()).await?;
+
+        let mut withdrawn = vec![];
+        for (token, balance) in [
+            (metadata.token0.address, unused.balance0),
+            (metadata.token1.address, unused.balance1),
+        ] {
+            let ledger = Principal::from_text(&token)
+                .map_err(|e| format!("Invalid pool token {}: {}", token, e))?;
+            let fee = ledger_fee(ledger).await?;
+            if balance <= fee {
+                continue;
+            }
+
+            withdrawn.push(
+                call_pool::<_, Nat>(
+                    pool,
+                    "withdraw",
+                    PoolWithdrawArgs {
+                        token,
+                        amount: balance,
+                        fee,
+                    },
+                )
+                .await?,
+            );
+        }
+
+        Ok::<Vec<Nat>, String>(withdrawn)
+    };
+
+    recover.await.map_err(|message| Error { message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimum_amount_out_uses_the_tighter_bound() {
+        assert_eq!(minimum_amount_out(10_000, 0, 100), 9_900);
+        assert_eq!(minimum_amount_out(10_000, 9_950, 100), 9_950);
+        assert_eq!(minimum_amount_out(10_000, 0, 0), 10_000);
+        assert_eq!(minimum_amount_out(10_000, 0, 20_000), 0);
+    }
+}
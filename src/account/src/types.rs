@@ -135,6 +135,8 @@ use std::str::FromStr;
 enum Network {
     ICP,
     ETH,
+    BASE,
+    POLYGON,
 }
 
 // Define the supported token types
@@ -164,6 +166,8 @@ impl FromStr for URN {
         let network = match parts[0] {
             "icp" => Network::ICP,
             "eth" => Network::ETH,
+            "base" => Network::BASE,
+            "polygon" => Network::POLYGON,
             _ => return Err("Unsupported network".to_string()),
         };
 
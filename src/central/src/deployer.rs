@@ -2,6 +2,9 @@ use candid::{CandidType, Principal};
 use ic_cdk::api;
 use serde::Deserialize;
 
+/// Cycles a vault starts with, and gets again from `request_cycles` when it runs low.
+pub const VAULT_CYCLES: u128 = 240_000_000_000;
+
 #[derive(CandidType, Deserialize)]
 pub enum InstallMode {
     #[serde(rename = "install")]
@@ -33,7 +36,7 @@ pub async fn deploy(bytecode: Vec<u8>, arg: Vec<u8>) -> Result<Principal, String
         management_canister,
         "create_canister",
         (),
-        240000000000u128,
+        VAULT_CYCLES,
     )
     .await;
 
@@ -4,23 +4,47 @@ mod repository;
 
 use std::cell::RefCell;
 
-use candid::Principal;
+use candid::{Nat, Principal};
+use ic_cdk::api::management_canister::main::{deposit_cycles, CanisterIdRecord};
 use ic_stable_structures::{
-    memory_manager::{MemoryId, MemoryManager},
-    DefaultMemoryImpl,
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    DefaultMemoryImpl, StableBTreeMap, StableCell,
 };
 use repository::UserRepository;
 use keygate_core::types::central::{UserData, Vault, VaultInitArgs};
+use keygate_core::types::environment::VaultEnvironment;
 
 const USERS_MEMORY: MemoryId = MemoryId::new(0);
 const VAULTS_MEMORY: MemoryId = MemoryId::new(1);
 const VAULT_NAMES_MEMORY: MemoryId = MemoryId::new(2);
+const VAULT_ENVIRONMENT_MEMORY: MemoryId = MemoryId::new(3);
+const LAST_REFUELS_MEMORY: MemoryId = MemoryId::new(4);
+
+/// Vaults get cycles from `request_cycles` at most once a day.
+const REFUEL_INTERVAL_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Cycles central keeps for itself, so refuels can't stop it from deploying vaults.
+const RESERVED_CYCLES: u128 = 1_000_000_000_000;
 
 thread_local! {
     static WALLET_WASM: RefCell<Option<Vec<u8>>> = RefCell::default();
 
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    /// Environment every vault is deployed with, local until a controller sets it.
+    static VAULT_ENVIRONMENT: RefCell<StableCell<VaultEnvironment, VirtualMemory<DefaultMemoryImpl>>> =
+        RefCell::new(StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(VAULT_ENVIRONMENT_MEMORY)),
+            VaultEnvironment::local(),
+        ).expect("Failed to initialize the vault environment StableCell"));
+
+    /// Time of the last refuel of each vault, kept across upgrades so they don't reset
+    /// the daily limit.
+    static LAST_REFUELS: RefCell<StableBTreeMap<Principal, u64, VirtualMemory<DefaultMemoryImpl>>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LAST_REFUELS_MEMORY)),
+        ));
 }
 
 #[ic_cdk::query]
@@ -106,6 +130,8 @@ async fn deploy_account(args: VaultInitArgs) -> Principal {
     let deployment_arg = candid::encode_one(keygate_core::types::canister_init::VaultInitArgs {
             name: args.name.clone(),
             signers: vec![owner_principal],
+            environment: Some(get_vault_environment()),
+            central: Some(ic_cdk::id()),
         })
         .unwrap();
 
@@ -122,6 +148,53 @@ async fn deploy_account(args: VaultInitArgs) -> Principal {
     }
 }
 
+#[ic_cdk::query]
+fn get_vault_environment() -> VaultEnvironment {
+    VAULT_ENVIRONMENT.with(|environment| environment.borrow().get().clone())
+}
+
+/// Sets the environment of the vaults deployed from now on, e.g. `VaultEnvironment::mainnet()`
+/// on mainnet. Vaults that are already deployed keep theirs.
+#[ic_cdk::update]
+fn set_vault_environment(environment: VaultEnvironment) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err("Only controllers can set the vault environment".to_string());
+    }
+
+    VAULT_ENVIRONMENT
+        .with(|cell| cell.borrow_mut().set(environment))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to set the vault environment: {:?}", e))
+}
+
+/// Sends cycles to a vault deployed by central that is running low.
+#[ic_cdk::update]
+async fn request_cycles() -> Result<Nat, String> {
+    let vault = ic_cdk::caller();
+    if UserRepository::default().get_vault_by_id(&vault).is_none() {
+        return Err(format!("{} is not a vault deployed by central", vault));
+    }
+
+    let now = ic_cdk::api::time();
+    let last_refuel = LAST_REFUELS.with(|refuels| refuels.borrow().get(&vault));
+    if last_refuel.is_some_and(|last_refuel| now < last_refuel + REFUEL_INTERVAL_NANOS) {
+        return Err(format!("Vault {} was already refueled today", vault));
+    }
+    if ic_cdk::api::canister_balance128() < RESERVED_CYCLES + deployer::VAULT_CYCLES {
+        return Err("Central does not have enough cycles to spare".to_string());
+    }
+
+    LAST_REFUELS.with(|refuels| refuels.borrow_mut().insert(vault, now));
+    deposit_cycles(CanisterIdRecord { canister_id: vault }, deployer::VAULT_CYCLES)
+        .await
+        .map_err(|(code, msg)| {
+            LAST_REFUELS.with(|refuels| refuels.borrow_mut().remove(&vault));
+            format!("Failed to refuel vault {}: {:?}: {}", vault, code, msg)
+        })?;
+
+    Ok(Nat::from(deployer::VAULT_CYCLES))
+}
+
 #[ic_cdk::update]
 fn load_wallet_wasm() {
     let wasm_module: Vec<u8> =
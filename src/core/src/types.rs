@@ -2,10 +2,207 @@ pub mod canister_init {
     use candid::{CandidType, Principal};
     use serde::{Deserialize, Serialize};
 
+    use super::environment::VaultEnvironment;
+
     #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
     pub struct VaultInitArgs {
         pub name: String,
         pub signers: Vec<Principal>,
+        /// Deployment specific settings. Falls back to `VaultEnvironment::local()` when omitted.
+        pub environment: Option<VaultEnvironment>,
+        /// Central canister that deployed the vault, which refuels it with cycles by default.
+        pub central: Option<Principal>,
+    }
+}
+
+pub mod environment {
+    use std::borrow::Cow;
+
+    use candid::{CandidType, Principal};
+    use ic_ledger_types::MAINNET_LEDGER_CANISTER_ID;
+    use ic_stable_structures::{storable::Bound, Storable};
+    use serde::{Deserialize, Serialize};
+
+    /// RPC provider used by the EVM RPC canister for a given chain.
+    #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub enum EvmRpcProvider {
+        EthMainnetPublicNode,
+        EthSepoliaPublicNode,
+        Custom(String),
+    }
+
+    #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct EvmChainConfig {
+        /// Short chain name used in token paths, e.g. "eth", "base" or "polygon".
+        pub chain: String,
+        pub chain_id: u64,
+        pub rpc: EvmRpcProvider,
+        /// Block explorer base URL, e.g. "https://etherscan.io".
+        pub explorer_url: Option<String>,
+        /// Blocks on top of a transaction's block before it counts as completed.
+        pub confirmations: Option<u64>,
+    }
+
+    impl EvmChainConfig {
+        /// Link to a transaction on the chain's block explorer, if one is configured.
+        pub fn explorer_tx_url(&self, hash: &str) -> Option<String> {
+            self.explorer_url
+                .as_ref()
+                .map(|url| format!("{}/tx/{}", url.trim_end_matches('/'), hash))
+        }
+    }
+
+    /// Bitcoin network served by the management canister's Bitcoin API.
+    #[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+    pub enum BitcoinNetwork {
+        Mainnet,
+        Testnet,
+        Regtest,
+    }
+
+    /// Solana cluster the vault sends SOL and SPL tokens on.
+    #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct SolanaConfig {
+        /// JSON-RPC endpoint called through HTTPS outcalls.
+        pub rpc_url: String,
+        /// Block explorer base URL, e.g. "https://explorer.solana.com".
+        pub explorer_url: Option<String>,
+    }
+
+    impl SolanaConfig {
+        fn devnet() -> SolanaConfig {
+            SolanaConfig {
+                rpc_url: "https://api.devnet.solana.com".to_string(),
+                explorer_url: Some("https://explorer.solana.com/?cluster=devnet".to_string()),
+            }
+        }
+    }
+
+    /// Settings that differ between a local replica, a staging subnet and mainnet.
+    ///
+    /// Passed to the vault on install and kept in stable memory, so the same wasm
+    /// can be deployed everywhere.
+    #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct VaultEnvironment {
+        pub icp_ledger: Principal,
+        pub ecdsa_key_name: String,
+        pub evm_chains: Vec<EvmChainConfig>,
+        /// Network of the vault's BTC, none when the vault does not hold BTC.
+        #[serde(default)]
+        pub bitcoin_network: Option<BitcoinNetwork>,
+        /// Solana cluster of the vault, none when the vault does not hold SOL.
+        #[serde(default)]
+        pub solana: Option<SolanaConfig>,
+    }
+
+    impl VaultEnvironment {
+        /// Local replica: dfx test key, public EVM testnets, Bitcoin regtest and Solana devnet.
+        pub fn local() -> VaultEnvironment {
+            VaultEnvironment {
+                icp_ledger: MAINNET_LEDGER_CANISTER_ID,
+                ecdsa_key_name: "dfx_test_key".to_string(),
+                evm_chains: Self::testnet_chains(),
+                bitcoin_network: Some(BitcoinNetwork::Regtest),
+                solana: Some(SolanaConfig::devnet()),
+            }
+        }
+
+        /// Staging subnet on mainnet: threshold test key, public EVM and Bitcoin testnets
+        /// and Solana devnet.
+        pub fn staging() -> VaultEnvironment {
+            VaultEnvironment {
+                icp_ledger: MAINNET_LEDGER_CANISTER_ID,
+                ecdsa_key_name: "test_key_1".to_string(),
+                evm_chains: Self::testnet_chains(),
+                bitcoin_network: Some(BitcoinNetwork::Testnet),
+                solana: Some(SolanaConfig::devnet()),
+            }
+        }
+
+        /// Production: threshold production key, EVM mainnets, Bitcoin mainnet and Solana
+        /// mainnet-beta.
+        pub fn mainnet() -> VaultEnvironment {
+            VaultEnvironment {
+                icp_ledger: MAINNET_LEDGER_CANISTER_ID,
+                ecdsa_key_name: "key_1".to_string(),
+                evm_chains: vec![
+                    EvmChainConfig {
+                        chain: "eth".to_string(),
+                        chain_id: 1,
+                        rpc: EvmRpcProvider::EthMainnetPublicNode,
+                        explorer_url: Some("https://etherscan.io".to_string()),
+                        confirmations: Some(12),
+                    },
+                    EvmChainConfig {
+                        chain: "base".to_string(),
+                        chain_id: 8453,
+                        rpc: EvmRpcProvider::Custom("https://mainnet.base.org/".to_string()),
+                        explorer_url: Some("https://basescan.org".to_string()),
+                        confirmations: Some(12),
+                    },
+                    EvmChainConfig {
+                        chain: "polygon".to_string(),
+                        chain_id: 137,
+                        rpc: EvmRpcProvider::Custom("https://polygon-rpc.com".to_string()),
+                        explorer_url: Some("https://polygonscan.com".to_string()),
+                        confirmations: Some(32),
+                    },
+                ],
+                bitcoin_network: Some(BitcoinNetwork::Mainnet),
+                solana: Some(SolanaConfig {
+                    rpc_url: "https://api.mainnet-beta.solana.com".to_string(),
+                    explorer_url: Some("https://explorer.solana.com".to_string()),
+                }),
+            }
+        }
+
+        fn testnet_chains() -> Vec<EvmChainConfig> {
+            vec![
+                EvmChainConfig {
+                    chain: "eth".to_string(),
+                    chain_id: 11155111,
+                    rpc: EvmRpcProvider::EthSepoliaPublicNode,
+                    explorer_url: Some("https://sepolia.etherscan.io".to_string()),
+                    confirmations: Some(3),
+                },
+                EvmChainConfig {
+                    chain: "base".to_string(),
+                    chain_id: 84532,
+                    rpc: EvmRpcProvider::Custom("https://sepolia.base.org/".to_string()),
+                    explorer_url: Some("https://sepolia.basescan.org".to_string()),
+                    confirmations: Some(3),
+                },
+                EvmChainConfig {
+                    chain: "polygon".to_string(),
+                    chain_id: 80002,
+                    rpc: EvmRpcProvider::Custom("https://rpc-amoy.polygon.technology".to_string()),
+                    explorer_url: Some("https://amoy.polygonscan.com".to_string()),
+                    confirmations: Some(3),
+                },
+            ]
+        }
+
+        pub fn evm_chain(&self, chain: &str) -> Option<&EvmChainConfig> {
+            self.evm_chains.iter().find(|c| c.chain == chain)
+        }
+    }
+
+    impl Default for VaultEnvironment {
+        fn default() -> Self {
+            Self::local()
+        }
+    }
+
+    impl Storable for VaultEnvironment {
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(candid::encode_one(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+            candid::decode_one(bytes.as_ref()).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
     }
 }
 
@@ -31,6 +228,521 @@ pub mod vault {
     pub enum TransactionType {
         Swap,
         Transfer,
+        Approve,
+        RevokeApproval,
+        TransferFrom,
+        ContractCall,
+        SignMessage,
+        Deposit,
+        Withdraw,
+        Stake,
+        ManageNeuron,
+        CanisterCall,
+        ManageCanister,
+        TopUp,
+        CreateCanister,
+        Batch,
+        SetFeeCap,
+    }
+
+    /// Parameters of an ICRC-2 approval or revocation.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct ApprovalArgs {
+        /// Nanoseconds since the epoch after which the approval lapses.
+        pub expires_at: Option<u64>,
+        /// The approval is rejected by the ledger unless the current allowance matches.
+        pub expected_allowance: Option<u128>,
+    }
+
+    /// Parameters of an ICRC-2 `transfer_from`. `to` on the proposal is the recipient.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct TransferFromArgs {
+        /// Principal or ICRC-1 textual account that approved the vault.
+        pub from: String,
+    }
+
+    /// Parameters of a swap. `to` on the proposal is the pool and `token` the input token.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct SwapArgs {
+        pub token_out: TokenPath,
+        /// Absolute minimum of `token_out` to receive, in its smallest unit.
+        pub min_amount_out: u128,
+        /// Maximum accepted drop from the quote at execution time, in basis points.
+        pub max_slippage_bps: u16,
+    }
+
+    /// Parameters of an EVM contract call. `to` on the proposal is the contract, `amount`
+    /// the native value sent along and `token` the chain's native token, e.g. "base:native".
+    ///
+    /// Either `calldata` is given as hex, or it is encoded from `signature` and `args`,
+    /// e.g. "approve(address,uint256)" and ["0x...", "1000"].
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct ContractCallArgs {
+        pub calldata: String,
+        pub signature: Option<String>,
+        pub args: Vec<String>,
+    }
+
+    /// A message to sign with the vault's EVM key.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum MessageToSign {
+        /// EIP-191 `personal_sign` of a text, or of bytes given as 0x-prefixed hex.
+        PersonalSign(String),
+        /// EIP-712 typed data as the JSON of `eth_signTypedData_v4`, which the vault hashes
+        /// so signers see the domain and message they approve.
+        TypedData(String),
+    }
+
+    /// Signature produced by an approved message signing proposal.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct MessageSignature {
+        /// The signed EIP-191 or EIP-712 digest.
+        pub hash: String,
+        /// 65 bytes r, s, v signature as hex.
+        pub signature: String,
+        pub address: String,
+    }
+
+    impl Storable for MessageSignature {
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(candid::encode_one(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+            candid::decode_one(bytes.as_ref()).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    /// A contract call decoded for display.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct ContractCallDetails {
+        pub chain: String,
+        pub contract: String,
+        pub value: f64,
+        pub selector: String,
+        /// Canonical signature, when the proposal gives one.
+        pub function: Option<String>,
+        /// Decoded arguments, or the raw 32 bytes words without a signature.
+        pub args: Vec<String>,
+    }
+
+    /// A call of any method of the canister in the proposal's `to`, made by the vault.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct CanisterCallArgs {
+        pub method: String,
+        /// Candid encoded arguments.
+        #[serde(with = "serde_bytes")]
+        pub args: Vec<u8>,
+        /// Cycles attached to the call, taken from the vault's balance.
+        pub cycles: Option<u64>,
+    }
+
+    /// What the called canister answered to an executed canister call proposal.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum CanisterCallResult {
+        /// Candid encoded reply.
+        Reply(#[serde(with = "serde_bytes")] Vec<u8>),
+        Reject { code: i32, message: String },
+    }
+
+    impl Storable for CanisterCallResult {
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(candid::encode_one(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+            candid::decode_one(bytes.as_ref()).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    /// A canister call proposal decoded for review.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct CanisterCallDetails {
+        pub canister_id: Principal,
+        pub method: String,
+        pub cycles: Option<u64>,
+        /// Arguments in Candid text format. Record fields are named when the canister's
+        /// interface is given, and numbered by their hash otherwise.
+        pub args: String,
+    }
+
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+    pub enum CanisterInstallMode {
+        Install,
+        Reinstall,
+        Upgrade,
+    }
+
+    /// Settings to change on a controlled canister, the unset ones are kept.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+    pub struct CanisterSettingsArgs {
+        pub controllers: Option<Vec<Principal>>,
+        pub compute_allocation: Option<u64>,
+        pub memory_allocation: Option<u64>,
+        pub freezing_threshold: Option<u64>,
+        pub reserved_cycles_limit: Option<u128>,
+        pub wasm_memory_limit: Option<u64>,
+    }
+
+    /// A management canister operation on the canister in the proposal's `to`, which
+    /// the vault controls.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum CanisterCommand {
+        /// Installs a module uploaded with `upload_wasm_chunk` and registered with
+        /// `register_wasm_module`, identified by its SHA-256 hash.
+        InstallCode {
+            mode: CanisterInstallMode,
+            #[serde(with = "serde_bytes")]
+            wasm_module_hash: Vec<u8>,
+            /// Candid encoded init or post-upgrade arguments.
+            #[serde(with = "serde_bytes")]
+            arg: Vec<u8>,
+        },
+        UpdateSettings(CanisterSettingsArgs),
+        Start,
+        Stop,
+        /// Deletes a stopped canister. Its remaining cycles are lost.
+        Delete,
+        TakeSnapshot {
+            replace_snapshot: Option<Vec<u8>>,
+        },
+        LoadSnapshot {
+            snapshot_id: Vec<u8>,
+        },
+        DeleteSnapshot {
+            snapshot_id: Vec<u8>,
+        },
+        /// Sends cycles of the vault to the canister.
+        TopUp {
+            cycles: u128,
+        },
+    }
+
+    /// A Wasm module uploaded to the vault in chunks.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct WasmModule {
+        /// SHA-256 of the whole module.
+        pub hash: Vec<u8>,
+        pub size: u64,
+        /// SHA-256 of each chunk, in order.
+        pub chunk_hashes: Vec<Vec<u8>>,
+    }
+
+    impl Storable for WasmModule {
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(candid::encode_one(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+            candid::decode_one(bytes.as_ref()).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+    pub enum CanisterRunStatus {
+        Running,
+        Stopping,
+        Stopped,
+    }
+
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct CanisterSnapshot {
+        pub id: Vec<u8>,
+        /// Nanoseconds since the epoch.
+        pub taken_at_timestamp: u64,
+        pub total_size: u64,
+    }
+
+    /// A canister the vault controls, as of its last status check.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct ControlledCanister {
+        pub canister_id: Principal,
+        pub status: CanisterRunStatus,
+        /// SHA-256 of the installed module, empty when no code is installed.
+        pub module_hash: Option<Vec<u8>>,
+        pub cycles: u128,
+        pub memory_size: u64,
+        pub controllers: Vec<Principal>,
+        pub snapshots: Vec<CanisterSnapshot>,
+        /// Nanoseconds since the epoch of the status check.
+        pub updated_at: u64,
+    }
+
+    impl Storable for ControlledCanister {
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(candid::encode_one(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+            candid::decode_one(bytes.as_ref()).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    /// Where the vault gets cycles from when its balance runs low.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum CyclesRefuelSource {
+        /// Converts `e8s` of the vault's ICP through the CMC.
+        Icp { e8s: u64 },
+        /// Asks the central canister that deployed the vault.
+        Central(Principal),
+    }
+
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct CyclesMonitorConfig {
+        /// Balance under which the vault refuels, or raises an alert when it can't.
+        pub threshold: u128,
+        /// No automatic refuel when empty, only alerts.
+        pub refuel: Option<CyclesRefuelSource>,
+    }
+
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct CyclesStatus {
+        pub balance: u128,
+        /// Cycles burnt per day over the recent checks, none until there are two checks.
+        pub burn_rate_per_day: Option<u128>,
+        pub config: CyclesMonitorConfig,
+        /// Nanoseconds since the epoch of the last check.
+        pub checked_at: Option<u64>,
+    }
+
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum AuditEventKind {
+        CyclesLow {
+            balance: u128,
+            threshold: u128,
+        },
+        CyclesRefueled {
+            source: CyclesRefuelSource,
+            cycles: u128,
+        },
+        CyclesRefuelFailed {
+            source: CyclesRefuelSource,
+            error: String,
+        },
+    }
+
+    /// Something the vault did or noticed on its own, outside of proposals.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct AuditEvent {
+        /// Nanoseconds since the epoch.
+        pub timestamp: u64,
+        pub kind: AuditEventKind,
+    }
+
+    impl Storable for AuditEvent {
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(candid::encode_one(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+            candid::decode_one(bytes.as_ref()).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    /// Chain-key minter a deposit or withdrawal goes through. `token` on the proposal is
+    /// the ledger of the ck token, e.g. "icp:icrc1:<ckBTC ledger>", and `to` the Bitcoin
+    /// or Ethereum address that receives a withdrawal.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+    pub enum ChainKeyMinter {
+        CkBtc(Principal),
+        CkEth(Principal),
+    }
+
+    impl ChainKeyMinter {
+        pub fn canister_id(&self) -> Principal {
+            match self {
+                ChainKeyMinter::CkBtc(minter) | ChainKeyMinter::CkEth(minter) => *minter,
+            }
+        }
+    }
+
+    /// A change to one of the vault's NNS neurons. Neurons are created by a Stake
+    /// proposal of "icp:native", whose amount is the stake in e8s.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum NeuronCommand {
+        IncreaseDissolveDelay {
+            neuron_id: u64,
+            additional_dissolve_delay_seconds: u32,
+        },
+        StartDissolving {
+            neuron_id: u64,
+        },
+        StopDissolving {
+            neuron_id: u64,
+        },
+        /// Replaces the followees of a topic, an empty list removes the following.
+        SetFollowing {
+            neuron_id: u64,
+            topic: i32,
+            followees: Vec<u64>,
+        },
+        Vote {
+            neuron_id: u64,
+            proposal_id: u64,
+            approve: bool,
+        },
+        /// Disburses a dissolved neuron to a hex account identifier, or back to the
+        /// vault. The whole stake when `amount_e8s` is empty.
+        Disburse {
+            neuron_id: u64,
+            to_account: Option<String>,
+            amount_e8s: Option<u64>,
+        },
+    }
+
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+    pub enum NeuronState {
+        Unspecified,
+        Locked,
+        Dissolving,
+        Dissolved,
+        Spawning,
+    }
+
+    /// An NNS neuron controlled by the vault.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct NnsNeuron {
+        pub id: u64,
+        pub stake_e8s: u64,
+        /// Remaining dissolve delay when dissolving.
+        pub dissolve_delay_seconds: u64,
+        pub state: NeuronState,
+        /// Followed neurons by topic.
+        pub followees: Vec<(i32, Vec<u64>)>,
+        /// Recent votes as proposal ids and whether the neuron voted yes.
+        pub recent_votes: Vec<(u64, bool)>,
+    }
+
+    /// What an SNS proposal of the vault does once adopted.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum SnsProposalAction {
+        Motion {
+            motion_text: String,
+        },
+        /// Calls a function the SNS registered through AddGenericNervousSystemFunction,
+        /// with its candid encoded arguments.
+        ExecuteGenericNervousSystemFunction {
+            function_id: u64,
+            payload: Vec<u8>,
+        },
+    }
+
+    /// A change to one of the vault's neurons in the SNS whose governance canister is
+    /// the proposal's `to`. Neurons are created by a Stake proposal of the SNS ledger's
+    /// "icp:icrc1:<ledger>" token. SNS neuron ids are the bytes of their subaccount.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum SnsNeuronCommand {
+        IncreaseDissolveDelay {
+            neuron_id: Vec<u8>,
+            additional_dissolve_delay_seconds: u32,
+        },
+        StartDissolving {
+            neuron_id: Vec<u8>,
+        },
+        StopDissolving {
+            neuron_id: Vec<u8>,
+        },
+        /// Replaces the followees of a function, an empty list removes the following.
+        SetFollowing {
+            neuron_id: Vec<u8>,
+            function_id: u64,
+            followees: Vec<Vec<u8>>,
+        },
+        Vote {
+            neuron_id: Vec<u8>,
+            proposal_id: u64,
+            approve: bool,
+        },
+        MakeProposal {
+            neuron_id: Vec<u8>,
+            title: String,
+            url: String,
+            summary: String,
+            action: SnsProposalAction,
+        },
+        /// Disburses a dissolved neuron to an ICRC-1 textual account, or back to the
+        /// vault. The whole stake when `amount_e8s` is empty.
+        Disburse {
+            neuron_id: Vec<u8>,
+            to_account: Option<String>,
+            amount_e8s: Option<u64>,
+        },
+    }
+
+    /// A neuron the vault controls in an SNS.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct SnsNeuron {
+        pub id: Vec<u8>,
+        pub stake_e8s: u64,
+        /// Remaining dissolve delay when dissolving.
+        pub dissolve_delay_seconds: u64,
+        pub state: NeuronState,
+        /// Followed neurons by function id.
+        pub followees: Vec<(u64, Vec<Vec<u8>>)>,
+    }
+
+    /// Extra parameters for transaction types that need more than a recipient and an amount.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub enum TransactionPayload {
+        Approval(ApprovalArgs),
+        TransferFrom(TransferFromArgs),
+        Swap(SwapArgs),
+        ContractCall(ContractCallArgs),
+        SignMessage(MessageToSign),
+        Minter(ChainKeyMinter),
+        Neuron(NeuronCommand),
+        SnsNeuron(SnsNeuronCommand),
+        CanisterCall(CanisterCallArgs),
+        Canister(CanisterCommand),
+        /// Maximum fee in wei of a `SetFeeCap` proposal, none removes the cap.
+        FeeCap(Option<u128>),
+    }
+
+    /// Bitcoin addresses of the vault. P2WPKH keys come from threshold ECDSA and
+    /// Taproot keys from threshold Schnorr (BIP340).
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum BitcoinAddressType {
+        P2wpkh,
+        P2tr,
+    }
+
+    /// Tokens the vault owns in a registered ICRC-7 collection.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct NftHoldings {
+        pub collection: Principal,
+        pub token_ids: Vec<candid::Nat>,
+    }
+
+    /// Outcome of an EVM transaction once it is included in a block.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct EvmReceipt {
+        pub chain: String,
+        pub hash: String,
+        /// False when the transaction reverted.
+        pub success: bool,
+        pub gas_used: u128,
+        pub effective_gas_price: u128,
+        pub block_number: u64,
+        pub confirmations: u64,
+    }
+
+    /// An allowance the vault has granted on an ICRC-2 ledger.
+    #[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+    pub struct GrantedAllowance {
+        pub ledger: Principal,
+        pub spender: Account,
+        pub allowance: candid::Nat,
+        pub expires_at: Option<u64>,
     }
 
     pub mod ledger {
@@ -45,6 +757,10 @@ pub mod vault {
         pub network: SupportedNetwork,
         pub amount: f64,
         pub transaction_type: TransactionType,
+        pub payload: Option<TransactionPayload>,
+        /// Named EVM wallet the transaction is sent from, the vault's main address when empty.
+        #[serde(default)]
+        pub wallet: Option<String>,
     }
 
     #[derive(
@@ -68,6 +784,12 @@ pub mod vault {
         pub transaction_type: TransactionType,
         pub signers: Vec<Principal>,
         pub rejections: Vec<Principal>,
+        #[serde(default)]
+        pub payload: Option<TransactionPayload>,
+        #[serde(default)]
+        pub wallet: Option<String>,
+        #[serde(default)]
+        pub executed: bool,
     }
 
     impl Storable for ProposedTransaction {
@@ -145,6 +867,9 @@ pub mod vault {
         ICP,
         ETH,
         BTC,
+        SOL,
+        BASE,
+        POLYGON,
     }
 
     #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
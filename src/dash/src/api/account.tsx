@@ -3,6 +3,7 @@ import { Identity } from "@dfinity/agent";
 import { createActor as createCentralActor } from "../../../declarations/central";
 import { createActor as createAccountActor } from "../../../declarations/account";
 import {
+  CanisterCallDetails,
   IntentStatus,
   ProposedTransaction,
   ProposeTransactionArgs,
@@ -102,6 +103,22 @@ export function executeTransaction(
   );
 }
 
+export async function getCanisterCallDetails(
+  account_canister_id: Principal,
+  proposal_id: bigint,
+  did: string | undefined,
+  identity: Identity
+): Promise<CanisterCallDetails> {
+  const result = await getAccountActor(
+    account_canister_id,
+    identity
+  ).get_canister_call_details(proposal_id, did ? [did] : []);
+  if ("Err" in result) {
+    throw new Error(result.Err);
+  }
+  return result.Ok;
+}
+
 export function getProposedTransactions(
   account_canister_id: Principal,
   identity: Identity
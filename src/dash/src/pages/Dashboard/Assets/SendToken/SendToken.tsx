@@ -189,6 +189,8 @@ const SendToken: React.FC = () => {
           ? { ETH: null }
           : { ICP: null },
         transaction_type: { Transfer: null },
+        payload: [] as [],
+        wallet: [] as [],
         from: nativeAccountId,
       };
 
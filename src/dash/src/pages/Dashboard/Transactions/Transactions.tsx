@@ -9,6 +9,7 @@ import {
   Divider,
   Chip,
   Paper,
+  Button,
 } from "@mui/material";
 import {
   Send as SendIcon,
@@ -19,6 +20,7 @@ import {
   Cancel as RejectedIcon,
 } from "@mui/icons-material";
 import AccountPageLayout from "../../VaultPageLayout";
+import CanisterCallModal from "./modal/CanisterCallModal";
 import {
   IntentStatus,
   Transaction,
@@ -43,6 +45,7 @@ const Transactions: React.FC = () => {
   const [transactions, setTransactions] = useState<UnifiedTransaction[]>([]);
   const [threshold, setThreshold] = useState<bigint>(BigInt(0));
   const [isLoading, setIsLoading] = useState(true);
+  const [callProposalId, setCallProposalId] = useState<bigint | null>(null);
   const { vaultCanisterId, nativeAccountId } = useVaultDetail();
   const { identity } = useInternetIdentity();
 
@@ -128,6 +131,10 @@ const Transactions: React.FC = () => {
     return "Unknown";
   };
 
+  const isCanisterCall = (transaction: ProposedTransaction) =>
+    transaction.payload.length > 0 &&
+    "CanisterCall" in transaction.payload[0]!;
+
   const renderSignersInfo = (signers: Principal[], rejections: Principal[]) => {
     return (
       <Box sx={{ mt: 1 }}>
@@ -243,6 +250,14 @@ const Transactions: React.FC = () => {
                             transaction.signers,
                             transaction.rejections
                           )}
+                          {isCanisterCall(transaction) && (
+                            <Button
+                              size="small"
+                              onClick={() => setCallProposalId(transaction.id)}
+                            >
+                              View call
+                            </Button>
+                          )}
                         </>
                       )}
                     <Box
@@ -306,6 +321,15 @@ const Transactions: React.FC = () => {
           renderTransactionsList()
         )}
       </Box>
+      {vaultCanisterId && identity && callProposalId !== null && (
+        <CanisterCallModal
+          visible
+          onClose={() => setCallProposalId(null)}
+          vaultCanisterId={vaultCanisterId}
+          identity={identity}
+          proposalId={callProposalId}
+        />
+      )}
     </AccountPageLayout>
   );
 };
@@ -0,0 +1,145 @@
+import React, { useEffect, useState } from "react";
+import { Box, Typography, Modal, Button, TextField } from "@mui/material";
+import { Identity } from "@dfinity/agent";
+import { Principal } from "@dfinity/principal";
+import { CanisterCallDetails } from "../../../../../../declarations/account/account.did";
+import { getCanisterCallDetails } from "../../../../api/account";
+
+interface CanisterCallModalProps {
+  visible: boolean;
+  onClose: () => void;
+  vaultCanisterId: Principal;
+  identity: Identity;
+  proposalId: bigint;
+}
+
+// Shows what a canister call proposal does before approving it. The arguments are
+// decoded by the vault, and get their field names when the .did of the called
+// canister is supplied.
+const CanisterCallModal: React.FC<CanisterCallModalProps> = ({
+  visible,
+  onClose,
+  vaultCanisterId,
+  identity,
+  proposalId,
+}) => {
+  const [did, setDid] = useState("");
+  const [details, setDetails] = useState<CanisterCallDetails | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  const decode = async (calleeDid?: string) => {
+    try {
+      setDetails(
+        await getCanisterCallDetails(
+          vaultCanisterId,
+          proposalId,
+          calleeDid,
+          identity
+        )
+      );
+      setError(null);
+    } catch (e) {
+      setError(e instanceof Error ? e.message : String(e));
+    }
+  };
+
+  useEffect(() => {
+    if (visible) {
+      setDid("");
+      setDetails(null);
+      setError(null);
+      decode();
+    }
+  }, [visible, proposalId]);
+
+  const handleFile = async (event: React.ChangeEvent<HTMLInputElement>) => {
+    const file = event.target.files?.[0];
+    if (file) {
+      setDid(await file.text());
+    }
+  };
+
+  return (
+    <Modal
+      open={visible}
+      onClose={onClose}
+      sx={{ display: "flex", alignItems: "center", justifyContent: "center" }}
+    >
+      <Box
+        p={2}
+        sx={{ bgcolor: "background.paper", borderRadius: 1, width: "600px" }}
+        data-testid="canister-call-modal"
+      >
+        <Typography variant="h6">
+          Canister call #{proposalId.toString()}
+        </Typography>
+        {details && (
+          <Box mt={2}>
+            <Typography variant="body2">
+              Canister: {details.canister_id.toString()}
+            </Typography>
+            <Typography variant="body2">Method: {details.method}</Typography>
+            {details.cycles.length > 0 && (
+              <Typography variant="body2">
+                Cycles: {details.cycles[0]!.toString()}
+              </Typography>
+            )}
+            <Box
+              component="pre"
+              mt={1}
+              p={1}
+              sx={{
+                bgcolor: "rgba(255, 255, 255, 0.05)",
+                borderRadius: 1,
+                maxHeight: "240px",
+                overflow: "auto",
+                whiteSpace: "pre-wrap",
+                wordBreak: "break-all",
+              }}
+            >
+              {details.args}
+            </Box>
+          </Box>
+        )}
+        {error && (
+          <Typography variant="body2" color="error" mt={2}>
+            {error}
+          </Typography>
+        )}
+        <Box mt={2}>
+          <TextField
+            value={did}
+            onChange={(event) => setDid(event.target.value)}
+            placeholder="Candid interface (.did) of the called canister"
+            multiline
+            minRows={3}
+            maxRows={8}
+            fullWidth
+            size="small"
+          />
+        </Box>
+        <Box mt={2} display="flex" justifyContent="space-between">
+          <Button component="label">
+            Load .did
+            <input type="file" accept=".did" hidden onChange={handleFile} />
+          </Button>
+          <Box>
+            <Button onClick={onClose} style={{ marginRight: 8 }}>
+              Close
+            </Button>
+            <Button
+              variant="contained"
+              color="primary"
+              disabled={!did.trim()}
+              onClick={() => decode(did)}
+            >
+              Decode
+            </Button>
+          </Box>
+        </Box>
+      </Box>
+    </Modal>
+  );
+};
+
+export default CanisterCallModal;
@@ -3,8 +3,15 @@ use candid::{encode_one, CandidType, Decode, Principal};
 use ic_ledger_types::AccountIdentifier;
 use crate::setup::setup_new_env_with_config;
 use crate::setup::SetupConfig;
+use crate::setup::{install_icrc1_ledger, install_mock_bitcoin, install_mock_cmc, install_mock_cycles_ledger, install_mock_icrc7, install_mock_minter, install_mock_nns_governance, install_mock_sns_governance, install_mock_swap_pool};
+use crate::types::{Batch, BatchTransfer};
+use crate::types::{EvmWallet, EvmWalletBalance};
+use crate::types::ExecutedTransaction;
+use crate::types::IsApprovedArg;
+use crate::types::MockSwapPoolArgs;
 use crate::types::NnsLedgerCanisterInitPayload;
 use crate::types::NnsLedgerCanisterUpgradePayload;
+use crate::types::PendingWithdrawal;
 use crate::TestEnv;
 use pocket_ic::{query_candid_as, update_candid_as};
 use serde::{Deserialize, Serialize};
@@ -13,6 +20,7 @@ use serde::{Deserialize, Serialize};
 use keygate_core::utils::to_subaccount;
 // use core
 use keygate_core::types::vault::{IntentStatus, ProposeTransactionArgs, ProposedTransaction, SupportedNetwork, TransactionType};
+use keygate_core::types::environment::VaultEnvironment;
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Clone, Eq, PartialEq, Debug, CandidType, Deserialize, Serialize)]
@@ -76,6 +84,29 @@ fn should_initialize_with_default_values() {
 }
 
 
+#[test]
+fn should_default_to_local_environment() {
+    let caller = generate_principal();
+    let TestEnv {
+        env,
+        canister_ids,
+    } = setup_new_env_with_config(SetupConfig {
+        default_account_owner: Some(caller),
+        ..Default::default()
+    });
+
+    let (environment,): (VaultEnvironment,) = query_candid_as(
+        &env,
+        canister_ids.account,
+        caller,
+        "get_environment",
+        (),
+    ).unwrap();
+
+    assert_eq!(environment, VaultEnvironment::local());
+    assert_eq!(environment.icp_ledger, canister_ids.icp_ledger);
+}
+
 #[test]
 fn should_add_signer() {
     let caller = generate_principal();
@@ -147,6 +178,8 @@ fn should_propose_transaction() {
             network: SupportedNetwork::ICP,
             amount: 100_000_000.0,
             transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
         },),
     )
     .unwrap();
@@ -158,6 +191,24 @@ fn should_propose_transaction() {
     assert_eq!(proposed_transaction.amount, 100_000_000.0);
     assert_eq!(proposed_transaction.transaction_type, TransactionType::Transfer);
     assert_eq!(proposed_transaction.signers, vec![caller]);
+
+    // The network has to be the one of the token's chain
+    let result: Result<(ProposedTransaction,), _> = update_candid_as(
+        &env,
+        account_id,
+        caller,
+        "propose_transaction",
+        (ProposeTransactionArgs {
+            to: "0x000000000000000000000000000000000000dEaD".to_string(),
+            token: "eth:native".to_string(),
+            network: SupportedNetwork::POLYGON,
+            amount: 1.0,
+            transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
+        },),
+    );
+    assert!(result.is_err(), "A proposal named another network than its token's");
 }
 #[test]
 fn should_get_proposed_transaction() {
@@ -190,6 +241,8 @@ fn should_get_proposed_transaction() {
             network: SupportedNetwork::ICP,
             amount: 100_000_000.0,
             transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
         },),
     ).unwrap();
 
@@ -242,6 +295,8 @@ fn should_get_proposed_transactions() {
             network: SupportedNetwork::ICP,
             amount: 100_000_000.0,
             transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
         },),
     ).unwrap();
 
@@ -292,6 +347,8 @@ fn should_approve_transaction() {
             network: SupportedNetwork::ICP,
             amount: 100_000_000.0,
             transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
         },),
     ).unwrap();
 
@@ -383,6 +440,8 @@ fn should_reject_transaction() {
             network: SupportedNetwork::ICP,
             amount: 100_000_000.0,
             transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
         },),
     ).unwrap();
 
@@ -517,6 +576,8 @@ fn should_not_allow_tx_if_threshold_not_met() {
             network: SupportedNetwork::ICP,
             amount: 100_000_000_000.0,
             transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
         },),
     ).unwrap();
     
@@ -575,6 +636,94 @@ fn should_not_allow_tx_if_threshold_not_met() {
     assert_eq!(balance, 100_000_000_000, "Receiver should have received the transfer amount");
 }
 
+#[test]
+fn should_count_each_signer_once_and_execute_once() {
+    let caller = generate_principal();
+    let TestEnv {
+        env,
+        canister_ids,
+    } = setup_new_env_with_config(SetupConfig {
+        default_account_owner: Some(caller),
+        ..Default::default()
+    });
+
+    let signer_2 = generate_principal();
+    let _: () = update_candid_as(&env, canister_ids.account, caller, "add_signer", (signer_2,)).unwrap();
+    let _: () = update_candid_as(&env, canister_ids.account, caller, "set_threshold", (2u64,)).unwrap();
+
+    let receiver = generate_principal();
+    let args = ProposeTransactionArgs {
+        to: receiver.to_text(),
+        token: format!("icp:icrc1:{}", canister_ids.icrc1_ledger.to_text()),
+        network: SupportedNetwork::ICP,
+        amount: 100_000_000_000.0,
+        transaction_type: TransactionType::Transfer,
+        payload: None,
+        wallet: None,
+    };
+
+    // Only signers can propose
+    let result: Result<(ProposedTransaction,), _> = update_candid_as(
+        &env,
+        canister_ids.account,
+        generate_principal(),
+        "propose_transaction",
+        (args.clone(),),
+    );
+    assert!(result.is_err(), "A non-signer could propose");
+
+    let (proposed_transaction,): (ProposedTransaction,) = update_candid_as(
+        &env,
+        canister_ids.account,
+        caller,
+        "propose_transaction",
+        (args,),
+    ).unwrap();
+
+    // The proposer approving again doesn't meet the threshold of two
+    let result: Result<(), _> = update_candid_as(
+        &env,
+        canister_ids.account,
+        caller,
+        "approve_transaction",
+        (proposed_transaction.id,),
+    );
+    assert!(result.is_err(), "A signer could approve twice");
+
+    let _: () = update_candid_as(
+        &env,
+        canister_ids.account,
+        signer_2,
+        "approve_transaction",
+        (proposed_transaction.id,),
+    ).unwrap();
+
+    let execute = || {
+        let (status,): (IntentStatus,) = update_candid_as(
+            &env,
+            canister_ids.account,
+            caller,
+            "execute_transaction",
+            (proposed_transaction.id,),
+        ).unwrap();
+        status
+    };
+    assert!(matches!(execute(), IntentStatus::Completed(_)));
+    assert_eq!(
+        execute(),
+        IntentStatus::Failed(format!("Proposal {} was already executed", proposed_transaction.id))
+    );
+
+    let (balance,): (u128,) = query_candid_as(
+        &env,
+        canister_ids.icrc1_ledger,
+        caller,
+        "icrc1_balance_of",
+        (ICRCAccount::new(receiver, None),),
+    ).unwrap();
+    assert_eq!(balance, 100_000_000_000, "The transfer should have run once");
+}
+
 #[test]
 fn should_not_add_signer_if_exists() {
     println!("Starting should_not_add_signer_if_exists test");
@@ -717,36 +866,117 @@ fn should_get_default_account_for_icrc() {
     println!("Test completed successfully");
 }
 
+#[test]
+fn should_add_named_evm_wallets() {
+    let caller = generate_principal();
+    let TestEnv {
+        env,
+        canister_ids,
+    } = setup_new_env_with_config(SetupConfig {
+        default_account_owner: Some(caller),
+        ..Default::default()
+    });
+    let account_id = canister_ids.account;
+
+    let add_wallet = |name: &str| {
+        let (result,): (Result<EvmWallet, String>,) =
+            update_candid_as(&env, account_id, caller, "add_evm_wallet", (name.to_string(),))
+                .unwrap();
+        result
+    };
+
+    let payroll = add_wallet("payroll").unwrap();
+    let reserves = add_wallet("reserves").unwrap();
+    assert_ne!(payroll.address, reserves.address);
+    assert!(add_wallet("payroll").is_err(), "Wallet names are unique");
+    assert!(add_wallet("main").is_err(), "The main wallet is reserved");
+
+    let (main_address,): (String,) =
+        query_candid_as(&env, account_id, caller, "pubkey_bytes_to_address", ()).unwrap();
+    assert_ne!(main_address, payroll.address);
+
+    let (wallets,): (Vec<EvmWallet>,) =
+        query_candid_as(&env, account_id, caller, "get_evm_wallets", ()).unwrap();
+    let names: Vec<&str> = wallets.iter().map(|w| w.name.as_str()).collect();
+    assert_eq!(names, vec!["main", "payroll", "reserves"]);
+    assert_eq!(wallets[0].address, main_address);
+
+    // Balances are fetched with HTTPS outcalls the vault pays for, so only signers can ask
+    let (balances,): (Result<Vec<EvmWalletBalance>, String>,) = update_candid_as(
+        &env,
+        account_id,
+        generate_principal(),
+        "get_evm_wallet_balances",
+        ("eth".to_string(), None::<String>),
+    )
+    .unwrap();
+    assert_eq!(balances, Err("Caller is not a signer".to_string()));
+
+    // Proposals can only spend from wallets that exist
+    let propose = |wallet: &str| {
+        update_candid_as::<_, (ProposedTransaction,)>(
+            &env,
+            account_id,
+            caller,
+            "propose_transaction",
+            (ProposeTransactionArgs {
+                to: "0x000000000000000000000000000000000000dEaD".to_string(),
+                token: "eth:native".to_string(),
+                network: SupportedNetwork::ETH,
+                amount: 0.01,
+                transaction_type: TransactionType::Transfer,
+                payload: None,
+                wallet: Some(wallet.to_string()),
+            },),
+        )
+    };
+
+    let (proposal,) = propose("payroll").unwrap();
+    assert_eq!(proposal.wallet, Some("payroll".to_string()));
+    assert!(propose("marketing").is_err());
+}
+
 mod intent_tests {
     
 
     use ic_ledger_types::{AccountBalanceArgs, Tokens, DEFAULT_SUBACCOUNT};
+    use icrc_ledger_types::icrc1::account::Account;
     
     
     use pocket_ic::WasmResult;
+    use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, GetBalanceRequest};
 
 
     // use/move to core
     use keygate_core::types::vault::{
-        ledger::RECOMMENDED_ICP_TRANSACTION_FEE, IntentStatus, SupportedNetwork, TransactionType,
+        ledger::RECOMMENDED_ICP_TRANSACTION_FEE, ApprovalArgs, BitcoinAddressType, CanisterCallArgs,
+        CanisterCallDetails, CanisterCallResult, CanisterCommand, CanisterInstallMode, CanisterRunStatus,
+        CanisterSettingsArgs,
+        ChainKeyMinter, ControlledCanister, WasmModule, AuditEvent, AuditEventKind, CyclesMonitorConfig,
+        CyclesRefuelSource, CyclesStatus,
+        GrantedAllowance, IntentStatus, NeuronCommand, NeuronState, NftHoldings, NnsNeuron, SnsNeuron, SnsNeuronCommand,
+        SnsProposalAction, SupportedNetwork, SwapArgs, TransactionPayload, TransactionType,
     };
+    use crate::utils::{CYCLES_LEDGER_CANISTER_ID, NNS_GOVERNANCE_CANISTER_ID};
 
     use super::*;
 
     #[test]
     fn should_transfer_icrc1() {
+        let caller = generate_principal();
         let test_env = setup_new_env_with_config(SetupConfig {
-            default_account_owner: Some(generate_principal()),
+            default_account_owner: Some(caller),
             initial_mock_icrc1_balance: Some(1000_000_000_000),
             ..Default::default()
         });
-        
-        let caller = test_env.canister_ids.account;
+
         let receiver = generate_principal();
         
         let transfer_amount = 100_000_000_000.0;
         let proposed_tx = ProposeTransactionArgs {
             transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
             amount: transfer_amount,
             network: SupportedNetwork::ICP,
             to: receiver.to_text(),
@@ -804,13 +1034,13 @@ mod intent_tests {
 
     #[test]
     fn should_transfer_icp() {
+        let caller = generate_principal();
         let test_env = setup_new_env_with_config(SetupConfig {
-            default_account_owner: Some(generate_principal()),
+            default_account_owner: Some(caller),
             initial_icp_balance: Some(100_000_000_000_000),
             ..Default::default()
         });
-        
-        let caller = test_env.canister_ids.account;
+
         let receiver = generate_principal();
         
         // Create an intent to transfer ICP
@@ -818,6 +1048,8 @@ mod intent_tests {
         let receiver_account = AccountIdentifier::new(&receiver, &DEFAULT_SUBACCOUNT);
         let proposed_tx = ProposeTransactionArgs {
             transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
             amount: transfer_amount,
             network: SupportedNetwork::ICP,
             to: format!("{}", receiver_account.to_string()),
@@ -889,4 +1121,1905 @@ mod intent_tests {
             100_000_000_000_000 - transfer_amount as u64 - RECOMMENDED_ICP_TRANSACTION_FEE
         );
     }
+
+    #[test]
+    fn should_transfer_icp_to_principal() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            initial_icp_balance: Some(100_000_000_000_000),
+            ..Default::default()
+        });
+
+        let receiver = generate_principal();
+
+        let transfer_amount = 100_000_000.0;
+        let proposed_tx = ProposeTransactionArgs {
+            transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
+            amount: transfer_amount,
+            network: SupportedNetwork::ICP,
+            to: receiver.to_text(),
+            token: "icp:native".to_string(),
+        };
+
+        let add_intent_result: (ProposedTransaction,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "propose_transaction",
+            (proposed_tx,),
+        )
+        .unwrap();
+
+        let status: (IntentStatus,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "execute_transaction",
+            (add_intent_result.0.id,),
+        )
+        .unwrap();
+
+        assert_eq!(
+            status.0,
+            IntentStatus::Completed("Successfully transferred native ICP.".to_string())
+        );
+
+        let (receiver_balance,): (u128,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.icp_ledger,
+            caller,
+            "icrc1_balance_of",
+            (ICRCAccount::new(receiver, None),),
+        )
+        .unwrap();
+
+        assert_eq!(receiver_balance, transfer_amount as u128);
+
+        let account_balance_args = AccountBalanceArgs {
+            account: AccountIdentifier::new(&test_env.canister_ids.account, &DEFAULT_SUBACCOUNT),
+        };
+        let (account_balance,): (Tokens,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.icp_ledger,
+            caller,
+            "account_balance",
+            (account_balance_args,),
+        )
+        .unwrap();
+
+        assert_eq!(
+            account_balance.e8s(),
+            100_000_000_000_000 - transfer_amount as u64 - RECOMMENDED_ICP_TRANSACTION_FEE
+        );
+    }
+
+    #[test]
+    fn should_transfer_icp_to_icrc1_textual_account() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            initial_icp_balance: Some(100_000_000_000_000),
+            ..Default::default()
+        });
+
+        let receiver = Account {
+            owner: generate_principal(),
+            subaccount: Some(to_subaccount(7).0),
+        };
+
+        let transfer_amount = 100_000_000.0;
+        let proposed_tx = ProposeTransactionArgs {
+            transaction_type: TransactionType::Transfer,
+            payload: None,
+            wallet: None,
+            amount: transfer_amount,
+            network: SupportedNetwork::ICP,
+            to: receiver.to_string(),
+            token: "icp:native".to_string(),
+        };
+
+        let add_intent_result: (ProposedTransaction,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "propose_transaction",
+            (proposed_tx,),
+        )
+        .unwrap();
+
+        let status: (IntentStatus,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "execute_transaction",
+            (add_intent_result.0.id,),
+        )
+        .unwrap();
+
+        assert_eq!(
+            status.0,
+            IntentStatus::Completed("Successfully transferred native ICP.".to_string())
+        );
+
+        let (receiver_balance,): (u128,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.icp_ledger,
+            caller,
+            "icrc1_balance_of",
+            (receiver,),
+        )
+        .unwrap();
+
+        assert_eq!(receiver_balance, transfer_amount as u128);
+    }
+
+    fn propose_and_execute(
+        test_env: &TestEnv,
+        caller: Principal,
+        proposed_tx: ProposeTransactionArgs,
+    ) -> IntentStatus {
+        let (proposed_transaction,): (ProposedTransaction,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "propose_transaction",
+            (proposed_tx,),
+        )
+        .unwrap();
+
+        let (status,): (IntentStatus,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "execute_transaction",
+            (proposed_transaction.id,),
+        )
+        .unwrap();
+
+        status
+    }
+
+    #[test]
+    fn should_approve_and_revoke_icrc2_allowance() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+
+        let spender = generate_principal();
+        let token = format!("icp:icrc1:{}", test_env.canister_ids.icrc1_ledger.to_text());
+
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            ProposeTransactionArgs {
+                transaction_type: TransactionType::Approve,
+                amount: 5_000_000.0,
+                network: SupportedNetwork::ICP,
+                to: spender.to_text(),
+                token: token.clone(),
+                payload: Some(TransactionPayload::Approval(ApprovalArgs {
+                    expires_at: None,
+                    expected_allowance: Some(0),
+                })),
+                wallet: None,
+            },
+        );
+
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Successfully approved an ICRC-2 allowance.".to_string())
+        );
+
+        let (allowances,): (Vec<GrantedAllowance>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_allowances",
+            (),
+        )
+        .unwrap();
+
+        assert_eq!(allowances.len(), 1);
+        assert_eq!(allowances[0].ledger, test_env.canister_ids.icrc1_ledger);
+        assert_eq!(allowances[0].spender.owner, spender);
+        assert_eq!(allowances[0].allowance, candid::Nat::from(5_000_000u64));
+
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            ProposeTransactionArgs {
+                transaction_type: TransactionType::RevokeApproval,
+                amount: 0.0,
+                network: SupportedNetwork::ICP,
+                to: spender.to_text(),
+                token,
+                payload: None,
+                wallet: None,
+            },
+        );
+
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Successfully revoked an ICRC-2 allowance.".to_string())
+        );
+
+        let (allowances,): (Vec<GrantedAllowance>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_allowances",
+            (),
+        )
+        .unwrap();
+
+        assert!(allowances.is_empty());
+    }
+
+    fn nft_args(
+        transaction_type: TransactionType,
+        to: String,
+        token: String,
+    ) -> ProposeTransactionArgs {
+        ProposeTransactionArgs {
+            transaction_type,
+            amount: 1.0,
+            network: SupportedNetwork::ICP,
+            to,
+            token,
+            payload: None,
+            wallet: None,
+        }
+    }
+
+    fn nft_owner(test_env: &TestEnv, collection: Principal, token_id: u64) -> Option<Account> {
+        let (owners,): (Vec<Option<Account>>,) = query_candid_as(
+            &test_env.env,
+            collection,
+            test_env.canister_ids.account,
+            "icrc7_owner_of",
+            (vec![candid::Nat::from(token_id)],),
+        )
+        .unwrap();
+        owners[0]
+    }
+
+    fn nft_approved(
+        test_env: &TestEnv,
+        collection: Principal,
+        spender: Principal,
+        token_id: u64,
+    ) -> bool {
+        let (approved,): (Vec<bool>,) = query_candid_as(
+            &test_env.env,
+            collection,
+            test_env.canister_ids.account,
+            "icrc37_is_approved",
+            (vec![IsApprovedArg {
+                spender: Account {
+                    owner: spender,
+                    subaccount: None,
+                },
+                from_subaccount: None,
+                token_id: candid::Nat::from(token_id),
+            }],),
+        )
+        .unwrap();
+        approved[0]
+    }
+
+    #[test]
+    fn should_transfer_and_approve_icrc7_tokens() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+
+        let vault = Account {
+            owner: test_env.canister_ids.account,
+            subaccount: None,
+        };
+        let other = Account {
+            owner: generate_principal(),
+            subaccount: None,
+        };
+        let collection = install_mock_icrc7(
+            &test_env.env,
+            vec![
+                (candid::Nat::from(1u64), vault),
+                (candid::Nat::from(2u64), vault),
+                (candid::Nat::from(3u64), other),
+            ],
+        );
+
+        let (result,): (Result<(), keygate_core::error::Error>,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "register_nft_collection",
+            (collection,),
+        )
+        .unwrap();
+        assert!(result.is_ok());
+
+        let nfts = |test_env: &TestEnv| {
+            let (holdings,): (Vec<NftHoldings>,) = query_candid_as(
+                &test_env.env,
+                test_env.canister_ids.account,
+                caller,
+                "get_nfts",
+                (),
+            )
+            .unwrap();
+            holdings
+        };
+        assert_eq!(
+            nfts(&test_env),
+            vec![NftHoldings {
+                collection,
+                token_ids: vec![candid::Nat::from(1u64), candid::Nat::from(2u64)],
+            }]
+        );
+
+        let receiver = generate_principal();
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            nft_args(
+                TransactionType::Transfer,
+                receiver.to_text(),
+                format!("icp:icrc7:{}:1", collection),
+            ),
+        );
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Successfully transferred an ICRC-7 token.".to_string())
+        );
+        assert_eq!(
+            nft_owner(&test_env, collection, 1),
+            Some(Account {
+                owner: receiver,
+                subaccount: None
+            })
+        );
+        assert_eq!(nfts(&test_env)[0].token_ids, vec![candid::Nat::from(2u64)]);
+
+        // The vault can't move a token it doesn't own
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            nft_args(
+                TransactionType::Transfer,
+                receiver.to_text(),
+                format!("icp:icrc7:{}:3", collection),
+            ),
+        );
+        assert!(
+            matches!(status, IntentStatus::Failed(ref e) if e.contains("Unauthorized")),
+            "{:?}",
+            status
+        );
+        assert_eq!(nft_owner(&test_env, collection, 3), Some(other));
+
+        // Approving and revoking a single token
+        let spender = generate_principal();
+        let token = format!("icp:icrc7:{}:2", collection);
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            nft_args(TransactionType::Approve, spender.to_text(), token.clone()),
+        );
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Successfully approved an ICRC-37 spender.".to_string())
+        );
+        assert!(nft_approved(&test_env, collection, spender, 2));
+
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            nft_args(TransactionType::RevokeApproval, spender.to_text(), token),
+        );
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Successfully revoked an ICRC-37 approval.".to_string())
+        );
+        assert!(!nft_approved(&test_env, collection, spender, 2));
+
+        // Approving the whole collection, then revoking every spender with an empty recipient
+        let token = format!("icp:icrc7:{}", collection);
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            nft_args(TransactionType::Approve, spender.to_text(), token.clone()),
+        );
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Successfully approved an ICRC-37 spender.".to_string())
+        );
+        assert!(nft_approved(&test_env, collection, spender, 2));
+
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            nft_args(
+                TransactionType::RevokeApproval,
+                String::new(),
+                token.clone(),
+            ),
+        );
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Successfully revoked an ICRC-37 approval.".to_string())
+        );
+        assert!(!nft_approved(&test_env, collection, spender, 2));
+
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            nft_args(TransactionType::RevokeApproval, String::new(), token),
+        );
+        assert!(
+            matches!(status, IntentStatus::Failed(ref e) if e.contains("ApprovalDoesNotExist")),
+            "{:?}",
+            status
+        );
+    }
+    /// Deploys a second mock ledger and a pool trading the environment's mock token
+    /// for it at twice its amount. Returns the pool and the second ledger.
+    fn setup_swap_pool(test_env: &TestEnv) -> (Principal, Principal) {
+        let minter = generate_principal();
+        let token1 = install_icrc1_ledger(&test_env.env, minter, vec![], minter);
+        let pool = install_mock_swap_pool(
+            &test_env.env,
+            MockSwapPoolArgs {
+                token0: test_env.canister_ids.icrc1_ledger,
+                token1,
+                price_bps: 20_000,
+            },
+        );
+
+        // Transfers from the minting account mint the pool's liquidity
+        let (minted,): (Result<candid::Nat, icrc_ledger_types::icrc1::transfer::TransferError>,) =
+            update_candid_as(
+                &test_env.env,
+                token1,
+                minter,
+                "icrc1_transfer",
+                (icrc_ledger_types::icrc1::transfer::TransferArg {
+                    from_subaccount: None,
+                    to: Account {
+                        owner: pool,
+                        subaccount: None,
+                    },
+                    fee: None,
+                    created_at_time: None,
+                    memo: None,
+                    amount: candid::Nat::from(1_000_000_000_000u128),
+                },),
+            )
+            .unwrap();
+        minted.unwrap();
+
+        (pool, token1)
+    }
+
+    fn swap_args(test_env: &TestEnv, pool: Principal, token1: Principal) -> ProposeTransactionArgs {
+        ProposeTransactionArgs {
+            transaction_type: TransactionType::Swap,
+            amount: 100_000_000.0,
+            network: SupportedNetwork::ICP,
+            to: pool.to_text(),
+            token: format!("icp:icrc1:{}", test_env.canister_ids.icrc1_ledger.to_text()),
+            payload: Some(TransactionPayload::Swap(SwapArgs {
+                token_out: format!("icp:icrc1:{}", token1.to_text()),
+                min_amount_out: 0,
+                max_slippage_bps: 100,
+            })),
+            wallet: None,
+        }
+    }
+
+    #[test]
+    fn should_swap_tokens_through_pool() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+
+        let (pool, token1) = setup_swap_pool(&test_env);
+
+        let (quote,): (Result<u128, keygate_core::error::Error>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "quote_swap",
+            (
+                pool,
+                format!("icp:icrc1:{}", test_env.canister_ids.icrc1_ledger.to_text()),
+                format!("icp:icrc1:{}", token1.to_text()),
+                100_000_000u128,
+            ),
+        )
+        .unwrap();
+        assert_eq!(quote.unwrap(), 200_000_000);
+
+        let status = propose_and_execute(&test_env, caller, swap_args(&test_env, pool, token1));
+
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Successfully swapped tokens, received 200_000_000.".to_string())
+        );
+
+        let (received,): (u128,) = query_candid_as(
+            &test_env.env,
+            token1,
+            caller,
+            "icrc1_balance_of",
+            (ICRCAccount::new(test_env.canister_ids.account, None),),
+        )
+        .unwrap();
+
+        // The pool pays the withdrawal fee out of the swapped amount
+        assert_eq!(received, 200_000_000 - 1_000_000);
+    }
+
+    #[test]
+    fn should_return_deposit_when_swap_fails() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+
+        let (pool, token1) = setup_swap_pool(&test_env);
+
+        let _: () = update_candid_as(&test_env.env, pool, caller, "set_fail_swaps", (true,)).unwrap();
+
+        let status = propose_and_execute(&test_env, caller, swap_args(&test_env, pool, token1));
+
+        match status {
+            IntentStatus::Failed(message) => {
+                assert!(message.contains("The deposit was returned to the vault."))
+            }
+            other => panic!("Expected the swap to fail, got {:?}", other),
+        }
+
+        let (balance,): (u128,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.icrc1_ledger,
+            caller,
+            "icrc1_balance_of",
+            (ICRCAccount::new(test_env.canister_ids.account, None),),
+        )
+        .unwrap();
+
+        // Only the approve, deposit and withdrawal fees are lost
+        assert_eq!(balance, 1000_000_000_000 - 3 * 1_000_000);
+    }
+
+    #[test]
+    fn should_transfer_btc_on_regtest() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let bitcoin = install_mock_bitcoin(&test_env.env);
+        let account = test_env.canister_ids.account;
+
+        let (address,): (Result<String, String>,) =
+            update_candid_as(&test_env.env, account, caller, "get_btc_address", (None::<BitcoinAddressType>,)).unwrap();
+        let address = address.unwrap();
+        assert!(address.starts_with("bcrt1q"), "Expected a regtest P2WPKH address, got {}", address);
+
+        // Deriving addresses and reading balances cost the vault cycles, so only signers can ask
+        let (result,): (Result<String, String>,) = update_candid_as(
+            &test_env.env,
+            account,
+            generate_principal(),
+            "get_btc_address",
+            (None::<BitcoinAddressType>,),
+        )
+        .unwrap();
+        assert_eq!(result, Err("Caller is not a signer".to_string()));
+        let (result,): (Result<u64, String>,) = update_candid_as(
+            &test_env.env,
+            account,
+            generate_principal(),
+            "get_btc_balance",
+            (None::<BitcoinAddressType>,),
+        )
+        .unwrap();
+        assert_eq!(result, Err("Caller is not a signer".to_string()));
+
+        let _: () = update_candid_as(&test_env.env, bitcoin, caller, "mock_add_utxo", (address.clone(), 30_000u64)).unwrap();
+        let _: () = update_candid_as(&test_env.env, bitcoin, caller, "mock_add_utxo", (address.clone(), 50_000u64)).unwrap();
+        // 2 sat/vbyte at the median
+        let _: () = update_candid_as(&test_env.env, bitcoin, caller, "mock_set_fee_percentiles", (vec![2_000u64; 101],)).unwrap();
+
+        let recipient = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080".to_string();
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            ProposeTransactionArgs {
+                transaction_type: TransactionType::Transfer,
+                amount: 60_000.0,
+                network: SupportedNetwork::BTC,
+                to: recipient.clone(),
+                token: "btc:native".to_string(),
+                payload: None,
+                wallet: None,
+            },
+        );
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+
+        let (sent,): (Vec<Vec<u8>>,) =
+            query_candid_as(&test_env.env, bitcoin, caller, "mock_get_sent_transactions", ()).unwrap();
+        assert_eq!(sent.len(), 1);
+
+        let (received,): (u64,) = query_candid_as(
+            &test_env.env,
+            bitcoin,
+            caller,
+            "bitcoin_get_balance",
+            (GetBalanceRequest {
+                address: recipient,
+                network: BitcoinNetwork::Regtest,
+                min_confirmations: None,
+            },),
+        )
+        .unwrap();
+        assert_eq!(received, 60_000);
+
+        // Both UTXOs are spent, 2 inputs and 2 outputs at 2 sat/vbyte
+        let (balance,): (Result<u64, String>,) =
+            update_candid_as(&test_env.env, account, caller, "get_btc_balance", (None::<BitcoinAddressType>,)).unwrap();
+        assert_eq!(balance.unwrap(), 80_000 - 60_000 - 2 * (11 + 2 * 68 + 2 * 31));
+    }
+
+    #[test]
+    fn should_transfer_btc_from_taproot_address() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let bitcoin = install_mock_bitcoin(&test_env.env);
+        let account = test_env.canister_ids.account;
+        let taproot = Some(BitcoinAddressType::P2tr);
+
+        let (address,): (Result<String, String>,) =
+            update_candid_as(&test_env.env, account, caller, "get_btc_address", (taproot,)).unwrap();
+        let address = address.unwrap();
+        assert!(address.starts_with("bcrt1p"), "Expected a regtest Taproot address, got {}", address);
+
+        let _: () = update_candid_as(&test_env.env, bitcoin, caller, "mock_add_utxo", (address, 100_000u64)).unwrap();
+        let _: () = update_candid_as(&test_env.env, bitcoin, caller, "mock_set_fee_percentiles", (vec![2_000u64; 101],)).unwrap();
+
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            ProposeTransactionArgs {
+                transaction_type: TransactionType::Transfer,
+                amount: 40_000.0,
+                network: SupportedNetwork::BTC,
+                to: "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080".to_string(),
+                token: "btc:taproot".to_string(),
+                payload: None,
+                wallet: None,
+            },
+        );
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+
+        // A key path input, a P2WPKH output and the Taproot change at 2 sat/vbyte
+        let (balance,): (Result<u64, String>,) =
+            update_candid_as(&test_env.env, account, caller, "get_btc_balance", (taproot,)).unwrap();
+        assert_eq!(balance.unwrap(), 100_000 - 40_000 - 2 * (11 + 58 + 31 + 43));
+    }
+
+    #[test]
+    fn should_derive_solana_address() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let account = test_env.canister_ids.account;
+
+        let (address,): (Result<String, String>,) =
+            update_candid_as(&test_env.env, account, caller, "get_solana_address", ()).unwrap();
+        let address = address.unwrap();
+
+        // Base58 of a 32 bytes Ed25519 key
+        assert!((32..=44).contains(&address.len()), "Unexpected address {}", address);
+        assert!(address.chars().all(|c| c.is_ascii_alphanumeric() && !"0OIl".contains(c)));
+
+        let (again,): (Result<String, String>,) =
+            update_candid_as(&test_env.env, account, caller, "get_solana_address", ()).unwrap();
+        assert_eq!(again.unwrap(), address);
+    }
+
+    /// Deploys a minter mock whose ledger holds 1_000_000_000 tokens of the vault.
+    /// Returns the minter and the ck token of its ledger.
+    fn setup_minter(test_env: &TestEnv) -> (Principal, String) {
+        let vault = Account {
+            owner: test_env.canister_ids.account,
+            subaccount: None,
+        };
+        let (minter, ledger) = install_mock_minter(&test_env.env, vec![(vault, 1_000_000_000)]);
+
+        (minter, format!("icp:icrc1:{}", ledger.to_text()))
+    }
+
+    fn withdraw_args(token: String, minter: ChainKeyMinter, to: &str) -> ProposeTransactionArgs {
+        ProposeTransactionArgs {
+            transaction_type: TransactionType::Withdraw,
+            amount: 100_000_000.0,
+            network: SupportedNetwork::ICP,
+            to: to.to_string(),
+            token,
+            payload: Some(TransactionPayload::Minter(minter)),
+            wallet: None,
+        }
+    }
+
+    /// Lets the withdrawal poll timer run once.
+    fn advance_to_next_poll(test_env: &TestEnv) {
+        test_env.env.advance_time(std::time::Duration::from_secs(61));
+        for _ in 0..5 {
+            test_env.env.tick();
+        }
+    }
+
+    fn last_transaction(test_env: &TestEnv, caller: Principal) -> ExecutedTransaction {
+        let (transactions,): (Vec<ExecutedTransaction>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_transactions",
+            (),
+        )
+        .unwrap();
+
+        transactions.last().cloned().unwrap()
+    }
+
+    fn pending_withdrawals(test_env: &TestEnv, caller: Principal) -> Vec<(u64, PendingWithdrawal)> {
+        let (pending,): (Vec<(u64, PendingWithdrawal)>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_pending_withdrawals",
+            (),
+        )
+        .unwrap();
+
+        pending
+    }
+
+    #[test]
+    fn should_withdraw_ckbtc_and_track_its_status() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let (minter, token) = setup_minter(&test_env);
+
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            withdraw_args(
+                token.clone(),
+                ChainKeyMinter::CkBtc(minter),
+                "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+            ),
+        );
+        assert!(matches!(status, IntentStatus::InProgress(_)), "Unexpected status {:?}", status);
+
+        let pending = pending_withdrawals(&test_env, caller);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].1.minter, ChainKeyMinter::CkBtc(minter));
+
+        // Still queued at the minter
+        advance_to_next_poll(&test_env);
+        assert!(matches!(last_transaction(&test_env, caller).status, IntentStatus::InProgress(_)));
+
+        let _: () = update_candid_as(&test_env.env, minter, caller, "mock_confirm_withdrawals", ()).unwrap();
+        advance_to_next_poll(&test_env);
+
+        let transaction = last_transaction(&test_env, caller);
+        assert_eq!(transaction.transaction_type, TransactionType::Withdraw);
+        assert!(
+            matches!(transaction.status, IntentStatus::Completed(_)),
+            "Unexpected status {:?}",
+            transaction.status
+        );
+        assert!(pending_withdrawals(&test_env, caller).is_empty());
+
+        let ledger = Principal::from_text(token.trim_start_matches("icp:icrc1:")).unwrap();
+        let (balance,): (u128,) = query_candid_as(
+            &test_env.env,
+            ledger,
+            caller,
+            "icrc1_balance_of",
+            (ICRCAccount::new(test_env.canister_ids.account, None),),
+        )
+        .unwrap();
+
+        // The withdrawn amount is burned, and the approval costs a fee
+        assert_eq!(balance, 1_000_000_000 - 100_000_000 - 1_000_000);
+    }
+
+    #[test]
+    fn should_withdraw_cketh_and_track_its_status() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let (minter, token) = setup_minter(&test_env);
+
+        let status = propose_and_execute(
+            &test_env,
+            caller,
+            withdraw_args(
+                token,
+                ChainKeyMinter::CkEth(minter),
+                "0x000000000000000000000000000000000000dEaD",
+            ),
+        );
+        assert!(matches!(status, IntentStatus::InProgress(_)), "Unexpected status {:?}", status);
+
+        let _: () = update_candid_as(&test_env.env, minter, caller, "mock_confirm_withdrawals", ()).unwrap();
+        advance_to_next_poll(&test_env);
+
+        match last_transaction(&test_env, caller).status {
+            IntentStatus::Completed(message) => {
+                assert!(message.contains("finalized in Ethereum transaction 0x"))
+            }
+            status => panic!("Unexpected status {:?}", status),
+        }
+    }
+
+    #[test]
+    fn should_mint_ckbtc_for_deposits() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let (minter, token) = setup_minter(&test_env);
+        let account = test_env.canister_ids.account;
+
+        let (address,): (Result<String, String>,) =
+            update_candid_as(&test_env.env, account, caller, "get_ckbtc_deposit_address", (minter,)).unwrap();
+        assert!(address.unwrap().starts_with("bcrt1q"));
+
+        let deposit = ProposeTransactionArgs {
+            transaction_type: TransactionType::Deposit,
+            amount: 0.0,
+            network: SupportedNetwork::ICP,
+            to: account.to_text(),
+            token: token.clone(),
+            payload: Some(TransactionPayload::Minter(ChainKeyMinter::CkBtc(minter))),
+            wallet: None,
+        };
+
+        // Nothing to mint before a deposit
+        let status = propose_and_execute(&test_env, caller, deposit.clone());
+        assert!(matches!(status, IntentStatus::Failed(_)), "Unexpected status {:?}", status);
+
+        let _: () = update_candid_as(
+            &test_env.env,
+            minter,
+            caller,
+            "mock_deposit",
+            (account, None::<Vec<u8>>, 50_000_000u64),
+        )
+        .unwrap();
+
+        let status = propose_and_execute(&test_env, caller, deposit);
+        assert_eq!(
+            status,
+            IntentStatus::Completed("Minted 50000000 ckBTC satoshis from 1 UTXOs".to_string())
+        );
+
+        let ledger = Principal::from_text(token.trim_start_matches("icp:icrc1:")).unwrap();
+        let (balance,): (u128,) = query_candid_as(
+            &test_env.env,
+            ledger,
+            caller,
+            "icrc1_balance_of",
+            (ICRCAccount::new(account, None),),
+        )
+        .unwrap();
+        assert_eq!(balance, 1_000_000_000 + 50_000_000);
+    }
+
+    fn manage_neuron_args(command: NeuronCommand) -> ProposeTransactionArgs {
+        ProposeTransactionArgs {
+            transaction_type: TransactionType::ManageNeuron,
+            amount: 0.0,
+            network: SupportedNetwork::ICP,
+            to: NNS_GOVERNANCE_CANISTER_ID.to_text(),
+            token: "icp:native".to_string(),
+            payload: Some(TransactionPayload::Neuron(command)),
+            wallet: None,
+        }
+    }
+
+    fn nns_neurons(test_env: &TestEnv, caller: Principal) -> Vec<NnsNeuron> {
+        let (neurons,): (Result<Vec<NnsNeuron>, String>,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_nns_neurons",
+            (),
+        )
+        .unwrap();
+
+        neurons.unwrap()
+    }
+
+    #[test]
+    fn should_manage_nns_neuron() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            initial_icp_balance: Some(100_000_000_000),
+            ..Default::default()
+        });
+        install_mock_nns_governance(&test_env.env, test_env.canister_ids.icp_ledger);
+
+        let stake = ProposeTransactionArgs {
+            transaction_type: TransactionType::Stake,
+            amount: 1_000_000_000.0,
+            network: SupportedNetwork::ICP,
+            to: NNS_GOVERNANCE_CANISTER_ID.to_text(),
+            token: "icp:native".to_string(),
+            payload: None,
+            wallet: None,
+        };
+        let status = propose_and_execute(&test_env, caller, stake);
+        assert_eq!(status, IntentStatus::Completed("Successfully staked neuron 1.".to_string()));
+
+        let commands = vec![
+            NeuronCommand::IncreaseDissolveDelay {
+                neuron_id: 1,
+                additional_dissolve_delay_seconds: 86_400,
+            },
+            NeuronCommand::SetFollowing {
+                neuron_id: 1,
+                topic: 4,
+                followees: vec![27],
+            },
+            NeuronCommand::Vote {
+                neuron_id: 1,
+                proposal_id: 42,
+                approve: true,
+            },
+        ];
+        for command in commands {
+            let status = propose_and_execute(&test_env, caller, manage_neuron_args(command));
+            assert_eq!(status, IntentStatus::Completed("Successfully managed neuron 1.".to_string()));
+        }
+
+        let neurons = nns_neurons(&test_env, caller);
+        assert_eq!(
+            neurons,
+            vec![NnsNeuron {
+                id: 1,
+                stake_e8s: 1_000_000_000,
+                dissolve_delay_seconds: 86_400,
+                state: NeuronState::Locked,
+                followees: vec![(4, vec![27])],
+                recent_votes: vec![(42, true)],
+            }]
+        );
+
+        // A locked neuron cannot be disbursed
+        let disburse = manage_neuron_args(NeuronCommand::Disburse {
+            neuron_id: 1,
+            to_account: None,
+            amount_e8s: None,
+        });
+        let status = propose_and_execute(&test_env, caller, disburse.clone());
+        assert!(matches!(status, IntentStatus::Failed(_)), "Unexpected status {:?}", status);
+
+        let start_dissolving = manage_neuron_args(NeuronCommand::StartDissolving { neuron_id: 1 });
+        let status = propose_and_execute(&test_env, caller, start_dissolving);
+        assert_eq!(status, IntentStatus::Completed("Successfully managed neuron 1.".to_string()));
+        assert_eq!(nns_neurons(&test_env, caller)[0].state, NeuronState::Dissolving);
+
+        test_env.env.advance_time(std::time::Duration::from_secs(86_401));
+        test_env.env.tick();
+        assert_eq!(nns_neurons(&test_env, caller)[0].state, NeuronState::Dissolved);
+
+        let status = propose_and_execute(&test_env, caller, disburse);
+        assert!(
+            matches!(&status, IntentStatus::Completed(message) if message.starts_with("Successfully disbursed neuron 1")),
+            "Unexpected status {:?}",
+            status
+        );
+
+        let (balance,): (Tokens,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.icp_ledger,
+            caller,
+            "account_balance",
+            (AccountBalanceArgs {
+                account: AccountIdentifier::new(&test_env.canister_ids.account, &DEFAULT_SUBACCOUNT),
+            },),
+        )
+        .unwrap();
+
+        // Staking and disbursing each cost a transfer fee
+        assert_eq!(balance.e8s(), 100_000_000_000 - 2 * RECOMMENDED_ICP_TRANSACTION_FEE);
+    }
+
+    fn manage_sns_neuron_args(
+        governance: Principal,
+        token: &str,
+        command: SnsNeuronCommand,
+    ) -> ProposeTransactionArgs {
+        ProposeTransactionArgs {
+            transaction_type: TransactionType::ManageNeuron,
+            amount: 0.0,
+            network: SupportedNetwork::ICP,
+            to: governance.to_text(),
+            token: token.to_string(),
+            payload: Some(TransactionPayload::SnsNeuron(command)),
+            wallet: None,
+        }
+    }
+
+    fn sns_neurons(test_env: &TestEnv, caller: Principal, governance: Principal) -> Vec<SnsNeuron> {
+        let (neurons,): (Result<Vec<SnsNeuron>, String>,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_sns_neurons",
+            (governance,),
+        )
+        .unwrap();
+
+        neurons.unwrap()
+    }
+
+    #[test]
+    fn should_manage_sns_neuron() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let vault = test_env.canister_ids.account;
+        let (governance, ledger) = install_mock_sns_governance(
+            &test_env.env,
+            vec![(Account { owner: vault, subaccount: None }, 1_000_000_000)],
+        );
+        let token = format!("icp:icrc1:{}", ledger.to_text());
+
+        let stake = ProposeTransactionArgs {
+            transaction_type: TransactionType::Stake,
+            amount: 100_000_000.0,
+            network: SupportedNetwork::ICP,
+            to: governance.to_text(),
+            token: token.clone(),
+            payload: None,
+            wallet: None,
+        };
+        let status = propose_and_execute(&test_env, caller, stake);
+        assert!(
+            matches!(&status, IntentStatus::Completed(message) if message.starts_with("Successfully staked SNS neuron")),
+            "Unexpected status {:?}",
+            status
+        );
+
+        let neurons = sns_neurons(&test_env, caller, governance);
+        assert_eq!(neurons.len(), 1);
+        assert_eq!(neurons[0].stake_e8s, 100_000_000);
+        let neuron_id = neurons[0].id.clone();
+
+        let commands = vec![
+            SnsNeuronCommand::IncreaseDissolveDelay {
+                neuron_id: neuron_id.clone(),
+                additional_dissolve_delay_seconds: 86_400,
+            },
+            SnsNeuronCommand::SetFollowing {
+                neuron_id: neuron_id.clone(),
+                function_id: 0,
+                followees: vec![vec![7; 32]],
+            },
+            SnsNeuronCommand::Vote {
+                neuron_id: neuron_id.clone(),
+                proposal_id: 3,
+                approve: true,
+            },
+        ];
+        for command in commands {
+            let status = propose_and_execute(&test_env, caller, manage_sns_neuron_args(governance, &token, command));
+            assert!(
+                matches!(&status, IntentStatus::Completed(message) if message.starts_with("Successfully managed SNS neuron")),
+                "Unexpected status {:?}",
+                status
+            );
+        }
+
+        let make_proposal = SnsNeuronCommand::MakeProposal {
+            neuron_id: neuron_id.clone(),
+            title: "Fund the treasury".to_string(),
+            url: "https://example.com".to_string(),
+            summary: "Motion proposed by the vault".to_string(),
+            action: SnsProposalAction::Motion {
+                motion_text: "Fund the treasury".to_string(),
+            },
+        };
+        let status = propose_and_execute(&test_env, caller, manage_sns_neuron_args(governance, &token, make_proposal));
+        assert!(
+            matches!(&status, IntentStatus::Completed(message) if message.starts_with("Successfully submitted SNS proposal 1")),
+            "Unexpected status {:?}",
+            status
+        );
+
+        assert_eq!(
+            sns_neurons(&test_env, caller, governance),
+            vec![SnsNeuron {
+                id: neuron_id.clone(),
+                stake_e8s: 100_000_000,
+                dissolve_delay_seconds: 86_400,
+                state: NeuronState::Locked,
+                followees: vec![(0, vec![vec![7; 32]])],
+            }]
+        );
+        let (ballots,): (Vec<(u64, i32)>,) =
+            query_candid_as(&test_env.env, governance, caller, "mock_ballots", (neuron_id.clone(),)).unwrap();
+        assert_eq!(ballots, vec![(3, 1)]);
+        let (proposals,): (Vec<(Vec<u8>, String)>,) =
+            query_candid_as(&test_env.env, governance, caller, "mock_proposals", ()).unwrap();
+        assert_eq!(proposals, vec![(neuron_id.clone(), "Fund the treasury".to_string())]);
+
+        let start_dissolving = SnsNeuronCommand::StartDissolving {
+            neuron_id: neuron_id.clone(),
+        };
+        propose_and_execute(&test_env, caller, manage_sns_neuron_args(governance, &token, start_dissolving));
+        test_env.env.advance_time(std::time::Duration::from_secs(86_401));
+        test_env.env.tick();
+        assert_eq!(sns_neurons(&test_env, caller, governance)[0].state, NeuronState::Dissolved);
+
+        let disburse = SnsNeuronCommand::Disburse {
+            neuron_id,
+            to_account: None,
+            amount_e8s: None,
+        };
+        let status = propose_and_execute(&test_env, caller, manage_sns_neuron_args(governance, &token, disburse));
+        assert!(
+            matches!(&status, IntentStatus::Completed(message) if message.starts_with("Successfully disbursed SNS neuron")),
+            "Unexpected status {:?}",
+            status
+        );
+
+        let (balance,): (u128,) = query_candid_as(
+            &test_env.env,
+            ledger,
+            caller,
+            "icrc1_balance_of",
+            (ICRCAccount::new(vault, None),),
+        )
+        .unwrap();
+
+        // Staking and disbursing each cost a transfer fee
+        assert_eq!(balance, 1_000_000_000 - 2 * 1_000_000);
+    }
+
+    fn propose_canister_call(
+        test_env: &TestEnv,
+        caller: Principal,
+        canister_id: Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> u64 {
+        let proposal = ProposeTransactionArgs {
+            transaction_type: TransactionType::CanisterCall,
+            amount: 0.0,
+            network: SupportedNetwork::ICP,
+            to: canister_id.to_text(),
+            token: "icp:native".to_string(),
+            payload: Some(TransactionPayload::CanisterCall(CanisterCallArgs {
+                method: method.to_string(),
+                args,
+                cycles: None,
+            })),
+            wallet: None,
+        };
+        let (proposed_transaction,): (ProposedTransaction,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "propose_transaction",
+            (proposal,),
+        )
+        .unwrap();
+
+        proposed_transaction.id
+    }
+
+    #[test]
+    fn should_call_canister_and_store_the_reply() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let ledger = test_env.canister_ids.icrc1_ledger;
+        let vault = ICRCAccount::new(test_env.canister_ids.account, None);
+
+        let proposal_id = propose_canister_call(
+            &test_env,
+            caller,
+            ledger,
+            "icrc1_balance_of",
+            encode_one(vault.clone()).unwrap(),
+        );
+
+        let did = "type Account = record { owner : principal; subaccount : opt blob };
+            service : { icrc1_balance_of : (Account) -> (nat) query }";
+        let (details,): (Result<CanisterCallDetails, String>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_canister_call_details",
+            (proposal_id, Some(did.to_string())),
+        )
+        .unwrap();
+        let details = details.unwrap();
+        assert_eq!(details.canister_id, ledger);
+        assert_eq!(details.method, "icrc1_balance_of");
+        assert!(
+            details.args.contains(&format!("owner = principal \"{}\"", test_env.canister_ids.account)),
+            "Unexpected args {}",
+            details.args
+        );
+
+        let (status,): (IntentStatus,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "execute_transaction",
+            (proposal_id,),
+        )
+        .unwrap();
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+
+        let (result,): (Option<CanisterCallResult>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_canister_call_result",
+            (proposal_id,),
+        )
+        .unwrap();
+        let reply = match result {
+            Some(CanisterCallResult::Reply(reply)) => reply,
+            result => panic!("Unexpected result {:?}", result),
+        };
+
+        let (balance,): (candid::Nat,) =
+            query_candid_as(&test_env.env, ledger, caller, "icrc1_balance_of", (vault,)).unwrap();
+        assert_eq!(Decode!(&reply, candid::Nat).unwrap(), balance);
+    }
+
+    #[test]
+    fn should_store_the_reject_of_a_canister_call() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let ledger = test_env.canister_ids.icrc1_ledger;
+
+        let proposal_id = propose_canister_call(
+            &test_env,
+            caller,
+            ledger,
+            "missing_method",
+            encode_one(()).unwrap(),
+        );
+        let (status,): (IntentStatus,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "execute_transaction",
+            (proposal_id,),
+        )
+        .unwrap();
+        assert!(matches!(status, IntentStatus::Failed(_)), "Unexpected status {:?}", status);
+
+        let (result,): (Option<CanisterCallResult>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_canister_call_result",
+            (proposal_id,),
+        )
+        .unwrap();
+        assert!(
+            matches!(&result, Some(CanisterCallResult::Reject { message, .. }) if message.contains("missing_method")),
+            "Unexpected result {:?}",
+            result
+        );
+    }
+
+    fn manage_canister(
+        test_env: &TestEnv,
+        caller: Principal,
+        canister_id: Principal,
+        command: CanisterCommand,
+    ) -> IntentStatus {
+        let proposal = ProposeTransactionArgs {
+            transaction_type: TransactionType::ManageCanister,
+            amount: 0.0,
+            network: SupportedNetwork::ICP,
+            to: canister_id.to_text(),
+            token: "icp:native".to_string(),
+            payload: Some(TransactionPayload::Canister(command)),
+            wallet: None,
+        };
+        let (proposed_transaction,): (ProposedTransaction,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "propose_transaction",
+            (proposal,),
+        )
+        .unwrap();
+
+        let (status,): (IntentStatus,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "execute_transaction",
+            (proposed_transaction.id,),
+        )
+        .unwrap();
+        status
+    }
+
+    fn controlled_canisters(test_env: &TestEnv, caller: Principal) -> Vec<ControlledCanister> {
+        let (canisters,): (Vec<ControlledCanister>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_controlled_canisters",
+            (),
+        )
+        .unwrap();
+        canisters
+    }
+
+    #[test]
+    fn should_manage_controlled_canister() {
+        use sha2::{Digest, Sha256};
+
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let vault = test_env.canister_ids.account;
+        let target = test_env.env.create_canister_with_settings(Some(vault), None);
+        test_env.env.add_cycles(target, 2_000_000_000_000);
+        test_env.env.add_cycles(vault, 2_000_000_000_000);
+
+        let wasm_module =
+            include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_bitcoin.wasm").to_vec();
+        let mut chunk_hashes = vec![];
+        for chunk in wasm_module.chunks(wasm_module.len() / 2 + 1) {
+            let (chunk_hash,): (Result<Vec<u8>, String>,) = update_candid_as(
+                &test_env.env,
+                vault,
+                caller,
+                "upload_wasm_chunk",
+                (chunk.to_vec(),),
+            )
+            .unwrap();
+            chunk_hashes.push(chunk_hash.unwrap());
+        }
+        let (module,): (Result<WasmModule, String>,) = update_candid_as(
+            &test_env.env,
+            vault,
+            caller,
+            "register_wasm_module",
+            (chunk_hashes,),
+        )
+        .unwrap();
+        let module = module.unwrap();
+        assert_eq!(module.hash, Sha256::digest(&wasm_module).to_vec());
+        assert_eq!(module.size, wasm_module.len() as u64);
+
+        let install = |mode| CanisterCommand::InstallCode {
+            mode,
+            wasm_module_hash: module.hash.clone(),
+            arg: candid::encode_args(()).unwrap(),
+        };
+
+        let status = manage_canister(&test_env, caller, target, install(CanisterInstallMode::Install));
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        let canisters = controlled_canisters(&test_env, caller);
+        assert_eq!(canisters.len(), 1);
+        assert_eq!(canisters[0].canister_id, target);
+        assert_eq!(canisters[0].status, CanisterRunStatus::Running);
+        assert_eq!(canisters[0].module_hash, Some(module.hash.clone()));
+        assert!(canisters[0].controllers.contains(&vault));
+
+        let status = manage_canister(&test_env, caller, target, CanisterCommand::Stop);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert_eq!(controlled_canisters(&test_env, caller)[0].status, CanisterRunStatus::Stopped);
+
+        let status = manage_canister(
+            &test_env,
+            caller,
+            target,
+            CanisterCommand::TakeSnapshot { replace_snapshot: None },
+        );
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert_eq!(controlled_canisters(&test_env, caller)[0].snapshots.len(), 1);
+
+        let status = manage_canister(&test_env, caller, target, CanisterCommand::Start);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+
+        let cycles = controlled_canisters(&test_env, caller)[0].cycles;
+        let status = manage_canister(
+            &test_env,
+            caller,
+            target,
+            CanisterCommand::TopUp { cycles: 100_000_000_000 },
+        );
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert!(controlled_canisters(&test_env, caller)[0].cycles > cycles);
+
+        let status = manage_canister(&test_env, caller, target, install(CanisterInstallMode::Upgrade));
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        let status = test_env.env.canister_status(target, Some(vault)).unwrap();
+        assert_eq!(status.module_hash, Some(module.hash.clone()));
+
+        let status = manage_canister(&test_env, caller, target, CanisterCommand::Stop);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        let status = manage_canister(&test_env, caller, target, CanisterCommand::Delete);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert!(controlled_canisters(&test_env, caller).is_empty());
+        assert!(!test_env.env.canister_exists(target));
+
+        // Deleting the module frees its chunks
+        let (result,): (Result<(), String>,) =
+            update_candid_as(&test_env.env, vault, caller, "delete_wasm_module", (module.hash.clone(),))
+                .unwrap();
+        result.unwrap();
+        let (modules,): (Vec<WasmModule>,) =
+            query_candid_as(&test_env.env, vault, caller, "get_wasm_modules", ()).unwrap();
+        assert!(modules.is_empty());
+        let (result,): (Result<(), String>,) = update_candid_as(
+            &test_env.env,
+            vault,
+            caller,
+            "delete_wasm_chunk",
+            (module.chunk_hashes[0].clone(),),
+        )
+        .unwrap();
+        assert!(result.is_err(), "The chunk should be gone with its module");
+    }
+
+    #[test]
+    fn should_forget_canisters_the_vault_no_longer_controls() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let vault = test_env.canister_ids.account;
+        let target = test_env.env.create_canister_with_settings(Some(vault), None);
+        test_env.env.add_cycles(target, 2_000_000_000_000);
+
+        let status = manage_canister(&test_env, caller, target, CanisterCommand::Start);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert_eq!(controlled_canisters(&test_env, caller).len(), 1);
+
+        // Handing the canister over to the caller leaves the vault without control of it
+        let hand_over = CanisterCommand::UpdateSettings(CanisterSettingsArgs {
+            controllers: Some(vec![caller]),
+            ..Default::default()
+        });
+        let status = manage_canister(&test_env, caller, target, hand_over);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert!(controlled_canisters(&test_env, caller).is_empty());
+        assert!(test_env.env.canister_exists(target));
+    }
+
+    #[test]
+    fn should_not_propose_installing_unknown_modules() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let target = test_env.env.create_canister_with_settings(Some(test_env.canister_ids.account), None);
+
+        let proposal = ProposeTransactionArgs {
+            transaction_type: TransactionType::ManageCanister,
+            amount: 0.0,
+            network: SupportedNetwork::ICP,
+            to: target.to_text(),
+            token: "icp:native".to_string(),
+            payload: Some(TransactionPayload::Canister(CanisterCommand::InstallCode {
+                mode: CanisterInstallMode::Install,
+                wasm_module_hash: vec![0; 32],
+                arg: candid::encode_args(()).unwrap(),
+            })),
+            wallet: None,
+        };
+        let result: Result<(ProposedTransaction,), _> = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "propose_transaction",
+            (proposal,),
+        );
+        assert!(result.is_err());
+    }
+
+    fn cycles_ledger_balance(test_env: &TestEnv, owner: Principal) -> candid::Nat {
+        let (balance,): (candid::Nat,) = query_candid_as(
+            &test_env.env,
+            CYCLES_LEDGER_CANISTER_ID,
+            owner,
+            "icrc1_balance_of",
+            (Account { owner, subaccount: None },),
+        )
+        .unwrap();
+        balance
+    }
+
+    #[test]
+    fn should_transfer_and_withdraw_cycles() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            ..Default::default()
+        });
+        let vault = test_env.canister_ids.account;
+        install_mock_cycles_ledger(
+            &test_env.env,
+            vec![(Account { owner: vault, subaccount: None }, 10_000_000_000_000)],
+        );
+
+        let receiver = generate_principal();
+        let transfer = ProposeTransactionArgs {
+            transaction_type: TransactionType::Transfer,
+            amount: 1_000_000_000_000.0,
+            network: SupportedNetwork::ICP,
+            to: receiver.to_text(),
+            token: "icp:cycles".to_string(),
+            payload: None,
+            wallet: None,
+        };
+        let status = propose_and_execute(&test_env, caller, transfer);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert_eq!(cycles_ledger_balance(&test_env, receiver), candid::Nat::from(1_000_000_000_000u128));
+
+        let target = test_env.env.create_canister();
+        let cycles = test_env.env.cycle_balance(target);
+        let withdraw = ProposeTransactionArgs {
+            transaction_type: TransactionType::Withdraw,
+            amount: 2_000_000_000_000.0,
+            network: SupportedNetwork::ICP,
+            to: target.to_text(),
+            token: "icp:cycles".to_string(),
+            payload: None,
+            wallet: None,
+        };
+        let status = propose_and_execute(&test_env, caller, withdraw);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert_eq!(test_env.env.cycle_balance(target), cycles + 2_000_000_000_000);
+
+        // Each operation costs the 100M cycles fee of the cycles ledger
+        assert_eq!(
+            cycles_ledger_balance(&test_env, vault),
+            candid::Nat::from(10_000_000_000_000u128 - 3_000_000_000_000 - 2 * 100_000_000)
+        );
+    }
+
+    #[test]
+    fn should_top_up_and_create_canisters_with_icp() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            initial_icp_balance: Some(100_000_000_000),
+            ..Default::default()
+        });
+        let vault = test_env.canister_ids.account;
+        install_mock_cmc(&test_env.env, test_env.canister_ids.icp_ledger);
+
+        // The mock converts 1 ICP into 1T cycles
+        let target = test_env.env.create_canister();
+        let cycles = test_env.env.cycle_balance(target);
+        let top_up = ProposeTransactionArgs {
+            transaction_type: TransactionType::TopUp,
+            amount: 100_000_000.0,
+            network: SupportedNetwork::ICP,
+            to: target.to_text(),
+            token: "icp:native".to_string(),
+            payload: None,
+            wallet: None,
+        };
+        let status = propose_and_execute(&test_env, caller, top_up);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+        assert_eq!(test_env.env.cycle_balance(target), cycles + (100_000_000 - 10_000) * 10_000);
+
+        let create = ProposeTransactionArgs {
+            transaction_type: TransactionType::CreateCanister,
+            amount: 100_000_000.0,
+            network: SupportedNetwork::ICP,
+            to: String::new(),
+            token: "icp:native".to_string(),
+            payload: None,
+            wallet: None,
+        };
+        let status = propose_and_execute(&test_env, caller, create);
+        assert!(matches!(status, IntentStatus::Completed(_)), "Unexpected status {:?}", status);
+
+        let canisters = controlled_canisters(&test_env, caller);
+        assert_eq!(canisters.len(), 1);
+        assert_eq!(canisters[0].controllers, vec![vault]);
+        assert_eq!(canisters[0].module_hash, None);
+        assert!(test_env.env.canister_exists(canisters[0].canister_id));
+    }
+
+    #[test]
+    fn should_refuel_cycles_with_icp_when_low() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            initial_icp_balance: Some(100_000_000_000),
+            ..Default::default()
+        });
+        let vault = test_env.canister_ids.account;
+        install_mock_cmc(&test_env.env, test_env.canister_ids.icp_ledger);
+
+        let check = || {
+            let (status,): (Result<CyclesStatus, String>,) =
+                update_candid_as(&test_env.env, vault, caller, "check_cycles_now", ()).unwrap();
+            status.unwrap()
+        };
+        let set_config = |refuel| {
+            let config = CyclesMonitorConfig {
+                threshold: 10_000_000_000_000,
+                refuel,
+            };
+            let (result,): (Result<(), String>,) =
+                update_candid_as(&test_env.env, vault, caller, "set_cycles_monitor_config", (config,))
+                    .unwrap();
+            result.unwrap();
+        };
+
+        set_config(Some(CyclesRefuelSource::Icp { e8s: 100_000_000 }));
+        let balance = test_env.env.cycle_balance(vault);
+        let status = check();
+        assert!(status.balance > balance, "Unexpected status {:?}", status);
+        assert_eq!(status.burn_rate_per_day, None);
+
+        // Without a refuel source, a low balance only raises an alert
+        set_config(None);
+        test_env.env.advance_time(std::time::Duration::from_secs(60 * 60));
+        let status = check();
+        assert!(status.burn_rate_per_day.is_some());
+
+        let (trail,): (Vec<AuditEvent>,) =
+            query_candid_as(&test_env.env, vault, caller, "get_audit_trail", ()).unwrap();
+        let kinds: Vec<AuditEventKind> = trail.into_iter().map(|event| event.kind).collect();
+        assert_eq!(kinds.len(), 3, "Unexpected audit trail {:?}", kinds);
+        assert!(matches!(kinds[0], AuditEventKind::CyclesLow { .. }));
+        assert_eq!(
+            kinds[1],
+            AuditEventKind::CyclesRefueled {
+                source: CyclesRefuelSource::Icp { e8s: 100_000_000 },
+                cycles: (100_000_000 - 10_000) * 10_000,
+            }
+        );
+        assert!(matches!(kinds[2], AuditEventKind::CyclesLow { threshold: 10_000_000_000_000, .. }));
+
+        // Only signers can change the configuration
+        let config = CyclesMonitorConfig {
+            threshold: 0,
+            refuel: None,
+        };
+        let (result,): (Result<(), String>,) = update_candid_as(
+            &test_env.env,
+            vault,
+            generate_principal(),
+            "set_cycles_monitor_config",
+            (config,),
+        )
+        .unwrap();
+        assert!(result.is_err());
+
+        // Signers can't raise the threshold without bound or refuel from another canister
+        for config in [
+            CyclesMonitorConfig {
+                threshold: 1_000_000_000_000_000,
+                refuel: None,
+            },
+            CyclesMonitorConfig {
+                threshold: 0,
+                refuel: Some(CyclesRefuelSource::Icp { e8s: 100_000_000_000 }),
+            },
+            CyclesMonitorConfig {
+                threshold: 0,
+                refuel: Some(CyclesRefuelSource::Central(generate_principal())),
+            },
+        ] {
+            let (result,): (Result<(), String>,) =
+                update_candid_as(&test_env.env, vault, caller, "set_cycles_monitor_config", (config,))
+                    .unwrap();
+            assert!(result.is_err());
+        }
+    }
+
+    fn get_batch(test_env: &TestEnv, caller: Principal, proposal_id: u64) -> Batch {
+        let (batch,): (Option<Batch>,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "get_batch",
+            (proposal_id,),
+        )
+        .unwrap();
+
+        batch.unwrap()
+    }
+
+    /// Lets the timer callbacks work through the pending transfers of a batch.
+    fn wait_for_batch(test_env: &TestEnv, caller: Principal, proposal_id: u64) -> Batch {
+        for _ in 0..200 {
+            test_env.env.tick();
+            let batch = get_batch(test_env, caller, proposal_id);
+            if !batch.running {
+                return batch;
+            }
+        }
+        panic!("The batch is still executing");
+    }
+
+    fn retry_batch(
+        test_env: &TestEnv,
+        caller: Principal,
+        proposal_id: u64,
+        items: Option<Vec<u32>>,
+    ) -> Result<IntentStatus, String> {
+        let (result,): (Result<IntentStatus, String>,) = update_candid_as(
+            &test_env.env,
+            test_env.canister_ids.account,
+            caller,
+            "retry_batch",
+            (proposal_id, items),
+        )
+        .unwrap();
+
+        result
+    }
+
+    #[test]
+    fn should_execute_batch_transfers_in_chunks() {
+        let caller = generate_principal();
+        let test_env = setup_new_env_with_config(SetupConfig {
+            default_account_owner: Some(caller),
+            initial_icp_balance: Some(2_000_000_000),
+            initial_mock_icrc1_balance: Some(1_000_000_000_000),
+            ..Default::default()
+        });
+        let vault = test_env.canister_ids.account;
+        let icrc1_token = format!("icp:icrc1:{}", test_env.canister_ids.icrc1_ledger.to_text());
+
+        // More transfers than fit in a chunk, and one the vault can't afford
+        let receivers: Vec<Principal> = (0..12).map(|_| generate_principal()).collect();
+        let mut transfers: Vec<BatchTransfer> = receivers
+            .iter()
+            .map(|receiver| BatchTransfer {
+                to: AccountIdentifier::new(receiver, &DEFAULT_SUBACCOUNT).to_string(),
+                token: "icp:native".to_string(),
+                network: SupportedNetwork::ICP,
+                amount: 100_000_000.0,
+                wallet: None,
+            })
+            .collect();
+        let icrc1_receiver = generate_principal();
+        transfers.push(BatchTransfer {
+            to: icrc1_receiver.to_text(),
+            token: icrc1_token,
+            network: SupportedNetwork::ICP,
+            amount: 100_000_000_000.0,
+            wallet: None,
+        });
+        transfers.push(BatchTransfer {
+            to: AccountIdentifier::new(&generate_principal(), &DEFAULT_SUBACCOUNT).to_string(),
+            token: "icp:native".to_string(),
+            network: SupportedNetwork::ICP,
+            amount: 5_000_000_000.0,
+            wallet: None,
+        });
+
+        let (proposal,): (ProposedTransaction,) =
+            update_candid_as(&test_env.env, vault, caller, "propose_batch", (transfers,)).unwrap();
+        assert_eq!(proposal.transaction_type, TransactionType::Batch);
+
+        let (status,): (IntentStatus,) =
+            update_candid_as(&test_env.env, vault, caller, "execute_transaction", (proposal.id,))
+                .unwrap();
+        assert_eq!(status, IntentStatus::InProgress("Executing 14 of 14 transfers".to_string()));
+
+        let batch = wait_for_batch(&test_env, caller, proposal.id);
+        for item in &batch.items[..13] {
+            assert!(
+                matches!(item.status, IntentStatus::Completed(_)),
+                "Unexpected item {:?}",
+                item
+            );
+            assert_eq!(item.attempts, 1);
+        }
+        assert!(matches!(batch.items[13].status, IntentStatus::Failed(_)));
+
+        for receiver in &receivers {
+            let (balance,): (Tokens,) = query_candid_as(
+                &test_env.env,
+                test_env.canister_ids.icp_ledger,
+                caller,
+                "account_balance",
+                (AccountBalanceArgs {
+                    account: AccountIdentifier::new(receiver, &DEFAULT_SUBACCOUNT),
+                },),
+            )
+            .unwrap();
+            assert_eq!(balance.e8s(), 100_000_000);
+        }
+        let (balance,): (u128,) = query_candid_as(
+            &test_env.env,
+            test_env.canister_ids.icrc1_ledger,
+            caller,
+            "icrc1_balance_of",
+            (ICRCAccount::new(icrc1_receiver, None),),
+        )
+        .unwrap();
+        assert_eq!(balance, 100_000_000_000);
+
+        // Executing the batch again doesn't repeat the transfers
+        let (status,): (IntentStatus,) =
+            update_candid_as(&test_env.env, vault, caller, "execute_transaction", (proposal.id,))
+                .unwrap();
+        assert_eq!(
+            status,
+            IntentStatus::Failed(format!("Proposal {} was already executed", proposal.id))
+        );
+
+        // Only failed transfers are retried
+        assert_eq!(
+            retry_batch(&test_env, caller, proposal.id, Some(vec![0])),
+            Err("Transfer 0 did not fail".to_string())
+        );
+        assert_eq!(
+            retry_batch(&test_env, caller, proposal.id, None),
+            Ok(IntentStatus::InProgress("Executing 1 of 14 transfers".to_string()))
+        );
+
+        let batch = wait_for_batch(&test_env, caller, proposal.id);
+        assert_eq!(batch.items[0].attempts, 1);
+        assert_eq!(batch.items[13].attempts, 2);
+        assert!(matches!(batch.items[13].status, IntentStatus::Failed(_)));
+    }
 }
@@ -2,11 +2,13 @@ use std::{error::Error, io::Write};
 
 use candid::{encode_one, Decode, Principal};
 use ed25519_dalek::SigningKey;
-use pocket_ic::{PocketIc, WasmResult};
+use pocket_ic::{query_candid_as, update_candid_as, PocketIc, WasmResult};
 use rand::rngs::OsRng;
 use libflate::gzip::Encoder;
 
 use keygate_core::types::central::{UserData, Vault, VaultInitArgs};
+use keygate_core::types::environment::VaultEnvironment;
+use keygate_core::types::vault::{AuditEvent, AuditEventKind, CyclesMonitorConfig, CyclesRefuelSource, CyclesStatus};
 
 fn generate_principal() -> Principal {
     let mut csprng = OsRng;
@@ -548,3 +550,128 @@ fn get_vault_by_id_should_return_none_if_vault_not_found() {
         }
     };
 }
+
+#[test]
+fn should_refuel_vaults_it_deployed() {
+    let pic = PocketIc::new();
+    let central_id = pic.create_canister();
+    pic.add_cycles(central_id, 10_000_000_000_000);
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/central.wasm").to_vec();
+    pic.install_canister(central_id, wasm_module, Vec::new(), None);
+
+    let caller = generate_principal();
+    let (vault,): (Principal,) = update_candid_as(
+        &pic,
+        central_id,
+        caller,
+        "deploy_account",
+        (VaultInitArgs {
+            name: "Funding".to_string(),
+        },),
+    )
+    .unwrap();
+
+    // Vaults refuel from central by default, raise the threshold so this one does
+    let config = CyclesMonitorConfig {
+        threshold: 10_000_000_000_000,
+        refuel: Some(CyclesRefuelSource::Central(central_id)),
+    };
+    let (status,): (CyclesStatus,) =
+        query_candid_as(&pic, vault, caller, "get_cycles_status", ()).unwrap();
+    assert_eq!(status.config.refuel, config.refuel);
+    let (result,): (Result<(), String>,) =
+        update_candid_as(&pic, vault, caller, "set_cycles_monitor_config", (config,)).unwrap();
+    result.unwrap();
+
+    let balance = pic.cycle_balance(vault);
+    let (status,): (Result<CyclesStatus, String>,) =
+        update_candid_as(&pic, vault, caller, "check_cycles_now", ()).unwrap();
+    assert!(status.unwrap().balance > balance);
+
+    // Refuels are limited to one a day
+    let (status,): (Result<CyclesStatus, String>,) =
+        update_candid_as(&pic, vault, caller, "check_cycles_now", ()).unwrap();
+    status.unwrap();
+
+    let (trail,): (Vec<AuditEvent>,) =
+        query_candid_as(&pic, vault, caller, "get_audit_trail", ()).unwrap();
+    let kinds: Vec<AuditEventKind> = trail.into_iter().map(|event| event.kind).collect();
+    assert_eq!(kinds.len(), 4, "Unexpected audit trail {:?}", kinds);
+    assert!(matches!(kinds[0], AuditEventKind::CyclesLow { .. }));
+    assert_eq!(
+        kinds[1],
+        AuditEventKind::CyclesRefueled {
+            source: CyclesRefuelSource::Central(central_id),
+            cycles: 240_000_000_000,
+        }
+    );
+    assert!(matches!(kinds[2], AuditEventKind::CyclesLow { .. }));
+    assert!(
+        matches!(&kinds[3], AuditEventKind::CyclesRefuelFailed { error, .. } if error.contains("already refueled")),
+        "Unexpected audit event {:?}",
+        kinds[3]
+    );
+
+    // Only vaults deployed by central get cycles
+    let (result,): (Result<candid::Nat, String>,) =
+        update_candid_as(&pic, central_id, caller, "request_cycles", ()).unwrap();
+    assert!(result.is_err());
+}
+
+#[test]
+fn should_deploy_vaults_with_the_configured_environment() {
+    let pic = PocketIc::new();
+    let central_id = pic.create_canister();
+    pic.add_cycles(central_id, 2_000_000_000_000);
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/central.wasm").to_vec();
+    pic.install_canister(central_id, wasm_module, Vec::new(), None);
+
+    let (environment,): (VaultEnvironment,) =
+        query_candid_as(&pic, central_id, Principal::anonymous(), "get_vault_environment", ())
+            .unwrap();
+    assert_eq!(environment, VaultEnvironment::local());
+
+    let staging = VaultEnvironment {
+        icp_ledger: VaultEnvironment::local().icp_ledger,
+        ..VaultEnvironment::staging()
+    };
+
+    // Only controllers can change it
+    let caller = generate_principal();
+    let (result,): (Result<(), String>,) = update_candid_as(
+        &pic,
+        central_id,
+        caller,
+        "set_vault_environment",
+        (staging.clone(),),
+    )
+    .unwrap();
+    assert!(result.is_err());
+
+    let (result,): (Result<(), String>,) = update_candid_as(
+        &pic,
+        central_id,
+        Principal::anonymous(),
+        "set_vault_environment",
+        (staging.clone(),),
+    )
+    .unwrap();
+    result.unwrap();
+
+    let (vault,): (Principal,) = update_candid_as(
+        &pic,
+        central_id,
+        caller,
+        "deploy_account",
+        (VaultInitArgs {
+            name: "Staging".to_string(),
+        },),
+    )
+    .unwrap();
+
+    let (environment,): (VaultEnvironment,) =
+        query_candid_as(&pic, vault, caller, "get_environment", ()).unwrap();
+    assert_eq!(environment, staging);
+}
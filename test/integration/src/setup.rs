@@ -1,11 +1,11 @@
 use std::{collections::HashSet, env, path::Path};
 
-use candid::{encode_one, Principal};
+use candid::{encode_one, Nat, Principal};
 use ic_ledger_types::{AccountIdentifier, Tokens, DEFAULT_SUBACCOUNT};
 use icrc_ledger_types::icrc1::account::Account;
 use pocket_ic::{PocketIc, PocketIcBuilder};
 
-use crate::{types::{ArchiveOptions, FeatureFlags, ICRC1Args, ICRC1InitArgs, LedgerCanisterPayload, NnsLedgerCanisterInitPayload}, utils::{generate_principal, NNS_ROOT_CANISTER_ID}, CanisterIds, TestEnv};
+use crate::{types::{ArchiveOptions, FeatureFlags, ICRC1Args, ICRC1InitArgs, LedgerCanisterPayload, MockCmcArgs, MockCyclesLedgerArgs, MockGovernanceArgs, MockIcrc7Args, MockMinterArgs, MockSnsGovernanceArgs, MockSwapPoolArgs, NnsLedgerCanisterInitPayload}, utils::{generate_principal, BITCOIN_TESTNET_CANISTER_ID, CYCLES_LEDGER_CANISTER_ID, CYCLES_MINTING_CANISTER_ID, NNS_GOVERNANCE_CANISTER_ID, NNS_ROOT_CANISTER_ID}, CanisterIds, TestEnv};
 
 
 #[derive(Clone)]
@@ -46,6 +46,8 @@ pub fn install_canisters(pic: &PocketIc, config: SetupConfig) -> CanisterIds {
     pic.install_canister(account_id, account_wasm, candid::encode_one(keygate_core::types::canister_init::VaultInitArgs {
         name: "".to_string(),
         signers: signers.clone(),
+        environment: None,
+        central: None,
     }).unwrap(), None);
 
     let specified_nns_ledger_canister_id = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
@@ -94,37 +96,58 @@ pub fn install_canisters(pic: &PocketIc, config: SetupConfig) -> CanisterIds {
         None,
     );
 
+    let mint_amount = config.initial_mock_icrc1_balance.unwrap_or(1000_000_000_000u128);
+    let icrc1_ledger = install_icrc1_ledger(
+        pic,
+        signers[0],
+        vec![(
+            Account {
+                owner: account_id,
+                subaccount: None,
+            },
+            mint_amount,
+        )],
+        account_id,
+    );
+
+    CanisterIds {
+        central: central_id,
+        account: account_id,
+        icp_ledger: nns_ledger_canister_id,
+        icrc1_ledger,
+    }
+}
+
+pub fn install_icrc1_ledger(
+    pic: &PocketIc,
+    minter: Principal,
+    initial_balances: Vec<(Account, u128)>,
+    archive_controller: Principal,
+) -> Principal {
     let icrc1_ledger = pic.create_canister();
     pic.add_cycles(icrc1_ledger, 2_000_000_000_000);
     let icrc_wasm_module = include_bytes!("../../../mock_icrc1_wasm_build.gz").to_vec();
 
-    let mint_amount = config.initial_mock_icrc1_balance.unwrap_or(1000_000_000_000u128);
     let icrc1_deploy_args = ICRC1Args::Init(ICRC1InitArgs {
         token_symbol: "MCK".to_string(),
         token_name: "Mock Token".to_string(),
         minting_account: Account {
-            owner: signers[0],
+            owner: minter,
             subaccount: None,
         },
         transfer_fee: 1_000_000,
         metadata: vec![],
-        initial_balances: vec![(
-            Account {
-                owner: account_id,
-                subaccount: None,
-            },
-            mint_amount,
-        )],
+        initial_balances,
         archive_options: ArchiveOptions {
             num_blocks_to_archive: 10,
             trigger_threshold: 5,
-            controller_id: account_id,
+            controller_id: archive_controller,
             max_transactions_per_response: None,
             max_message_size_bytes: None,
             cycles_for_archive_creation: None,
             node_max_memory_size_bytes: None,
         },
-        feature_flags: Some(FeatureFlags { icrc2: false }),
+        feature_flags: Some(FeatureFlags { icrc2: true }),
         decimals: Some(3),
         maximum_number_of_accounts: None,
         accounts_overflow_trim_quantity: None,
@@ -139,12 +162,132 @@ pub fn install_canisters(pic: &PocketIc, config: SetupConfig) -> CanisterIds {
         None,
     );
 
-    CanisterIds {
-        central: central_id,
-        account: account_id,
-        icp_ledger: nns_ledger_canister_id,
-        icrc1_ledger,
-    }
+    icrc1_ledger
+}
+
+pub fn install_mock_swap_pool(pic: &PocketIc, args: MockSwapPoolArgs) -> Principal {
+    let pool = pic.create_canister();
+    pic.add_cycles(pool, 2_000_000_000_000);
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_swap_pool.wasm").to_vec();
+    pic.install_canister(pool, wasm_module, encode_one(args).unwrap(), None);
+
+    pool
+}
+
+/// Installs a chain-key minter mock and the ck token ledger it mints. Returns the
+/// minter and the ledger.
+pub fn install_mock_minter(
+    pic: &PocketIc,
+    initial_balances: Vec<(Account, u128)>,
+) -> (Principal, Principal) {
+    let minter = pic.create_canister();
+    pic.add_cycles(minter, 2_000_000_000_000);
+    let ledger = install_icrc1_ledger(pic, minter, initial_balances, minter);
+
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_minter.wasm").to_vec();
+    pic.install_canister(minter, wasm_module, encode_one(MockMinterArgs { ledger }).unwrap(), None);
+
+    (minter, ledger)
+}
+
+/// Installs the regtest Bitcoin canister mock on the Bitcoin subnet.
+pub fn install_mock_bitcoin(pic: &PocketIc) -> Principal {
+    let bitcoin = pic
+        .create_canister_with_id(None, None, BITCOIN_TESTNET_CANISTER_ID)
+        .unwrap();
+    pic.add_cycles(bitcoin, 2_000_000_000_000);
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_bitcoin.wasm").to_vec();
+    pic.install_canister(bitcoin, wasm_module, vec![], None);
+
+    bitcoin
+}
+
+/// Installs the NNS governance mock on the NNS subnet, staking on `ledger`.
+pub fn install_mock_nns_governance(pic: &PocketIc, ledger: Principal) -> Principal {
+    let governance = pic
+        .create_canister_with_id(None, None, NNS_GOVERNANCE_CANISTER_ID)
+        .unwrap();
+    pic.add_cycles(governance, 2_000_000_000_000);
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_nns_governance.wasm")
+            .to_vec();
+    pic.install_canister(
+        governance,
+        wasm_module,
+        encode_one(MockGovernanceArgs { ledger }).unwrap(),
+        None,
+    );
+
+    governance
+}
+
+/// Deploys an SNS governance mock and its ledger, which starts with `initial_balances`.
+/// Returns the governance canister and the ledger.
+pub fn install_mock_sns_governance(
+    pic: &PocketIc,
+    initial_balances: Vec<(Account, u128)>,
+) -> (Principal, Principal) {
+    let governance = pic.create_canister();
+    pic.add_cycles(governance, 2_000_000_000_000);
+    let ledger = install_icrc1_ledger(pic, governance, initial_balances, governance);
+
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_sns_governance.wasm")
+            .to_vec();
+    pic.install_canister(
+        governance,
+        wasm_module,
+        encode_one(MockSnsGovernanceArgs { ledger }).unwrap(),
+        None,
+    );
+
+    (governance, ledger)
+}
+
+/// Installs the CMC mock on the NNS subnet, converting ICP of `ledger` into cycles.
+pub fn install_mock_cmc(pic: &PocketIc, ledger: Principal) -> Principal {
+    let cmc = pic
+        .create_canister_with_id(None, None, CYCLES_MINTING_CANISTER_ID)
+        .unwrap();
+    pic.add_cycles(cmc, 100_000_000_000_000);
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_cmc.wasm").to_vec();
+    pic.install_canister(cmc, wasm_module, encode_one(MockCmcArgs { ledger }).unwrap(), None);
+
+    cmc
+}
+
+/// Installs the cycles ledger mock on the fiduciary subnet, starting with `initial_balances`.
+pub fn install_mock_cycles_ledger(pic: &PocketIc, initial_balances: Vec<(Account, u128)>) -> Principal {
+    let cycles_ledger = pic
+        .create_canister_with_id(None, None, CYCLES_LEDGER_CANISTER_ID)
+        .unwrap();
+    pic.add_cycles(cycles_ledger, 100_000_000_000_000);
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_cycles_ledger.wasm")
+            .to_vec();
+    pic.install_canister(
+        cycles_ledger,
+        wasm_module,
+        encode_one(MockCyclesLedgerArgs { initial_balances }).unwrap(),
+        None,
+    );
+
+    cycles_ledger
+}
+
+/// Installs an ICRC-7 collection mock whose tokens start with the given owners.
+pub fn install_mock_icrc7(pic: &PocketIc, tokens: Vec<(Nat, Account)>) -> Principal {
+    let collection = pic.create_canister();
+    pic.add_cycles(collection, 2_000_000_000_000);
+    let wasm_module =
+        include_bytes!("../../../target/wasm32-unknown-unknown/release/mock_icrc7.wasm").to_vec();
+    pic.install_canister(collection, wasm_module, encode_one(MockIcrc7Args { tokens }).unwrap(), None);
+
+    collection
 }
 
 pub fn setup_new_env_with_config(config: SetupConfig) -> TestEnv {
@@ -170,6 +313,8 @@ pub fn setup_new_env_with_config(config: SetupConfig) -> TestEnv {
         .with_ii_subnet()
         .with_nns_subnet()
         .with_application_subnet()
+        .with_bitcoin_subnet()
+        .with_fiduciary_subnet()
         .build();
 
     println!("Installing canisters");
@@ -3,6 +3,7 @@ use ic_ledger_types::{Subaccount, Tokens};
 use ic_stable_structures::storable::{Bound, Storable};
 use icrc_ledger_types::icrc1::account::Account;
 use serde::{Deserialize, Serialize};
+use keygate_core::types::vault::{ChainKeyMinter, IntentStatus, SupportedNetwork, TransactionType};
 use std::borrow::Cow;
 use std::collections::{HashSet};
 use std::time::Duration;
@@ -163,3 +164,104 @@ impl PartialEq for FeatureFlags {
 }
 
 
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MockSwapPoolArgs {
+    pub token0: Principal,
+    pub token1: Principal,
+    pub price_bps: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct MockMinterArgs {
+    pub ledger: Principal,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct MockGovernanceArgs {
+    pub ledger: Principal,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct MockSnsGovernanceArgs {
+    pub ledger: Principal,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct MockCmcArgs {
+    pub ledger: Principal,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct MockCyclesLedgerArgs {
+    pub initial_balances: Vec<(Account, u128)>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct MockIcrc7Args {
+    pub tokens: Vec<(Nat, Account)>,
+}
+
+/// Argument of `icrc37_is_approved`.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct IsApprovedArg {
+    pub spender: Account,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub token_id: Nat,
+}
+
+/// Withdrawal returned by `get_pending_withdrawals`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingWithdrawal {
+    pub minter: ChainKeyMinter,
+    pub block_index: u64,
+    pub polls: u32,
+}
+
+/// Transfer of a batch proposal.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatchTransfer {
+    pub to: String,
+    pub token: String,
+    pub network: SupportedNetwork,
+    pub amount: f64,
+    pub wallet: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatchItem {
+    pub transfer: BatchTransfer,
+    pub status: IntentStatus,
+    pub attempts: u32,
+    pub transaction_index: Option<u64>,
+}
+
+/// Batch returned by `get_batch`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Batch {
+    pub items: Vec<BatchItem>,
+    pub running: bool,
+}
+
+/// The fields of an executed transaction the tests look at.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExecutedTransaction {
+    pub transaction_type: TransactionType,
+    pub status: IntentStatus,
+}
+
+/// Named EVM wallet returned by the account canister.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EvmWallet {
+    pub name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub address: String,
+}
+
+/// Balance of a wallet returned by `get_evm_wallet_balances`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EvmWalletBalance {
+    pub wallet: String,
+    pub address: String,
+    pub balance: String,
+}
@@ -32,6 +32,8 @@ fn test_user_linked_vaults() {
        (VaultInitArgs {
            name: "Funding".to_string(),
            signers: vec![alice],
+           environment: None,
+           central: None,
        },),
    ).unwrap();
 
@@ -1,6 +1,18 @@
 use candid::Principal;
 
 pub const NNS_ROOT_CANISTER_ID: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 3, 1, 1]);
+/// Bitcoin canister serving testnet and regtest, g4xu7-jiaaa-aaaan-aaaaq-cai.
+pub const BITCOIN_TESTNET_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 1, 160, 0, 1, 1, 1]);
+/// NNS governance canister, rrkah-fqaaa-aaaaa-aaaaq-cai.
+pub const NNS_GOVERNANCE_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+/// Cycles minting canister, rkp4c-7iaaa-aaaaa-aaaca-cai.
+pub const CYCLES_MINTING_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 4, 1, 1]);
+/// Cycles ledger on the fiduciary subnet, um5iw-rqaaa-aaaaq-qaaba-cai.
+pub const CYCLES_LEDGER_CANISTER_ID: Principal =
+    Principal::from_slice(&[0, 0, 0, 0, 2, 16, 0, 2, 1, 1]);
 
 
 pub fn controller_test_id() -> Principal {
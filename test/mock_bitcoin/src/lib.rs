@@ -0,0 +1,132 @@
+//! Regtest Bitcoin canister used by the integration tests. Installed under the
+//! Bitcoin canister's id on the PocketIC Bitcoin subnet, so the management canister
+//! routes the vault's Bitcoin API calls to it. Sent transactions are applied to the
+//! UTXO set right away, as if they were mined in the next block.
+
+use std::{cell::RefCell, collections::HashMap, str::FromStr};
+
+use bitcoin::{consensus::deserialize, hashes::Hash, Address, Network, Transaction};
+use ic_cdk::api::management_canister::bitcoin::{
+    GetBalanceRequest, GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse,
+    MillisatoshiPerByte, Outpoint, Satoshi, SendTransactionRequest, Utxo,
+};
+
+#[derive(Default)]
+struct State {
+    utxos: HashMap<String, Vec<Utxo>>,
+    fee_percentiles: Vec<MillisatoshiPerByte>,
+    sent_transactions: Vec<Vec<u8>>,
+    height: u32,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn normalize(address: &str) -> String {
+    match Address::from_str(address) {
+        Ok(address) => address.assume_checked().to_string(),
+        Err(_) => address.to_string(),
+    }
+}
+
+#[ic_cdk::query]
+fn bitcoin_get_utxos(request: GetUtxosRequest) -> GetUtxosResponse {
+    STATE.with(|s| {
+        let s = s.borrow();
+        GetUtxosResponse {
+            utxos: s
+                .utxos
+                .get(&normalize(&request.address))
+                .cloned()
+                .unwrap_or_default(),
+            tip_block_hash: vec![0; 32],
+            tip_height: s.height,
+            next_page: None,
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn bitcoin_get_balance(request: GetBalanceRequest) -> Satoshi {
+    STATE.with(|s| {
+        s.borrow()
+            .utxos
+            .get(&normalize(&request.address))
+            .map(|utxos| utxos.iter().map(|utxo| utxo.value).sum())
+            .unwrap_or(0)
+    })
+}
+
+#[ic_cdk::query]
+fn bitcoin_get_current_fee_percentiles(
+    _request: GetCurrentFeePercentilesRequest,
+) -> Vec<MillisatoshiPerByte> {
+    STATE.with(|s| s.borrow().fee_percentiles.clone())
+}
+
+#[ic_cdk::update]
+fn bitcoin_send_transaction(request: SendTransactionRequest) {
+    let transaction: Transaction = deserialize(&request.transaction)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Invalid transaction: {}", e)));
+    let txid = transaction.compute_txid().to_byte_array().to_vec();
+
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.height += 1;
+        let height = s.height;
+
+        for input in &transaction.input {
+            let spent = Outpoint {
+                txid: input.previous_output.txid.to_byte_array().to_vec(),
+                vout: input.previous_output.vout,
+            };
+            for utxos in s.utxos.values_mut() {
+                utxos.retain(|utxo| utxo.outpoint != spent);
+            }
+        }
+
+        for (vout, output) in transaction.output.iter().enumerate() {
+            if let Ok(address) = Address::from_script(&output.script_pubkey, Network::Regtest) {
+                s.utxos.entry(address.to_string()).or_default().push(Utxo {
+                    outpoint: Outpoint {
+                        txid: txid.clone(),
+                        vout: vout as u32,
+                    },
+                    value: output.value.to_sat(),
+                    height,
+                });
+            }
+        }
+
+        s.sent_transactions.push(request.transaction);
+    });
+}
+
+/// Funds an address with a new UTXO.
+#[ic_cdk::update]
+fn mock_add_utxo(address: String, value: Satoshi) {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.height += 1;
+        let txid = bitcoin::hashes::sha256d::Hash::hash(&s.height.to_le_bytes())
+            .to_byte_array()
+            .to_vec();
+        let utxo = Utxo {
+            outpoint: Outpoint { txid, vout: 0 },
+            value,
+            height: s.height,
+        };
+        s.utxos.entry(normalize(&address)).or_default().push(utxo);
+    });
+}
+
+#[ic_cdk::update]
+fn mock_set_fee_percentiles(fee_percentiles: Vec<MillisatoshiPerByte>) {
+    STATE.with(|s| s.borrow_mut().fee_percentiles = fee_percentiles);
+}
+
+#[ic_cdk::query]
+fn mock_get_sent_transactions() -> Vec<Vec<u8>> {
+    STATE.with(|s| s.borrow().sent_transactions.clone())
+}
@@ -0,0 +1,160 @@
+//! Cycles minting canister used by the integration tests. Installed under the CMC's
+//! id, it converts the ICP sent to its subaccounts at a fixed rate, paying the cycles
+//! out of its own balance. Blocks are not inspected: the whole balance of the
+//! beneficiary's subaccount is converted, so the memo is not checked either.
+
+use std::{cell::RefCell, collections::BTreeSet};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::management_canister::main::{
+    create_canister, deposit_cycles, CanisterIdRecord, CanisterSettings, CreateCanisterArgument,
+};
+use ic_ledger_types::{
+    AccountBalanceArgs, AccountIdentifier, Memo, Subaccount, Tokens, TransferArgs,
+    DEFAULT_SUBACCOUNT,
+};
+use serde::Deserialize;
+
+const TRANSFER_FEE: u64 = 10_000;
+
+/// 1 ICP buys 1T cycles.
+pub const CYCLES_PER_E8: u128 = 10_000;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MockCmcArgs {
+    pub ledger: Principal,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct NotifyTopUpArg {
+    pub block_index: u64,
+    pub canister_id: Principal,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct NotifyCreateCanisterArg {
+    pub block_index: u64,
+    pub controller: Principal,
+    pub subnet_type: Option<String>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum NotifyError {
+    Refunded {
+        reason: String,
+        block_index: Option<u64>,
+    },
+    Processing,
+    TransactionTooOld(u64),
+    InvalidTransaction(String),
+    Other { error_code: u64, error_message: String },
+}
+
+#[derive(Default)]
+struct State {
+    ledger: Option<Principal>,
+    notified_blocks: BTreeSet<u64>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn ledger() -> Principal {
+    STATE.with(|s| s.borrow().ledger.expect("ledger is not set"))
+}
+
+fn other(message: String) -> NotifyError {
+    NotifyError::Other {
+        error_code: 0,
+        error_message: message,
+    }
+}
+
+fn principal_subaccount(principal: Principal) -> Subaccount {
+    let bytes = principal.as_slice();
+    let mut subaccount = [0; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    Subaccount(subaccount)
+}
+
+/// Moves the ICP on the subaccount of `beneficiary` to the main account of the mock,
+/// and returns the cycles it is worth.
+async fn convert(block_index: u64, beneficiary: Principal) -> Result<u128, NotifyError> {
+    let first_notification = STATE.with(|s| s.borrow_mut().notified_blocks.insert(block_index));
+    if !first_notification {
+        return Err(NotifyError::InvalidTransaction(format!(
+            "Block {} was already notified",
+            block_index
+        )));
+    }
+
+    let subaccount = principal_subaccount(beneficiary);
+    let balance = ic_ledger_types::account_balance(
+        ledger(),
+        AccountBalanceArgs {
+            account: AccountIdentifier::new(&ic_cdk::id(), &subaccount),
+        },
+    )
+    .await
+    .map_err(|(_, message)| other(message))?;
+    if balance.e8s() <= TRANSFER_FEE {
+        return Err(NotifyError::InvalidTransaction("Nothing to convert".to_string()));
+    }
+
+    let e8s = balance.e8s() - TRANSFER_FEE;
+    let args = TransferArgs {
+        memo: Memo(0),
+        amount: Tokens::from_e8s(e8s),
+        fee: Tokens::from_e8s(TRANSFER_FEE),
+        from_subaccount: Some(subaccount),
+        to: AccountIdentifier::new(&ic_cdk::id(), &DEFAULT_SUBACCOUNT),
+        created_at_time: None,
+    };
+    match ic_ledger_types::transfer(ledger(), args).await {
+        Ok(Ok(_)) => Ok(e8s as u128 * CYCLES_PER_E8),
+        Ok(Err(e)) => Err(other(format!("{:?}", e))),
+        Err((_, message)) => Err(other(message)),
+    }
+}
+
+#[ic_cdk::init]
+fn init(args: MockCmcArgs) {
+    STATE.with(|s| s.borrow_mut().ledger = Some(args.ledger));
+}
+
+#[ic_cdk::update]
+async fn notify_top_up(arg: NotifyTopUpArg) -> Result<Nat, NotifyError> {
+    let cycles = convert(arg.block_index, arg.canister_id).await?;
+    deposit_cycles(
+        CanisterIdRecord {
+            canister_id: arg.canister_id,
+        },
+        cycles,
+    )
+    .await
+    .map_err(|(_, message)| other(message))?;
+
+    Ok(Nat::from(cycles))
+}
+
+#[ic_cdk::update]
+async fn notify_create_canister(arg: NotifyCreateCanisterArg) -> Result<Principal, NotifyError> {
+    if ic_cdk::caller() != arg.controller {
+        return Err(other("Only the controller can create its canister".to_string()));
+    }
+
+    let cycles = convert(arg.block_index, arg.controller).await?;
+    let argument = CreateCanisterArgument {
+        settings: Some(CanisterSettings {
+            controllers: Some(vec![arg.controller]),
+            ..Default::default()
+        }),
+    };
+    let (record,) = create_canister(argument, cycles)
+        .await
+        .map_err(|(_, message)| other(message))?;
+
+    Ok(record.canister_id)
+}
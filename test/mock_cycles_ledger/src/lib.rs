@@ -0,0 +1,158 @@
+//! Cycles ledger used by the integration tests. Installed under the cycles ledger's
+//! id, it keeps balances on the heap and pays withdrawals out of its own cycles.
+//! Deduplication and `created_at_time` are not supported.
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::{
+    call::RejectionCode,
+    management_canister::main::{deposit_cycles, CanisterIdRecord},
+};
+use icrc_ledger_types::icrc1::{
+    account::Account,
+    transfer::{TransferArg, TransferError},
+};
+use serde::Deserialize;
+
+pub const FEE: u128 = 100_000_000;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MockCyclesLedgerArgs {
+    pub initial_balances: Vec<(Account, u128)>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct WithdrawArgs {
+    pub amount: Nat,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Principal,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum WithdrawError {
+    InsufficientFunds {
+        balance: Nat,
+    },
+    FailedToWithdraw {
+        fee_block: Option<Nat>,
+        rejection_code: RejectionCode,
+        rejection_reason: String,
+    },
+    GenericError {
+        error_code: Nat,
+        message: String,
+    },
+}
+
+#[derive(Default)]
+struct State {
+    balances: BTreeMap<Account, u128>,
+    blocks: u64,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn to_u128(amount: &Nat) -> u128 {
+    amount.0.clone().try_into().unwrap_or(u128::MAX)
+}
+
+fn account(owner: Principal, subaccount: Option<[u8; 32]>) -> Account {
+    // The default subaccount and no subaccount are the same account
+    Account {
+        owner,
+        subaccount: subaccount.filter(|subaccount| *subaccount != [0; 32]),
+    }
+}
+
+fn normalize(account_arg: Account) -> Account {
+    account(account_arg.owner, account_arg.subaccount)
+}
+
+/// Takes `amount` and the fee from `from`, returning the balance when it is too low.
+fn debit(from: Account, amount: u128) -> Result<u64, u128> {
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let balance = state.balances.get(&from).copied().unwrap_or_default();
+        if balance < amount + FEE {
+            return Err(balance);
+        }
+        state.balances.insert(from, balance - amount - FEE);
+        state.blocks += 1;
+        Ok(state.blocks - 1)
+    })
+}
+
+fn credit(to: Account, amount: u128) {
+    STATE.with(|s| *s.borrow_mut().balances.entry(to).or_default() += amount);
+}
+
+#[ic_cdk::init]
+fn init(args: MockCyclesLedgerArgs) {
+    for (owner, amount) in args.initial_balances {
+        credit(normalize(owner), amount);
+    }
+}
+
+#[ic_cdk::query]
+fn icrc1_fee() -> Nat {
+    Nat::from(FEE)
+}
+
+#[ic_cdk::query]
+fn icrc1_balance_of(owner: Account) -> Nat {
+    STATE.with(|s| Nat::from(s.borrow().balances.get(&normalize(owner)).copied().unwrap_or_default()))
+}
+
+#[ic_cdk::update]
+fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    if let Some(fee) = arg.fee.as_ref().filter(|fee| to_u128(fee) != FEE) {
+        return Err(TransferError::BadFee {
+            expected_fee: Nat::from(FEE),
+        });
+    }
+
+    let amount = to_u128(&arg.amount);
+    let from = account(ic_cdk::caller(), arg.from_subaccount);
+    let block = debit(from, amount).map_err(|balance| TransferError::InsufficientFunds {
+        balance: Nat::from(balance),
+    })?;
+    credit(normalize(arg.to), amount);
+
+    Ok(Nat::from(block))
+}
+
+#[ic_cdk::update]
+async fn withdraw(args: WithdrawArgs) -> Result<Nat, WithdrawError> {
+    let from_subaccount = match args.from_subaccount {
+        Some(subaccount) => Some(<[u8; 32]>::try_from(subaccount).map_err(|_| {
+            WithdrawError::GenericError {
+                error_code: Nat::from(0u8),
+                message: "Invalid subaccount".to_string(),
+            }
+        })?),
+        None => None,
+    };
+    let from = account(ic_cdk::caller(), from_subaccount);
+    let amount = to_u128(&args.amount);
+    let block = debit(from, amount).map_err(|balance| WithdrawError::InsufficientFunds {
+        balance: Nat::from(balance),
+    })?;
+
+    if let Err((rejection_code, rejection_reason)) =
+        deposit_cycles(CanisterIdRecord { canister_id: args.to }, amount).await
+    {
+        // Refunds the amount but keeps the fee, as the cycles ledger does
+        credit(from, amount);
+        return Err(WithdrawError::FailedToWithdraw {
+            fee_block: Some(Nat::from(block)),
+            rejection_code,
+            rejection_reason,
+        });
+    }
+
+    Ok(Nat::from(block))
+}
@@ -0,0 +1,297 @@
+//! ICRC-7 collection with ICRC-37 approvals used by the integration tests. It keeps
+//! owners and approvals on the heap. Expiries, memos and deduplication are ignored.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use candid::{CandidType, Nat, Principal};
+use icrc_ledger_types::icrc1::account::Account;
+use serde::Deserialize;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MockIcrc7Args {
+    pub tokens: Vec<(Nat, Account)>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ApprovalInfo {
+    pub spender: Account,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub expires_at: Option<u64>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ApproveTokenArg {
+    pub token_id: Nat,
+    pub approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ApproveCollectionArg {
+    pub approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RevokeTokenApprovalArg {
+    pub spender: Option<Account>,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RevokeCollectionApprovalArg {
+    pub spender: Option<Account>,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct IsApprovedArg {
+    pub spender: Account,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub token_id: Nat,
+}
+
+/// The errors of ICRC-7 transfers and ICRC-37 approvals this mock returns.
+#[derive(CandidType, Deserialize)]
+pub enum NftError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    InvalidSpender,
+    Unauthorized,
+    ApprovalDoesNotExist,
+}
+
+type BatchResult = Vec<Option<Result<Nat, NftError>>>;
+
+#[derive(Default)]
+struct State {
+    owners: BTreeMap<u128, Account>,
+    token_approvals: BTreeSet<(u128, Account)>,
+    collection_approvals: BTreeSet<(Account, Account)>,
+    blocks: u64,
+}
+
+impl State {
+    fn next_block(&mut self) -> Nat {
+        self.blocks += 1;
+        Nat::from(self.blocks - 1)
+    }
+
+    /// Checks that `from` owns `token_id`.
+    fn check_owner(&self, token_id: u128, from: &Account) -> Result<(), NftError> {
+        match self.owners.get(&token_id) {
+            None => Err(NftError::NonExistingTokenId),
+            Some(owner) if owner != from => Err(NftError::Unauthorized),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn to_u128(amount: &Nat) -> u128 {
+    amount.0.clone().try_into().unwrap_or(u128::MAX)
+}
+
+fn account(owner: Principal, subaccount: Option<[u8; 32]>) -> Account {
+    // The default subaccount and no subaccount are the same account
+    Account {
+        owner,
+        subaccount: subaccount.filter(|subaccount| *subaccount != [0; 32]),
+    }
+}
+
+fn normalize(account_arg: Account) -> Account {
+    account(account_arg.owner, account_arg.subaccount)
+}
+
+fn subaccount(from_subaccount: Option<Vec<u8>>) -> Option<[u8; 32]> {
+    from_subaccount.map(|subaccount| {
+        <[u8; 32]>::try_from(subaccount).unwrap_or_else(|_| ic_cdk::trap("Invalid subaccount"))
+    })
+}
+
+/// Applies `f` to every argument of a batch call, one result per argument.
+fn batch<A>(args: Vec<A>, f: impl Fn(&mut State, A) -> Result<(), NftError>) -> BatchResult {
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        args.into_iter()
+            .map(|arg| Some(f(&mut state, arg).map(|_| state.next_block())))
+            .collect()
+    })
+}
+
+#[ic_cdk::init]
+fn init(args: MockIcrc7Args) {
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        for (token_id, owner) in args.tokens {
+            state.owners.insert(to_u128(&token_id), normalize(owner));
+        }
+    });
+}
+
+#[ic_cdk::query]
+fn icrc7_owner_of(token_ids: Vec<Nat>) -> Vec<Option<Account>> {
+    STATE.with(|s| {
+        let state = s.borrow();
+        token_ids
+            .iter()
+            .map(|token_id| state.owners.get(&to_u128(token_id)).copied())
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn icrc7_tokens_of(owner: Account, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    let owner = normalize(owner);
+    let start = prev
+        .map(|prev| to_u128(&prev).saturating_add(1))
+        .unwrap_or_default();
+    let take = take
+        .map(|take| to_u128(&take) as usize)
+        .unwrap_or(usize::MAX);
+
+    STATE.with(|s| {
+        s.borrow()
+            .owners
+            .range(start..)
+            .filter(|(_, token_owner)| **token_owner == owner)
+            .take(take)
+            .map(|(token_id, _)| Nat::from(*token_id))
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+fn icrc7_transfer(args: Vec<TransferArg>) -> BatchResult {
+    let caller = ic_cdk::caller();
+    batch(args, |state, arg| {
+        let from = account(caller, subaccount(arg.from_subaccount));
+        let token_id = to_u128(&arg.token_id);
+        state.check_owner(token_id, &from)?;
+        let to = normalize(arg.to);
+        if to == from {
+            return Err(NftError::InvalidRecipient);
+        }
+
+        state.owners.insert(token_id, to);
+        // Token approvals are granted by the owner and end with the transfer
+        state
+            .token_approvals
+            .retain(|(approved_id, _)| *approved_id != token_id);
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn icrc37_approve_tokens(args: Vec<ApproveTokenArg>) -> BatchResult {
+    let caller = ic_cdk::caller();
+    batch(args, |state, arg| {
+        let from = account(caller, subaccount(arg.approval_info.from_subaccount));
+        let token_id = to_u128(&arg.token_id);
+        state.check_owner(token_id, &from)?;
+        let spender = normalize(arg.approval_info.spender);
+        if spender == from {
+            return Err(NftError::InvalidSpender);
+        }
+
+        state.token_approvals.insert((token_id, spender));
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn icrc37_approve_collection(args: Vec<ApproveCollectionArg>) -> BatchResult {
+    let caller = ic_cdk::caller();
+    batch(args, |state, arg| {
+        let from = account(caller, subaccount(arg.approval_info.from_subaccount));
+        let spender = normalize(arg.approval_info.spender);
+        if spender == from {
+            return Err(NftError::InvalidSpender);
+        }
+
+        state.collection_approvals.insert((from, spender));
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn icrc37_revoke_token_approvals(args: Vec<RevokeTokenApprovalArg>) -> BatchResult {
+    let caller = ic_cdk::caller();
+    batch(args, |state, arg| {
+        let from = account(caller, subaccount(arg.from_subaccount));
+        let token_id = to_u128(&arg.token_id);
+        state.check_owner(token_id, &from)?;
+        let spender = arg.spender.map(normalize);
+
+        let approvals = state.token_approvals.len();
+        state
+            .token_approvals
+            .retain(|(approved_id, approved_spender)| {
+                *approved_id != token_id
+                    || spender.is_some_and(|spender| spender != *approved_spender)
+            });
+        if state.token_approvals.len() == approvals {
+            return Err(NftError::ApprovalDoesNotExist);
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn icrc37_revoke_collection_approvals(args: Vec<RevokeCollectionApprovalArg>) -> BatchResult {
+    let caller = ic_cdk::caller();
+    batch(args, |state, arg| {
+        let from = account(caller, subaccount(arg.from_subaccount));
+        let spender = arg.spender.map(normalize);
+
+        let approvals = state.collection_approvals.len();
+        state
+            .collection_approvals
+            .retain(|(owner, approved_spender)| {
+                *owner != from || spender.is_some_and(|spender| spender != *approved_spender)
+            });
+        if state.collection_approvals.len() == approvals {
+            return Err(NftError::ApprovalDoesNotExist);
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn icrc37_is_approved(args: Vec<IsApprovedArg>) -> Vec<bool> {
+    let caller = ic_cdk::caller();
+    STATE.with(|s| {
+        let state = s.borrow();
+        args.into_iter()
+            .map(|arg| {
+                let from = account(caller, subaccount(arg.from_subaccount));
+                let token_id = to_u128(&arg.token_id);
+                let spender = normalize(arg.spender);
+                state.owners.get(&token_id) == Some(&from)
+                    && (state.token_approvals.contains(&(token_id, spender))
+                        || state.collection_approvals.contains(&(from, spender)))
+            })
+            .collect()
+    })
+}
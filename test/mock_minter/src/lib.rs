@@ -0,0 +1,334 @@
+//! Chain-key minter used by the integration tests. It serves both the ckBTC and the
+//! ckETH withdrawal endpoints and is the minting account of its ledger. Deposits are
+//! registered with `mock_deposit` and withdrawals stay pending until
+//! `mock_confirm_withdrawals` is called.
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use icrc_ledger_types::{
+    icrc1::{
+        account::Account,
+        transfer::{TransferArg, TransferError},
+    },
+    icrc2::transfer_from::{TransferFromArgs, TransferFromError},
+};
+use serde::Deserialize;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MockMinterArgs {
+    pub ledger: Principal,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct MinterAccountArgs {
+    pub owner: Option<Principal>,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct OutPoint {
+    pub txid: Vec<u8>,
+    pub vout: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    pub height: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum UtxoStatus {
+    ValueTooSmall(Utxo),
+    Tainted(Utxo),
+    Checked(Utxo),
+    Minted {
+        block_index: u64,
+        minted_amount: u64,
+        utxo: Utxo,
+    },
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum UpdateBalanceError {
+    GenericError { error_code: u64, error_message: String },
+    TemporarilyUnavailable(String),
+    AlreadyProcessing,
+    NoNewUtxos {
+        current_confirmations: Option<u32>,
+        required_confirmations: u32,
+    },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RetrieveBtcWithApprovalArgs {
+    pub address: String,
+    pub amount: u64,
+    pub from_subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RetrieveBtcOk {
+    pub block_index: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum RetrieveBtcWithApprovalError {
+    MalformedAddress(String),
+    GenericError { error_code: u64, error_message: String },
+    TemporarilyUnavailable(String),
+    InsufficientAllowance { allowance: u64 },
+    AlreadyProcessing,
+    AmountTooLow(u64),
+    InsufficientFunds { balance: u64 },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RetrieveBtcStatusRequest {
+    pub block_index: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum RetrieveBtcStatusV2 {
+    Unknown,
+    Pending,
+    Confirmed { txid: Vec<u8> },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct WithdrawalArg {
+    pub amount: Nat,
+    pub recipient: String,
+    pub from_subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RetrieveEthRequest {
+    pub block_index: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum WithdrawalError {
+    TemporarilyUnavailable(String),
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum TxFinalizedStatus {
+    Success {
+        transaction_hash: String,
+        effective_transaction_fee: Option<Nat>,
+    },
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum RetrieveEthStatus {
+    NotFound,
+    Pending,
+    TxFinalized(TxFinalizedStatus),
+}
+
+#[derive(Default)]
+struct State {
+    ledger: Option<Principal>,
+    deposits: BTreeMap<Account, Vec<u64>>,
+    /// Withdrawals by ledger block index, `true` once confirmed.
+    withdrawals: BTreeMap<u64, bool>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn ledger() -> Principal {
+    STATE.with(|s| s.borrow().ledger.unwrap())
+}
+
+/// The default subaccount is `None`, so accounts can be compared.
+fn subaccount(subaccount: &Option<Vec<u8>>) -> Option<[u8; 32]> {
+    subaccount
+        .as_ref()
+        .and_then(|subaccount| subaccount.as_slice().try_into().ok())
+        .filter(|subaccount| subaccount != &[0; 32])
+}
+
+fn account(args: &MinterAccountArgs) -> Account {
+    Account {
+        owner: args.owner.unwrap_or_else(ic_cdk::caller),
+        subaccount: subaccount(&args.subaccount),
+    }
+}
+
+/// Burns the caller's tokens through the allowance it gave the minter.
+async fn burn(from_subaccount: Option<[u8; 32]>, amount: Nat) -> Result<u64, String> {
+    let transfer = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account {
+            owner: ic_cdk::caller(),
+            subaccount: from_subaccount,
+        },
+        to: Account {
+            owner: ic_cdk::id(),
+            subaccount: None,
+        },
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let result: CallResult<(Result<Nat, TransferFromError>,)> =
+        ic_cdk::call(ledger(), "icrc2_transfer_from", (transfer,)).await;
+
+    let block_index = match result {
+        Ok((Ok(block_index),)) => block_index,
+        Ok((Err(e),)) => return Err(format!("{:?}", e)),
+        Err((_, message)) => return Err(message),
+    };
+    let block_index: u64 = block_index.0.try_into().unwrap();
+    STATE.with(|s| s.borrow_mut().withdrawals.insert(block_index, false));
+    Ok(block_index)
+}
+
+fn fake_hash(block_index: u64) -> Vec<u8> {
+    let mut hash = vec![0u8; 32];
+    hash[24..].copy_from_slice(&block_index.to_be_bytes());
+    hash
+}
+
+fn withdrawal(block_index: u64) -> Option<bool> {
+    STATE.with(|s| s.borrow().withdrawals.get(&block_index).copied())
+}
+
+#[ic_cdk::init]
+fn init(args: MockMinterArgs) {
+    STATE.with(|s| s.borrow_mut().ledger = Some(args.ledger));
+}
+
+#[ic_cdk::update]
+fn get_btc_address(args: MinterAccountArgs) -> String {
+    format!("bcrt1q{}", account(&args).owner.to_text().replace('-', ""))
+}
+
+#[ic_cdk::update]
+async fn update_balance(args: MinterAccountArgs) -> Result<Vec<UtxoStatus>, UpdateBalanceError> {
+    let account = account(&args);
+    let deposits = STATE.with(|s| s.borrow_mut().deposits.remove(&account).unwrap_or_default());
+    if deposits.is_empty() {
+        return Err(UpdateBalanceError::NoNewUtxos {
+            current_confirmations: None,
+            required_confirmations: 6,
+        });
+    }
+
+    let mut statuses = vec![];
+    for (vout, value) in deposits.into_iter().enumerate() {
+        // Transfers from the minting account are mints
+        let transfer = TransferArg {
+            from_subaccount: None,
+            to: account,
+            fee: None,
+            created_at_time: None,
+            memo: None,
+            amount: Nat::from(value),
+        };
+        let result: CallResult<(Result<Nat, TransferError>,)> =
+            ic_cdk::call(ledger(), "icrc1_transfer", (transfer,)).await;
+        let block_index = match result {
+            Ok((Ok(block_index),)) => block_index.0.try_into().unwrap(),
+            Ok((Err(e),)) => {
+                return Err(UpdateBalanceError::GenericError {
+                    error_code: 0,
+                    error_message: format!("{:?}", e),
+                })
+            }
+            Err((_, message)) => return Err(UpdateBalanceError::TemporarilyUnavailable(message)),
+        };
+
+        statuses.push(UtxoStatus::Minted {
+            block_index,
+            minted_amount: value,
+            utxo: Utxo {
+                outpoint: OutPoint {
+                    txid: vec![0; 32],
+                    vout: vout as u32,
+                },
+                value,
+                height: 0,
+            },
+        });
+    }
+
+    Ok(statuses)
+}
+
+#[ic_cdk::update]
+async fn retrieve_btc_with_approval(
+    args: RetrieveBtcWithApprovalArgs,
+) -> Result<RetrieveBtcOk, RetrieveBtcWithApprovalError> {
+    burn(subaccount(&args.from_subaccount), Nat::from(args.amount))
+        .await
+        .map(|block_index| RetrieveBtcOk { block_index })
+        .map_err(|e| RetrieveBtcWithApprovalError::GenericError {
+            error_code: 0,
+            error_message: e,
+        })
+}
+
+#[ic_cdk::query]
+fn retrieve_btc_status_v2(request: RetrieveBtcStatusRequest) -> RetrieveBtcStatusV2 {
+    match withdrawal(request.block_index) {
+        None => RetrieveBtcStatusV2::Unknown,
+        Some(false) => RetrieveBtcStatusV2::Pending,
+        Some(true) => RetrieveBtcStatusV2::Confirmed {
+            txid: fake_hash(request.block_index),
+        },
+    }
+}
+
+#[ic_cdk::update]
+async fn withdraw_eth(args: WithdrawalArg) -> Result<RetrieveEthRequest, WithdrawalError> {
+    burn(subaccount(&args.from_subaccount), args.amount)
+        .await
+        .map(|block_index| RetrieveEthRequest {
+            block_index: Nat::from(block_index),
+        })
+        .map_err(WithdrawalError::TemporarilyUnavailable)
+}
+
+#[ic_cdk::query]
+fn retrieve_eth_status(block_index: u64) -> RetrieveEthStatus {
+    match withdrawal(block_index) {
+        None => RetrieveEthStatus::NotFound,
+        Some(false) => RetrieveEthStatus::Pending,
+        Some(true) => RetrieveEthStatus::TxFinalized(TxFinalizedStatus::Success {
+            transaction_hash: format!("0x{}", to_hex(&fake_hash(block_index))),
+            effective_transaction_fee: None,
+        }),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Registers a confirmed Bitcoin deposit to the deposit address of `owner`.
+#[ic_cdk::update]
+fn mock_deposit(owner: Principal, subaccount: Option<Vec<u8>>, value: u64) {
+    let account = account(&MinterAccountArgs {
+        owner: Some(owner),
+        subaccount,
+    });
+    STATE.with(|s| s.borrow_mut().deposits.entry(account).or_default().push(value));
+}
+
+/// Settles every pending withdrawal.
+#[ic_cdk::update]
+fn mock_confirm_withdrawals() {
+    STATE.with(|s| {
+        for confirmed in s.borrow_mut().withdrawals.values_mut() {
+            *confirmed = true;
+        }
+    });
+}
@@ -0,0 +1,478 @@
+//! NNS governance used by the integration tests. Installed under the governance
+//! canister's id, it claims neurons from the ICP staked on its subaccounts and keeps
+//! them on the heap. Proposals are not validated, any proposal id can be voted on.
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use candid::{CandidType, Principal};
+use ic_ledger_types::{
+    AccountBalanceArgs, AccountIdentifier, Memo, Subaccount, Tokens, TransferArgs,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const TRANSFER_FEE: u64 = 10_000;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MockGovernanceArgs {
+    pub ledger: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct NeuronId {
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ProposalId {
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct GovernanceError {
+    pub error_type: i32,
+    pub error_message: String,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ClaimOrRefreshNeuronFromAccount {
+    pub controller: Option<Principal>,
+    pub memo: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum ClaimOrRefreshResult {
+    Error(GovernanceError),
+    NeuronId(NeuronId),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ClaimOrRefreshNeuronFromAccountResponse {
+    pub result: Option<ClaimOrRefreshResult>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Empty {}
+
+#[derive(CandidType, Deserialize)]
+pub struct IncreaseDissolveDelay {
+    pub additional_dissolve_delay_seconds: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum Operation {
+    IncreaseDissolveDelay(IncreaseDissolveDelay),
+    StartDissolving(Empty),
+    StopDissolving(Empty),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Configure {
+    pub operation: Option<Operation>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Follow {
+    pub topic: i32,
+    pub followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RegisterVote {
+    pub vote: i32,
+    pub proposal: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LedgerAccount {
+    pub hash: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Amount {
+    pub e8s: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Disburse {
+    pub to_account: Option<LedgerAccount>,
+    pub amount: Option<Amount>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum Command {
+    Configure(Configure),
+    Follow(Follow),
+    RegisterVote(RegisterVote),
+    Disburse(Disburse),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ManageNeuron {
+    pub id: Option<NeuronId>,
+    pub command: Option<Command>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct DisburseResponse {
+    pub transfer_block_height: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum CommandResponse {
+    Error(GovernanceError),
+    Configure(Empty),
+    Follow(Empty),
+    RegisterVote(Empty),
+    Disburse(DisburseResponse),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ManageNeuronResponse {
+    pub command: Option<CommandResponse>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ListNeurons {
+    pub neuron_ids: Vec<u64>,
+    pub include_neurons_readable_by_caller: bool,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct BallotInfo {
+    pub vote: i32,
+    pub proposal_id: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct NeuronInfo {
+    pub dissolve_delay_seconds: u64,
+    pub state: i32,
+    pub stake_e8s: u64,
+    pub recent_ballots: Vec<BallotInfo>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Followees {
+    pub followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Neuron {
+    pub id: Option<NeuronId>,
+    pub followees: Vec<(i32, Followees)>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ListNeuronsResponse {
+    pub neuron_infos: Vec<(u64, NeuronInfo)>,
+    pub full_neurons: Vec<Neuron>,
+}
+
+struct MockNeuron {
+    controller: Principal,
+    subaccount: Subaccount,
+    stake_e8s: u64,
+    dissolve_delay_seconds: u64,
+    /// Seconds since the epoch when a dissolving neuron is dissolved.
+    when_dissolved: Option<u64>,
+    followees: BTreeMap<i32, Vec<u64>>,
+    ballots: Vec<(u64, i32)>,
+}
+
+impl MockNeuron {
+    fn state(&self, now: u64) -> i32 {
+        match self.when_dissolved {
+            Some(when) if when > now => 2,
+            Some(_) => 3,
+            None if self.dissolve_delay_seconds == 0 => 3,
+            None => 1,
+        }
+    }
+
+    fn dissolve_delay(&self, now: u64) -> u64 {
+        match self.when_dissolved {
+            Some(when) => when.saturating_sub(now),
+            None => self.dissolve_delay_seconds,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    ledger: Option<Principal>,
+    neurons: BTreeMap<u64, MockNeuron>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn now() -> u64 {
+    ic_cdk::api::time() / 1_000_000_000
+}
+
+fn ledger() -> Principal {
+    STATE.with(|s| s.borrow().ledger.unwrap())
+}
+
+fn neuron_subaccount(controller: Principal, memo: u64) -> Subaccount {
+    let mut hasher = Sha256::new();
+    hasher.update([0x0c]);
+    hasher.update(b"neuron-stake");
+    hasher.update(controller.as_slice());
+    hasher.update(memo.to_be_bytes());
+    Subaccount(hasher.finalize().into())
+}
+
+fn error(message: &str) -> GovernanceError {
+    GovernanceError {
+        error_type: 0,
+        error_message: message.to_string(),
+    }
+}
+
+#[ic_cdk::init]
+fn init(args: MockGovernanceArgs) {
+    STATE.with(|s| s.borrow_mut().ledger = Some(args.ledger));
+}
+
+#[ic_cdk::update]
+async fn claim_or_refresh_neuron_from_account(
+    request: ClaimOrRefreshNeuronFromAccount,
+) -> ClaimOrRefreshNeuronFromAccountResponse {
+    let controller = request.controller.unwrap_or_else(ic_cdk::caller);
+    let subaccount = neuron_subaccount(controller, request.memo);
+
+    let balance = ic_ledger_types::account_balance(
+        ledger(),
+        AccountBalanceArgs {
+            account: AccountIdentifier::new(&ic_cdk::id(), &subaccount),
+        },
+    )
+    .await;
+    let stake_e8s = match balance {
+        Ok(balance) if balance.e8s() > 0 => balance.e8s(),
+        Ok(_) => {
+            return ClaimOrRefreshNeuronFromAccountResponse {
+                result: Some(ClaimOrRefreshResult::Error(error("Nothing staked"))),
+            }
+        }
+        Err((_, message)) => {
+            return ClaimOrRefreshNeuronFromAccountResponse {
+                result: Some(ClaimOrRefreshResult::Error(error(&message))),
+            }
+        }
+    };
+
+    let id = STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let existing = s
+            .neurons
+            .iter()
+            .find(|(_, neuron)| neuron.subaccount == subaccount)
+            .map(|(id, _)| *id);
+
+        match existing {
+            Some(id) => {
+                s.neurons.get_mut(&id).unwrap().stake_e8s = stake_e8s;
+                id
+            }
+            None => {
+                let id = s.neurons.len() as u64 + 1;
+                s.neurons.insert(
+                    id,
+                    MockNeuron {
+                        controller,
+                        subaccount,
+                        stake_e8s,
+                        dissolve_delay_seconds: 0,
+                        when_dissolved: None,
+                        followees: BTreeMap::new(),
+                        ballots: vec![],
+                    },
+                );
+                id
+            }
+        }
+    });
+
+    ClaimOrRefreshNeuronFromAccountResponse {
+        result: Some(ClaimOrRefreshResult::NeuronId(NeuronId { id })),
+    }
+}
+
+/// Applies a command that does not call the ledger.
+fn apply(neuron: &mut MockNeuron, command: Command) -> Result<CommandResponse, GovernanceError> {
+    let now = now();
+    match command {
+        Command::Configure(Configure {
+            operation: Some(operation),
+        }) => {
+            match operation {
+                Operation::IncreaseDissolveDelay(increase) => {
+                    let additional = increase.additional_dissolve_delay_seconds as u64;
+                    match neuron.when_dissolved.as_mut() {
+                        Some(when) => *when = (*when).max(now) + additional,
+                        None => neuron.dissolve_delay_seconds += additional,
+                    }
+                }
+                Operation::StartDissolving(_) => {
+                    if neuron.when_dissolved.is_some() {
+                        return Err(error("Already dissolving"));
+                    }
+                    neuron.when_dissolved = Some(now + neuron.dissolve_delay_seconds);
+                }
+                Operation::StopDissolving(_) => match neuron.when_dissolved {
+                    Some(when) if when > now => {
+                        neuron.dissolve_delay_seconds = when - now;
+                        neuron.when_dissolved = None;
+                    }
+                    _ => return Err(error("Not dissolving")),
+                },
+            }
+            Ok(CommandResponse::Configure(Empty {}))
+        }
+        Command::Configure(Configure { operation: None }) => Err(error("Missing operation")),
+        Command::Follow(follow) => {
+            let followees: Vec<u64> = follow.followees.iter().map(|f| f.id).collect();
+            if followees.is_empty() {
+                neuron.followees.remove(&follow.topic);
+            } else {
+                neuron.followees.insert(follow.topic, followees);
+            }
+            Ok(CommandResponse::Follow(Empty {}))
+        }
+        Command::RegisterVote(vote) => {
+            let proposal = vote.proposal.ok_or_else(|| error("Missing proposal"))?;
+            if neuron.ballots.iter().any(|(id, _)| *id == proposal.id) {
+                return Err(error("Already voted"));
+            }
+            neuron.ballots.push((proposal.id, vote.vote));
+            Ok(CommandResponse::RegisterVote(Empty {}))
+        }
+        Command::Disburse(_) => unreachable!("disbursing calls the ledger"),
+    }
+}
+
+async fn disburse(id: u64, disburse: Disburse) -> Result<CommandResponse, GovernanceError> {
+    let (controller, subaccount, stake_e8s, state) = STATE.with(|s| {
+        let s = s.borrow();
+        let neuron = &s.neurons[&id];
+        (neuron.controller, neuron.subaccount, neuron.stake_e8s, neuron.state(now()))
+    });
+    if state != 3 {
+        return Err(error("Neuron is not dissolved"));
+    }
+
+    let to = match disburse.to_account {
+        Some(account) => AccountIdentifier::from_slice(&account.hash)
+            .map_err(|_| error("Invalid account"))?,
+        None => AccountIdentifier::new(&controller, &Subaccount([0; 32])),
+    };
+    let amount = disburse
+        .amount
+        .map(|amount| amount.e8s)
+        .unwrap_or(stake_e8s.saturating_sub(TRANSFER_FEE));
+
+    let result = ic_ledger_types::transfer(
+        ledger(),
+        TransferArgs {
+            memo: Memo(0),
+            amount: Tokens::from_e8s(amount),
+            fee: Tokens::from_e8s(TRANSFER_FEE),
+            from_subaccount: Some(subaccount),
+            to,
+            created_at_time: None,
+        },
+    )
+    .await;
+
+    match result {
+        Ok(Ok(block_height)) => {
+            STATE.with(|s| {
+                let mut s = s.borrow_mut();
+                let neuron = s.neurons.get_mut(&id).unwrap();
+                neuron.stake_e8s = neuron.stake_e8s.saturating_sub(amount + TRANSFER_FEE);
+            });
+            Ok(CommandResponse::Disburse(DisburseResponse {
+                transfer_block_height: block_height,
+            }))
+        }
+        Ok(Err(e)) => Err(error(&format!("{:?}", e))),
+        Err((_, message)) => Err(error(&message)),
+    }
+}
+
+#[ic_cdk::update]
+async fn manage_neuron(request: ManageNeuron) -> ManageNeuronResponse {
+    let result = async {
+        let id = request.id.ok_or_else(|| error("Missing neuron id"))?.id;
+        let command = request.command.ok_or_else(|| error("Missing command"))?;
+
+        let controller = STATE.with(|s| s.borrow().neurons.get(&id).map(|n| n.controller));
+        match controller {
+            None => return Err(error("Neuron not found")),
+            Some(controller) if controller != ic_cdk::caller() => {
+                return Err(error("Caller is not the controller"))
+            }
+            Some(_) => (),
+        }
+
+        match command {
+            Command::Disburse(args) => disburse(id, args).await,
+            command => STATE.with(|s| apply(s.borrow_mut().neurons.get_mut(&id).unwrap(), command)),
+        }
+    }
+    .await;
+
+    ManageNeuronResponse {
+        command: Some(result.unwrap_or_else(CommandResponse::Error)),
+    }
+}
+
+#[ic_cdk::query]
+fn list_neurons(_request: ListNeurons) -> ListNeuronsResponse {
+    let caller = ic_cdk::caller();
+    let now = now();
+
+    STATE.with(|s| {
+        let s = s.borrow();
+        let neurons = s.neurons.iter().filter(|(_, n)| n.controller == caller);
+
+        ListNeuronsResponse {
+            neuron_infos: neurons
+                .clone()
+                .map(|(id, neuron)| {
+                    let info = NeuronInfo {
+                        dissolve_delay_seconds: neuron.dissolve_delay(now),
+                        state: neuron.state(now),
+                        stake_e8s: neuron.stake_e8s,
+                        recent_ballots: neuron
+                            .ballots
+                            .iter()
+                            .map(|(proposal, vote)| BallotInfo {
+                                vote: *vote,
+                                proposal_id: Some(ProposalId { id: *proposal }),
+                            })
+                            .collect(),
+                    };
+                    (*id, info)
+                })
+                .collect(),
+            full_neurons: neurons
+                .map(|(id, neuron)| Neuron {
+                    id: Some(NeuronId { id: *id }),
+                    followees: neuron
+                        .followees
+                        .iter()
+                        .map(|(topic, followees)| {
+                            let followees = followees.iter().map(|id| NeuronId { id: *id }).collect();
+                            (*topic, Followees { followees })
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    })
+}
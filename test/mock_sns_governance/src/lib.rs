@@ -0,0 +1,541 @@
+//! SNS governance used by the integration tests. It claims neurons from the tokens
+//! staked on its subaccounts of its ledger and keeps them on the heap. Proposals are
+//! recorded but never decided, so any proposal id can be voted on.
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use icrc_ledger_types::icrc1::{
+    account::Account as IcrcAccount,
+    transfer::{TransferArg, TransferError},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MockSnsGovernanceArgs {
+    pub ledger: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct NeuronId {
+    pub id: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ProposalId {
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct GovernanceError {
+    pub error_type: i32,
+    pub error_message: String,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct MemoAndController {
+    pub controller: Option<Principal>,
+    pub memo: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum By {
+    MemoAndController(MemoAndController),
+    NeuronId(Empty),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ClaimOrRefresh {
+    pub by: Option<By>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Empty {}
+
+#[derive(CandidType, Deserialize)]
+pub struct IncreaseDissolveDelay {
+    pub additional_dissolve_delay_seconds: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum Operation {
+    IncreaseDissolveDelay(IncreaseDissolveDelay),
+    StartDissolving(Empty),
+    StopDissolving(Empty),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Configure {
+    pub operation: Option<Operation>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Follow {
+    pub function_id: u64,
+    pub followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RegisterVote {
+    pub vote: i32,
+    pub proposal: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Motion {
+    pub motion_text: String,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ExecuteGenericNervousSystemFunction {
+    pub function_id: u64,
+    pub payload: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum Action {
+    Motion(Motion),
+    ExecuteGenericNervousSystemFunction(ExecuteGenericNervousSystemFunction),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Proposal {
+    pub title: String,
+    pub url: String,
+    pub summary: String,
+    pub action: Option<Action>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Subaccount {
+    pub subaccount: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Option<Principal>,
+    pub subaccount: Option<Subaccount>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Amount {
+    pub e8s: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Disburse {
+    pub to_account: Option<Account>,
+    pub amount: Option<Amount>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum Command {
+    ClaimOrRefresh(ClaimOrRefresh),
+    Configure(Configure),
+    Follow(Follow),
+    RegisterVote(RegisterVote),
+    MakeProposal(Proposal),
+    Disburse(Disburse),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ManageNeuron {
+    pub subaccount: Vec<u8>,
+    pub command: Option<Command>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ClaimOrRefreshResponse {
+    pub refreshed_neuron_id: Option<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct GetProposal {
+    pub proposal_id: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct DisburseResponse {
+    pub transfer_block_height: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum CommandResponse {
+    Error(GovernanceError),
+    ClaimOrRefresh(ClaimOrRefreshResponse),
+    Configure(Empty),
+    Follow(Empty),
+    RegisterVote(Empty),
+    MakeProposal(GetProposal),
+    Disburse(DisburseResponse),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ManageNeuronResponse {
+    pub command: Option<CommandResponse>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ListNeurons {
+    pub of_principal: Option<Principal>,
+    pub limit: u32,
+    pub start_page_at: Option<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum DissolveState {
+    DissolveDelaySeconds(u64),
+    WhenDissolvedTimestampSeconds(u64),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Followees {
+    pub followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Neuron {
+    pub id: Option<NeuronId>,
+    pub cached_neuron_stake_e8s: u64,
+    pub neuron_fees_e8s: u64,
+    pub dissolve_state: Option<DissolveState>,
+    pub followees: Vec<(u64, Followees)>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ListNeuronsResponse {
+    pub neurons: Vec<Neuron>,
+}
+
+struct MockNeuron {
+    controller: Principal,
+    stake_e8s: u64,
+    dissolve_delay_seconds: u64,
+    /// Seconds since the epoch when a dissolving neuron is dissolved.
+    when_dissolved: Option<u64>,
+    followees: BTreeMap<u64, Vec<Vec<u8>>>,
+    ballots: Vec<(u64, i32)>,
+}
+
+impl MockNeuron {
+    fn dissolve_state(&self) -> DissolveState {
+        match self.when_dissolved {
+            Some(when) => DissolveState::WhenDissolvedTimestampSeconds(when),
+            None => DissolveState::DissolveDelaySeconds(self.dissolve_delay_seconds),
+        }
+    }
+
+    fn is_dissolved(&self, now: u64) -> bool {
+        match self.when_dissolved {
+            Some(when) => when <= now,
+            None => self.dissolve_delay_seconds == 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    ledger: Option<Principal>,
+    /// Neurons by id, which is their subaccount.
+    neurons: BTreeMap<Vec<u8>, MockNeuron>,
+    proposals: Vec<(Vec<u8>, String)>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn now() -> u64 {
+    ic_cdk::api::time() / 1_000_000_000
+}
+
+fn ledger() -> Principal {
+    STATE.with(|s| s.borrow().ledger.unwrap())
+}
+
+fn neuron_subaccount(controller: Principal, memo: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0x0c]);
+    hasher.update(b"neuron-stake");
+    hasher.update(controller.as_slice());
+    hasher.update(memo.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn error(message: &str) -> GovernanceError {
+    GovernanceError {
+        error_type: 0,
+        error_message: message.to_string(),
+    }
+}
+
+#[ic_cdk::init]
+fn init(args: MockSnsGovernanceArgs) {
+    STATE.with(|s| s.borrow_mut().ledger = Some(args.ledger));
+}
+
+async fn claim_or_refresh(claim: ClaimOrRefresh) -> Result<CommandResponse, GovernanceError> {
+    let (controller, memo) = match claim.by {
+        Some(By::MemoAndController(by)) => {
+            (by.controller.unwrap_or_else(ic_cdk::caller), by.memo)
+        }
+        _ => return Err(error("Only claims by memo and controller are supported")),
+    };
+    let subaccount = neuron_subaccount(controller, memo);
+
+    let account = IcrcAccount {
+        owner: ic_cdk::id(),
+        subaccount: Some(subaccount),
+    };
+    let result: CallResult<(Nat,)> = ic_cdk::call(ledger(), "icrc1_balance_of", (account,)).await;
+    let stake_e8s: u64 = match result {
+        Ok((balance,)) => balance.0.try_into().unwrap(),
+        Err((_, message)) => return Err(error(&message)),
+    };
+    if stake_e8s == 0 {
+        return Err(error("Nothing staked"));
+    }
+
+    STATE.with(|s| {
+        s.borrow_mut()
+            .neurons
+            .entry(subaccount.to_vec())
+            .and_modify(|neuron| neuron.stake_e8s = stake_e8s)
+            .or_insert(MockNeuron {
+                controller,
+                stake_e8s,
+                dissolve_delay_seconds: 0,
+                when_dissolved: None,
+                followees: BTreeMap::new(),
+                ballots: vec![],
+            });
+    });
+
+    Ok(CommandResponse::ClaimOrRefresh(ClaimOrRefreshResponse {
+        refreshed_neuron_id: Some(NeuronId {
+            id: subaccount.to_vec(),
+        }),
+    }))
+}
+
+/// Applies a command that does not call the ledger.
+fn apply(
+    id: &[u8],
+    neuron: &mut MockNeuron,
+    proposals: &mut Vec<(Vec<u8>, String)>,
+    command: Command,
+) -> Result<CommandResponse, GovernanceError> {
+    let now = now();
+    match command {
+        Command::Configure(Configure {
+            operation: Some(operation),
+        }) => {
+            match operation {
+                Operation::IncreaseDissolveDelay(increase) => {
+                    let additional = increase.additional_dissolve_delay_seconds as u64;
+                    match neuron.when_dissolved.as_mut() {
+                        Some(when) => *when = (*when).max(now) + additional,
+                        None => neuron.dissolve_delay_seconds += additional,
+                    }
+                }
+                Operation::StartDissolving(_) => {
+                    if neuron.when_dissolved.is_some() {
+                        return Err(error("Already dissolving"));
+                    }
+                    neuron.when_dissolved = Some(now + neuron.dissolve_delay_seconds);
+                    neuron.dissolve_delay_seconds = 0;
+                }
+                Operation::StopDissolving(_) => match neuron.when_dissolved {
+                    Some(when) if when > now => {
+                        neuron.dissolve_delay_seconds = when - now;
+                        neuron.when_dissolved = None;
+                    }
+                    _ => return Err(error("Not dissolving")),
+                },
+            }
+            Ok(CommandResponse::Configure(Empty {}))
+        }
+        Command::Configure(Configure { operation: None }) => Err(error("Missing operation")),
+        Command::Follow(follow) => {
+            let followees: Vec<Vec<u8>> = follow.followees.into_iter().map(|f| f.id).collect();
+            if followees.is_empty() {
+                neuron.followees.remove(&follow.function_id);
+            } else {
+                neuron.followees.insert(follow.function_id, followees);
+            }
+            Ok(CommandResponse::Follow(Empty {}))
+        }
+        Command::RegisterVote(vote) => {
+            let proposal = vote.proposal.ok_or_else(|| error("Missing proposal"))?;
+            if neuron.ballots.iter().any(|(id, _)| *id == proposal.id) {
+                return Err(error("Already voted"));
+            }
+            neuron.ballots.push((proposal.id, vote.vote));
+            Ok(CommandResponse::RegisterVote(Empty {}))
+        }
+        Command::MakeProposal(proposal) => {
+            if proposal.action.is_none() {
+                return Err(error("Missing action"));
+            }
+            proposals.push((id.to_vec(), proposal.title));
+            Ok(CommandResponse::MakeProposal(GetProposal {
+                proposal_id: Some(ProposalId {
+                    id: proposals.len() as u64,
+                }),
+            }))
+        }
+        Command::ClaimOrRefresh(_) | Command::Disburse(_) => {
+            unreachable!("claiming and disbursing call the ledger")
+        }
+    }
+}
+
+async fn disburse(id: Vec<u8>, disburse: Disburse) -> Result<CommandResponse, GovernanceError> {
+    let (controller, stake_e8s, dissolved) = STATE.with(|s| {
+        let s = s.borrow();
+        let neuron = &s.neurons[&id];
+        (neuron.controller, neuron.stake_e8s, neuron.is_dissolved(now()))
+    });
+    if !dissolved {
+        return Err(error("Neuron is not dissolved"));
+    }
+
+    let (fee,): (Nat,) = ic_cdk::call(ledger(), "icrc1_fee", ())
+        .await
+        .map_err(|(_, message)| error(&message))?;
+    let fee: u64 = fee.0.try_into().unwrap();
+
+    let to = match disburse.to_account {
+        Some(account) => IcrcAccount {
+            owner: account.owner.ok_or_else(|| error("Missing owner"))?,
+            subaccount: account
+                .subaccount
+                .map(|subaccount| subaccount.subaccount.try_into())
+                .transpose()
+                .map_err(|_| error("Invalid subaccount"))?,
+        },
+        None => IcrcAccount {
+            owner: controller,
+            subaccount: None,
+        },
+    };
+    let amount = disburse
+        .amount
+        .map(|amount| amount.e8s)
+        .unwrap_or(stake_e8s.saturating_sub(fee));
+
+    let transfer = TransferArg {
+        from_subaccount: Some(id.clone().try_into().unwrap()),
+        to,
+        fee: None,
+        created_at_time: None,
+        memo: None,
+        amount: Nat::from(amount),
+    };
+    let result: CallResult<(Result<Nat, TransferError>,)> =
+        ic_cdk::call(ledger(), "icrc1_transfer", (transfer,)).await;
+
+    match result {
+        Ok((Ok(block_index),)) => {
+            STATE.with(|s| {
+                let mut s = s.borrow_mut();
+                let neuron = s.neurons.get_mut(&id).unwrap();
+                neuron.stake_e8s = neuron.stake_e8s.saturating_sub(amount + fee);
+            });
+            Ok(CommandResponse::Disburse(DisburseResponse {
+                transfer_block_height: block_index.0.try_into().unwrap(),
+            }))
+        }
+        Ok((Err(e),)) => Err(error(&format!("{:?}", e))),
+        Err((_, message)) => Err(error(&message)),
+    }
+}
+
+#[ic_cdk::update]
+async fn manage_neuron(request: ManageNeuron) -> ManageNeuronResponse {
+    let result = async {
+        let command = request.command.ok_or_else(|| error("Missing command"))?;
+        if let Command::ClaimOrRefresh(claim) = command {
+            return claim_or_refresh(claim).await;
+        }
+
+        let id = request.subaccount;
+        let controller = STATE.with(|s| s.borrow().neurons.get(&id).map(|n| n.controller));
+        match controller {
+            None => return Err(error("Neuron not found")),
+            Some(controller) if controller != ic_cdk::caller() => {
+                return Err(error("Caller is not the controller"))
+            }
+            Some(_) => (),
+        }
+
+        match command {
+            Command::Disburse(args) => disburse(id, args).await,
+            command => STATE.with(|s| {
+                let s = &mut *s.borrow_mut();
+                apply(&id, s.neurons.get_mut(&id).unwrap(), &mut s.proposals, command)
+            }),
+        }
+    }
+    .await;
+
+    ManageNeuronResponse {
+        command: Some(result.unwrap_or_else(CommandResponse::Error)),
+    }
+}
+
+#[ic_cdk::query]
+fn list_neurons(request: ListNeurons) -> ListNeuronsResponse {
+    let of_principal = request.of_principal.unwrap_or_else(ic_cdk::caller);
+    let start = request.start_page_at.map(|id| id.id);
+
+    STATE.with(|s| {
+        let s = s.borrow();
+        let neurons = s
+            .neurons
+            .iter()
+            .filter(|(id, _)| start.as_ref().map_or(true, |start| *id > start))
+            .filter(|(_, neuron)| neuron.controller == of_principal)
+            .take(request.limit as usize)
+            .map(|(id, neuron)| Neuron {
+                id: Some(NeuronId { id: id.clone() }),
+                cached_neuron_stake_e8s: neuron.stake_e8s,
+                neuron_fees_e8s: 0,
+                dissolve_state: Some(neuron.dissolve_state()),
+                followees: neuron
+                    .followees
+                    .iter()
+                    .map(|(function_id, followees)| {
+                        let followees = followees
+                            .iter()
+                            .map(|id| NeuronId { id: id.clone() })
+                            .collect();
+                        (*function_id, Followees { followees })
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        ListNeuronsResponse { neurons }
+    })
+}
+
+/// Proposals submitted so far as the proposing neuron and the title, by proposal id - 1.
+#[ic_cdk::query]
+fn mock_proposals() -> Vec<(Vec<u8>, String)> {
+    STATE.with(|s| s.borrow().proposals.clone())
+}
+
+/// Votes of a neuron as proposal ids and ballots.
+#[ic_cdk::query]
+fn mock_ballots(neuron_id: Vec<u8>) -> Vec<(u64, i32)> {
+    STATE.with(|s| {
+        s.borrow()
+            .neurons
+            .get(&neuron_id)
+            .map(|neuron| neuron.ballots.clone())
+            .unwrap_or_default()
+    })
+}
@@ -0,0 +1,310 @@
+//! ICPSwap-style pool used by the integration tests. It swaps at a fixed rate and
+//! keeps the deposited balances of each user on the heap.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+use icrc_ledger_types::{
+    icrc1::{
+        account::Account,
+        transfer::{TransferArg, TransferError},
+    },
+    icrc2::transfer_from::{TransferFromArgs, TransferFromError},
+};
+use serde::Deserialize;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MockSwapPoolArgs {
+    pub token0: Principal,
+    pub token1: Principal,
+    /// Amount of token1 paid per token0, in basis points.
+    pub price_bps: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum PoolError {
+    CommonError,
+    InternalError(String),
+    UnsupportedToken(String),
+    InsufficientFunds,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum PoolResult<T> {
+    #[serde(rename = "ok")]
+    Ok(T),
+    #[serde(rename = "err")]
+    Err(PoolError),
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PoolToken {
+    pub address: String,
+    pub standard: String,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PoolMetadata {
+    pub token0: PoolToken,
+    pub token1: PoolToken,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SwapArgs {
+    #[serde(rename = "amountIn")]
+    pub amount_in: String,
+    #[serde(rename = "zeroForOne")]
+    pub zero_for_one: bool,
+    #[serde(rename = "amountOutMinimum")]
+    pub amount_out_minimum: String,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct DepositArgs {
+    pub token: String,
+    pub amount: Nat,
+    pub fee: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct UnusedBalance {
+    pub balance0: Nat,
+    pub balance1: Nat,
+}
+
+#[derive(Default)]
+struct State {
+    token0: Option<Principal>,
+    token1: Option<Principal>,
+    price_bps: u64,
+    fail_swaps: bool,
+    balances: HashMap<(Principal, Principal), Nat>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn tokens() -> (Principal, Principal) {
+    STATE.with(|s| {
+        let s = s.borrow();
+        (s.token0.unwrap(), s.token1.unwrap())
+    })
+}
+
+fn balance_of(user: Principal, token: Principal) -> Nat {
+    STATE.with(|s| {
+        s.borrow()
+            .balances
+            .get(&(user, token))
+            .cloned()
+            .unwrap_or_else(|| Nat::from(0u64))
+    })
+}
+
+fn credit(user: Principal, token: Principal, amount: Nat) {
+    let balance = balance_of(user, token) + amount;
+    STATE.with(|s| s.borrow_mut().balances.insert((user, token), balance));
+}
+
+fn debit(user: Principal, token: Principal, amount: &Nat) -> Result<(), PoolError> {
+    let balance = balance_of(user, token);
+    if &balance < amount {
+        return Err(PoolError::InsufficientFunds);
+    }
+
+    STATE.with(|s| {
+        s.borrow_mut()
+            .balances
+            .insert((user, token), balance - amount.clone())
+    });
+    Ok(())
+}
+
+fn parse_token(token: &str) -> Result<Principal, PoolError> {
+    let (token0, token1) = tokens();
+    match Principal::from_text(token) {
+        Ok(p) if p == token0 || p == token1 => Ok(p),
+        _ => Err(PoolError::UnsupportedToken(token.to_string())),
+    }
+}
+
+fn amount_out(args: &SwapArgs) -> Result<Nat, PoolError> {
+    let amount_in: u128 = args
+        .amount_in
+        .parse()
+        .map_err(|_| PoolError::InternalError("Invalid amountIn".to_string()))?;
+    let price_bps = STATE.with(|s| s.borrow().price_bps) as u128;
+
+    let out = if args.zero_for_one {
+        amount_in * price_bps / 10_000
+    } else {
+        amount_in * 10_000 / price_bps
+    };
+    Ok(Nat::from(out))
+}
+
+#[ic_cdk::init]
+fn init(args: MockSwapPoolArgs) {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.token0 = Some(args.token0);
+        s.token1 = Some(args.token1);
+        s.price_bps = args.price_bps;
+    });
+}
+
+#[ic_cdk::query]
+fn metadata() -> PoolResult<PoolMetadata> {
+    let (token0, token1) = tokens();
+    let token = |p: Principal| PoolToken {
+        address: p.to_text(),
+        standard: "ICRC2".to_string(),
+    };
+
+    PoolResult::Ok(PoolMetadata {
+        token0: token(token0),
+        token1: token(token1),
+    })
+}
+
+#[ic_cdk::query]
+fn quote(args: SwapArgs) -> PoolResult<Nat> {
+    match amount_out(&args) {
+        Ok(out) => PoolResult::Ok(out),
+        Err(e) => PoolResult::Err(e),
+    }
+}
+
+#[ic_cdk::update(name = "depositFrom")]
+async fn deposit_from(args: DepositArgs) -> PoolResult<Nat> {
+    let caller = ic_cdk::caller();
+    let token = match parse_token(&args.token) {
+        Ok(token) => token,
+        Err(e) => return PoolResult::Err(e),
+    };
+
+    let transfer = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account {
+            owner: caller,
+            subaccount: None,
+        },
+        to: Account {
+            owner: ic_cdk::id(),
+            subaccount: None,
+        },
+        amount: args.amount.clone(),
+        fee: Some(args.fee),
+        memo: None,
+        created_at_time: None,
+    };
+    let result: CallResult<(Result<Nat, TransferFromError>,)> =
+        ic_cdk::call(token, "icrc2_transfer_from", (transfer,)).await;
+
+    match result {
+        Ok((Ok(_),)) => {
+            credit(caller, token, args.amount.clone());
+            PoolResult::Ok(args.amount)
+        }
+        Ok((Err(e),)) => PoolResult::Err(PoolError::InternalError(format!("{:?}", e))),
+        Err((_, message)) => PoolResult::Err(PoolError::InternalError(message)),
+    }
+}
+
+#[ic_cdk::update]
+fn swap(args: SwapArgs) -> PoolResult<Nat> {
+    if STATE.with(|s| s.borrow().fail_swaps) {
+        return PoolResult::Err(PoolError::InternalError("Swaps are disabled".to_string()));
+    }
+
+    let caller = ic_cdk::caller();
+    let (token0, token1) = tokens();
+    let (token_in, token_out) = if args.zero_for_one {
+        (token0, token1)
+    } else {
+        (token1, token0)
+    };
+
+    let result = (|| {
+        let amount_in = Nat::from(
+            args.amount_in
+                .parse::<u128>()
+                .map_err(|_| PoolError::InternalError("Invalid amountIn".to_string()))?,
+        );
+        let minimum: u128 = args
+            .amount_out_minimum
+            .parse()
+            .map_err(|_| PoolError::InternalError("Invalid amountOutMinimum".to_string()))?;
+        let out = amount_out(&args)?;
+        if out < Nat::from(minimum) {
+            return Err(PoolError::InternalError("Slippage exceeded".to_string()));
+        }
+
+        debit(caller, token_in, &amount_in)?;
+        credit(caller, token_out, out.clone());
+        Ok(out)
+    })();
+
+    match result {
+        Ok(out) => PoolResult::Ok(out),
+        Err(e) => PoolResult::Err(e),
+    }
+}
+
+#[ic_cdk::update]
+async fn withdraw(args: DepositArgs) -> PoolResult<Nat> {
+    let caller = ic_cdk::caller();
+    let token = match parse_token(&args.token) {
+        Ok(token) => token,
+        Err(e) => return PoolResult::Err(e),
+    };
+    if args.amount <= args.fee {
+        return PoolResult::Err(PoolError::InsufficientFunds);
+    }
+    if let Err(e) = debit(caller, token, &args.amount) {
+        return PoolResult::Err(e);
+    }
+
+    let transfer = TransferArg {
+        from_subaccount: None,
+        to: Account {
+            owner: caller,
+            subaccount: None,
+        },
+        amount: args.amount.clone() - args.fee.clone(),
+        fee: Some(args.fee),
+        memo: None,
+        created_at_time: None,
+    };
+    let result: CallResult<(Result<Nat, TransferError>,)> =
+        ic_cdk::call(token, "icrc1_transfer", (transfer,)).await;
+
+    match result {
+        Ok((Ok(_),)) => PoolResult::Ok(args.amount),
+        Ok((Err(e),)) => {
+            credit(caller, token, args.amount);
+            PoolResult::Err(PoolError::InternalError(format!("{:?}", e)))
+        }
+        Err((_, message)) => {
+            credit(caller, token, args.amount);
+            PoolResult::Err(PoolError::InternalError(message))
+        }
+    }
+}
+
+#[ic_cdk::query(name = "getUserUnusedBalance")]
+fn get_user_unused_balance(user: Principal) -> PoolResult<UnusedBalance> {
+    let (token0, token1) = tokens();
+    PoolResult::Ok(UnusedBalance {
+        balance0: balance_of(user, token0),
+        balance1: balance_of(user, token1),
+    })
+}
+
+/// Makes every following swap fail, to exercise the refund path.
+#[ic_cdk::update]
+fn set_fail_swaps(fail: bool) {
+    STATE.with(|s| s.borrow_mut().fail_swaps = fail);
+}